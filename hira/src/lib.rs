@@ -2,7 +2,8 @@
 pub fn hira(attr: proc_macro::TokenStream, item: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let item = proc_macro2::TokenStream::from(item);
     let attr = proc_macro2::TokenStream::from(attr);
-    hira_lib::module_loading::hira_mod2(item, attr).into()
+    let source_file = proc_macro::Span::call_site().file();
+    hira_lib::module_loading::hira_mod2(item, attr, source_file).into()
 }
 
 #[proc_macro_attribute]
@@ -0,0 +1,154 @@
+use std::io::Read;
+use hira_lib::{HiraConfig, parsing::{iter_hira_modules, get_ident_string, get_hira_attr_args}};
+use proc_macro2::{TokenStream, TokenTree};
+use quote::ToTokens;
+use serde::Serialize;
+
+pub const SEVERITY_ERROR: &str = "error";
+pub const SEVERITY_WARNING: &str = "warning";
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Diagnostic {
+    /// the name of the module that produced the diagnostic. empty if the source could not be parsed.
+    pub module: String,
+    pub severity: &'static str,
+    pub message: String,
+}
+
+/// a config that resolves dependencies from the module cache, but doesn't write any files.
+/// wasm is compiled in a temporary directory, so that checking doesn't touch the wasm of a build that may be running.
+pub fn get_check_config() -> HiraConfig {
+    let mut conf = HiraConfig::new();
+    conf.should_do_file_ops = false;
+    conf.should_output_build_script = false;
+    conf.dont_cache_modules = true;
+    let wasm_dir = std::env::temp_dir().join("hira_check").join(&conf.crate_name);
+    conf.wasm_directory = wasm_dir.to_string_lossy().to_string();
+    conf.logfile = format!("{}/hira.log", conf.wasm_directory);
+    conf
+}
+
+/// reads rust source code from `reader` and analyzes every hira module in it.
+/// returns the diagnostics as a json array.
+pub fn check_source_json(conf: &mut HiraConfig, mut reader: impl Read) -> Result<String, String> {
+    let mut source = String::new();
+    reader.read_to_string(&mut source)
+        .map_err(|e| format!("Failed to read source code\n{:?}", e))?;
+    let diagnostics = check_source(conf, &source);
+    serde_json::to_string(&diagnostics)
+        .map_err(|e| format!("Failed to serialize diagnostics\n{:?}", e))
+}
+
+pub fn check_source(conf: &mut HiraConfig, source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut modules = vec![];
+    let res = iter_hira_modules(source, &mut |m| {
+        if hira_lib::parsing::has_attr_that_ends_in(&m.attrs, "hira") {
+            modules.push((get_ident_string(&m.ident), get_hira_attr_args(&m.attrs), m.to_token_stream()));
+        }
+        Ok(true)
+    });
+    if let Err(e) = res {
+        collect_messages("", e, true, &mut diagnostics);
+        return diagnostics;
+    }
+    for (name, attr_args, tokens) in modules {
+        let attr_args = match attr_args {
+            Ok(a) => a,
+            Err(e) => {
+                diagnostics.push(Diagnostic { module: name, severity: SEVERITY_ERROR, message: e });
+                continue;
+            }
+        };
+        match hira_lib::module_loading::hira_mod2_inner_ex(conf, tokens, true, false, None, None, attr_args) {
+            Ok(stream) => collect_messages(&name, stream, false, &mut diagnostics),
+            Err(e) => collect_messages(&name, e, true, &mut diagnostics),
+        }
+    }
+    diagnostics
+}
+
+/// finds the messages of `compile_error!` and `#[deprecated(note = ...)]`, which is how hira emits errors and warnings.
+/// the output of a successful module also contains the user's code, so unless `in_generated` is true,
+/// only the modules that hira generated (`mod _hira_generated...`) are searched.
+fn collect_messages(module: &str, stream: TokenStream, in_generated: bool, out: &mut Vec<Diagnostic>) {
+    let tokens: Vec<TokenTree> = stream.into_iter().collect();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            TokenTree::Ident(ident) if ident == "mod" && !in_generated => {
+                let is_generated = matches!(tokens.get(i + 1), Some(TokenTree::Ident(x)) if x.to_string().starts_with("_hira_generated"));
+                if let (true, Some(TokenTree::Group(g))) = (is_generated, tokens.get(i + 2)) {
+                    collect_messages(module, g.stream(), true, out);
+                }
+            }
+            TokenTree::Ident(ident) if ident == "compile_error" && in_generated => {
+                if let Some(TokenTree::Group(g)) = tokens.get(i + 2) {
+                    if let Ok(lit) = syn::parse2::<syn::LitStr>(g.stream()) {
+                        out.push(Diagnostic { module: module.to_string(), severity: SEVERITY_ERROR, message: lit.value() });
+                    }
+                }
+            }
+            TokenTree::Ident(ident) if ident == "deprecated" && in_generated => {
+                if let Some(TokenTree::Group(g)) = tokens.get(i + 1) {
+                    if let Ok(syn::MetaNameValue { value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(lit), .. }), .. }) = syn::parse2(g.stream()) {
+                        out.push(Diagnostic { module: module.to_string(), severity: SEVERITY_WARNING, message: lit.value().trim().to_string() });
+                    }
+                }
+            }
+            TokenTree::Group(g) if in_generated => collect_messages(module, g.stream(), true, out),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stdin_source_produces_diagnostics() {
+        let source = r#"
+            #[hira::hira]
+            pub mod hira_base {
+                use super::L0Core;
+                pub fn config(l0core: &mut L0Core) {}
+            }
+            #[hira::hira]
+            pub mod good {
+                use super::L0Core;
+                pub fn config(l0core: &mut L0Core) {}
+            }
+        "#;
+        let cache_dir = std::env::temp_dir().join("hira_cli_check_test_cache");
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        let mut conf = get_check_config();
+        conf.module_cache_directory = cache_dir.to_string_lossy().to_string();
+        let json = check_source_json(&mut conf, source.as_bytes()).expect("Failed to check source");
+        let diagnostics: serde_json::Value = serde_json::from_str(&json).expect("Output should be json");
+        let diagnostics = diagnostics.as_array().expect("Output should be an array");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["module"], "hira_base");
+        assert_eq!(diagnostics[0]["severity"], SEVERITY_ERROR);
+        assert!(diagnostics[0]["message"].as_str().unwrap().contains("reserved"));
+        assert!(conf.modules2.contains_key("good"));
+        // checking is side effect free
+        assert!(!cache_dir.exists());
+        assert!(std::path::Path::new(&conf.wasm_directory).starts_with(std::env::temp_dir()));
+        assert_ne!(conf.wasm_directory, HiraConfig::new().wasm_directory);
+
+        let json = check_source_json(&mut get_check_config(), "pub mod bad { this is not rust".as_bytes()).expect("Failed to check source");
+        assert!(json.contains("Failed to parse as rust file"));
+    }
+
+    #[test]
+    fn generated_warnings_are_diagnostics() {
+        let stream: TokenStream = r##"
+            pub mod mymod { #[deprecated(note = "not from hira")] pub fn a() {} }
+            mod _hira_generated_warning { #[deprecated(note = r#"
+careful"#)]pub fn hira_generated_warning() {} }
+        "##.parse().unwrap();
+        let mut out = vec![];
+        collect_messages("mymod", stream, false, &mut out);
+        assert_eq!(out, vec![Diagnostic { module: "mymod".to_string(), severity: SEVERITY_WARNING, message: "careful".to_string() }]);
+    }
+}
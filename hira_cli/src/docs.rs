@@ -0,0 +1,122 @@
+use std::{path::Path, collections::HashMap};
+use hira_lib::{HiraConfig, module_loading::{HiraModule2, Output}};
+
+/// writes `{dir}/{module name}.md` for every module in the config.
+pub fn emit_docs(conf: &HiraConfig, dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create directory {:?}\n{:?}", dir, e))?;
+    let mut names: Vec<&String> = conf.modules2.keys().collect();
+    names.sort();
+    for name in names {
+        let module = &conf.modules2[name];
+        let markdown = get_module_markdown(conf, module)?;
+        let path = dir.join(format!("{name}.md"));
+        std::fs::write(&path, markdown)
+            .map_err(|e| format!("Failed to write {:?}\n{:?}", path, e))?;
+    }
+    Ok(())
+}
+
+/// escapes characters that markdown would otherwise interpret.
+/// newlines are converted to `<br>` so that the text stays within its table cell.
+pub fn escape_markdown(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.trim().chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '{' | '}' | '[' | ']' | '(' | ')' | '#' | '+' | '!' | '|' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\n' => out.push_str("<br>"),
+            '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn get_module_markdown(conf: &HiraConfig, module: &HiraModule2) -> Result<String, String> {
+    let mut out = format!("# {}\n\n", escape_markdown(&module.name));
+    if !module.documentation.trim().is_empty() {
+        out.push_str(&escape_markdown(&module.documentation).replace("<br>", "\n"));
+        out.push_str("\n\n");
+    }
+
+    out.push_str("## Inputs\n\n");
+    if module.input_definition.is_empty() {
+        out.push_str("This module has no inputs.\n\n");
+    } else {
+        out.push_str("| Name | Type | Required | Description |\n|---|---|---|---|\n");
+        let mut inputs: Vec<_> = module.input_definition.iter().collect();
+        inputs.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, def) in inputs {
+            let ty = def.ty.replace(' ', "");
+            let required = if ty.starts_with("Option<") { "no" } else { "yes" };
+            out.push_str(&format!("| {} | {} | {} | {} |\n", escape_markdown(name), escape_markdown(&ty), required, escape_markdown(&def.documentation)));
+        }
+        out.push('\n');
+    }
+
+    out.push_str("## Outputs\n\n");
+    let mut outputs: HashMap<String, Output> = HashMap::new();
+    module.get_all_output_docs(conf, &mut outputs)
+        .map_err(|e| format!("Failed to get outputs of module {}\n{}", module.name, e))?;
+    if outputs.is_empty() {
+        out.push_str("This module has no outputs.\n");
+    } else {
+        out.push_str("| Name | Default | Description |\n|---|---|---|\n");
+        let mut outputs: Vec<_> = outputs.into_iter().collect();
+        outputs.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, output) in outputs {
+            out.push_str(&format!("| {} | {} | {} |\n", escape_markdown(&name), escape_markdown(&output.default), escape_markdown(&output.documentation)));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use hira_lib::module_loading::parse_module_from_stream;
+    use proc_macro2::TokenStream;
+    use super::*;
+
+    #[test]
+    fn module_markdown_has_input_and_output_tables() {
+        let code = r#"
+        /// Creates a *very* useful thing.
+        pub mod documented {
+            use super::L0Core;
+            #[derive(Default)]
+            pub struct Input {
+                /// the name of the thing | used as the id
+                pub name: String,
+                /// how big the thing is
+                pub size: Option<u32>,
+            }
+            pub mod outputs {
+                /// the resulting id of the [thing]
+                pub const THING_ID: &str = "none";
+            }
+            pub fn config(input: &mut Input, l0core: &mut L0Core) {}
+        }
+        "#;
+        let stream = TokenStream::from_str(code).expect("Failed to parse test case as token stream");
+        let module = parse_module_from_stream(stream).expect("Failed to parse test case as module");
+        let conf = HiraConfig::default();
+        let markdown = get_module_markdown(&conf, &module).expect("Failed to get markdown");
+        assert!(markdown.starts_with("# documented\n\nCreates a \\*very\\* useful thing."));
+        assert!(markdown.contains("| Name | Type | Required | Description |"));
+        assert!(markdown.contains("| name | String | yes | the name of the thing \\| used as the id |"));
+        assert!(markdown.contains("| size | Option&lt;u32&gt; | no | how big the thing is |"));
+        assert!(markdown.contains("| Name | Default | Description |"));
+        assert!(markdown.contains("| THING\\_ID | none | the resulting id of the \\[thing\\] |"));
+    }
+
+    #[test]
+    fn markdown_is_escaped() {
+        assert_eq!(escape_markdown("a_b *c* <d>\n|e|"), "a\\_b \\*c\\* &lt;d&gt;<br>\\|e\\|");
+    }
+}
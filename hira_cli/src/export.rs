@@ -0,0 +1,221 @@
+use std::path::{Path, PathBuf};
+use hira_lib::ExportFormat;
+use serde_json::{Map, Value};
+
+/// merges the templates of every module into one template per stack.
+/// `data` is the runtime data of the deploy runtime. Template transformers are
+/// not applied, as those only run as part of the deploy runtime.
+/// The templates of nested stacks are returned separately as `{stack name}.{nested stack}`,
+/// as the location of their template is only known when deploying.
+pub fn get_stack_templates(data: &[String], default_stack_name: &str) -> Result<Map<String, Value>, String> {
+    let mut out = Map::new();
+    for line in data {
+        let value: Value = serde_json::from_str(line)
+            .map_err(|e| format!("Failed to parse deploy runtime data as json\n{:?}", e))?;
+        let templates = match value.get("template").and_then(|x| x.as_object()) {
+            Some(t) => t,
+            None => continue,
+        };
+        let nested_stack = value.get("nested_stack").and_then(|x| x.as_str());
+        for (stack_name, mod_template) in templates {
+            let stack_name = if stack_name.is_empty() { default_stack_name } else { stack_name.as_str() };
+            let template = mod_template.get(1).and_then(|x| x.as_object())
+                .ok_or_else(|| format!("Invalid template for stack {stack_name}"))?;
+            let name = match nested_stack {
+                Some(nested_stack) => format!("{stack_name}.{nested_stack}"),
+                None => stack_name.to_string(),
+            };
+            let existing = out.entry(name).or_insert_with(|| Value::Object(Map::new()));
+            let existing = existing.as_object_mut().expect("stack templates are always objects");
+            for (key, val) in template {
+                match (existing.get_mut(key), val) {
+                    (Some(Value::Object(existing_section)), Value::Object(section)) => {
+                        existing_section.extend(section.clone());
+                    }
+                    _ => {
+                        existing.insert(key.to_string(), val.clone());
+                    }
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// writes every stack template in each of the formats to `{dir}/{stack name}.{format suffix}`.
+/// returns the paths that were written.
+pub fn export_stacks(templates: &Map<String, Value>, formats: &[ExportFormat], dir: &Path) -> Result<Vec<PathBuf>, String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create directory {:?}\n{:?}", dir, e))?;
+    let mut out = vec![];
+    for (stack_name, template) in templates {
+        for format in formats {
+            let data = match format {
+                ExportFormat::CfnJson => serde_json::to_string_pretty(template)
+                    .map_err(|e| format!("Failed to serialize stack {stack_name}\n{:?}", e))?,
+                ExportFormat::CfnYaml => serde_yaml::to_string(template)
+                    .map_err(|e| format!("Failed to serialize stack {stack_name}\n{:?}", e))?,
+                ExportFormat::TfJson => serde_json::to_string_pretty(&to_tf_json(template))
+                    .map_err(|e| format!("Failed to serialize stack {stack_name}\n{:?}", e))?,
+            };
+            let path = dir.join(format!("{stack_name}.{}", format.file_suffix()));
+            std::fs::write(&path, data)
+                .map_err(|e| format!("Failed to write {:?}\n{:?}", path, e))?;
+            out.push(path);
+        }
+    }
+    Ok(out)
+}
+
+/// `SSESpecification` -> `sse_specification`
+pub fn to_snake_case(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::with_capacity(s.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).map(|x| x.is_ascii_lowercase()).unwrap_or(false);
+            if prev.is_ascii_lowercase() || prev.is_ascii_digit() || (prev.is_ascii_uppercase() && next_is_lower) {
+                out.push('_');
+            }
+        }
+        out.push(c.to_ascii_lowercase());
+    }
+    out
+}
+
+/// `AWS::S3::BucketPolicy` -> `awscc_s3_bucket_policy`, the naming of the AWS Cloud Control terraform provider
+pub fn to_tf_resource_type(cfn_type: &str) -> String {
+    let parts: Vec<&str> = cfn_type.split("::").collect();
+    match parts.as_slice() {
+        [_, service, resource] => format!("awscc_{}_{}", service.to_ascii_lowercase(), to_snake_case(resource)),
+        _ => format!("awscc_{}", to_snake_case(&cfn_type.replace("::", "_"))),
+    }
+}
+
+/// converts the keys of the properties to snake case. Intrinsic functions (`Ref`, `Fn::GetAtt`, ...)
+/// are left as is, as there is no equivalent.
+fn to_tf_properties(value: &Value) -> Value {
+    match value {
+        Value::Object(o) => {
+            if o.keys().any(|k| k == "Ref" || k.starts_with("Fn::")) {
+                return value.clone();
+            }
+            Value::Object(o.iter().map(|(k, v)| (to_snake_case(k), to_tf_properties(v))).collect())
+        }
+        Value::Array(a) => Value::Array(a.iter().map(to_tf_properties).collect()),
+        x => x.clone(),
+    }
+}
+
+/// an approximation of the stack as terraform json. This is meant for reviewing
+/// what a stack contains, it is not expected to be applied as is.
+pub fn to_tf_json(template: &Value) -> Value {
+    let mut resources = Map::new();
+    if let Some(cfn_resources) = template.get("Resources").and_then(|x| x.as_object()) {
+        for (name, resource) in cfn_resources {
+            let ty = resource.get("Type").and_then(|x| x.as_str()).unwrap_or_default();
+            let properties = resource.get("Properties").map(to_tf_properties).unwrap_or(Value::Object(Map::new()));
+            let of_type = resources.entry(to_tf_resource_type(ty)).or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(of_type) = of_type {
+                of_type.insert(name.to_string(), properties);
+            }
+        }
+    }
+    let mut outputs = Map::new();
+    if let Some(cfn_outputs) = template.get("Outputs").and_then(|x| x.as_object()) {
+        for (name, output) in cfn_outputs {
+            let mut tf_output = Map::new();
+            if let Some(val) = output.get("Value") {
+                tf_output.insert("value".to_string(), val.clone());
+            }
+            if let Some(description) = output.get("Description").and_then(|x| x.as_str()).filter(|x| !x.is_empty()) {
+                tf_output.insert("description".to_string(), Value::String(description.to_string()));
+            }
+            outputs.insert(name.to_string(), Value::Object(tf_output));
+        }
+    }
+    let mut out = Map::new();
+    out.insert("resource".to_string(), Value::Object(resources));
+    if !outputs.is_empty() {
+        out.insert("output".to_string(), Value::Object(outputs));
+    }
+    Value::Object(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use super::*;
+
+    fn deploy_data() -> Vec<String> {
+        vec![
+            r#"{"template":{"stack-a":["mod1",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"mybucket":{"Type":"AWS::S3::Bucket","Properties":{"BucketName":"abc","VersioningConfiguration":{"Status":"Enabled"}}}},"Outputs":{}}]}}"#.to_string(),
+            r#"{"template":{"stack-a":["mod2",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"mypolicy":{"Type":"AWS::S3::BucketPolicy","Properties":{"Bucket":{"Ref":"mybucket"},"PolicyDocument":{"Statement":[{"Effect":"Allow"}]}}}},"Outputs":{"BucketArn":{"Description":"","Value":{"Fn::GetAtt":["mybucket","Arn"]}}}}]}}"#.to_string(),
+        ]
+    }
+
+    #[test]
+    fn enabling_two_formats_writes_consistent_files() {
+        let templates = get_stack_templates(&deploy_data(), "default").expect("Failed to get templates");
+        assert_eq!(templates.len(), 1);
+        let dir = std::path::PathBuf::from("./test_out/export");
+        let _ = std::fs::remove_dir_all(&dir);
+        let paths = export_stacks(&templates, &[ExportFormat::CfnJson, ExportFormat::TfJson], &dir).expect("Failed to export");
+        assert_eq!(paths, vec![dir.join("stack-a.template.json"), dir.join("stack-a.tf.json")]);
+
+        let cfn: Value = serde_json::from_str(&std::fs::read_to_string(&paths[0]).unwrap()).unwrap();
+        let tf: Value = serde_json::from_str(&std::fs::read_to_string(&paths[1]).unwrap()).unwrap();
+        let cfn_names: BTreeSet<&String> = cfn["Resources"].as_object().unwrap().keys().collect();
+        let tf_names: BTreeSet<&String> = tf["resource"].as_object().unwrap().values()
+            .flat_map(|x| x.as_object().unwrap().keys()).collect();
+        assert_eq!(cfn_names, tf_names);
+        assert_eq!(cfn_names.len(), 2);
+
+        assert_eq!(tf["resource"]["awscc_s3_bucket"]["mybucket"]["versioning_configuration"]["status"], "Enabled");
+        assert_eq!(tf["resource"]["awscc_s3_bucket_policy"]["mypolicy"]["bucket"], serde_json::json!({"Ref": "mybucket"}));
+        assert_eq!(tf["output"]["BucketArn"]["value"], serde_json::json!({"Fn::GetAtt": ["mybucket", "Arn"]}));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn nested_stacks_are_exported_separately() {
+        let mut data = deploy_data();
+        data.push(r#"{"template":{"stack-a":["mod3",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"mytable":{"Type":"AWS::DynamoDB::Table","Properties":{}}},"Outputs":{}}]},"nested_stack":"mynested"}"#.to_string());
+        let templates = get_stack_templates(&data, "default").expect("Failed to get templates");
+        assert_eq!(templates.keys().collect::<Vec<_>>(), vec!["stack-a", "stack-a.mynested"]);
+        assert!(templates["stack-a"]["Resources"].get("mytable").is_none());
+        assert_eq!(templates["stack-a.mynested"]["Resources"]["mytable"]["Type"], "AWS::DynamoDB::Table");
+
+        let dir = std::path::PathBuf::from("./test_out/export_nested");
+        let _ = std::fs::remove_dir_all(&dir);
+        let paths = export_stacks(&templates, &[ExportFormat::CfnYaml], &dir).expect("Failed to export");
+        assert_eq!(paths, vec![dir.join("stack-a.template.yaml"), dir.join("stack-a.mynested.template.yaml")]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn yaml_export_works() {
+        let value = serde_json::json!({
+            "Resources": {
+                "mybucket": {
+                    "Type": "AWS::S3::Bucket",
+                    "Properties": { "Tags": [{ "Key": "a b", "Value": "x\"y" }], "Empty": [], "Name": "yes" },
+                },
+            },
+        });
+        let dir = std::path::PathBuf::from("./test_out/export_yaml");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut templates = Map::new();
+        templates.insert("stack-a".to_string(), value.clone());
+        let paths = export_stacks(&templates, &[ExportFormat::CfnYaml], &dir).expect("Failed to export");
+        let yaml = std::fs::read_to_string(&paths[0]).unwrap();
+        // strings that yaml would read as another type, eg: `yes`, must stay strings
+        let parsed: Value = serde_yaml::from_str(&yaml).expect("Failed to parse exported yaml");
+        assert_eq!(parsed, value);
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(to_snake_case("SSESpecification"), "sse_specification");
+        assert_eq!(to_snake_case("S3Key"), "s3_key");
+        assert_eq!(to_tf_resource_type("AWS::CloudFront::Distribution"), "awscc_cloudfront_distribution");
+    }
+}
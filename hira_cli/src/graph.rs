@@ -0,0 +1,72 @@
+use std::{path::Path, collections::{BTreeSet, HashMap}};
+use hira_lib::HiraConfig;
+
+pub fn emit_runtime_graph(conf: &HiraConfig, path: &Path) -> Result<(), String> {
+    let runtime_names: Vec<&String> = conf.runtimes.keys().collect();
+    let dot = get_runtime_graph_dot(&runtime_names, &conf.runtime_depends_on);
+    if let Some(parent) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory {:?}\n{:?}", parent, e))?;
+    }
+    std::fs::write(path, dot)
+        .map_err(|e| format!("Failed to write {:?}\n{:?}", path, e))
+}
+
+fn quote_dot_id(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// `depends_on` is a map of a runtime to the runtimes it depends on (see `HiraConfig::runtime_depends_on`).
+/// each edge points from a runtime to the runtime it depends on.
+/// nodes and edges are sorted by name so that the output is stable across runs.
+pub fn get_runtime_graph_dot<S: AsRef<str>>(runtime_names: &[S], depends_on: &HashMap<String, Vec<String>>) -> String {
+    let mut nodes: BTreeSet<&str> = runtime_names.iter().map(|x| x.as_ref()).collect();
+    let mut edges: BTreeSet<(&str, &str)> = BTreeSet::new();
+    for (dependent, dependencies) in depends_on {
+        nodes.insert(dependent.as_str());
+        for dependency in dependencies {
+            nodes.insert(dependency.as_str());
+            edges.insert((dependent.as_str(), dependency.as_str()));
+        }
+    }
+    let mut out = "digraph runtimes {\n".to_string();
+    for node in nodes {
+        out.push_str(&format!("    {};\n", quote_dot_id(node)));
+    }
+    for (from, to) in edges {
+        out.push_str(&format!("    {} -> {};\n", quote_dot_id(from), quote_dot_id(to)));
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hira_lib::{level0::L0RuntimeCreator, module_loading::HiraModule2};
+
+    #[test]
+    fn dot_graph_has_sorted_nodes_and_edges() {
+        // the lambda runtimes must be compiled before the deploy and plan runtimes that upload them
+        let mut runtimer = L0RuntimeCreator::new();
+        runtimer.depends_on("lambda_b", "deploy");
+        runtimer.depends_on("lambda_a", "deploy");
+        runtimer.depends_on("lambda_a", "plan");
+        let mut conf = HiraConfig::default();
+        runtimer.apply_changes(&mut conf, &mut HiraModule2::default(), &mut Default::default())
+            .expect("Failed to apply runtime dependencies");
+        let dot = get_runtime_graph_dot(&["plan", "deploy", "lambda_a", "lambda_b", "say \"hi\""], &conf.runtime_depends_on);
+        let expected = r#"digraph runtimes {
+    "deploy";
+    "lambda_a";
+    "lambda_b";
+    "plan";
+    "say \"hi\"";
+    "deploy" -> "lambda_a";
+    "deploy" -> "lambda_b";
+    "plan" -> "lambda_a";
+}
+"#;
+        assert_eq!(dot, expected);
+    }
+}
@@ -1,6 +1,17 @@
-use std::{path::{Path, PathBuf}, process::{Command, Stdio}, io::Write};
-use hira_lib::{HiraConfig, parsing::{iter_hira_modules, get_ident_string}, module_loading::print_debug, level0::RuntimeMeta};
-use quote::ToTokens;
+use std::{path::{Path, PathBuf}, process::{Command, Stdio}, io::Write, str::FromStr};
+use std::collections::{HashMap, HashSet};
+use hira_lib::{HiraConfig, parsing::{iter_hira_modules, get_ident_string, get_hira_attr_args}, module_loading::{print_debug, parse_module_from_stream, order_by_priority_and_run_after, OutputType}, level0::{RuntimeMeta, RunPolicy}};
+use proc_macro2::{TokenStream, LineColumn};
+use syn::spanned::Spanned;
+use rayon::prelude::*;
+
+mod new_module;
+mod docs;
+mod export;
+mod schema;
+mod graph;
+mod check;
+mod repro;
 
 
 fn iter_files_recursively<P: AsRef<Path>>(
@@ -41,7 +52,70 @@ fn find_closest_cargo_toml(mut search_dir: PathBuf) -> Option<PathBuf> {
 }
 
 fn main() {
-    let compile_and_run_runtime = std::env::args().nth(1);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (profile_overrides, mut args) = match parse_profile_overrides(args) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let emit_docs_dir = match take_flag_value(&mut args, EMIT_DOCS_FLAG) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let emit_schema_dir = match take_flag_value(&mut args, EMIT_INPUT_SCHEMA_FLAG) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let graph_dot_path = match take_flag_value(&mut args, GRAPH_DOT_FLAG) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let repro_dir = match take_flag_value(&mut args, REPRO_ON_ERROR_FLAG) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let new_module_name = match take_flag_value(&mut args, NAME_FLAG) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let print_stacks = args.iter().any(|x| x == "--print-stacks");
+    let keep_going = args.iter().any(|x| x == "--keep-going");
+    // forwarded to the runtime. the deploy runtime uses these to deploy unchanged stacks.
+    let runtime_args: Vec<String> = args.iter().filter(|x| *x == FORCE_FLAG || *x == REDEPLOY_ALL_FLAG).cloned().collect();
+    let compile_and_run_runtime = args.iter().find(|x| !x.starts_with("--")).cloned();
+    if compile_and_run_runtime.as_deref() == Some("new-module") {
+        let name = match get_new_module_name(&args, new_module_name) {
+            Some(n) => n,
+            None => {
+                eprintln!("Usage: hira_cli new-module <name>\n       hira_cli new-module {NAME_FLAG} <name>");
+                std::process::exit(1);
+            }
+        };
+        let currdir = std::env::current_dir().expect("Failed to get current directory");
+        if let Err(e) = new_module::create_module_crate(&currdir, &name) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        println!("Created module {name} in {:?}", currdir.join(&name));
+        return;
+    }
     let cargo_home = env!("CARGO_HOME");
     std::env::set_var("CARGO_HOME", cargo_home);
     let currdir = std::env::current_dir().expect("Failed to get current directory");
@@ -53,6 +127,21 @@ fn main() {
         }
     };
     std::env::set_var("CARGO_MANIFEST_DIR", manifest_dir);
+    if compile_and_run_runtime.as_deref() == Some("check") {
+        if !args.iter().any(|x| x == STDIN_FLAG) {
+            eprintln!("Usage: hira_cli check {STDIN_FLAG}");
+            std::process::exit(1);
+        }
+        let mut conf = check::get_check_config();
+        match check::check_source_json(&mut conf, std::io::stdin()) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     println!("Scanning all rust files from {:?}", currdir);
     let mut all_rust_files = vec![];
     let res = iter_files_recursively(&currdir, &mut |p| {
@@ -68,25 +157,237 @@ fn main() {
         eprintln!("{e}");
         std::process::exit(1);
     }
-    let mut conf = match fill_hira_graph(&all_rust_files) {
+    // read_dir order is platform specific, so modules would otherwise be analyzed in a different order per machine
+    all_rust_files.sort();
+    let mut failed_modules = vec![];
+    let (mut conf, analysis_errors) = match fill_hira_graph(&all_rust_files, keep_going, &mut failed_modules) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("{e}");
+            if let Some(dir) = &repro_dir {
+                write_repro_bundle(Path::new(dir), &currdir, &all_rust_files, &failed_modules, &[e], &HiraConfig::new().settings);
+            }
             std::process::exit(1);
         }
     };
+    if let Some(dir) = &repro_dir {
+        write_repro_bundle(Path::new(dir), &currdir, &all_rust_files, &failed_modules, &analysis_errors, &conf.settings);
+    }
+
+    if conf.settings.offline {
+        std::env::set_var("CARGO_NET_OFFLINE", "true");
+    }
+
+    match apply_profile_overrides(&mut conf, &profile_overrides) {
+        Ok(warnings) => {
+            for warning in warnings {
+                eprintln!("Warning: {warning}");
+            }
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+
+    if !conf.settings.export_formats.is_empty() {
+        let data = conf.runtimes.get(DEPLOY_RUNTIME_NAME).map(|x| x.3.as_slice()).unwrap_or_default();
+        let exported = export::get_stack_templates(data, DEFAULT_STACK_NAME)
+            .and_then(|templates| export::export_stacks(&templates, &conf.settings.export_formats, Path::new(&conf.gen_directory)));
+        match exported {
+            Ok(paths) => {
+                for path in paths {
+                    println!("Exported {:?}", path);
+                }
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(dir) = emit_docs_dir {
+        if let Err(e) = docs::emit_docs(&conf, Path::new(&dir)) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        println!("Wrote module documentation to {dir}");
+        exit_if_analysis_failed(&analysis_errors);
+        return;
+    }
 
-    if let Err(e) = build_runtimes(&mut conf, compile_and_run_runtime) {
+    if let Some(dir) = emit_schema_dir {
+        if let Err(e) = schema::emit_input_schemas(&conf, Path::new(&dir)) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        println!("Wrote input schemas to {dir}");
+        exit_if_analysis_failed(&analysis_errors);
+        return;
+    }
+
+    if let Some(path) = graph_dot_path {
+        if let Err(e) = graph::emit_runtime_graph(&conf, Path::new(&path)) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        println!("Wrote runtime graph to {path}");
+        exit_if_analysis_failed(&analysis_errors);
+        return;
+    }
+
+    if print_stacks {
+        let data = conf.runtimes.get(DEPLOY_RUNTIME_NAME).map(|x| x.3.as_slice()).unwrap_or_default();
+        match get_stack_names(data) {
+            Ok(names) => {
+                for name in names {
+                    println!("{name}");
+                }
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        exit_if_analysis_failed(&analysis_errors);
+        return;
+    }
+
+    // modules that failed analysis are missing from the runtimes' data. deploying without them
+    // would delete their resources, so only the report style commands above continue past errors.
+    exit_if_analysis_failed(&analysis_errors);
+    if let Err(e) = build_runtimes(&mut conf, compile_and_run_runtime, &runtime_args) {
         eprintln!("{e}");
         std::process::exit(1);
     }
 }
 
+/// only relevant for --keep-going, where errors are collected instead of exiting immediately.
+fn exit_if_analysis_failed(errors: &[String]) {
+    if errors.is_empty() {
+        return;
+    }
+    eprintln!("\n{} error(s) during analysis:", errors.len());
+    for e in errors {
+        eprintln!("{e}\n");
+    }
+    std::process::exit(1);
+}
+
+const PROFILE_OVERRIDE_FLAG: &str = "--profile-override";
+const EMIT_DOCS_FLAG: &str = "--emit-docs";
+const EMIT_INPUT_SCHEMA_FLAG: &str = "--emit-input-schema";
+const GRAPH_DOT_FLAG: &str = "--graph-dot";
+const STDIN_FLAG: &str = "--stdin";
+const REPRO_ON_ERROR_FLAG: &str = "--repro-on-error";
+const FORCE_FLAG: &str = "--force";
+const REDEPLOY_ALL_FLAG: &str = "--redeploy-all";
+const NAME_FLAG: &str = "--name";
+
+/// returns the name given to `new-module`, either via `--name <name>` or positionally: `new-module <name>`
+fn get_new_module_name(args: &[String], name_flag: Option<String>) -> Option<String> {
+    if name_flag.is_some() {
+        return name_flag;
+    }
+    args.iter()
+        .skip_while(|x| *x != "new-module")
+        .skip(1)
+        .find(|x| !x.starts_with("--"))
+        .cloned()
+}
+
+/// removes `flag` and the value after it from the args, and returns the value
+fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let index = match args.iter().position(|x| x == flag) {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+    if index + 1 >= args.len() {
+        return Err(format!("{flag} requires a value"));
+    }
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(value))
+}
+
+/// removes all `--profile-override <runtime>=<profile>` pairs from the args.
+/// returns the (runtime, profile) overrides, and the remaining args.
+fn parse_profile_overrides(args: Vec<String>) -> Result<(Vec<(String, String)>, Vec<String>), String> {
+    let mut overrides = vec![];
+    let mut remaining = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg != PROFILE_OVERRIDE_FLAG {
+            remaining.push(arg);
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("{PROFILE_OVERRIDE_FLAG} requires a value of the form <runtime>=<profile>"))?;
+        let (runtime, profile) = value.split_once('=')
+            .ok_or_else(|| format!("Invalid {PROFILE_OVERRIDE_FLAG} value '{value}'. Expected <runtime>=<profile>"))?;
+        overrides.push((runtime.to_string(), profile.to_string()));
+    }
+    Ok((overrides, remaining))
+}
+
+/// profiles are passed to cargo, so they must be a valid cargo profile name.
+fn validate_profile(profile: &str) -> Result<(), String> {
+    if profile.is_empty() || !profile.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid profile '{profile}'. Profile names may only contain alphanumeric characters, '-' or '_'"));
+    }
+    Ok(())
+}
+
+/// sets the profile of each overridden runtime. returns warnings for runtimes
+/// whose profile was explicitly chosen by a module for a specific target (eg: a lambda function).
+fn apply_profile_overrides(conf: &mut HiraConfig, overrides: &[(String, String)]) -> Result<Vec<String>, String> {
+    let mut warnings = vec![];
+    for (runtime_name, profile) in overrides {
+        validate_profile(profile)?;
+        let (_, meta, _, _) = conf.runtimes.get_mut(runtime_name)
+            .ok_or_else(|| format!("Cannot override profile of runtime {runtime_name}: runtime does not exist"))?;
+        if !meta.target.is_empty() && !meta.profile.is_empty() && meta.profile != *profile {
+            warnings.push(format!("Overriding profile of runtime {runtime_name} from '{}' to '{profile}'. This runtime is built for {} (eg: a lambda function), so this may change its binary size and behavior", meta.profile, meta.target));
+        }
+        meta.profile = profile.to_string();
+    }
+    Ok(warnings)
+}
+
+const DEPLOY_RUNTIME_NAME: &str = "deploy";
+const DEFAULT_STACK_NAME: &str = "hira-gen-default-stack";
+
+/// given the runtime data of the deploy runtime, return the unique
+/// names of the stacks that would be deployed, in the order they were defined.
+/// each data line is a serialized `SavedStack` which looks like:
+/// `{"template": {"<stack name>": ["<module name>", {<template>}]}}`
+fn get_stack_names(data: &[String]) -> Result<Vec<String>, String> {
+    let mut out: Vec<String> = vec![];
+    for line in data {
+        let value: serde_json::Value = serde_json::from_str(line)
+            .map_err(|e| format!("Failed to parse deploy runtime data as json\n{:?}", e))?;
+        let templates = match value.get("template").and_then(|x| x.as_object()) {
+            Some(t) => t,
+            None => continue,
+        };
+        for stack_name in templates.keys() {
+            let stack_name = if stack_name.is_empty() { DEFAULT_STACK_NAME } else { stack_name.as_str() };
+            if !out.iter().any(|x| x == stack_name) {
+                out.push(stack_name.to_string());
+            }
+        }
+    }
+    Ok(out)
+}
+
 fn build_runtimes(
     conf: &mut HiraConfig,
-    compile_and_run_runtime: Option<String>
+    compile_and_run_runtime: Option<String>,
+    runtime_args: &[String],
 ) -> Result<(), String> {
     let logfile = conf.logfile.clone();
+    let no_env = vec![];
+    let get_env = |name: &str| conf.runtime_env.get(name).unwrap_or(&no_env);
     // if a runtime was specified, compile only that runtime and then run it.
     // otherwise, simply compile all runtimes, but dont run them.
     if let Some(runtime_name) = compile_and_run_runtime {
@@ -97,7 +398,7 @@ fn build_runtimes(
                     build_runtime(
                         &dep_name, &conf.wasm_directory,
                         &conf.runtime_directory, &conf.crate_name,
-                        runtime, &logfile, Some(&runtime_name),
+                        runtime, get_env(dep_name), &logfile, Some(&runtime_name),
                     )?;
                 } else {
                     return Err(format!("Failed to find dependency {} on runtime {}", dep_name, runtime_name));
@@ -109,10 +410,10 @@ fn build_runtimes(
             build_runtime(
                 &runtime_name, &conf.wasm_directory,
                 &conf.runtime_directory, &conf.crate_name,
-                runtime, &logfile, None,
+                runtime, get_env(&runtime_name), &logfile, None,
             )?;
             println!("Running {}:\n", runtime_name);
-            run_runtime(&conf.runtime_directory, &runtime_name)?;
+            run_runtime(&conf.runtime_directory, &runtime_name, runtime_args, get_env(&runtime_name), runtime.run_policy)?;
         } else {
             return Err(format!("Runtime {} does not exist", runtime_name));
         }
@@ -122,19 +423,36 @@ fn build_runtimes(
             build_runtime(
                 &name, &conf.wasm_directory,
                 &conf.runtime_directory, &conf.crate_name,
-                runtime, &logfile, None
+                runtime, get_env(name), &logfile, None
             )?;
         }
     }
     Ok(())
 }
 
+/// the file in the runtime directory that lists the `RunPolicy::Once` runtimes that completed, one per line.
+const RUN_ONCE_STATE_FILE: &str = "run_once_completed.txt";
+
+/// `RunPolicy::Once` runtimes are skipped if they already completed, unless `--force` is passed.
 fn run_runtime(
     runtime_dir: &str,
     name: &str,
+    args: &[String],
+    env: &[(String, String)],
+    run_policy: RunPolicy,
 ) -> Result<(), String> {
+    let state_file = format!("{runtime_dir}/{RUN_ONCE_STATE_FILE}");
+    let completed = std::fs::read_to_string(&state_file).unwrap_or_default();
+    let already_completed = completed.lines().any(|x| x == name);
+    let force = args.iter().any(|x| x == FORCE_FLAG);
+    if run_policy == RunPolicy::Once && already_completed && !force {
+        println!("Skipping runtime {name}. It only runs once, and it already completed. Pass {FORCE_FLAG} to run it again");
+        return Ok(());
+    }
     let cmd = format!("{runtime_dir}/{name}");
     let out = Command::new(&cmd)
+        .args(args)
+        .envs(env.iter().map(|(k, v)| (k, v)))
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
         .stdin(Stdio::null())
@@ -142,6 +460,11 @@ fn run_runtime(
     if !out.status.success() {
         return Err(format!("Failed to run runtime {name}"));
     }
+    if run_policy == RunPolicy::Once && !already_completed {
+        let mut f = std::fs::OpenOptions::new().create(true).append(true).open(&state_file)
+            .map_err(|e| format!("Failed to open {state_file}\n{:?}", e))?;
+        writeln!(f, "{name}").map_err(|e| format!("Failed to write to {state_file}\n{:?}", e))?;
+    }
 
     Ok(())
 }
@@ -168,18 +491,19 @@ fn build_runtime(
     runtime_dir: &str,
     crate_name: &str,
     runtime: &RuntimeMeta,
+    env: &[(String, String)],
     logfile: &str,
     dependency_of: Option<&String>,
 ) -> Result<(), String> {
     let target_dir = format!("{}/target_{}", wasm_dir, name);
-    let hira_runtime_output_path = format!("{}/{}", runtime_dir, name);
+    let hira_runtime_output_path = HiraConfig::get_runtime_output_path(runtime_dir, name, runtime);
     if let Some(dep) = &dependency_of {
         println!("Building runtime {name} (dependency of {dep})");
     } else {
         println!("Building runtime {name}");
     }
     let now = std::time::Instant::now();
-    HiraConfig::run_build_runtime_cmd(runtime, &name, &target_dir, crate_name, &hira_runtime_output_path, Some(handle_stdout))?;
+    HiraConfig::run_build_runtime_cmd(runtime, &name, &target_dir, crate_name, &hira_runtime_output_path, env, Some(handle_stdout))?;
     print!("\n");
     let _ = std::io::stdout().flush();
     let elapsed = now.elapsed().as_millis();
@@ -192,29 +516,634 @@ fn compile_log(name: &str) {
     println!("Analyzing {name}");
 }
 
-fn fill_hira_graph(files: &Vec<PathBuf>) -> Result<HiraConfig, String> {
+/// only relevant for --repro-on-error. does nothing if nothing failed.
+/// failing to write the bundle is only printed, the analysis error is what the user cares about.
+fn write_repro_bundle(dir: &Path, root: &Path, files: &[PathBuf], failed_modules: &[String], errors: &[String], settings: &hira_lib::HiraSettings) {
+    if failed_modules.is_empty() && errors.is_empty() {
+        return;
+    }
+    match repro::write_repro_bundle(dir, root, files, failed_modules, errors, settings) {
+        Ok(_) => println!("Wrote a reproduction of the failure to {:?}", dir),
+        Err(e) => eprintln!("Failed to write reproduction bundle\n{e}"),
+    }
+}
+
+/// returns the filled config, as well as all of the errors that were
+/// collected along the way. errors are only collected if `keep_going` is true,
+/// otherwise the first error is returned.
+/// the names of the modules that failed to analyze are added to `failed_modules`.
+fn fill_hira_graph(files: &Vec<PathBuf>, keep_going: bool, failed_modules: &mut Vec<String>) -> Result<(HiraConfig, Vec<String>), String> {
     let mut conf = HiraConfig::new();
     conf.should_do_file_ops = true;
     conf.should_output_build_script = false;
+    let errors = analyze_files(&mut conf, files, keep_going, &mut |conf, tokens| {
+        let name = repro::get_module_name(&tokens);
+        let res = hira_lib::module_loading::hira_mod2_inner_ex(
+            conf, tokens, true,
+            false, None, Some(compile_log), Default::default()).map(|_| ());
+        if res.is_err() {
+            failed_modules.extend(name);
+        }
+        res
+    })?;
+    Ok((conf, errors))
+}
+
+/// a hira module found by `find_hira_file`. token streams cannot be sent across threads,
+/// so the module's source text is kept along with the position it starts at in its file.
+struct FoundHiraModule {
+    ident: String,
+    run_after: Vec<String>,
+    priority: Option<i64>,
+    start: LineColumn,
+    source: String,
+}
+
+impl FoundHiraModule {
+    /// lexes the module's source text back into a token stream. The source is padded
+    /// such that spans have the same line/column as they do in the original file.
+    fn lex(&self) -> Result<TokenStream, String> {
+        let padded = format!("{}{}{}", "\n".repeat(self.start.line - 1), " ".repeat(self.start.column), self.source);
+        TokenStream::from_str(&padded).map_err(|e| format!("Failed to lex module '{}'\n{:?}", self.ident, e))
+    }
+}
+
+/// returns the byte offset of a line/column position in `contents`. columns are counted in chars.
+fn get_byte_offset(contents: &str, pos: LineColumn) -> usize {
+    let line_start: usize = contents.split_inclusive('\n').take(pos.line - 1).map(|l| l.len()).sum();
+    let line = &contents[line_start..];
+    line_start + line.char_indices().nth(pos.column).map(|(i, _)| i).unwrap_or(line.len())
+}
+
+/// reads and parses the file, and returns every hira module in it, or None if it doesn't contain any.
+fn find_hira_file(f: &PathBuf) -> Result<Option<(&PathBuf, Vec<FoundHiraModule>)>, String> {
+    let contents = std::fs::read_to_string(f)
+        .map_err(|e| format!("Failed to read file {:?}\n{:?}", f, e))?;
+    let mut modules = vec![];
+    iter_hira_modules(&contents, &mut |m| {
+        if !hira_lib::parsing::has_attr_that_ends_in(&m.attrs, "hira") {
+            return Ok(true);
+        }
+        // invalid attributes get reported when the module is analyzed
+        let attr_args = get_hira_attr_args(&m.attrs).unwrap_or_default();
+        let span = m.span();
+        let source = contents[get_byte_offset(&contents, span.start())..get_byte_offset(&contents, span.end())].to_string();
+        modules.push(FoundHiraModule {
+            ident: get_ident_string(&m.ident),
+            run_after: attr_args.after,
+            priority: attr_args.priority,
+            start: span.start(),
+            source,
+        });
+        Ok(true)
+    }).map_err(|e| format!("Failed to get hira modules from {:?}\n{:?}", f, e))?;
+    Ok(if modules.is_empty() { None } else { Some((f, modules)) })
+}
+
+/// calls `find_hira_file` on every file. the results are in the same order as `files`.
+/// if `parallel` is true, the files are read and parsed on a thread pool.
+fn find_hira_files(files: &[PathBuf], parallel: bool) -> Vec<Result<Option<(&PathBuf, Vec<FoundHiraModule>)>, String>> {
+    if parallel {
+        files.par_iter().map(find_hira_file).collect()
+    } else {
+        files.iter().map(find_hira_file).collect()
+    }
+}
+
+/// calls `analyze` on every hira module found in `files`. Modules are analyzed in the order they are found,
+/// except for modules with `#[hira(priority = N)]` which are sorted by priority (lower first),
+/// and modules with `#[hira(after = "...")]` which are moved after the modules they list.
+/// if `keep_going` is true, a file that fails to be read/parsed, or a module that fails
+/// to be analyzed is recorded as an error, and the remaining files are still analyzed.
+/// modules that depend on a module that failed are not analyzed, and are recorded as errors as well.
+fn analyze_files(
+    conf: &mut HiraConfig,
+    files: &Vec<PathBuf>,
+    keep_going: bool,
+    analyze: &mut impl FnMut(&mut HiraConfig, TokenStream) -> Result<(), TokenStream>,
+) -> Result<Vec<String>, String> {
+    analyze_files_ex(conf, files, keep_going, true, analyze)
+}
+
+/// same as `analyze_files`, but reading and parsing the files is only done on a thread pool if `parallel` is true.
+fn analyze_files_ex(
+    conf: &mut HiraConfig,
+    files: &Vec<PathBuf>,
+    keep_going: bool,
+    parallel: bool,
+    analyze: &mut impl FnMut(&mut HiraConfig, TokenStream) -> Result<(), TokenStream>,
+) -> Result<Vec<String>, String> {
     let logfile = conf.logfile.clone();
-    for f in files.iter() {
-        let contents = std::fs::read_to_string(f)
-            .map_err(|e| format!("Failed to read file {:?}\n{:?}", f, e))?;
-        iter_hira_modules(&contents, &mut |m| {
-            if !hira_lib::parsing::has_attr_that_ends_in(&m.attrs, "hira") {
-                return Ok(true);
-            }
-            let tokens = m.to_token_stream();
-            let ident = get_ident_string(&m.ident);
-            let now = std::time::Instant::now();
-            hira_lib::module_loading::hira_mod2_inner_ex(
-                &mut conf, tokens, true,
-                false, None, Some(compile_log))?;
-            let elapsed = now.elapsed().as_millis();
-            let contents = format!("Analyzing {ident}, dur={elapsed}ms\n");
-            print_debug(&logfile, &contents);
-            Ok(true)
-        }).map_err(|e| format!("Failed to get hira modules from {:?}\n{:?}", f, e))?;
-    }
-    Ok(conf)
+    let mut errors = vec![];
+    let mut modules = vec![];
+    let now = std::time::Instant::now();
+    let hira_files = find_hira_files(files, parallel);
+    let elapsed = now.elapsed().as_millis();
+    print_debug(&logfile, &format!("Parsing {} files, parallel={parallel}, dur={elapsed}ms\n", files.len()));
+    let now = std::time::Instant::now();
+    for hira_file in hira_files {
+        let (f, file_modules) = match hira_file {
+            Ok(Some(x)) => x,
+            Ok(None) => continue,
+            Err(err) => {
+                if !keep_going {
+                    return Err(err);
+                }
+                errors.push(err);
+                continue;
+            }
+        };
+        for m in file_modules {
+            let tokens = match m.lex() {
+                Ok(t) => t,
+                Err(e) => {
+                    let err = format!("Failed to get hira modules from {:?}\n{e}", f);
+                    if !keep_going {
+                        return Err(err);
+                    }
+                    errors.push(err);
+                    continue;
+                }
+            };
+            modules.push((m.ident, m.run_after, (f, tokens, m.priority)));
+        }
+    }
+    let elapsed = now.elapsed().as_millis();
+    print_debug(&logfile, &format!("Collecting {} hira modules, dur={elapsed}ms\n", modules.len()));
+
+    // ordering refers to modules by name, so duplicates must be removed before ordering
+    let mut seen: HashMap<String, &PathBuf> = HashMap::new();
+    let mut unique_modules = Vec::with_capacity(modules.len());
+    for module in modules {
+        let f = module.2.0;
+        if let Some(existing) = seen.get(&module.0) {
+            let err = if *existing == f {
+                format!("Module '{}' is defined more than once in {:?}. Module names must be unique", module.0, f)
+            } else {
+                format!("Module '{}' is defined in both {:?} and {:?}. Module names must be unique", module.0, existing, f)
+            };
+            if !keep_going {
+                return Err(err);
+            }
+            errors.push(err);
+            continue;
+        }
+        seen.insert(module.0.clone(), f);
+        unique_modules.push(module);
+    }
+    let modules = order_by_priority_and_run_after(unique_modules, |x| x.2)?;
+    let mut failed_modules: HashSet<String> = HashSet::new();
+    for (ident, run_after, (f, tokens, _)) in modules {
+        if keep_going {
+            let failed_dep = parse_module_from_stream(tokens.clone()).ok()
+                .and_then(|module| {
+                    let output_deps = module.fill_outputs.into_iter().map(|x| match x {
+                        OutputType::AllFromModule(name) => name,
+                        OutputType::SpecificFromModule(name, _, _) => name,
+                    });
+                    module.use_dependencies.into_iter().chain(output_deps).chain(run_after).find(|x| failed_modules.contains(x))
+                });
+            if let Some(dep) = failed_dep {
+                errors.push(format!("Skipped module '{ident}' from {:?} because it depends on '{dep}' which failed", f));
+                failed_modules.insert(ident);
+                continue;
+            }
+        }
+        let now = std::time::Instant::now();
+        conf.source_file = f.to_string_lossy().to_string();
+        if let Err(e) = analyze(conf, tokens) {
+            let err = format!("Failed to analyze module '{ident}' from {:?}\n{:?}", f, e);
+            if !keep_going {
+                return Err(err);
+            }
+            errors.push(err);
+            failed_modules.insert(ident);
+            continue;
+        }
+        let elapsed = now.elapsed().as_millis();
+        let contents = format!("Analyzing {ident}, dur={elapsed}ms\n");
+        print_debug(&logfile, &contents);
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use hira_lib::parsing::compiler_error;
+    use super::*;
+
+    #[test]
+    fn get_stack_names_works() {
+        let data = [
+            r#"{"template":{"stack-a":["mod1",{"Resources":{}}]}}"#.to_string(),
+            r#"{"template":{"hira-gen-default-stack":["mod2",{"Resources":{}}]}}"#.to_string(),
+            r#"{"template":{"stack-a":["mod3",{"Resources":{}}]}}"#.to_string(),
+            r#"{"template":{"":["mod4",{"Resources":{}}]}}"#.to_string(),
+        ];
+        let names = get_stack_names(&data).expect("Failed to get stack names");
+        assert_eq!(names, vec!["stack-a", "hira-gen-default-stack"]);
+        assert!(get_stack_names(&["not json".to_string()]).is_err());
+    }
+
+    #[test]
+    fn flag_values_are_taken_from_args() {
+        let mut args = ["deploy", "--emit-docs", "docs", "--keep-going"].map(String::from).to_vec();
+        let dir = take_flag_value(&mut args, EMIT_DOCS_FLAG).expect("Failed to take flag value");
+        assert_eq!(dir.as_deref(), Some("docs"));
+        assert_eq!(args, vec!["deploy", "--keep-going"]);
+        assert_eq!(take_flag_value(&mut args, EMIT_DOCS_FLAG), Ok(None));
+        let mut args = vec!["--emit-docs".to_string()];
+        assert!(take_flag_value(&mut args, EMIT_DOCS_FLAG).is_err());
+    }
+
+    #[test]
+    fn new_module_name_can_come_before_the_subcommand() {
+        let mut args = ["--name", "mymod", "new-module"].map(String::from).to_vec();
+        let name = take_flag_value(&mut args, NAME_FLAG).expect("Failed to take flag value");
+        assert_eq!(name.as_deref(), Some("mymod"));
+        assert_eq!(args.iter().find(|x| !x.starts_with("--")).map(|x| x.as_str()), Some("new-module"));
+        assert_eq!(get_new_module_name(&args, name).as_deref(), Some("mymod"));
+    }
+
+    #[test]
+    fn new_module_name_can_be_positional() {
+        let args = ["new-module", "--keep-going", "mymod"].map(String::from).to_vec();
+        assert_eq!(get_new_module_name(&args, None).as_deref(), Some("mymod"));
+        let args = ["new-module"].map(String::from).to_vec();
+        assert_eq!(get_new_module_name(&args, None), None);
+    }
+
+    #[test]
+    fn profile_override_changes_build_profile() {
+        let args = ["--profile-override", "mylambda=dev", "deploy", "--keep-going"].map(String::from).to_vec();
+        let (overrides, remaining) = parse_profile_overrides(args).expect("Failed to parse overrides");
+        assert_eq!(overrides, vec![("mylambda".to_string(), "dev".to_string())]);
+        assert_eq!(remaining, vec!["deploy", "--keep-going"]);
+
+        let mut conf = HiraConfig::default();
+        let meta = RuntimeMeta {
+            profile: "release".to_string(),
+            target: "aarch64-unknown-linux-musl".to_string(),
+            ..Default::default()
+        };
+        conf.runtimes.insert("mylambda".to_string(), (false, meta, vec![], vec![]));
+        let warnings = apply_profile_overrides(&mut conf, &overrides).expect("Failed to apply overrides");
+        assert_eq!(warnings.len(), 1);
+
+        let meta = &conf.runtimes["mylambda"].1;
+        let (_, args, location) = HiraConfig::get_build_runtime_args(meta, "mylambda", "target_dir");
+        let profile_index = args.iter().position(|x| x == "--profile").expect("Missing --profile arg");
+        assert_eq!(args[profile_index + 1], "dev");
+        assert_eq!(location, "aarch64-unknown-linux-musl/debug");
+
+        assert!(apply_profile_overrides(&mut conf, &[("mylambda".to_string(), "a b".to_string())]).is_err());
+        assert!(apply_profile_overrides(&mut conf, &[("doesntexist".to_string(), "dev".to_string())]).is_err());
+        assert!(parse_profile_overrides(vec!["--profile-override".to_string()]).is_err());
+        assert!(parse_profile_overrides(["--profile-override", "nope"].map(String::from).to_vec()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_once_runtimes_are_skipped_after_completing() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join("hira_cli_run_once_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("Failed to create test dir");
+        let runtime_dir = dir.to_string_lossy().to_string();
+        let runs_file = dir.join("runs.txt");
+        for name in ["migrate", "deploy"] {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\necho {name} >> {:?}\n", runs_file)).expect("Failed to write runtime");
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).expect("Failed to set permissions");
+        }
+        let runs = || std::fs::read_to_string(&runs_file).unwrap_or_default();
+        for _ in 0..2 {
+            run_runtime(&runtime_dir, "migrate", &[], &[], RunPolicy::Once).expect("Failed to run runtime");
+            run_runtime(&runtime_dir, "deploy", &[], &[], RunPolicy::Always).expect("Failed to run runtime");
+        }
+        assert_eq!(runs(), "migrate\ndeploy\ndeploy\n");
+
+        run_runtime(&runtime_dir, "migrate", &[FORCE_FLAG.to_string()], &[], RunPolicy::Once).expect("Failed to run runtime");
+        assert_eq!(runs(), "migrate\ndeploy\ndeploy\nmigrate\n");
+        let state = std::fs::read_to_string(dir.join(RUN_ONCE_STATE_FILE)).expect("Failed to read state file");
+        assert_eq!(state, "migrate\n");
+    }
+
+    fn write_test_files(dir_name: &str, files: &[(&str, &str)]) -> Vec<PathBuf> {
+        let dir = std::env::temp_dir().join(dir_name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("Failed to create test dir");
+        files.iter().map(|(name, contents)| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).expect("Failed to write test file");
+            path
+        }).collect()
+    }
+
+    fn parse_only(analyzed: &mut Vec<String>) -> impl FnMut(&mut HiraConfig, TokenStream) -> Result<(), TokenStream> + '_ {
+        move |_, tokens| {
+            let module = parse_module_from_stream(tokens)?;
+            analyzed.push(module.name);
+            Ok(())
+        }
+    }
+
+    const GOOD_FILE: &str = r#"
+        #[hira::hira]
+        pub mod good {
+            use super::L0Core;
+            pub fn config(l0core: &mut L0Core) {}
+        }
+    "#;
+
+    #[test]
+    fn keep_going_collects_errors_per_file() {
+        let files = write_test_files("hira_cli_keep_going_test", &[
+            ("bad.rs", "pub mod bad { this is not rust"),
+            ("good.rs", GOOD_FILE),
+        ]);
+        let mut conf = HiraConfig::default();
+        let mut analyzed = vec![];
+        let errors = analyze_files(&mut conf, &files, true, &mut parse_only(&mut analyzed))
+            .expect("keep going should not return an error");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("bad.rs"));
+        assert_eq!(analyzed, vec!["good"]);
+
+        let mut analyzed = vec![];
+        assert!(analyze_files(&mut conf, &files, false, &mut parse_only(&mut analyzed)).is_err());
+        assert!(analyzed.is_empty());
+    }
+
+    #[test]
+    fn modules_are_analyzed_after_their_after_attribute() {
+        let files = write_test_files("hira_cli_after_test", &[
+            ("a.rs", r#"
+                #[hira::hira(after = "second")]
+                pub mod third {
+                    use super::L0Core;
+                    pub fn config(l0core: &mut L0Core) {}
+                }
+                #[hira::hira(after = "first")]
+                pub mod second {
+                    use super::L0Core;
+                    pub fn config(l0core: &mut L0Core) {}
+                }
+            "#),
+            ("b.rs", r#"
+                #[hira::hira]
+                pub mod first {
+                    use super::L0Core;
+                    pub fn config(l0core: &mut L0Core) {}
+                }
+            "#),
+        ]);
+        let mut conf = HiraConfig::default();
+        let mut analyzed = vec![];
+        let errors = analyze_files(&mut conf, &files, false, &mut parse_only(&mut analyzed))
+            .expect("Failed to analyze files");
+        assert!(errors.is_empty());
+        assert_eq!(analyzed, vec!["first", "second", "third"]);
+
+        let mut source_files = vec![];
+        analyze_files(&mut conf, &files, false, &mut |conf, tokens| {
+            let module = parse_module_from_stream(tokens)?;
+            source_files.push((module.name, conf.source_file.clone()));
+            Ok(())
+        }).expect("Failed to analyze files");
+        let expected: Vec<_> = [("first", &files[1]), ("second", &files[0]), ("third", &files[0])].into_iter()
+            .map(|(name, f)| (name.to_string(), f.to_string_lossy().to_string()))
+            .collect();
+        assert_eq!(source_files, expected);
+
+        let files = write_test_files("hira_cli_after_cycle_test", &[
+            ("a.rs", r#"
+                #[hira::hira(after = "b")]
+                pub mod a {}
+                #[hira::hira(after = "a")]
+                pub mod b {}
+            "#),
+        ]);
+        let mut analyzed = vec![];
+        let err = analyze_files(&mut conf, &files, true, &mut parse_only(&mut analyzed))
+            .err().expect("Expected a cycle error");
+        assert!(err.contains("a -> b -> a"));
+    }
+
+    #[test]
+    fn duplicate_module_names_across_files_are_errors() {
+        let files = write_test_files("hira_cli_duplicate_names_test", &[
+            ("a.rs", GOOD_FILE),
+            ("b.rs", GOOD_FILE),
+        ]);
+        // duplicates are found before any module is analyzed
+        let mut conf = HiraConfig::default();
+        let mut analyzed = vec![];
+        let err = analyze_files(&mut conf, &files, false, &mut parse_only(&mut analyzed))
+            .err().expect("Expected a duplicate name error");
+        assert!(err.contains("Module 'good' is defined in both"));
+        assert!(err.contains("a.rs") && err.contains("b.rs"));
+        assert!(analyzed.is_empty());
+
+        let mut conf = HiraConfig::default();
+        let mut analyzed = vec![];
+        let errors = analyze_files(&mut conf, &files, true, &mut parse_only(&mut analyzed))
+            .expect("keep going should not return an error");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(analyzed, vec!["good"]);
+
+        // a duplicate that comes first once ordered is still the one that gets skipped
+        let files = write_test_files("hira_cli_duplicate_names_ordered_test", &[
+            ("a.rs", "#[hira::hira] pub mod dup {} #[hira::hira] pub mod other {}"),
+            ("b.rs", "#[hira::hira(priority = -1)] pub mod dup {}"),
+        ]);
+        let mut conf = HiraConfig::default();
+        let mut analyzed = vec![];
+        let errors = analyze_files(&mut conf, &files, true, &mut |conf, tokens| {
+            let module = parse_module_from_stream(tokens)?;
+            analyzed.push((module.name, conf.source_file.clone()));
+            Ok(())
+        }).expect("keep going should not return an error");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("Module 'dup' is defined in both"));
+        let a_file = files[0].to_string_lossy().to_string();
+        assert_eq!(analyzed, vec![("dup".to_string(), a_file.clone()), ("other".to_string(), a_file)]);
+    }
+
+    #[test]
+    fn keep_going_skips_dependents_of_failed_modules() {
+        let files = write_test_files("hira_cli_keep_going_deps_test", &[
+            ("a.rs", GOOD_FILE),
+            ("b.rs", r#"
+                #[hira::hira]
+                pub mod dependent {
+                    use super::L0Core;
+                    use super::good;
+                    pub fn config(l0core: &mut L0Core) {}
+                }
+            "#),
+        ]);
+        let mut conf = HiraConfig::default();
+        let mut analyzed = vec![];
+        let errors = analyze_files(&mut conf, &files, true, &mut |_, tokens| {
+            let module = parse_module_from_stream(tokens)?;
+            if module.name == "good" {
+                return Err(compiler_error("good failed"));
+            }
+            analyzed.push(module.name);
+            Ok(())
+        }).expect("keep going should not return an error");
+        assert_eq!(errors.len(), 2);
+        assert!(errors[1].contains("depends on 'good'"));
+        assert!(analyzed.is_empty());
+    }
+
+    #[test]
+    fn repro_bundle_contains_failing_file_and_its_dependencies() {
+        let files = write_test_files("hira_cli_repro_test", &[
+            ("dep.rs", GOOD_FILE),
+            ("failing.rs", r#"
+                #[hira::hira]
+                pub mod failing {
+                    use super::L0Core;
+                    use super::good;
+                    pub fn config(l0core: &mut L0Core) {}
+                }
+            "#),
+            ("unrelated.rs", r#"
+                #[hira::hira]
+                pub mod unrelated {
+                    use super::L0Core;
+                    pub fn config(l0core: &mut L0Core) {}
+                }
+            "#),
+            (".env", "API_KEY=hunter2\n"),
+        ]);
+        let root = files[0].parent().unwrap().to_path_buf();
+        let files = files[..3].to_vec();
+        let mut conf = HiraConfig::default();
+        let mut failed_modules = vec![];
+        let err = analyze_files(&mut conf, &files, false, &mut |_, tokens| {
+            let name = repro::get_module_name(&tokens);
+            if name.as_deref() == Some("failing") {
+                failed_modules.extend(name);
+                return Err(compiler_error("failing failed"));
+            }
+            Ok(())
+        });
+        assert!(err.is_err());
+        assert_eq!(failed_modules, vec!["failing"]);
+
+        let out_dir = std::env::temp_dir().join("hira_cli_repro_test_out");
+        let _ = std::fs::remove_dir_all(&out_dir);
+        let written = repro::write_repro_bundle(&out_dir, &root, &files, &failed_modules, &[], &Default::default())
+            .expect("Failed to write repro bundle");
+        assert_eq!(written.len(), 3);
+        assert_eq!(std::fs::read_to_string(out_dir.join("failing.rs")).unwrap(), std::fs::read_to_string(&files[1]).unwrap());
+        assert!(out_dir.join("dep.rs").exists());
+        assert!(!out_dir.join("unrelated.rs").exists());
+        let config = std::fs::read_to_string(out_dir.join(repro::REPRO_CONFIG_FILE)).expect("Failed to read config dump");
+        assert!(config.contains("\"failing\""));
+        assert!(config.contains(&format!("API_KEY={}", repro::REDACTED)));
+        assert!(!config.contains("hunter2"));
+    }
+
+    #[test]
+    fn repro_bundle_is_written_when_files_fail_to_parse() {
+        let files = write_test_files("hira_cli_repro_parse_test", &[
+            ("good.rs", GOOD_FILE),
+            ("bad.rs", "pub mod bad { this is not rust"),
+            ("not_hira.rs", "pub fn helper() {}"),
+        ]);
+        let root = files[0].parent().unwrap().to_path_buf();
+        let mut conf = HiraConfig::default();
+        let err = analyze_files(&mut conf, &files, false, &mut |_, _| Ok(()))
+            .expect_err("Expected bad.rs to fail to parse");
+
+        let out_dir = std::env::temp_dir().join("hira_cli_repro_parse_test_out");
+        let _ = std::fs::remove_dir_all(&out_dir);
+        // no module failed, so every hira file and every file that can't be parsed is needed to reproduce it
+        repro::write_repro_bundle(&out_dir, &root, &files, &[], &[err.clone()], &Default::default())
+            .expect("Failed to write repro bundle");
+        assert!(out_dir.join("good.rs").exists());
+        assert!(out_dir.join("bad.rs").exists());
+        assert!(!out_dir.join("not_hira.rs").exists());
+        let config = std::fs::read_to_string(out_dir.join(repro::REPRO_CONFIG_FILE)).expect("Failed to read config dump");
+        assert!(config.contains(&err));
+    }
+
+    #[test]
+    fn parallel_parsing_matches_sequential_parsing() {
+        let mut files = vec![
+            ("bad.rs".to_string(), "pub mod bad { this is not rust".to_string()),
+            ("not_hira.rs".to_string(), "pub fn helper() {}".to_string()),
+            ("setter.rs".to_string(), r#"
+                #[hira::hira]
+                pub mod setter {
+                    use super::L0Core;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub val: String,
+                    }
+                    pub mod outputs {
+                        pub const VAL: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                        l0core.set_output("VAL", &input.val);
+                    }
+                }
+            "#.to_string()),
+        ];
+        // a chain of modules, each one after the previous one
+        for i in 0..20 {
+            let after = if i == 0 { "".to_string() } else { format!("(after = \"mod{}\")", 20 - i) };
+            let contents = format!(r#"
+                #[hira::hira{after}]
+                pub mod mod{0} {{
+                    use super::setter;
+                    pub mod outputs {{
+                        pub use setter::outputs::*;
+                    }}
+                    pub fn config(input: &mut setter::Input) {{
+                        input.val = "mod{0}".to_string();
+                    }}
+                }}
+            "#, 19 - i);
+            files.push((format!("file{i}.rs"), contents));
+        }
+        let files: Vec<(&str, &str)> = files.iter().map(|(a, b)| (a.as_str(), b.as_str())).collect();
+        let files = write_test_files("hira_cli_parallel_parse_test", &files);
+
+        let analyze_with = |parallel: bool| {
+            let mut conf = HiraConfig::new();
+            conf.wasm_directory = std::env::temp_dir().join("hira_cli_parallel_parse_test_out").to_string_lossy().to_string();
+            conf.build_script_path = format!("{}/build.sh", conf.wasm_directory);
+            conf.should_output_build_script = false;
+            let mut analyzed = vec![];
+            let errors = analyze_files_ex(&mut conf, &files, true, parallel, &mut |conf, tokens| {
+                let module = parse_module_from_stream(tokens.clone())?;
+                analyzed.push((module.name, module.span, tokens.to_string()));
+                hira_lib::module_loading::hira_mod2_inner_ex(conf, tokens, true, false, None, None, Default::default()).map(|_| ())
+            }).expect("keep going should not return an error");
+            let outputs: Vec<_> = analyzed.iter()
+                .map(|(name, _, _)| conf.get_mod2(name).expect("Failed to find analyzed module").resolved_outputs.clone())
+                .collect();
+            (analyzed, outputs, errors)
+        };
+        let (sequential, sequential_outputs, sequential_errors) = analyze_with(false);
+        let (parallel, parallel_outputs, parallel_errors) = analyze_with(true);
+        assert_eq!(sequential.len(), 21);
+        assert_eq!(sequential_errors.len(), 1);
+        assert!(sequential_errors[0].contains("bad.rs"));
+        assert_eq!(sequential, parallel);
+        assert_eq!(sequential_errors, parallel_errors);
+        assert_eq!(sequential_outputs, parallel_outputs);
+        let names: Vec<&str> = sequential.iter().map(|x| x.0.as_str()).collect();
+        assert_eq!(names[..4], ["setter", "mod19", "mod18", "mod17"]);
+        // spans are the same as in the files the modules were read from
+        assert_eq!(sequential[1].1, (2, 17));
+        for (i, (name, _, _)) in sequential.iter().enumerate().skip(1) {
+            assert_eq!(sequential_outputs[i]["VAL"], *name);
+        }
+    }
 }
@@ -0,0 +1,149 @@
+use std::path::Path;
+
+/// creates a new crate at `{parent_dir}/{name}` containing a minimal level2 module.
+pub fn create_module_crate(parent_dir: &Path, name: &str) -> Result<(), String> {
+    validate_module_name(name)?;
+    let crate_dir = parent_dir.join(name);
+    if crate_dir.exists() {
+        return Err(format!("Cannot create module {name}: {:?} already exists", crate_dir));
+    }
+    let src_dir = crate_dir.join("src");
+    std::fs::create_dir_all(&src_dir)
+        .map_err(|e| format!("Failed to create directory {:?}\n{:?}", src_dir, e))?;
+    let cargo_toml_path = crate_dir.join("Cargo.toml");
+    std::fs::write(&cargo_toml_path, get_cargo_toml(name))
+        .map_err(|e| format!("Failed to write {:?}\n{:?}", cargo_toml_path, e))?;
+    let lib_rs_path = src_dir.join("lib.rs");
+    std::fs::write(&lib_rs_path, get_lib_rs(name))
+        .map_err(|e| format!("Failed to write {:?}\n{:?}", lib_rs_path, e))?;
+    Ok(())
+}
+
+/// module names become both the crate name and the name of the rust module,
+/// so they must be valid rust identifiers.
+pub fn validate_module_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid_start = match chars.next() {
+        Some(c) => c.is_ascii_alphabetic() || c == '_',
+        None => false,
+    };
+    if !valid_start || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("Invalid module name '{name}'. Must be a valid rust identifier, eg: my_module"));
+    }
+    Ok(())
+}
+
+pub fn get_cargo_toml(name: &str) -> String {
+    format!(r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+hira_lib = {{ git = "https://github.com/nikita-skobov/hira" }}
+hira = {{ git = "https://github.com/nikita-skobov/hira" }}
+
+[dev-dependencies]
+hira_lib = {{ git = "https://github.com/nikita-skobov/hira", features = ["e2e"] }}
+"#)
+}
+
+pub fn get_lib_rs(name: &str) -> String {
+    format!(r##"use hira_lib::level0::*;
+
+/// TODO: describe what your module does. This documentation is shown to users of your module.
+#[hira::hira]
+pub mod {name} {{
+    use super::L0Core;
+
+    // uncomment to request capabilities, for example to create runtimes
+    // or write to files. Users will be able to review these.
+    // pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[
+    //     ("RUNTIME", &[""]),
+    //     ("FILES", &["myfile.txt"]),
+    // ];
+
+    pub mod outputs {{
+        /// TODO: describe this output. Other modules can import it
+        /// after your module has been evaluated.
+        pub const MY_OUTPUT: &str = "";
+    }}
+
+    #[derive(Default)]
+    pub struct Input {{}}
+
+    pub fn config(input: &mut Input, l0core: &mut L0Core) {{
+        let _ = input;
+        l0core.set_output("MY_OUTPUT", "hello");
+    }}
+}}
+
+#[cfg(test)]
+mod tests {{
+    use hira_lib::e2e::*;
+
+    #[test]
+    fn config_sets_my_output() {{
+        // expands this module, and then a module that uses it, the same way the hira macro would.
+        let module = get_hira_module_source(include_str!("lib.rs"), "{name}");
+        let user_module = r#"
+            pub mod my_user_module {{
+                use super::{name};
+                pub mod outputs {{
+                    pub use {name}::outputs::*;
+                }}
+                pub fn config(input: &mut {name}::Input) {{}}
+            }}
+        "#;
+        let conf = e2e_module2_run(&[module.as_str(), user_module], |_| {{}}).expect("Failed to expand modules");
+        let user_module = conf.get_mod2("my_user_module").expect("Failed to find my_user_module");
+        assert_eq!(user_module.resolved_outputs["MY_OUTPUT"], "hello");
+    }}
+}}
+"##)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use hira_lib::{HiraConfig, parsing::{iter_hira_modules, has_attr_that_ends_in}, module_loading::{parse_module_from_stream, ModuleLevel}};
+    use proc_macro2::TokenStream;
+    use quote::ToTokens;
+    use super::*;
+
+    #[test]
+    fn scaffolded_module_is_valid_level2_module() {
+        let lib_rs = get_lib_rs("my_module");
+        let mut found = vec![];
+        iter_hira_modules(&lib_rs, &mut |m| {
+            if has_attr_that_ends_in(&m.attrs, "hira") {
+                found.push(m.to_token_stream().to_string());
+            }
+            Ok(true)
+        }).expect("Failed to parse scaffolded lib.rs");
+        assert_eq!(found.len(), 1);
+        let stream = TokenStream::from_str(&found[0]).expect("Failed to parse scaffolded module as token stream");
+        let mut module = parse_module_from_stream(stream).expect("Failed to parse scaffolded module");
+        let mut conf = HiraConfig::default();
+        module.verify_config_signature(&mut conf).expect("Failed to verify scaffolded module");
+        assert_eq!(module.name, "my_module");
+        assert!(module.level == ModuleLevel::Level2);
+    }
+
+    #[test]
+    fn scaffolded_test_uses_the_e2e_harness() {
+        let lib_rs = get_lib_rs("my_module");
+        assert!(!lib_rs.contains("TODO: evaluate"));
+        assert!(lib_rs.contains(r#"get_hira_module_source(include_str!("lib.rs"), "my_module")"#));
+        assert!(lib_rs.contains("use super::my_module;"));
+        assert!(get_cargo_toml("my_module").contains(r#"features = ["e2e"]"#));
+    }
+
+    #[test]
+    fn module_name_must_be_identifier() {
+        assert!(validate_module_name("my_module").is_ok());
+        assert!(validate_module_name("").is_err());
+        assert!(validate_module_name("1module").is_err());
+        assert!(validate_module_name("my-module").is_err());
+    }
+}
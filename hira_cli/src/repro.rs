@@ -0,0 +1,169 @@
+use std::{path::{Path, PathBuf}, collections::{BTreeSet, HashMap}};
+use hira_lib::{HiraSettings, parsing::{iter_hira_modules, get_ident_string}, module_loading::{parse_module_from_stream, OutputType}};
+use proc_macro2::TokenStream;
+use quote::ToTokens;
+
+/// the file in the repro directory that contains the settings and the sanitized .env files
+pub const REPRO_CONFIG_FILE: &str = "hira_repro_config.txt";
+pub const REDACTED: &str = "<redacted>";
+
+/// returns the name of the module in `tokens`, if it can be parsed as a module.
+pub fn get_module_name(tokens: &TokenStream) -> Option<String> {
+    syn::parse2::<syn::ItemMod>(tokens.clone()).ok().map(|m| get_ident_string(&m.ident))
+}
+
+/// writes everything needed to reproduce the failure of `failed_modules` to `dir`:
+/// the files that define them, the files of all of the modules they depend on (transitively),
+/// and a config dump with the errors, the settings and the keys of the .env files in `root`. values of .env files are redacted.
+/// if no module failed, the failure happened before analyzing modules (eg: a file could not be read or parsed,
+/// or the modules could not be ordered), so every file that defines a hira module or can't be parsed is written.
+/// files are written relative to `root`. returns the paths that were written.
+pub fn write_repro_bundle(
+    dir: &Path,
+    root: &Path,
+    files: &[PathBuf],
+    failed_modules: &[String],
+    errors: &[String],
+    settings: &HiraSettings,
+) -> Result<Vec<PathBuf>, String> {
+    let (modules, unparseable_files) = get_module_files(files);
+    let mut needed_files = BTreeSet::new();
+    if failed_modules.is_empty() {
+        needed_files.extend(modules.values().map(|(file, _)| *file));
+        needed_files.extend(unparseable_files);
+    }
+    let mut visited = BTreeSet::new();
+    let mut stack: Vec<&str> = failed_modules.iter().map(|x| x.as_str()).collect();
+    while let Some(name) = stack.pop() {
+        if !visited.insert(name) {
+            continue;
+        }
+        // dependencies that aren't defined in the user's files (eg: hira_base) come from other crates
+        if let Some((file, deps)) = modules.get(name) {
+            needed_files.insert(*file);
+            stack.extend(deps.iter().map(|x| x.as_str()));
+        }
+    }
+
+    let mut written = vec![];
+    for file in needed_files {
+        let relative = file.strip_prefix(root).unwrap_or(file);
+        let out_path = dir.join(relative);
+        if let Some(parent) = out_path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory {:?}\n{:?}", parent, e))?;
+        }
+        std::fs::copy(file, &out_path)
+            .map_err(|e| format!("Failed to copy {:?} to {:?}\n{:?}", file, out_path, e))?;
+        written.push(out_path);
+    }
+
+    let mut config = format!("failed modules: {:?}\n\nerrors:\n{}\n\n{:#?}\n", failed_modules, errors.join("\n"), settings);
+    let mut env_files: Vec<PathBuf> = std::fs::read_dir(root)
+        .map_err(|e| format!("Failed to read directory {:?}\n{:?}", root, e))?
+        .filter_map(|entry| entry.ok().map(|x| x.path()))
+        .filter(|path| path.is_file() && path.file_name().map(|x| x.to_string_lossy().starts_with(".env")).unwrap_or(false))
+        .collect();
+    env_files.sort();
+    for path in env_files {
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {:?}\n{:?}", path, e))?;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        config.push_str(&format!("\n{file_name}:\n{}", sanitize_dotenv(&contents)));
+    }
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create directory {:?}\n{:?}", dir, e))?;
+    let config_path = dir.join(REPRO_CONFIG_FILE);
+    std::fs::write(&config_path, config)
+        .map_err(|e| format!("Failed to write {:?}\n{:?}", config_path, e))?;
+    written.push(config_path);
+    Ok(written)
+}
+
+/// returns the key of a .env line of the form `^(export\s+)?[A-Za-z_][A-Za-z0-9_]*=`, including the `export`.
+fn get_dotenv_key(line: &str) -> Option<&str> {
+    let key_start = match line.strip_prefix("export") {
+        Some(rest) if rest.starts_with(char::is_whitespace) => line.len() - rest.trim_start().len(),
+        _ => 0,
+    };
+    let (_, rest) = line.split_at(key_start);
+    let key_len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+    let starts_with_digit = rest.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(true);
+    if key_len == 0 || starts_with_digit || !rest[key_len..].starts_with('=') {
+        return None;
+    }
+    Some(&line[..key_start + key_len])
+}
+
+/// keeps only the keys of a .env file. every line that isn't a `KEY=value` pair is removed,
+/// as comments and malformed lines can contain secrets too.
+pub fn sanitize_dotenv(contents: &str) -> String {
+    let mut out = String::new();
+    for line in contents.lines() {
+        if let Some(key) = get_dotenv_key(line) {
+            out.push_str(&format!("{key}={REDACTED}\n"));
+        }
+    }
+    out
+}
+
+/// returns a map of every hira module in `files` to the file it is defined in, and the modules it depends on,
+/// as well as the files that could not be read or parsed.
+fn get_module_files(files: &[PathBuf]) -> (HashMap<String, (&PathBuf, Vec<String>)>, Vec<&PathBuf>) {
+    let mut out = HashMap::new();
+    let mut unparseable = vec![];
+    for file in files {
+        let contents = match std::fs::read_to_string(file) {
+            Ok(c) => c,
+            Err(_) => {
+                unparseable.push(file);
+                continue;
+            }
+        };
+        let mut modules = vec![];
+        let res = iter_hira_modules(&contents, &mut |m| {
+            if hira_lib::parsing::has_attr_that_ends_in(&m.attrs, "hira") {
+                modules.push(m.to_token_stream());
+            }
+            Ok(true)
+        });
+        if res.is_err() {
+            unparseable.push(file);
+        }
+        for tokens in modules {
+            let module = match parse_module_from_stream(tokens) {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+            let output_deps = module.fill_outputs.into_iter().map(|x| match x {
+                OutputType::AllFromModule(name) => name,
+                OutputType::SpecificFromModule(name, _, _) => name,
+            });
+            let deps = module.use_dependencies.into_iter().chain(output_deps).collect();
+            out.insert(module.name, (file, deps));
+        }
+    }
+    (out, unparseable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dotenv_values_are_redacted() {
+        let sanitized = sanitize_dotenv(concat!(
+            "# my password is hunter2\n",
+            "API_KEY=hunter2\n",
+            "\n",
+            "export TOKEN=abc=def\n",
+            "  # indented comment SECRET=hunter2\n",
+            "NAME = value\n",
+            "password: hunter2=x\n",
+            "1KEY=x\n",
+            "exporter=x\n",
+            "not a pair\n",
+        ));
+        assert_eq!(sanitized, format!("API_KEY={REDACTED}\nexport TOKEN={REDACTED}\nexporter={REDACTED}\n"));
+    }
+}
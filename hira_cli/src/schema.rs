@@ -0,0 +1,177 @@
+use std::{path::Path, collections::HashMap};
+use hira_lib::{HiraConfig, module_loading::HiraModule2};
+use serde_json::{json, Map, Value};
+
+/// writes `{dir}/{module name}.schema.json` for every module that has an Input struct.
+pub fn emit_input_schemas(conf: &HiraConfig, dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create directory {:?}\n{:?}", dir, e))?;
+    let mut names: Vec<&String> = conf.modules2.keys().collect();
+    names.sort();
+    for name in names {
+        let module = &conf.modules2[name];
+        if module.input_struct.is_empty() {
+            continue;
+        }
+        let schema = get_input_schema(module);
+        let data = serde_json::to_string_pretty(&schema)
+            .map_err(|e| format!("Failed to serialize schema of module {name}\n{:?}", e))?;
+        let path = dir.join(format!("{name}.schema.json"));
+        std::fs::write(&path, data)
+            .map_err(|e| format!("Failed to write {:?}\n{:?}", path, e))?;
+    }
+    Ok(())
+}
+
+/// returns the variant names of every enum in the module whose variants are all unit variants.
+/// these serialize as plain strings (the variant name) with the default serde representation.
+pub fn get_unit_enums(module: &HiraModule2) -> HashMap<String, Vec<String>> {
+    let mut out = HashMap::new();
+    let file = match syn::parse_file(&module.contents) {
+        Ok(f) => f,
+        Err(_) => return out,
+    };
+    let mut items: Vec<&syn::Item> = file.items.iter().collect();
+    while let Some(item) = items.pop() {
+        match item {
+            syn::Item::Mod(m) => {
+                if let Some((_, inner)) = &m.content {
+                    items.extend(inner.iter());
+                }
+            }
+            syn::Item::Enum(e) => {
+                if e.variants.iter().all(|v| matches!(v.fields, syn::Fields::Unit)) {
+                    let variants = e.variants.iter().map(|v| v.ident.to_string()).collect();
+                    out.insert(e.ident.to_string(), variants);
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// the JSON schema of a rust type as it would be serialized by serde. Types that
+/// can't be represented (eg: types from other crates) are left unconstrained.
+pub fn get_type_schema(ty: &syn::Type, enums: &HashMap<String, Vec<String>>) -> Value {
+    match ty {
+        syn::Type::Reference(r) => get_type_schema(&r.elem, enums),
+        syn::Type::Paren(p) => get_type_schema(&p.elem, enums),
+        syn::Type::Tuple(t) => {
+            let items: Vec<Value> = t.elems.iter().map(|x| get_type_schema(x, enums)).collect();
+            let len = items.len();
+            json!({ "type": "array", "prefixItems": items, "minItems": len, "maxItems": len })
+        }
+        syn::Type::Array(a) => json!({ "type": "array", "items": get_type_schema(&a.elem, enums) }),
+        syn::Type::Slice(a) => json!({ "type": "array", "items": get_type_schema(&a.elem, enums) }),
+        syn::Type::Path(p) => {
+            let last = match p.path.segments.last() {
+                Some(l) => l,
+                None => return json!({}),
+            };
+            let generics: Vec<&syn::Type> = match &last.arguments {
+                syn::PathArguments::AngleBracketed(args) => args.args.iter().filter_map(|x| match x {
+                    syn::GenericArgument::Type(t) => Some(t),
+                    _ => None,
+                }).collect(),
+                _ => vec![],
+            };
+            let name = last.ident.to_string();
+            match (name.as_str(), generics.as_slice()) {
+                ("bool", _) => json!({ "type": "boolean" }),
+                ("String" | "str" | "char" | "StrVal", _) => json!({ "type": "string" }),
+                ("u8" | "u16" | "u32" | "u64" | "u128" | "usize", _) => json!({ "type": "integer", "minimum": 0 }),
+                ("i8" | "i16" | "i32" | "i64" | "i128" | "isize", _) => json!({ "type": "integer" }),
+                ("f32" | "f64", _) => json!({ "type": "number" }),
+                ("Option" | "Box", [inner]) => get_type_schema(inner, enums),
+                ("Vec" | "HashSet" | "BTreeSet", [inner]) => json!({ "type": "array", "items": get_type_schema(inner, enums) }),
+                ("HashMap" | "BTreeMap", [_, val]) => json!({ "type": "object", "additionalProperties": get_type_schema(val, enums) }),
+                _ => match enums.get(&name) {
+                    Some(variants) => json!({ "type": "string", "enum": variants }),
+                    None => json!({}),
+                },
+            }
+        }
+        _ => json!({}),
+    }
+}
+
+pub fn get_input_schema(module: &HiraModule2) -> Value {
+    let enums = get_unit_enums(module);
+    let mut properties = Map::new();
+    let mut required = vec![];
+    let mut inputs: Vec<_> = module.input_definition.iter().collect();
+    inputs.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, def) in inputs {
+        let mut schema = match syn::parse_str::<syn::Type>(&def.ty) {
+            Ok(ty) => get_type_schema(&ty, &enums),
+            Err(_) => json!({}),
+        };
+        let ty = def.ty.replace(' ', "");
+        if !ty.starts_with("Option<") {
+            required.push(name.to_string());
+        }
+        if let Value::Object(map) = &mut schema {
+            if !def.documentation.is_empty() {
+                map.insert("description".to_string(), Value::String(def.documentation.clone()));
+            }
+        }
+        properties.insert(name.to_string(), schema);
+    }
+    let mut out = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": module.name,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    });
+    if !module.input_documentation.is_empty() {
+        out["description"] = Value::String(module.input_documentation.clone());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use hira_lib::module_loading::parse_module_from_stream;
+    use proc_macro2::TokenStream;
+    use super::*;
+
+    #[test]
+    fn enum_fields_have_allowed_values() {
+        let code = r#"
+        pub mod schemamod {
+            pub enum Arch {
+                Arm64,
+                X86,
+            }
+            impl Default for Arch {
+                fn default() -> Self { Self::Arm64 }
+            }
+            /// the input of schemamod
+            #[derive(Default)]
+            pub struct Input {
+                /// which cpu to use
+                pub architecture: Arch,
+                pub memory: Option<u32>,
+                pub policies: Vec<(String, String)>,
+                pub extra: some_crate::Thing,
+            }
+            pub fn config(input: &mut Input) {}
+        }
+        "#;
+        let stream = TokenStream::from_str(code).expect("Failed to parse test case as token stream");
+        let module = parse_module_from_stream(stream).expect("Failed to parse test case as module");
+        let schema = get_input_schema(&module);
+        assert_eq!(schema["title"], "schemamod");
+        assert_eq!(schema["description"], "the input of schemamod");
+        assert_eq!(schema["properties"]["architecture"], json!({
+            "type": "string", "enum": ["Arm64", "X86"], "description": "which cpu to use",
+        }));
+        assert_eq!(schema["properties"]["memory"], json!({ "type": "integer", "minimum": 0 }));
+        assert_eq!(schema["properties"]["policies"]["items"]["prefixItems"], json!([{ "type": "string" }, { "type": "string" }]));
+        assert_eq!(schema["properties"]["extra"], json!({}));
+        assert_eq!(schema["required"], json!(["architecture", "extra", "policies"]));
+    }
+}
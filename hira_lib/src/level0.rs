@@ -6,7 +6,7 @@ use quote::{ToTokens};
 use wasm_type_gen::*;
 
 use crate::{HiraConfig, module_loading::{HiraModule2, OutputType, print_debug}, parsing::{compiler_error, iterate_mod_def_generic, parse_fn_signature}, wasm_types::{to_map_entry}};
-use crate::parsing::FunctionSignature;
+use crate::parsing::{FunctionSignature, StructDefinition, parse_struct_definition};
 
 
 #[derive(WasmTypeGen, Debug)]
@@ -52,23 +52,29 @@ pub struct LibraryObj {
     pub l0_code_writer: L0CodeWriter,
 
     pub l0_runtime_creator: L0RuntimeCreator,
+
+    pub l0_scratch: L0Scratch,
 }
 
 
 impl LibraryObj {
     pub fn apply_changes(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2, stream: &mut TokenStream) -> Result<(), TokenStream> {
         self.l0_core.apply_changes(conf, module, stream)?;
+        self.l0_kv_reader.apply_changes(conf, module, stream)?;
         self.l0_append_file.apply_changes(conf, module, stream)?;
         self.l0_code_writer.apply_changes(conf, module, stream)?;
         self.l0_runtime_creator.apply_changes(conf, module, stream)?;
+        self.l0_scratch.apply_changes(conf, module, stream)?;
         Ok(())
     }
     pub fn initialize_capabilities(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2) -> Result<(), TokenStream> {
         self.l0_core.initialize_capabilities(conf, module)?;
+        self.l0_kv_reader.initialize_capabilities(conf, module)?;
         self.l0_append_file.initialize_capabilities(conf, module)?;
         self.l0_code_reader.initialize_capabilities(conf, module)?;
         self.l0_code_writer.initialize_capabilities(conf, module)?;
         self.l0_runtime_creator.initialize_capabilities(conf, module)?;
+        self.l0_scratch.initialize_capabilities(conf, module)?;
         Ok(())
     }
 }
@@ -82,6 +88,14 @@ pub struct SharedOutputEntry {
     pub line: String,
     pub unique: bool,
     pub after: Option<String>,
+    /// if true, the line is inserted at the front of the label instead of the end.
+    /// if `after` is also set, it is inserted before the search string instead of after it.
+    pub prepend: bool,
+    /// if true, lines equal to `line` are removed from the label. if `after` is also set,
+    /// they are replaced with `after` instead.
+    pub remove: bool,
+    /// name of the module that requested this line be written
+    pub creator: String,
 }
 
 #[derive(WasmTypeGen, Debug)]
@@ -111,6 +125,39 @@ pub struct L0Core {
     lvl3_module_name: String,
     crate_name: String,
     dotenv_location: Option<String>,
+    use_git_info: bool,
+    stack_name_prefix: String,
+    iam_name_prefix: String,
+    iam_permissions_boundary: Option<String>,
+    sibling_modules: Vec<String>,
+    module_span: SpanInfo,
+    input_sources: std::collections::HashMap<String, InputSource>,
+}
+
+/// who set a field of a module's Input. see `L0Core::input_source`
+#[derive(WasmTypeGen, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+pub enum InputSource {
+    /// the field has its default value
+    Default,
+    /// the field was set by the user's lvl3 module
+    User,
+    /// the field was set by a lvl2 module that wraps this module
+    Wrapper,
+    /// the field's type does not implement PartialEq, so it can't be known if it was set
+    Unknown,
+}
+
+/// location of a module in the user's source code.
+/// line and column are 1-based, and are 0 if unknown.
+/// file is empty if the source file could not be determined.
+#[derive(WasmTypeGen, Debug, Clone)]
+#[derive(Default)]
+#[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpanInfo {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
 }
 
 #[derive(WasmTypeGen, Debug)]
@@ -119,6 +166,8 @@ pub struct L0Core {
 pub struct L0CodeReader {
     current_module_name: String,
     function_signatures: std::collections::HashMap<String, FunctionSignature>,
+    struct_definitions: std::collections::HashMap<String, StructDefinition>,
+    fn_names: Vec<String>,
 }
 
 #[derive(WasmTypeGen, Debug)]
@@ -127,6 +176,8 @@ pub struct L0CodeReader {
 pub struct L0CodeWriter {
     current_module_name: String,
     functions: std::collections::HashMap<String, std::collections::HashMap::<String, String>>,
+    output_consts: std::collections::HashMap<String, Vec<String>>,
+    static_asserts: std::collections::HashMap<String, Vec<String>>,
 }
 
 #[derive(WasmTypeGen, Debug)]
@@ -137,6 +188,36 @@ pub struct L0RuntimeCreator {
     runtime_base_path: String,
     runtimes: std::collections::HashMap<String, RuntimeData>,
     depends_on: std::collections::HashMap<String, Vec<String>>,
+    output_variables: Vec<RuntimeOutputVariable>,
+    env_vars: Vec<RuntimeEnvVar>,
+}
+
+#[derive(WasmTypeGen, Debug)]
+#[derive(Default)]
+#[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuntimeEnvVar {
+    pub creator: String,
+    pub runtime_name: String,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(WasmTypeGen, Debug)]
+#[derive(Default)]
+#[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuntimeOutputVariable {
+    pub creator: String,
+    pub runtime_name: String,
+    pub module: String,
+    pub output_key: String,
+    pub var_name: String,
+}
+
+#[derive(WasmTypeGen, Debug)]
+#[derive(Default)]
+#[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+pub struct L0Scratch {
+    scratch_dir: String,
 }
 
 #[derive(WasmTypeGen, Debug)]
@@ -173,22 +254,82 @@ pub struct RuntimeMeta {
     pub target: String,
     pub profile: String,
     pub no_tokio_async_runtime: bool,
+    /// optionally provide a snippet of code that builds an async runtime and runs the body of the runtime.
+    /// the body is available as a future called `hira_runtime_body`. For example:
+    /// `tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build().unwrap().block_on(hira_runtime_body)`
+    /// if not provided, the runtime uses `#[tokio::main]`.
+    pub runtime_builder: Option<String>,
+    /// extra cfg flags to set when compiling this runtime, eg: `hira_preview`.
+    /// each is passed to rustc as `--cfg <feature>`, and so applies to all crates
+    /// compiled for this runtime. Features are merged across all modules that add to the same runtime.
+    pub features: Vec<String>,
+    /// cargo features to enable when compiling this runtime. passed to cargo as `--features a,b`.
+    /// merged across all modules that add to the same runtime.
+    pub cargo_features: Vec<String>,
+    /// if true, cargo is passed `--no-default-features` when compiling this runtime.
+    /// set if any module that adds to the runtime sets it.
+    pub no_default_features: bool,
+    /// whether the runtime can be ran more than once. if any module that adds to the runtime
+    /// sets `RunPolicy::Once`, the runtime is `Once`.
+    pub run_policy: RunPolicy,
+    /// how the runtime's binary is compressed when it is packaged as a zip, eg: for upload to lambda.
+    /// `stored`, `deflated`, or a deflate level from 0 to 9. if not set, the packaging module decides.
+    pub zip_level: Option<String>,
+    /// if true, the runtime is built with `cargo lambda build --bin {runtime name}` instead of `cargo rustc`,
+    /// and is output to `lambda/{runtime name}/bootstrap` within the runtimes directory, which is the layout cargo lambda uses.
+    /// cargo lambda only builds binaries, so the crate must declare a bin named after the runtime.
+    /// set if any module that adds to the runtime sets it.
+    pub cargo_lambda: bool,
+}
+
+/// whether a runtime can be re-ran. see `RuntimeMeta::run_policy`
+#[derive(WasmTypeGen, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+pub enum RunPolicy {
+    /// the runtime is idempotent and can be ran any number of times, eg: deploy.
+    Always,
+    /// the runtime should only be ran once, eg: migrations or seeding data.
+    /// hira_cli records when it completes, and refuses to run it again unless `--force` is passed.
+    Once,
+}
+
+#[output_and_stringify_basic_const(RUN_POLICY_IMPL)]
+impl Default for RunPolicy {
+    fn default() -> Self {
+        RunPolicy::Always
+    }
 }
 
 #[derive(Default, Debug)]
 struct FillCodeReader {
     function_signatures: std::collections::HashMap<String, FunctionSignature>,
     requested_fns: HashSet<String>,
+    /// set if `fn:*` was requested. all functions are read, and their names are collected in order.
+    all_fns_requested: bool,
+    fn_names: Vec<String>,
+    struct_definitions: std::collections::HashMap<String, StructDefinition>,
+    requested_structs: HashSet<String>,
 }
 
 fn set_functions(filler: &mut FillCodeReader, item: &mut ItemFn) {
     let name = item.sig.ident.to_string();
-    if !filler.requested_fns.contains(&name) { return }
+    if filler.all_fns_requested {
+        filler.fn_names.push(name.clone());
+    } else if !filler.requested_fns.contains(&name) {
+        return
+    }
 
     let sig = parse_fn_signature(&item);
     filler.function_signatures.insert(name, sig);
 }
 
+fn set_structs(filler: &mut FillCodeReader, item: &mut syn::ItemStruct) {
+    let name = item.ident.to_string();
+    if !filler.requested_structs.contains(&name) { return }
+
+    filler.struct_definitions.insert(name, parse_struct_definition(item));
+}
+
 fn get_all_capability_params(conf: &HiraConfig, module: &HiraModule2, capability_names: &[&str]) -> std::collections::HashMap<String, Vec<(String, String)>> {
     // find all transient modules that might have requested this capability
     let mut all_transient_deps = HashSet::new();
@@ -222,7 +363,7 @@ impl L0CodeWriter {
     }
     pub fn apply_changes(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2, stream: &mut TokenStream) -> Result<(), TokenStream> {
         // skip expensive calculations if theres nothing to output
-        if self.functions.is_empty() {
+        if self.functions.is_empty() && self.output_consts.is_empty() && self.static_asserts.is_empty() {
             return Ok(());
         }
 
@@ -256,6 +397,27 @@ impl L0CodeWriter {
                         Some(x) => x,
                         None => continue,
                     };
+                    if sig_type != "global" && sig_type != "module" {
+                        // structs and impls
+                        let full_item = format!("{} {{ {} }}", signature, body);
+                        let tokens = TokenStream::from_str(&full_item)
+                            .map_err(|e| compiler_error(&format!("Module {} provided invalid {} header '{}'\n{:?}", requestor, sig_type, signature, e)))?;
+                        let item = syn::parse2::<Item>(tokens.clone())
+                            .map_err(|e| compiler_error(&format!("Module {} provided invalid {} header '{}'\n{:?}", requestor, sig_type, signature, e)))?;
+                        let name = Self::get_item_name(sig_type, &item).ok_or_else(|| compiler_error(
+                            &format!("Module {} attempted to write a {} but provided '{}'", requestor, sig_type, signature)
+                        ))?;
+                        let desired_capability = format!("{}:{}", sig_type, name);
+                        if !requestor_allowed.contains(&&desired_capability) {
+                            return Err(compiler_error(&format!("Module {} attempted to write {} but no {} capability was defined", requestor, name, desired_capability)));
+                        }
+                        if sig_type.ends_with("_global") {
+                            add_after.push(tokens);
+                        } else {
+                            contents.push(item);
+                        }
+                        continue;
+                    }
                     // first, parse the fn_signature
                     let full_fn = format!("{} {{ {} }}", signature, body);
                     let tokens = TokenStream::from_str(&full_fn)
@@ -286,6 +448,29 @@ impl L0CodeWriter {
             }
         }
 
+        let mut requestors: Vec<_> = self.output_consts.iter().collect();
+        requestors.sort();
+        for (requestor, keys) in requestors {
+            for key in keys {
+                let desired_capability = format!("const_module:{key}");
+                if !allowed_global_fn_map.get(requestor).map(|x| x.contains(&&desired_capability)).unwrap_or(false) {
+                    return Err(compiler_error(&format!("Module {} attempted to write output const {} but no {} capability was defined", requestor, key, desired_capability)));
+                }
+                contents.push(Self::get_output_const(conf, module, key)?);
+            }
+        }
+
+        let mut requestors: Vec<_> = self.static_asserts.iter().collect();
+        requestors.sort();
+        for (requestor, exprs) in requestors {
+            if !allowed_global_fn_map.get(requestor).map(|x| x.iter().any(|p| *p == "assert")).unwrap_or(false) {
+                return Err(compiler_error(&format!("Module {} attempted to write a static assertion but no assert capability was defined", requestor)));
+            }
+            for expr in exprs {
+                add_after.push(Self::get_static_assert(requestor, expr)?);
+            }
+        }
+
         // now put it back together
         let mut out_stream = mod_def.to_token_stream();
         out_stream.extend(add_after);
@@ -294,11 +479,49 @@ impl L0CodeWriter {
     }
 }
 
+impl L0CodeWriter {
+    /// returns the name of the struct/enum, or the name of the type an impl is for.
+    /// returns None if the item is not of the kind that was requested.
+    fn get_item_name(sig_type: &str, item: &Item) -> Option<String> {
+        match (sig_type, item) {
+            ("struct_global" | "struct_module", Item::Struct(x)) => Some(x.ident.to_string()),
+            ("struct_global" | "struct_module", Item::Enum(x)) => Some(x.ident.to_string()),
+            ("impl_global", Item::Impl(x)) => match &*x.self_ty {
+                syn::Type::Path(p) => p.path.segments.last().map(|x| x.ident.to_string()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    /// `const _: () = assert!({expr});`. errors if the expression does not parse.
+    fn get_static_assert(requestor: &str, expr: &str) -> Result<TokenStream, TokenStream> {
+        let parsed = syn::parse_str::<syn::Expr>(expr)
+            .map_err(|e| compiler_error(&format!("Module {} provided an invalid static assertion expression '{}'\n{:?}", requestor, expr, e)))?;
+        Ok(quote::quote! { const _: () = assert!(#parsed); })
+    }
+    /// `pub const {key}: &str = "{resolved value}";`. errors if the module doesn't have
+    /// a resolved value for this output, or if the value is still the default that was declared for it.
+    fn get_output_const(conf: &HiraConfig, module: &HiraModule2, key: &str) -> Result<Item, TokenStream> {
+        let val = module.resolved_outputs.get(key).ok_or_else(|| compiler_error(
+            &format!("Cannot write output const {key} for module '{}' because it has no resolved output named {key}. Ensure '{}' has `pub mod outputs` that uses this output", module.name, module.name)
+        ))?;
+        let mut declared = std::collections::HashMap::new();
+        module.get_all_output_docs(conf, &mut declared)?;
+        if declared.get(key).map(|x| x.default == *val).unwrap_or(false) {
+            return Err(compiler_error(&format!("Cannot write output const {key} for module '{}' because it was never set, and still has its placeholder value '{val}'", module.name)));
+        }
+        let item = format!("pub const {key}: &str = {};", proc_macro2::Literal::string(val));
+        syn::parse_str::<Item>(&item)
+            .map_err(|e| compiler_error(&format!("Failed to write output const {key} for module '{}'\n{:?}", module.name, e)))
+    }
+}
+
 impl L0CodeReader {
     pub fn initialize_capabilities(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2) -> Result<(), TokenStream> {
         let mut params = get_all_capability_params(conf, &module, &["CODE_READ"]);
         // find all the requested function signatures across all modules:
         let mut function_signature_set = HashSet::new();
+        let mut struct_set = HashSet::new();
         let code_read_params = params.remove("CODE_READ").unwrap();
         for (dep, p) in code_read_params.iter() {
             if let Some((key, val)) = p.split_once(":") {
@@ -306,6 +529,9 @@ impl L0CodeReader {
                     "fn" => {
                         function_signature_set.insert(val.to_string());
                     },
+                    "struct" => {
+                        struct_set.insert(val.to_string());
+                    },
                     x => {
                         return Err(compiler_error(&format!("Module {} requested READ_CODE capability of an unknown type '{}'", dep, x)));
                     }
@@ -321,12 +547,14 @@ impl L0CodeReader {
             .map_err(|e| compiler_error(&format!("failed to parse module contents as a... module? {:?}", e)))?;
 
         let mut filler = FillCodeReader::default();
+        filler.all_fns_requested = function_signature_set.contains("*");
         filler.requested_fns = function_signature_set;
+        filler.requested_structs = struct_set;
         iterate_mod_def_generic(
             &mut filler,
             &mut mod_def,
             &[set_functions],
-            &[],
+            &[set_structs],
             &[],
             &[],
             &[],
@@ -335,7 +563,63 @@ impl L0CodeReader {
             &[],
         );
         self.function_signatures = filler.function_signatures;
+        self.struct_definitions = filler.struct_definitions;
+        self.fn_names = filler.fn_names;
+
+        Ok(())
+    }
+}
+
+impl L0KvReader {
+    pub fn initialize_capabilities(&mut self, conf: &mut HiraConfig, _module: &mut HiraModule2) -> Result<(), TokenStream> {
+        self.data = conf.shared_data.clone();
+        Ok(())
+    }
+    pub fn apply_changes(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2, _stream: &mut TokenStream) -> Result<(), TokenStream> {
+        // shared data is append only: modules can add new keys,
+        // but they cannot change the value of a key that another module already set.
+        for (key, val) in self.data.drain() {
+            match conf.shared_data.get(&key) {
+                Some(existing) if *existing != val => {
+                    return Err(compiler_error(&format!("Module '{}' had a dependency that attempted to change shared data key '{}' from '{}' to '{}'. Shared data keys can only be set once", module.name, key, existing, val)));
+                }
+                Some(_) => {}
+                None => {
+                    conf.shared_data.insert(key, val);
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
+impl L0Scratch {
+    pub fn initialize_capabilities(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2) -> Result<(), TokenStream> {
+        let tmp_dir = format!("{}/{}", conf.hira_directory, crate::HIRA_TMP_DIR_NAME);
+        // remove everything from the previous build the first time
+        // any module is initialized
+        if !conf.has_cleaned_scratch_directory && conf.should_do_file_ops {
+            let _ = std::fs::remove_dir_all(&tmp_dir);
+            conf.has_cleaned_scratch_directory = true;
+        }
+        let params = get_all_capability_params(conf, &module, &["SCRATCH"]);
+        if params["SCRATCH"].is_empty() {
+            return Ok(());
+        }
+        // module names are rust identifiers, but we check anyway
+        // to guarantee the scratch dir is within the hira directory
+        if module.name.is_empty() || !module.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(compiler_error(&format!("Cannot create a scratch directory for module '{}'", module.name)));
+        }
+        let scratch_dir = format!("{}/{}", tmp_dir, module.name);
+        if conf.should_do_file_ops {
+            std::fs::create_dir_all(&scratch_dir)
+                .map_err(|e| compiler_error(&format!("Failed to create scratch directory {}\n{:?}", scratch_dir, e)))?;
+        }
+        self.scratch_dir = scratch_dir;
+        Ok(())
+    }
+    pub fn apply_changes(&mut self, _conf: &mut HiraConfig, _module: &mut HiraModule2, _stream: &mut TokenStream) -> Result<(), TokenStream> {
         Ok(())
     }
 }
@@ -347,7 +631,7 @@ impl L0AppendFile {
         // put data in this struct, and then we verify that its valid when we leave the wasm context.
         Ok(())
     }
-    pub fn apply_changes(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2, _stream: &mut TokenStream) -> Result<(), TokenStream> {
+    pub fn apply_changes(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2, stream: &mut TokenStream) -> Result<(), TokenStream> {
         let mut all_transient_deps = HashSet::new();
         module.visit_lvl3_dependency_names(&conf, &mut |dep| {
             all_transient_deps.insert(dep.to_string());
@@ -376,12 +660,74 @@ impl L0AppendFile {
             return out;
         }
 
+        // track which modules wrote to which file+label. if more than 1 module
+        // writes to the same label, the ordering between them is not obvious, so we
+        // let the module authors know in debug builds that they should coordinate.
+        let mut overlaps = vec![];
+        for entry in contents.iter() {
+            let key = (entry.filename.clone(), entry.label.clone());
+            let contributors = conf.shared_file_contributors.entry(key).or_default();
+            if contributors.contains(&entry.creator) {
+                continue;
+            }
+            contributors.push(entry.creator.clone());
+            if contributors.len() > 1 {
+                overlaps.push(format!("file '{}' label '{}' is written to by modules {:?}", entry.filename, entry.label, contributors));
+            }
+        }
+        if cfg!(debug_assertions) && !overlaps.is_empty() {
+            let msg = format!("\nMultiple modules are appending to the same file label. Their lines will be interleaved in the order the modules run:\n{}", overlaps.join("\n"));
+            print_debug(&conf.logfile, format!("{}\n", msg));
+            let add = format!("mod _hira_generated_file_warning {{ #[deprecated(note = r#\"{}\"#)]pub fn hira_generated_warning() {{}}\n fn _hira_use_warning() {{ hira_generated_warning() }} }}", msg);
+            let add_tokens = TokenStream::from_str(&add)
+                .map_err(|e| compiler_error(&format!("Failed to generate compiler warning {:?}", e)))?;
+            stream.extend(add_tokens);
+        }
+
         let mapped_data = to_map_entry(contents);
         conf.output_shared_files(&module.name, mapped_data)?;
         out
     }
 }
 
+/// runtime code lines get `include!`d into the generated runtime, so a snippet with
+/// unbalanced delimiters or invalid tokens would only fail once the runtime is compiled.
+pub fn validate_runtime_code_line(code: &str) -> Result<(), String> {
+    let stream = TokenStream::from_str(code).map_err(|e| format!("Failed to parse as rust tokens: {e}"))?;
+    if stream.is_empty() {
+        return Err("Code is empty".to_string());
+    }
+    Ok(())
+}
+
+/// a module's RUNTIME capability params are the names of the runtimes it may use.
+/// an empty name grants every runtime, eg: `&[("RUNTIME", &[""])]`
+fn verify_runtime_granted(runtime_params: &[(String, String)], creator: &str, runtime_name: &str) -> Result<(), TokenStream> {
+    let granted: Vec<&str> = runtime_params.iter().filter(|x| x.0 == creator).map(|x| x.1.as_str()).collect();
+    if granted.is_empty() {
+        return Err(compiler_error(&format!("Module '{}' requested to use runtime {} but no RUNTIME capability was found", creator, runtime_name)));
+    }
+    if granted.iter().any(|x| x.is_empty() || *x == runtime_name) {
+        return Ok(());
+    }
+    Err(compiler_error(&format!("Module '{}' requested to use runtime {} but its RUNTIME capability only grants: {}", creator, runtime_name, granted.join(", "))))
+}
+
+/// the hira CLI uses these names as subcommands, so runtimes with these names could never be run
+pub const RESERVED_RUNTIME_NAMES: &[&str] = &["check", "new-module"];
+
+/// cargo features are written into the build script as is, so only characters
+/// that can appear in a feature name (or `dependency/feature`) are allowed.
+pub fn validate_cargo_feature(feature: &str) -> Result<(), String> {
+    if feature.is_empty() {
+        return Err("cargo features cannot be empty".to_string());
+    }
+    if let Some(c) = feature.chars().find(|c| !c.is_ascii_alphanumeric() && !['_', '-', '/'].contains(c)) {
+        return Err(format!("cargo feature '{feature}' contains invalid character {c:?}. Cargo features can only contain a-z, A-Z, 0-9, '_', '-' and '/'"));
+    }
+    Ok(())
+}
+
 impl L0RuntimeCreator {
     pub fn initialize_capabilities(&mut self, conf: &mut HiraConfig, _module: &mut HiraModule2) -> Result<(), TokenStream> {
         self.runtime_base_path = conf.runtime_directory.clone();
@@ -397,7 +743,44 @@ impl L0RuntimeCreator {
                 conf.runtime_depends_on.insert(runtime_name, depends_on);
             }
         }
+        for var in self.output_variables.drain(..) {
+            verify_runtime_granted(&runtime_params, &var.creator, &var.runtime_name)?;
+            if syn::parse_str::<syn::Ident>(&var.var_name).is_err() {
+                return Err(compiler_error(&format!("Module '{}' requested to add output {} to runtime {} as an invalid variable name '{}'", var.creator, var.output_key, var.runtime_name, var.var_name)));
+            }
+            // the outputs of the current module are not in the config yet
+            let outputs = if var.module == module.name {
+                Some(&module.resolved_outputs)
+            } else {
+                conf.get_mod2(&var.module).map(|m| &m.resolved_outputs)
+            };
+            let value = match outputs.and_then(|o| o.get(&var.output_key)) {
+                Some(v) => v.to_string(),
+                None => {
+                    return Err(compiler_error(&format!("Module '{}' requested to add output {} of module {} to runtime {}, but that output does not exist", var.creator, var.output_key, var.module, var.runtime_name)));
+                }
+            };
+            conf.add_runtime_variable(&var.runtime_name, var.var_name, value)?;
+        }
+        for var in self.env_vars.drain(..) {
+            verify_runtime_granted(&runtime_params, &var.creator, &var.runtime_name)?;
+            let is_valid_key = !var.key.is_empty()
+                && !var.key.starts_with(|c: char| c.is_ascii_digit())
+                && var.key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !is_valid_key {
+                return Err(compiler_error(&format!("Module '{}' requested to add an invalid env var name '{}' to runtime {}", var.creator, var.key, var.runtime_name)));
+            }
+            conf.add_runtime_env(&var.runtime_name, var.key, var.value);
+        }
         for (runtime_name, runtime_info) in self.runtimes.drain() {
+            if RESERVED_RUNTIME_NAMES.contains(&runtime_name.as_str()) {
+                return Err(compiler_error(&format!("Module '{}' added runtime '{runtime_name}', but that name is reserved by the hira CLI. Runtime names cannot be any of {:?}", module.name, RESERVED_RUNTIME_NAMES)));
+            }
+            for feature in runtime_info.meta.cargo_features.iter() {
+                if let Err(e) = validate_cargo_feature(feature) {
+                    return Err(compiler_error(&format!("Module '{}' added runtime '{runtime_name}' with invalid cargo features: {e}", module.name)));
+                }
+            }
             let mut beginnings = vec![];
             let mut nones = vec![];
             let mut ends = vec![];
@@ -418,8 +801,9 @@ impl L0RuntimeCreator {
             for position_type in [beginnings, nones, ends] {
                 for info in position_type {
                     let RuntimeInfo { creator, code, unique_line, .. } = info;
-                    if !runtime_params.iter().any(|x| x.0 == *creator) {
-                        return Err(compiler_error(&format!("Module '{}' requested to use runtime {} but no RUNTIME capability was found", creator, runtime_name)));
+                    verify_runtime_granted(&runtime_params, &creator, &runtime_name)?;
+                    if let Err(e) = validate_runtime_code_line(&code) {
+                        return Err(compiler_error(&format!("Module '{}' added invalid code to runtime {}: {}\n{}", creator, runtime_name, e, code)));
                     }
                     conf.add_to_runtime(runtime_name.to_string(), runtime_info.meta.clone(), code, unique_line);
                 }
@@ -432,6 +816,56 @@ impl L0RuntimeCreator {
     }
 }
 
+/// the git state of the user's crate. Read at most once per build, see `HiraConfig::git_info`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GitInfo {
+    /// the full commit hash, or `unknown` if it could not be read
+    pub commit: String,
+    pub dirty: bool,
+}
+
+impl GitInfo {
+    pub const UNKNOWN_COMMIT: &'static str = "unknown";
+
+    /// given the stdout of `git rev-parse HEAD` and `git status --porcelain`.
+    /// None means the command failed to run (eg: git is not installed, or not a git repository)
+    pub fn parse(rev_parse_output: Option<&str>, status_output: Option<&str>) -> Self {
+        let commit = rev_parse_output.map(|x| x.trim())
+            .filter(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_hexdigit()))
+            .unwrap_or(Self::UNKNOWN_COMMIT);
+        let dirty = status_output.map(|x| x.lines().any(|l| !l.trim().is_empty())).unwrap_or(false);
+        Self { commit: commit.to_string(), dirty }
+    }
+
+    /// runs git in the given directory. never fails, see `parse`
+    pub fn read(dir: &str) -> Self {
+        let run = |args: &[&str]| -> Option<String> {
+            let out = std::process::Command::new("git").args(args).current_dir(dir).output().ok()?;
+            if !out.status.success() {
+                return None;
+            }
+            String::from_utf8(out.stdout).ok()
+        };
+        let rev_parse = run(&["rev-parse", "HEAD"]);
+        // no point checking the status if we dont know the commit
+        let status = if rev_parse.is_some() { run(&["status", "--porcelain"]) } else { None };
+        Self::parse(rev_parse.as_deref(), status.as_deref())
+    }
+
+    pub fn short_commit(&self) -> String {
+        self.commit.chars().take(7).collect()
+    }
+
+    /// the values of the outputs of the `git_info` module
+    pub fn outputs(&self) -> [(&'static str, String); 3] {
+        [
+            ("GIT_COMMIT", self.commit.clone()),
+            ("GIT_DIRTY", self.dirty.to_string()),
+            ("GIT_SHORT", self.short_commit()),
+        ]
+    }
+}
+
 impl L0Core {
     pub fn drain_outputs_into(&mut self, mod_name: &str, existing: &mut std::collections::HashMap<String, String>) {
         if let Some(mut kv_pairs) = self.module_outputs.remove(mod_name) {
@@ -501,25 +935,111 @@ impl L0Core {
         }
         Ok(())
     }
-    pub fn initialize_capabilities(&mut self, _conf: &mut HiraConfig, module: &mut HiraModule2) -> Result<(), TokenStream> {
+    pub fn initialize_capabilities(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2) -> Result<(), TokenStream> {
         self.lvl3_module_name = module.name.clone();
-        self.crate_name = std::env::var("CARGO_CRATE_NAME").unwrap_or("".to_string());
+        self.crate_name = if conf.crate_name == crate::UNKNOWN_CRATE_NAME {
+            Default::default()
+        } else {
+            conf.crate_name.clone()
+        };
+        self.module_span = SpanInfo {
+            file: module.source_file.clone(),
+            line: module.span.0,
+            column: module.span.1,
+        };
+        self.stack_name_prefix = conf.settings.stack_name_prefix.clone();
+        self.iam_name_prefix = conf.settings.iam_name_prefix.clone();
+        self.iam_permissions_boundary = conf.settings.iam_permissions_boundary.clone();
+        // modules2 also has modules from previous expansions of the crate, and modules loaded from the cache,
+        // so only the modules expanded in this pass are siblings
+        let mut siblings: Vec<String> = conf.expanded_modules.iter()
+            .map(|(name, _)| name)
+            .filter(|x| **x != module.name)
+            .cloned().collect();
+        siblings.sort();
+        self.sibling_modules = siblings;
         Ok(())
     }
-    pub fn read_dotenv(base_path: &str, location: &str) -> Result<std::collections::HashMap<String, String>, TokenStream> {
+    /// returns the values of the .env file at `location`, and any problems found while parsing it.
+    pub fn read_dotenv(base_path: &str, location: &str) -> Result<(std::collections::HashMap<String, String>, Vec<String>), TokenStream> {
         let mut path = std::path::PathBuf::from(base_path);
         path.push(location);
         let contents = std::fs::read_to_string(&path)
             .map_err(|e| compiler_error(&format!("Failed to read file '{:?}' as a .env\n{:?}", path, e)))?;
+        Ok(Self::parse_dotenv(&contents))
+    }
+    /// parsing is lenient: lines that can't be parsed are skipped, and duplicate keys use the last value.
+    /// each of these cases is reported in the returned diagnostics so they can be shown as warnings.
+    /// values in double quotes are unescaped (`\\`, `\"`, `\$`, `\``, `\n`, `\r`), and values in
+    /// single quotes are taken literally. This is the inverse of how the aws_cfn_stack runtime writes .env files.
+    pub fn parse_dotenv(contents: &str) -> (std::collections::HashMap<String, String>, Vec<String>) {
         let mut map = std::collections::HashMap::new();
-        for line in contents.lines() {
+        let mut seen_on_line = std::collections::HashMap::new();
+        let mut diagnostics = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line_num = i + 1;
             let trimmed = line.trim();
-            if trimmed.starts_with("#") { continue; }
-            if let Some((name, val)) = trimmed.split_once("=") {
-                map.insert(name.to_string(), val.to_string());
+            if trimmed.is_empty() || trimmed.starts_with("#") { continue; }
+            let (name, val) = match trimmed.split_once("=") {
+                Some((name, val)) => (name.trim_end(), val),
+                None => {
+                    diagnostics.push(format!("line {line_num}: expected KEY=value, found '{trimmed}'"));
+                    continue;
+                }
+            };
+            let is_valid_key = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !is_valid_key {
+                diagnostics.push(format!("line {line_num}: invalid key '{name}'. Keys can only contain letters, digits, and underscores, and cannot start with a digit"));
+                continue;
+            }
+            let val = match Self::unquote_dotenv_value(val) {
+                Ok(v) => v,
+                Err(e) => {
+                    diagnostics.push(format!("line {line_num}: {e}"));
+                    continue;
+                }
+            };
+            if let Some(previous) = seen_on_line.insert(name.to_string(), line_num) {
+                diagnostics.push(format!("line {line_num}: duplicate key '{name}' (previously set on line {previous}). The last value is used"));
             }
+            map.insert(name.to_string(), val);
         }
-        Ok(map)
+        (map, diagnostics)
+    }
+    /// unquoted values are returned as is. After the closing quote, only whitespace or a `#` comment is allowed.
+    fn unquote_dotenv_value(val: &str) -> Result<String, String> {
+        let quoted = val.trim_start();
+        let quote = match quoted.chars().next() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => return Ok(val.to_string()),
+        };
+        let mut out = String::new();
+        let mut chars = quoted[1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            if c == quote {
+                let rest = quoted[1 + i + 1..].trim_start();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Err(format!("unexpected characters after the closing quote: '{rest}'"));
+                }
+                return Ok(out);
+            }
+            if c != '\\' || quote == '\'' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some((_, 'n')) => out.push('\n'),
+                Some((_, 'r')) => out.push('\r'),
+                Some((_, c @ ('\\' | '"' | '$' | '`'))) => out.push(c),
+                Some((_, c)) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => break,
+            }
+        }
+        Err(format!("missing the closing quote of {quoted}"))
     }
     pub fn apply_changes(&mut self, conf: &mut HiraConfig, module: &mut HiraModule2, stream: &mut TokenStream) -> Result<(), TokenStream> {
         // apply compiler error if any
@@ -529,26 +1049,41 @@ impl L0Core {
                 .map_err(|e| compiler_error(&format!("Failed to generate compiler error {:?}", e)))?;
             stream.extend(add_tokens);
         }
-        // apply compiler warning if any
-        if !self.compiler_warning_message.is_empty() {
-            self.compiler_warning_message = format!("\n{}", self.compiler_warning_message);
-            let add = format!("mod _hira_generated_warning {{ #[deprecated(note = r#\"{}\"#)]pub fn hira_generated_warning() {{}}\n fn _hira_use_warning() {{ hira_generated_warning() }} }}", self.compiler_warning_message);
-            let add_tokens = TokenStream::from_str(&add)
-                .map_err(|e| compiler_error(&format!("Failed to generate compiler warning {:?}", e)))?;
-            stream.extend(add_tokens);
-        }
-
         // enables specialized dotenv functionality. unfortunately
         // due to the way include_str works, we need custom code for this in hira, rather than
         // making this a lvl2 functionality.
         // we read the provided file path, and load all env vars into a hashmap.
         // then we iterate over this module's outputs, and fill the resolved outputs
         // with the values from the .env file.
+        if !self.compiler_warning_message.is_empty() {
+            module.warnings.push(self.compiler_warning_message.clone());
+        }
         let mut dotenv_map = if let Some(location) = &self.dotenv_location {
-            Self::read_dotenv(&conf.cargo_directory, location)?
+            let (map, diagnostics) = Self::read_dotenv(&conf.cargo_directory, location)?;
+            for diagnostic in diagnostics {
+                if !self.compiler_warning_message.is_empty() {
+                    self.compiler_warning_message.push('\n');
+                }
+                let warning = format!("{location} {diagnostic}");
+                self.compiler_warning_message.push_str(&warning);
+                module.warnings.push(warning);
+            }
+            map
         } else {
             Default::default()
         };
+        if conf.settings.deny_warnings && !self.compiler_warning_message.is_empty() {
+            return Err(compiler_error(&format!("{}\n(warnings are denied by the deny_warnings setting)", self.compiler_warning_message)));
+        }
+        // apply compiler warning if any
+        if !self.compiler_warning_message.is_empty() {
+            self.compiler_warning_message = format!("\n{}", self.compiler_warning_message);
+            let add = format!("mod _hira_generated_warning {{ #[deprecated(note = r#\"{}\"#)]pub fn hira_generated_warning() {{}}\n fn _hira_use_warning() {{ hira_generated_warning() }} }}", self.compiler_warning_message);
+            let add_tokens = TokenStream::from_str(&add)
+                .map_err(|e| compiler_error(&format!("Failed to generate compiler warning {:?}", e)))?;
+            stream.extend(add_tokens);
+        }
+
 
         let lvl2_dep_name = module.level3_get_depends_on(module.lvl3_module_depends_on.as_ref())?;
         self.verify_outputs_and_set_defaults(conf, &lvl2_dep_name)?;
@@ -572,6 +1107,22 @@ impl L0Core {
                 }
             }
         }
+
+        // similar to dotenv, git info is read by hira rather than the wasm module
+        // because wasm modules cannot run processes.
+        if self.use_git_info {
+            if conf.git_info.is_none() {
+                conf.git_info = Some(GitInfo::read(&conf.cargo_directory));
+            }
+            if let Some(info) = &conf.git_info {
+                for (key, val) in info.outputs() {
+                    if let Some(existing) = module.resolved_outputs.get_mut(key) {
+                        *existing = val;
+                    }
+                }
+            }
+        }
+        module.validate_resolved_outputs(conf)?;
         Ok(())
     }
 }
@@ -609,13 +1160,13 @@ impl L0AppendFile {
     /// ```
     #[allow(dead_code)]
     pub fn append_to_file(&mut self, name: &str, label: &str, line: String) {
-        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line, filename: name.into(), unique: false, after: None });
+        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line, filename: name.into(), unique: false, after: None, prepend: false, remove: false, creator: self.current_module_name.clone() });
     }
 
     /// same as append_to_file, but the line will be unique within the label
     #[allow(dead_code)]
     pub fn append_to_file_unique(&mut self, name: &str, label: &str, line: String) {
-        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line, filename: name.into(), unique: true, after: None });
+        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line, filename: name.into(), unique: true, after: None, prepend: false, remove: false, creator: self.current_module_name.clone() });
     }
 
     /// like append_to_file, but given a search string, find that search string in that label
@@ -630,7 +1181,55 @@ impl L0AppendFile {
     /// ```
     #[allow(dead_code)]
     pub fn append_to_line(&mut self, name: &str, label: &str, search_str: String, after: String) {
-        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line: search_str, filename: name.into(), unique: false, after: Some(after) });
+        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line: search_str, filename: name.into(), unique: false, after: Some(after), prepend: false, remove: false, creator: self.current_module_name.clone() });
+    }
+
+    /// like append_to_file, but the line is inserted at the front of the label
+    /// rather than the end. Lines prepended by the same module keep their relative order. Example:
+    /// ```rust,ignore
+    /// # wasm module 1 does:
+    /// append_to_file("deploy.sh", "a", "echo deploying");
+    /// # wasm module 2 does:
+    /// prepend_to_file("deploy.sh", "a", "#!/usr/bin/env bash");
+    /// prepend_to_file("deploy.sh", "a", "set -e");
+    ///
+    /// # the output:
+    /// a
+    /// #!/usr/bin/env bash
+    /// set -e
+    /// echo deploying
+    /// ```
+    #[allow(dead_code)]
+    pub fn prepend_to_file(&mut self, name: &str, label: &str, line: String) {
+        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line, filename: name.into(), unique: false, after: None, prepend: true, remove: false, creator: self.current_module_name.clone() });
+    }
+
+    /// like append_to_line, but the `before` portion is inserted immediately before the search string. Example:
+    /// ```rust,ignore
+    /// append_to_line("hello.txt", "a", "world", "!");
+    /// prepend_to_line("hello.txt", "a", "world", "hello ");
+    ///
+    /// # the output:
+    /// hello world!
+    /// ```
+    #[allow(dead_code)]
+    pub fn prepend_to_line(&mut self, name: &str, label: &str, search_str: String, before: String) {
+        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line: search_str, filename: name.into(), unique: false, after: Some(before), prepend: true, remove: false, creator: self.current_module_name.clone() });
+    }
+
+    /// removes every line of the label that is exactly `line`. this applies to lines
+    /// written by this module, as well as lines written by modules that ran before this one.
+    /// if the line isn't present, this does nothing.
+    #[allow(dead_code)]
+    pub fn remove_from_file(&mut self, name: &str, label: &str, line: String) {
+        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line, filename: name.into(), unique: false, after: None, prepend: false, remove: true, creator: self.current_module_name.clone() });
+    }
+
+    /// like remove_from_file, but every line of the label that is exactly `old` is replaced with `new`.
+    /// if the line isn't present, this does nothing.
+    #[allow(dead_code)]
+    pub fn replace_in_file(&mut self, name: &str, label: &str, old: String, new: String) {
+        self.shared_output_data.push(SharedOutputEntry { label: label.into(), line: old, filename: name.into(), unique: false, after: Some(new), prepend: false, remove: true, creator: self.current_module_name.clone() });
     }
 }
 
@@ -645,6 +1244,23 @@ impl L0KvReader {
     pub fn insert(&mut self, key: String, val: String) {
         self.data.insert(key, val);
     }
+
+    /// get the value of a key that was set by this module, or by
+    /// any module that ran prior to this one.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.data.get(key).cloned()
+    }
+
+    /// returns all key/value pairs where the key starts with `prefix`.
+    /// the output is sorted by key.
+    pub fn get_with_prefix(&self, prefix: &str) -> Vec<(String, String)> {
+        let mut out: Vec<(String, String)> = self.data.iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
 }
 
 
@@ -659,6 +1275,13 @@ impl L0Core {
             lvl3_module_name: Default::default(),
             crate_name: Default::default(),
             dotenv_location: Default::default(),
+            use_git_info: false,
+            stack_name_prefix: Default::default(),
+            iam_name_prefix: Default::default(),
+            iam_permissions_boundary: Default::default(),
+            sibling_modules: Default::default(),
+            module_span: Default::default(),
+            input_sources: Default::default(),
         }
     }
 
@@ -687,6 +1310,13 @@ impl L0Core {
         self.dotenv_location = Some(location.to_string());
     }
 
+    /// niche function that enables git info functionality.
+    /// after wasm execution, hira runs git in CARGO_MANIFEST_DIR and fills the
+    /// lvl3 module's `GIT_COMMIT`, `GIT_DIRTY`, and `GIT_SHORT` outputs, if it has them.
+    pub fn use_git_info(&mut self) {
+        self.use_git_info = true;
+    }
+
     /// this is the name of the user's module where they are referencing your module.
     /// eg: if your module is `my_dependency`, then the user's module name would be `mymod3`
     /// in this example:
@@ -701,11 +1331,60 @@ impl L0Core {
         self.lvl3_module_name.clone()
     }
 
+    /// the names of all modules (of any level) of the current crate that were processed before the user's module, sorted by name.
+    /// Modules that are loaded from the cache of another crate are not included.
+    /// Only modules processed *before* the user's module are known, so a module that
+    /// summarizes the build (eg: generates an index) should be processed last,
+    /// eg: by listing the other modules in `#[hira(after = "...")]`.
+    pub fn sibling_modules(&self) -> Vec<&str> {
+        self.sibling_modules.iter().map(|x| x.as_str()).collect()
+    }
+
+    /// the prefix that should be prepended to the names of all stacks (or similar groupings
+    /// of resources). set by the user via the `stack_name_prefix` setting in hira.toml
+    pub fn stack_name_prefix(&self) -> String {
+        self.stack_name_prefix.clone()
+    }
+
+    /// the prefix that should be prepended to the names of all generated IAM roles and policies.
+    /// set by the user via the `iam_name_prefix` setting in hira.toml
+    pub fn iam_name_prefix(&self) -> String {
+        self.iam_name_prefix.clone()
+    }
+
+    /// the ARN of the permissions boundary that should be set on all generated IAM roles.
+    /// set by the user via the `iam_permissions_boundary` setting in hira.toml
+    pub fn iam_permissions_boundary(&self) -> Option<String> {
+        self.iam_permissions_boundary.clone()
+    }
+
+    /// whether the field of your Input was set by the user's lvl3 module, by a lvl2 module that
+    /// wraps your module, or was left as its default. Fields that are unknown
+    /// (eg: private fields) are `InputSource::Default`.
+    /// a field is considered set if it differs from its default value, so setting a field to its
+    /// default value is indistinguishable from not setting it.
+    pub fn input_source(&self, field: &str) -> InputSource {
+        self.input_sources.get(field).copied().unwrap_or(InputSource::Default)
+    }
+
+    // used by the code generator to set the sources of the Input fields
+    // of the module whose config is about to be called.
+    #[doc(hidden)]
+    pub fn set_input_sources(&mut self, sources: std::collections::HashMap<String, InputSource>) {
+        self.input_sources = sources;
+    }
+
     /// the name of the crate that will be compiled
     pub fn crate_name(&self) -> String {
         self.crate_name.clone()
     }
 
+    /// the location of the user's module in their source code.
+    /// useful for pointing users to the right place in error messages.
+    pub fn module_span_info(&self) -> SpanInfo {
+        self.module_span.clone()
+    }
+
     pub fn compiler_error(&mut self, err: &str) {
         if self.compiler_error_message.is_empty() {
             self.compiler_error_message = err.to_string();
@@ -722,7 +1401,7 @@ impl L0Core {
 #[output_and_stringify_basic_const(RUNTIME_IMPL)]
 impl L0RuntimeCreator {
     pub fn new() -> Self {
-        Self { current_module_name: Default::default(), runtimes: Default::default(), runtime_base_path: Default::default(), depends_on: Default::default() }
+        Self { current_module_name: Default::default(), runtimes: Default::default(), runtime_base_path: Default::default(), depends_on: Default::default(), output_variables: Default::default(), env_vars: Default::default() }
     }
     /// set a dependency of your runtime such that
     /// during build, your runtime (`runtime_name`) will be compiled prior to `depends_on`
@@ -734,7 +1413,10 @@ impl L0RuntimeCreator {
         }
     }
     pub fn get_full_runtime_path(&self, name: &str) -> String {
-        format!("{}/{}", self.runtime_base_path, name)
+        match self.runtimes.get(name) {
+            Some(runtime) if runtime.meta.cargo_lambda => format!("{}/lambda/{}/bootstrap", self.runtime_base_path, name),
+            _ => format!("{}/{}", self.runtime_base_path, name),
+        }
     }
     /// add code to the entrypoint of the runtime you define. runtime_name will become the
     /// name of an executable, and code is a line of code in the main function. Note
@@ -742,36 +1424,36 @@ impl L0RuntimeCreator {
     /// for example this is valid `my_function()`, same as `my_error_function().expect("error")`
     /// but this would not be valid: `let x = 2;`
     pub fn add_to_runtime(&mut self, runtime_name: &str, code: String) {
-        self.add_to_runtime_ex(runtime_name, code, RuntimeMeta { cargo_cmd: Default::default(), target: Default::default(), profile: Default::default(), no_tokio_async_runtime: Default::default() })
+        self.add_to_runtime_ex(runtime_name, code, RuntimeMeta::default())
     }
 
     /// same as `add_to_runtime`, but your line of code is ensured to be
     /// added to the beginning of the list of statements
     pub fn add_to_runtime_beginning(&mut self, runtime_name: &str, code: String) {
-        self.add_to_runtime_ex_beginning(runtime_name, code, RuntimeMeta { cargo_cmd: Default::default(), target: Default::default(), profile: Default::default(), no_tokio_async_runtime: Default::default() })
+        self.add_to_runtime_ex_beginning(runtime_name, code, RuntimeMeta::default())
     }
 
     /// same as `add_to_runtime`, but your line of code is ensured to be
     /// added to the end of the list of statements
     pub fn add_to_runtime_end(&mut self, runtime_name: &str, code: String) {
-        self.add_to_runtime_ex_end(runtime_name, code, RuntimeMeta { cargo_cmd: Default::default(), target: Default::default(), profile: Default::default(), no_tokio_async_runtime: Default::default() })
+        self.add_to_runtime_ex_end(runtime_name, code, RuntimeMeta::default())
     }
 
     /// same as `add_to_runtime`, but the line of code is guaranteed to be unique in the main function.
     /// use this when your module can be potentially called many times, and you wish to ensure
     /// that your entrypoint only executes this line of code once.
     pub fn add_to_runtime_unique(&mut self, runtime_name: &str, code: String) {
-        self.add_to_runtime_ex_unique(runtime_name, code, RuntimeMeta { cargo_cmd: Default::default(), target: Default::default(), profile: Default::default(), no_tokio_async_runtime: Default::default() })
+        self.add_to_runtime_ex_unique(runtime_name, code, RuntimeMeta::default())
     }
 
     /// same as `add_to_runtime_unique` but the line of code is added to the beginning
     pub fn add_to_runtime_unique_beginning(&mut self, runtime_name: &str, code: String) {
-        self.add_to_runtime_ex_unique_beginning(runtime_name, code, RuntimeMeta { cargo_cmd: Default::default(), target: Default::default(), profile: Default::default(), no_tokio_async_runtime: Default::default() })
+        self.add_to_runtime_ex_unique_beginning(runtime_name, code, RuntimeMeta::default())
     }
 
     /// same as `add_to_runtime_unique` but the line of code is added to the end
     pub fn add_to_runtime_unique_end(&mut self, runtime_name: &str, code: String) {
-        self.add_to_runtime_ex_unique_end(runtime_name, code, RuntimeMeta { cargo_cmd: Default::default(), target: Default::default(), profile: Default::default(), no_tokio_async_runtime: Default::default() })
+        self.add_to_runtime_ex_unique_end(runtime_name, code, RuntimeMeta::default())
     }
 
     /// same as `add_to_runtime`, but provide metadata for how this runtime should be compiled.
@@ -798,6 +1480,36 @@ impl L0RuntimeCreator {
         }
     }
 
+    /// set an env var when your runtime is built and run. In the build script this
+    /// prefixes the command that builds the runtime, eg: `AWS_REGION='us-west-2' cargo rustc ...`
+    /// if the same key is set multiple times, the last value wins.
+    pub fn add_runtime_env(&mut self, runtime_name: &str, key: &str, value: &str) {
+        self.env_vars.push(RuntimeEnvVar {
+            creator: self.current_module_name.clone(),
+            runtime_name: runtime_name.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    /// make the resolved value of an output of `module` available to your runtime code
+    /// as a `&str` variable called `var_name`. The value is resolved at build time
+    /// and defined at the start of the runtime. For example:
+    /// ```rust,ignore
+    /// add_output_to_runtime("hello", "my_bucket_module", "LOGICAL_BUCKET_NAME", "bucket_name");
+    /// add_to_runtime("hello", "println!(\"{}\", bucket_name)".to_string());
+    /// ```
+    /// `module` must have already been evaluated, or be the current module.
+    pub fn add_output_to_runtime(&mut self, runtime_name: &str, module: &str, output_key: &str, var_name: &str) {
+        self.output_variables.push(RuntimeOutputVariable {
+            creator: self.current_module_name.clone(),
+            runtime_name: runtime_name.to_string(),
+            module: module.to_string(),
+            output_key: output_key.to_string(),
+            var_name: var_name.to_string(),
+        });
+    }
+
     /// the `data_line` string will be added to a shared data array called `runtime_data`.
     /// your runtime code can then reference this `runtime_data` array. For example:
     /// ```rust,ignore
@@ -832,17 +1544,28 @@ impl L0RuntimeCreator {
 #[output_and_stringify_basic_const(CODE_READER_IMPL)]
 impl L0CodeReader {
     pub fn new() -> Self {
-        Self { current_module_name: Default::default(), function_signatures: Default::default() }
+        Self { current_module_name: Default::default(), function_signatures: Default::default(), struct_definitions: Default::default(), fn_names: Default::default() }
     }
     pub fn get_fn(&self, name: &str) -> Option<&FunctionSignature> {
         self.function_signatures.get(name)
     }
+    /// returns the fields of a struct defined in the user's module. requires a
+    /// `("CODE_READ", &["struct:{name}"])` capability.
+    pub fn get_struct(&self, name: &str) -> Option<&StructDefinition> {
+        self.struct_definitions.get(name)
+    }
+    /// returns the names of every function defined in the user's module, in the order they are defined.
+    /// requires a `("CODE_READ", &["fn:*"])` capability, otherwise this is empty. with this capability,
+    /// `get_fn` can also be used for any of these functions.
+    pub fn list_fns(&self) -> &[String] {
+        &self.fn_names
+    }
 }
 
 #[output_and_stringify_basic_const(CODE_WRITER_IMPL)]
 impl L0CodeWriter {
     pub fn new() -> Self {
-        Self { current_module_name: Default::default(), functions: Default::default() }
+        Self { current_module_name: Default::default(), functions: Default::default(), output_consts: Default::default(), static_asserts: Default::default() }
     }
     /// given a function signature and a function body, write
     /// this function inside the user's module. ie: this is internal
@@ -864,6 +1587,54 @@ impl L0CodeWriter {
     pub fn write_global_fn(&mut self, sig: String, body: String) {
         self.write_function(sig, body, "global");
     }
+    /// given a struct (or enum) header, eg: `#[derive(Debug)] pub enum MyError`, and the body
+    /// of the struct, write it outside the user's module. requires a
+    /// `("CODE_WRITE", &["struct_global:{name}"])` capability.
+    pub fn write_global_struct(&mut self, header: String, body: String) {
+        self.write_function(header, body, "struct_global");
+    }
+    /// same as write_global_struct, but the struct is written inside the user's module.
+    /// requires a `("CODE_WRITE", &["struct_module:{name}"])` capability.
+    pub fn write_internal_struct(&mut self, header: String, body: String) {
+        self.write_function(header, body, "struct_module");
+    }
+    /// given an impl header, eg: `impl From<std::io::Error> for MyError`, and the body
+    /// of the impl, write it outside the user's module. requires a
+    /// `("CODE_WRITE", &["impl_global:{name}"])` capability, where name is the type
+    /// being implemented (`MyError` in the example above).
+    pub fn write_global_impl(&mut self, header: String, body: String) {
+        self.write_function(header, body, "impl_global");
+    }
+    /// writes `pub const {key}: &str = "{value}";` inside the user's module, where value
+    /// is the resolved value of the user module's output `key`. requires a
+    /// `("CODE_WRITE", &["const_module:{key}"])` capability.
+    /// the output must have been set (eg: via `L0Core::set_output`), otherwise it is a compiler error.
+    pub fn write_output_const(&mut self, key: &str) {
+        self.output_consts.entry(self.current_module_name.to_string()).or_default().push(key.to_string());
+    }
+    /// writes `const _: () = assert!({expr});` at the crate scope, such that the user's crate fails
+    /// to compile if the expression is false, eg: `std::mem::size_of::<MyStruct>() <= 64`.
+    /// requires a `("CODE_WRITE", &["assert"])` capability.
+    pub fn write_static_assert(&mut self, expr: String) {
+        self.static_asserts.entry(self.current_module_name.to_string()).or_default().push(expr);
+    }
+}
+
+#[output_and_stringify_basic_const(SCRATCH_IMPL)]
+impl L0Scratch {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self { scratch_dir: Default::default() }
+    }
+
+    /// returns a directory that is unique to the current level3 module.
+    /// use this for intermediate files your module (or your runtimes) generate.
+    /// the directory is within the hira directory (`hira/tmp/<module>/`) and is cleared
+    /// at the start of every build. requires a `SCRATCH` capability, eg:
+    /// `pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("SCRATCH", &[""])];`
+    pub fn scratch_dir(&self) -> std::path::PathBuf {
+        std::path::PathBuf::from(&self.scratch_dir)
+    }
 }
 
 #[output_and_stringify_basic_const(LIBRARY_OBJ_IMPL)]
@@ -893,13 +1664,61 @@ impl LibraryObj {
             l0_code_reader: L0CodeReader::new(),
             l0_code_writer: L0CodeWriter::new(),
             l0_runtime_creator: L0RuntimeCreator::new(),
+            l0_scratch: L0Scratch::new(),
+        }
+    }
+}
+
+/// checks that `name` is a fully qualified domain name, eg: `www.mysite.com`.
+/// every label must be 1-63 characters of letters, digits, or `-`, and must not start or end with `-`.
+/// the name must have at least 2 labels, be at most 253 characters, and must not end with a `.`
+/// if `allow_wildcard` is true, the first label can be `*`, eg: `*.mysite.com`.
+/// this is available to modules as it is useful for validating domain names before they reach AWS.
+#[output_and_stringify_basic_const(DOMAIN_NAME_IMPL)]
+pub fn validate_domain_name(name: &str, allow_wildcard: bool) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Domain name must not be empty".to_string());
+    }
+    if name.ends_with('.') {
+        return Err(format!("Domain name '{}' must not end with a '.'", name));
+    }
+    if name.len() > 253 {
+        return Err(format!("Domain name '{}' is longer than 253 characters", name));
+    }
+    let labels: Vec<&str> = name.split('.').collect();
+    if labels.len() < 2 {
+        return Err(format!("Domain name '{}' must be fully qualified, eg: mysite.com", name));
+    }
+    for (i, label) in labels.iter().enumerate() {
+        if *label == "*" {
+            if !allow_wildcard {
+                return Err(format!("Domain name '{}' must not contain a wildcard", name));
+            }
+            if i != 0 {
+                return Err(format!("Domain name '{}' can only have a wildcard as its first label, eg: *.mysite.com", name));
+            }
+            continue;
+        }
+        if label.is_empty() {
+            return Err(format!("Domain name '{}' has an empty label", name));
+        }
+        if label.len() > 63 {
+            return Err(format!("Domain name '{}' has a label '{}' that is longer than 63 characters", name, label));
+        }
+        if let Some(c) = label.chars().find(|c| !c.is_ascii_alphanumeric() && *c != '-') {
+            return Err(format!("Domain name '{}' contains an invalid character '{}'. Only letters, digits, and '-' are allowed", name, c));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("Domain name '{}' has a label '{}' that starts or ends with '-'", name, label));
         }
     }
+    Ok(())
 }
 
 pub fn get_include_string() -> &'static [&'static str] {
     &[
         LIBRARY_OBJ_IMPL, FILE_IMPL, CORE_IMPL, KV_IMPL, CODE_READER_IMPL,
-        CODE_WRITER_IMPL, RUNTIME_IMPL,
+        CODE_WRITER_IMPL, RUNTIME_IMPL, SCRATCH_IMPL, DOMAIN_NAME_IMPL,
+        RUN_POLICY_IMPL,
     ]
 }
@@ -26,6 +26,338 @@ pub const HIRA_WASM_DIR_NAME: &'static str = "wasm_out";
 pub const HIRA_GEN_DIR_NAME: &'static str = "generated";
 pub const HIRA_MODULES_DIR_NAME: &'static str = "modules";
 pub const HIRA_RUNTIMES_DIR_NAME: &'static str = "runtimes";
+pub const HIRA_TMP_DIR_NAME: &'static str = "tmp";
+pub const UNKNOWN_CRATE_NAME: &'static str = "UNKNOWN_CRATE_NAME";
+pub const HIRA_TOML_FILE_NAME: &'static str = "hira.toml";
+
+/// a format that the stack templates can be exported to, in addition to the CloudFormation
+/// that is deployed. Exports are only meant for documentation/review.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    CfnJson,
+    CfnYaml,
+    TfJson,
+}
+
+impl ExportFormat {
+    pub const ALLOWED: &'static [&'static str] = &["cfn-json", "cfn-yaml", "tf-json"];
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "cfn-json" => Ok(Self::CfnJson),
+            "cfn-yaml" => Ok(Self::CfnYaml),
+            "tf-json" => Ok(Self::TfJson),
+            x => Err(format!("Unknown export format '{x}'. Expected one of: {}", Self::ALLOWED.join(", "))),
+        }
+    }
+
+    /// the suffix of the file that the stack is exported to, eg: `{stack name}.template.json`
+    pub fn file_suffix(&self) -> &'static str {
+        match self {
+            Self::CfnJson => "template.json",
+            Self::CfnYaml => "template.yaml",
+            Self::TfJson => "tf.json",
+        }
+    }
+}
+
+/// the kind of script that the build script is generated as. see `HiraSettings::shell`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildShell {
+    Bash,
+    PowerShell,
+}
+
+impl Default for BuildShell {
+    fn default() -> Self {
+        if cfg!(windows) { Self::PowerShell } else { Self::Bash }
+    }
+}
+
+impl BuildShell {
+    /// `powershell` and `pwsh` get a PowerShell script. Any other shell gets a bash script.
+    /// Defaults to PowerShell on windows, and bash everywhere else.
+    pub fn from_shell(shell: Option<&str>) -> Self {
+        match shell.map(|x| x.trim()) {
+            Some("powershell") | Some("pwsh") => Self::PowerShell,
+            Some(_) => Self::Bash,
+            None => Self::default(),
+        }
+    }
+
+    /// the file name of the build script, relative to the cargo directory
+    pub fn script_file_name(&self) -> &'static str {
+        match self {
+            Self::Bash => "build.sh",
+            Self::PowerShell => "build.ps1",
+        }
+    }
+}
+
+/// settings read from `hira.toml` in the root of the user's crate (next to Cargo.toml).
+/// every setting can also be set by the env var in its documentation. env vars take precedence over the file.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct HiraSettings {
+    /// `HIRA_DENY_WARNINGS`. if true, warnings emitted by modules fail the build.
+    pub deny_warnings: bool,
+    /// `HIRA_MODULE_CACHE_DIR`. where module source code is cached for other crates to use.
+    /// defaults to `$CARGO_HOME/hira/cached_modules`
+    pub module_cache_dir: Option<String>,
+    /// `HIRA_OFFLINE`. if true, runtimes are built without network access (`CARGO_NET_OFFLINE=true`)
+    pub offline: bool,
+    /// `HIRA_STACK_NAME_PREFIX`. prepended to the name of every stack.
+    /// modules can read this via `L0Core::stack_name_prefix`
+    pub stack_name_prefix: String,
+    /// `HIRA_SHELL`. the shell that the build script is generated for. `powershell` (or `pwsh`) generates
+    /// a PowerShell script at `build.ps1`. Any other shell generates a bash script at `build.sh`
+    /// that starts with `#!/usr/bin/env {shell}`. If not set, it is PowerShell on windows, and bash without a shebang everywhere else.
+    pub shell: Option<String>,
+    /// `HIRA_IAM_NAME_PREFIX`. prepended to the name of every IAM role and policy that modules generate.
+    /// modules can read this via `L0Core::iam_name_prefix`
+    pub iam_name_prefix: String,
+    /// `HIRA_IAM_PERMISSIONS_BOUNDARY`. the ARN of a managed policy that is set as the
+    /// permissions boundary of every IAM role that modules generate.
+    /// modules can read this via `L0Core::iam_permissions_boundary`
+    pub iam_permissions_boundary: Option<String>,
+    /// `HIRA_EXPORT_FORMATS` (comma separated). the formats that hira_cli exports the stack templates
+    /// to in `hira/generated`. see `ExportFormat::ALLOWED`
+    pub export_formats: Vec<ExportFormat>,
+    /// `HIRA_PRESERVE_BUILD_SCRIPT`. if true, the build script is not deleted on every run.
+    /// only the blocks that hira wrote (between `# hira:begin` and `# hira:end` lines) are updated,
+    /// so lines that the user added to the script are kept.
+    pub preserve_build_script: bool,
+}
+
+impl HiraSettings {
+    /// `toml_str` is the contents of hira.toml, if it exists. `get_env` returns the value of an env var
+    pub fn load(toml_str: Option<&str>, get_env: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut out = Self::default();
+        #[cfg(feature = "wasm")]
+        if let Some(toml_str) = toml_str {
+            let table = toml_str.parse::<Table>()
+                .map_err(|e| format!("Failed to parse {HIRA_TOML_FILE_NAME}\n{e}"))?;
+            for (key, value) in table.iter() {
+                let invalid = || format!("Invalid value for '{key}' in {HIRA_TOML_FILE_NAME}: {value}");
+                match (key.as_str(), value) {
+                    ("deny_warnings", toml::Value::Boolean(b)) => out.deny_warnings = *b,
+                    ("offline", toml::Value::Boolean(b)) => out.offline = *b,
+                    ("preserve_build_script", toml::Value::Boolean(b)) => out.preserve_build_script = *b,
+                    ("module_cache_dir", toml::Value::String(s)) => out.module_cache_dir = Some(s.to_string()),
+                    ("stack_name_prefix", toml::Value::String(s)) => out.stack_name_prefix = s.to_string(),
+                    ("shell", toml::Value::String(s)) => out.shell = Some(s.to_string()),
+                    ("iam_name_prefix", toml::Value::String(s)) => out.iam_name_prefix = s.to_string(),
+                    ("iam_permissions_boundary", toml::Value::String(s)) => out.iam_permissions_boundary = Some(s.to_string()),
+                    ("export_formats", toml::Value::Array(formats)) => {
+                        out.export_formats.clear();
+                        for format in formats {
+                            let format = format.as_str().ok_or_else(invalid)?;
+                            out.export_formats.push(ExportFormat::parse(format)?);
+                        }
+                    }
+                    ("deny_warnings", _) | ("offline", _) | ("module_cache_dir", _) | ("stack_name_prefix", _) | ("shell", _) |
+                    ("iam_name_prefix", _) | ("iam_permissions_boundary", _) | ("export_formats", _) |
+                    ("preserve_build_script", _) => {
+                        return Err(invalid());
+                    }
+                    _ => return Err(format!("Unknown setting '{key}' in {HIRA_TOML_FILE_NAME}")),
+                }
+            }
+        }
+        #[cfg(not(feature = "wasm"))]
+        let _ = toml_str;
+        let parse_bool = |name: &str, val: String| -> Result<bool, String> {
+            match val.as_str() {
+                "true" | "1" => Ok(true),
+                "false" | "0" => Ok(false),
+                _ => Err(format!("Invalid value for env var {name}: '{val}'. Expected true or false")),
+            }
+        };
+        if let Some(val) = get_env("HIRA_DENY_WARNINGS") {
+            out.deny_warnings = parse_bool("HIRA_DENY_WARNINGS", val)?;
+        }
+        if let Some(val) = get_env("HIRA_OFFLINE") {
+            out.offline = parse_bool("HIRA_OFFLINE", val)?;
+        }
+        if let Some(val) = get_env("HIRA_PRESERVE_BUILD_SCRIPT") {
+            out.preserve_build_script = parse_bool("HIRA_PRESERVE_BUILD_SCRIPT", val)?;
+        }
+        if let Some(val) = get_env("HIRA_MODULE_CACHE_DIR") {
+            out.module_cache_dir = Some(val);
+        }
+        if let Some(val) = get_env("HIRA_STACK_NAME_PREFIX") {
+            out.stack_name_prefix = val;
+        }
+        if let Some(val) = get_env("HIRA_SHELL") {
+            out.shell = Some(val);
+        }
+        if let Some(val) = get_env("HIRA_IAM_NAME_PREFIX") {
+            out.iam_name_prefix = val;
+        }
+        if let Some(val) = get_env("HIRA_IAM_PERMISSIONS_BOUNDARY") {
+            out.iam_permissions_boundary = Some(val);
+        }
+        if let Some(val) = get_env("HIRA_EXPORT_FORMATS") {
+            out.export_formats = val.split(',').filter(|x| !x.trim().is_empty())
+                .map(ExportFormat::parse).collect::<Result<_, _>>()?;
+        }
+        Ok(out)
+    }
+
+    /// the kind of script that the build script is generated as, according to `shell`
+    pub fn build_shell(&self) -> BuildShell {
+        BuildShell::from_shell(self.shell.as_deref())
+    }
+}
+
+/// runs `bash -n` on the script at `path` to catch syntax errors (eg: from bad quoting)
+/// before the user tries to run it. Does nothing on non-unix platforms,
+/// or if bash is not available.
+pub fn check_shell_syntax(path: &str) -> Result<(), String> {
+    if !cfg!(unix) {
+        return Ok(());
+    }
+    let output = match std::process::Command::new("bash").arg("-n").arg(path).output() {
+        Ok(o) => o,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Failed to run bash -n on {path}\n{:?}", e)),
+    };
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(format!("Generated script {path} has a syntax error:\n{}", String::from_utf8_lossy(&output.stderr).trim()))
+}
+
+/// shared files are written relative to the gen directory. nested paths like `config/app.json`
+/// are allowed, but empty components, `.`, `..`, and absolute paths are not.
+pub fn validate_shared_file_path(file_name: &str) -> Result<(), String> {
+    if file_name.starts_with('/') || file_name.starts_with('\\') || file_name.contains(':') {
+        return Err(format!("Shared file path {:?} must be relative", file_name));
+    }
+    for component in file_name.split(|c| c == '/' || c == '\\') {
+        if component.is_empty() || component == "." || component == ".." {
+            return Err(format!("Shared file path {:?} contains an invalid component {:?}", file_name, component));
+        }
+    }
+    Ok(())
+}
+
+/// returns the path that the shared file `file_name` is written to. `file_name` must already be validated
+/// by `validate_shared_file_path`. This is checked before creating or writing anything: none of the paths
+/// from `shared_dir` to the file can be a symlink, and the deepest ancestor of the file that already exists
+/// must resolve to somewhere inside `shared_dir`.
+pub fn resolve_shared_file_path(shared_dir: &std::path::Path, file_name: &str) -> Result<std::path::PathBuf, String> {
+    let canonical_shared_dir = shared_dir.canonicalize()
+        .map_err(|e| format!("Failed to resolve shared file directory {:?}\n{:?}", shared_dir, e))?;
+    let mut path = shared_dir.to_path_buf();
+    let mut deepest_existing = shared_dir.to_path_buf();
+    for component in file_name.split(|c| c == '/' || c == '\\') {
+        path.push(component);
+        match std::fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                return Err(format!("Shared file path {:?} cannot contain symlinks, but {:?} is a symlink", file_name, path));
+            }
+            Ok(_) => deepest_existing = path.clone(),
+            Err(_) => break,
+        }
+    }
+    let is_contained = deepest_existing.canonicalize()
+        .map(|x| x.starts_with(&canonical_shared_dir))
+        .unwrap_or(false);
+    if !is_contained {
+        return Err(format!("Shared file path {:?} resolves to outside of {:?}", file_name, shared_dir));
+    }
+    Ok(shared_dir.join(file_name))
+}
+
+/// wraps `contents` in `# hira:begin {name}` and `# hira:end {name}` lines, and replaces the existing block
+/// of the same name in `script` with it. if `script` does not have the block yet, it is appended,
+/// or if `at_start` is true, it is inserted at the start (after the shebang, if any).
+/// everything outside of the block is left as is.
+pub fn upsert_build_script_block(script: &str, name: &str, contents: &str, at_start: bool) -> String {
+    let begin = format!("# hira:begin {name}");
+    let end = format!("# hira:end {name}");
+    let mut block = format!("{begin}\n{contents}");
+    if !block.ends_with('\n') {
+        block.push('\n');
+    }
+    block.push_str(&end);
+    block.push('\n');
+
+    let mut out = String::with_capacity(script.len() + block.len());
+    let mut in_block = false;
+    let mut replaced = false;
+    for line in script.lines() {
+        if in_block {
+            if line.trim_end() == end {
+                in_block = false;
+            }
+            continue;
+        }
+        if line.trim_end() == begin && !replaced {
+            out.push_str(&block);
+            in_block = true;
+            replaced = true;
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    if replaced {
+        return out;
+    }
+    if !at_start {
+        out.push_str(&block);
+        return out;
+    }
+    match out.strip_prefix("#!").and_then(|_| out.split_once('\n')) {
+        Some((shebang, rest)) => format!("{shebang}\n{block}{rest}"),
+        None => format!("{block}{out}"),
+    }
+}
+
+/// removes every block from `script` whose name starts with `name_prefix`, eg: `runtime ` removes the blocks
+/// of all runtimes. everything outside of those blocks is left as is.
+pub fn remove_build_script_blocks(script: &str, name_prefix: &str) -> String {
+    let begin_prefix = format!("# hira:begin {name_prefix}");
+    let mut out = String::with_capacity(script.len());
+    let mut block_end: Option<String> = None;
+    for line in script.lines() {
+        if let Some(end) = &block_end {
+            if line.trim_end() == end {
+                block_end = None;
+            }
+            continue;
+        }
+        if let Some(name) = line.trim_end().strip_prefix("# hira:begin ").filter(|_| line.starts_with(&begin_prefix)) {
+            block_end = Some(format!("# hira:end {name}"));
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// resolves the name of the binary that cargo will output for the crate being compiled.
+/// prefers the bin name, then the crate name, and finally falls back to the package name
+/// from the crate's Cargo.toml (see `get_package_name`), as neither env var is set for some targets/build setups.
+pub fn resolve_crate_name(bin_name: Option<String>, crate_name: Option<String>, package_name: Option<String>) -> Result<String, String> {
+    for name in [bin_name, crate_name, package_name].into_iter().flatten() {
+        if !name.is_empty() {
+            return Ok(name);
+        }
+    }
+    Err("Failed to resolve crate name. Neither CARGO_BIN_NAME nor CARGO_CRATE_NAME were set, and no package name was found in Cargo.toml".to_string())
+}
+
+/// returns the `name` of the `[package]` section of a Cargo.toml
+#[cfg(feature = "wasm")]
+pub fn get_package_name(cargo_toml: &Table) -> Option<String> {
+    match cargo_toml.get("package").and_then(|x| x.get("name")) {
+        Some(toml::Value::String(s)) => Some(s.to_string()),
+        _ => None,
+    }
+}
 
 
 #[derive(Default, Debug)]
@@ -39,6 +371,10 @@ pub struct HiraConfig {
     pub runtime_directory: String,
     pub logfile: String,
     pub crate_name: String,
+    /// path of the file that defines the module that is about to be processed. empty if unknown.
+    /// the macro sets this from the span of the invocation, and the CLI sets it before analyzing each file.
+    /// see `HiraModule2::source_file`
+    pub source_file: String,
     /// this directory is in the user's target/ folder.
     /// its purpose is to cache the module source code such that
     /// if the user loads a dependency from another crate, as long as that
@@ -48,12 +384,22 @@ pub struct HiraConfig {
 
     pub should_output_build_script: bool,
     pub should_do_file_ops: bool,
+    /// if true, level2 modules are not written to the module cache.
+    /// used when checking code that might not be saved yet, eg: from an editor.
+    pub dont_cache_modules: bool,
     pub known_cargo_dependencies: HashSet<String>,
     pub shared_data: HashMap<String, String>,
     #[cfg(feature = "wasm")]
     pub shared_file_data: Vec<MapEntry<MapEntry<String>>>,
+    /// index of every file in `shared_file_data`, and the index of every label within that file.
+    #[cfg(feature = "wasm")]
+    pub shared_file_index: HashMap<String, (usize, HashMap<String, usize>)>,
     #[cfg(not(feature = "wasm"))]
     pub shared_file_data: Vec<()>,
+    /// a map of (file name, label) to the names of the modules that
+    /// have written to that label. used to warn when several modules
+    /// write to the same label.
+    pub shared_file_contributors: HashMap<(String, String), Vec<String>>,
     /// a map of module name to a string containing callback code that should
     /// run prior to any invocation of this module.
     pub default_callbacks: HashMap<String, String>,
@@ -71,17 +417,54 @@ pub struct HiraConfig {
     #[cfg(not(feature = "wasm"))]
     pub runtimes: HashMap<String, String>,
 
-    /// the key is the dependency runtime name, and the value
-    /// are the runtime names that depend on that dependency
+    /// the key is a runtime name, and the value are the runtime names that it depends on,
+    /// ie: that must be compiled before it. See `L0RuntimeCreator::depends_on`
     pub runtime_depends_on: HashMap<String, Vec<String>>,
 
+    /// a map of runtime names to a list of (variable name, value)
+    /// that are defined at the start of the runtime.
+    pub runtime_variables: HashMap<String, Vec<(String, String)>>,
+
+    /// a map of runtime names to a list of (env var name, value)
+    /// that are set when building and running the runtime.
+    pub runtime_env: HashMap<String, Vec<(String, String)>>,
+
     pub has_deleted_build_script: bool,
+    /// set once the scratch directories from a previous build
+    /// have been removed.
+    pub has_cleaned_scratch_directory: bool,
+
+    /// the names and priorities of the modules that were expanded in the current pass, in the order
+    /// they were expanded. Unlike `modules2`, this doesn't contain modules loaded from the cache, or
+    /// modules from a previous expansion of the same crate. See `HiraModule2::verify_priority`
+    pub expanded_modules: Vec<(String, Option<i64>)>,
+
+    /// settings from hira.toml and env vars. see `HiraSettings`
+    pub settings: HiraSettings,
+    /// set if hira.toml or one of the setting env vars is invalid. reported when the first module is processed.
+    pub settings_error: Option<String>,
+
+    /// read the first time a module uses git info, and then reused for the rest of the build.
+    pub git_info: Option<level0::GitInfo>,
+
+    /// if set, this is called with (module name, output key, output value) for every
+    /// output of a module once its outputs are resolved, and before other modules or runtimes
+    /// can use them. Returning an error fails the build with that error.
+    pub output_validator: Option<fn(&str, &str, &str) -> Result<(), String>>,
 }
 
 impl HiraConfig {
     pub fn get_mod2(&self, name: &str) -> Option<&module_loading::HiraModule2> {
         self.modules2.get(name)
     }
+    /// records that the module is being expanded. A module that was already expanded means
+    /// the crate is being expanded again (eg: by an IDE), so the previous expansion is forgotten.
+    pub fn add_expanded_module(&mut self, name: &str, priority: Option<i64>) {
+        if self.expanded_modules.iter().any(|(x, _)| x == name) {
+            self.expanded_modules.clear();
+        }
+        self.expanded_modules.push((name.to_string(), priority));
+    }
     pub fn insert_evaluated_outputs_for(&mut self, mod_names: &Vec<String>) -> Result<(), TokenStream> {
         for name in mod_names.iter() {
             if let Some(mut existing) = self.modules2.remove(name) {
@@ -93,7 +476,25 @@ impl HiraConfig {
     }
     #[cfg(feature = "wasm")]
     fn add_to_runtime(&mut self, runtime_name: String, meta: RuntimeMeta, runtime_code: String, unique_code: bool) {
-        if let Some((_, _, existing, _)) = self.runtimes.get_mut(&runtime_name) {
+        if let Some((_, existing_meta, existing, _)) = self.runtimes.get_mut(&runtime_name) {
+            for feature in meta.features {
+                if !existing_meta.features.contains(&feature) {
+                    existing_meta.features.push(feature);
+                }
+            }
+            for feature in meta.cargo_features {
+                if !existing_meta.cargo_features.contains(&feature) {
+                    existing_meta.cargo_features.push(feature);
+                }
+            }
+            existing_meta.no_default_features |= meta.no_default_features;
+            existing_meta.cargo_lambda |= meta.cargo_lambda;
+            if meta.run_policy == RunPolicy::Once {
+                existing_meta.run_policy = RunPolicy::Once;
+            }
+            if meta.zip_level.is_some() {
+                existing_meta.zip_level = meta.zip_level;
+            }
             if unique_code {
                 // if user wants this line to be unique, then only add it if
                 // it doesnt already exist
@@ -107,6 +508,25 @@ impl HiraConfig {
             self.runtimes.insert(runtime_name, (false, meta, vec![runtime_code], vec![]));
         }
     }
+    fn add_runtime_variable(&mut self, runtime_name: &str, var_name: String, value: String) -> Result<(), TokenStream> {
+        let existing = self.runtime_variables.entry(runtime_name.to_string()).or_default();
+        if let Some((_, existing_value)) = existing.iter().find(|(name, _)| *name == var_name) {
+            if *existing_value != value {
+                return Err(compiler_error(&format!("Runtime {} variable '{}' was already defined with a different value", runtime_name, var_name)));
+            }
+            return Ok(());
+        }
+        existing.push((var_name, value));
+        Ok(())
+    }
+    /// if the env var was already set for this runtime, the last value wins.
+    fn add_runtime_env(&mut self, runtime_name: &str, key: String, value: String) {
+        let existing = self.runtime_env.entry(runtime_name.to_string()).or_default();
+        match existing.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, existing_value)) => *existing_value = value,
+            None => existing.push((key, value)),
+        }
+    }
     #[cfg(feature = "wasm")]
     fn set_runtime_data(&mut self, runtime_name: &str, data: Vec<String>) {
         if let Some((_, _, _, existing_data)) = self.runtimes.get_mut(runtime_name) {
@@ -116,6 +536,7 @@ impl HiraConfig {
     pub fn new() -> Self {
         let mut out = Self::default();
         out.set_directories();
+        out.load_settings(|name| std::env::var(name).ok());
         #[cfg(feature = "wasm")]
         out.load_cargo_toml();
         out.set_should_do_file_ops();
@@ -189,34 +610,34 @@ impl HiraConfig {
     fn merge_shared_files(
         &mut self,
         wasm_module_name: &str,
-        data: Vec<MapEntry<MapEntry<(bool, String, Option<String>)>>>
+        data: Vec<MapEntry<MapEntry<SharedOutputEntry>>>
     ) -> Result<(), TokenStream> {
         // merge the current data with the previous data
         for entry in data {
             let file_name = entry.key;
             // this is how we enforce that shared files only get output
-            // to the shared directory. basically: it can only be a file name, not a path.
-            if file_name.contains("/") || file_name.contains("\\") {
-                return Err(compiler_error(&format!("Wasm module '{wasm_module_name}' attempted to output a shared file outside the shared file directory {:?}", file_name)));
+            // to the shared directory. nested paths are allowed, but only relative ones
+            // without any `..` components. this is checked again against the real
+            // directory when the files are written out.
+            if let Err(e) = validate_shared_file_path(&file_name) {
+                return Err(compiler_error(&format!("Wasm module '{wasm_module_name}' attempted to output a shared file outside the shared file directory {:?}\n{e}", file_name)));
             }
             for file_data in entry.lines {
                 let label = file_data.key;
 
-                let label_entry = if let Some(e) = self.shared_file_data.iter_mut().find(|x| x.key == file_name) {
-                    if let Some(l) = e.lines.iter_mut().find(|x| x.key == label) {
-                        l
-                    } else {
-                        let index = e.lines.len();
-                        e.lines.push(MapEntry { key: label.clone(), lines: vec![] });
-                        &mut e.lines[index]
-                    }
-                } else {
-                    let index = self.shared_file_data.len();
-                    self.shared_file_data.push(MapEntry { key: file_name.clone(), lines: vec![MapEntry { key: label.clone(), lines: vec![] }] });
-                    &mut self.shared_file_data[index].lines[0]
-                };
+                let (file_index, label_index) = self.get_shared_label_index(&file_name, &label);
+                let label_entry = &mut self.shared_file_data[file_index].lines[label_index];
 
-                for (unique, line, after) in file_data.lines {
+                // lines prepended by this module are inserted in order, starting at the front of the label
+                let mut prepend_index = 0;
+                for SharedOutputEntry { unique, line, after, prepend, remove, .. } in file_data.lines {
+                    if remove {
+                        let changed = Self::remove_shared_line(&mut label_entry.lines, &line, after);
+                        if !changed {
+                            print_debug(&self.logfile, format!("Module '{wasm_module_name}' attempted to remove/replace line {:?} from file {} label {}, but it was not found\n", line, file_name, label));
+                        }
+                        continue;
+                    }
                     if unique {
                         if !label_entry.lines.contains(&line) {
                             label_entry.lines.push(line);
@@ -226,18 +647,26 @@ impl HiraConfig {
                     // if after is provided, then treat 'line' as a search string, and
                     // try to insert the after portion immediately after the search string.
                     // if not found, output a newline concatenation of line+after
+                    // if prepend is also set, the after portion is inserted immediately before the search string.
                     if let Some(after) = after {
                         let found_str = label_entry.lines.iter_mut()
-                            .find_map(|l| l.find(&line).map(|index| (l, index + line.len())));
+                            .find_map(|l| l.find(&line).map(|index| if prepend { (l, index) } else { (l, index + line.len()) }));
                         if let Some((found_str, index)) = found_str {
                             // found, now insert the after portion at the index
                             found_str.insert_str(index, &after);
+                        } else if prepend {
+                            label_entry.lines.push(format!("{after}{line}"));
                         } else {
                             // not found, just concatenate and output
                             label_entry.lines.push(format!("{line}{after}"));
                         }
                         continue;
                     }
+                    if prepend {
+                        label_entry.lines.insert(prepend_index, line);
+                        prepend_index += 1;
+                        continue;
+                    }
                     // otherwise, its just a normal line entry
                     label_entry.lines.push(line);
                 }
@@ -246,15 +675,55 @@ impl HiraConfig {
         Ok(())
     }
 
+    /// returns the index of the file in `shared_file_data`, and the index of the label within that file.
+    /// the file and label are created if they don't exist yet.
+    #[cfg(feature = "wasm")]
+    fn get_shared_label_index(&mut self, file_name: &str, label: &str) -> (usize, usize) {
+        let file_data = &mut self.shared_file_data;
+        let (file_index, label_indices) = self.shared_file_index.entry(file_name.to_string()).or_insert_with(|| {
+            file_data.push(MapEntry { key: file_name.to_string(), lines: vec![] });
+            (file_data.len() - 1, HashMap::new())
+        });
+        let labels = &mut file_data[*file_index].lines;
+        let label_index = *label_indices.entry(label.to_string()).or_insert_with(|| {
+            labels.push(MapEntry { key: label.to_string(), lines: vec![] });
+            labels.len() - 1
+        });
+        (*file_index, label_index)
+    }
+
+    /// removes all lines that equal `line`, or replaces them with `replacement` if provided.
+    /// returns whether anything changed.
+    #[cfg(feature = "wasm")]
+    fn remove_shared_line(lines: &mut Vec<String>, line: &str, replacement: Option<String>) -> bool {
+        let mut changed = false;
+        match replacement {
+            Some(replacement) => {
+                for existing in lines.iter_mut().filter(|x| *x == line) {
+                    *existing = replacement.clone();
+                    changed = true;
+                }
+            }
+            None => {
+                let len_before = lines.len();
+                lines.retain(|x| x != line);
+                changed = lines.len() != len_before;
+            }
+        }
+        changed
+    }
+
     #[cfg(feature = "wasm")]
     fn iterate_map_entry(
-        file_entry: &mut MapEntry<MapEntry<String>>,
+        file_entry: &MapEntry<MapEntry<String>>,
         mut cb: impl FnMut(&str) -> Result<(), TokenStream>
     ) -> Result<(), TokenStream> {
-        // sort the labels alphabetically
-        file_entry.lines.sort_by(|a, b| a.key.cmp(&b.key));
+        // sort the labels alphabetically. the labels themselves are not reordered
+        // because `shared_file_index` refers to them by index.
+        let mut labels: Vec<&MapEntry<String>> = file_entry.lines.iter().collect();
+        labels.sort_by(|a, b| a.key.cmp(&b.key));
 
-        for label_entry in file_entry.lines.iter() {
+        for label_entry in labels {
             let label = &label_entry.key;
             cb(label)?;
             cb("\n")?;
@@ -272,8 +741,8 @@ impl HiraConfig {
     #[allow(dead_code)]
     #[cfg(debug_assertions)]
     fn get_shared_file_data(&mut self, name: &str) -> Option<String> {
-        let entry = self.shared_file_data.iter_mut()
-            .find(|x| x.key == name)?;
+        let (index, _) = self.shared_file_index.get(name)?;
+        let entry = &self.shared_file_data[*index];
         let mut out = "".to_string();
         let _ = Self::iterate_map_entry(entry, |s| {
             out.push_str(s);
@@ -286,7 +755,7 @@ impl HiraConfig {
     fn output_shared_files(
         &mut self,
         wasm_module_name: &str,
-        data: Vec<MapEntry<MapEntry<(bool, String, Option<String>)>>>
+        data: Vec<MapEntry<MapEntry<SharedOutputEntry>>>
     ) -> Result<(), TokenStream> {
         // set the wasm_module's data into the global shared data object.
         self.merge_shared_files(wasm_module_name, data)?;
@@ -297,12 +766,18 @@ impl HiraConfig {
         }
 
         // create dir if it doesnt exist yet
-        let shared_dir = &self.gen_directory;
-        let _ = std::fs::create_dir(&shared_dir);
+        let shared_dir = std::path::Path::new(&self.gen_directory);
+        let _ = std::fs::create_dir_all(&shared_dir);
         // iterate the shared data object and output to the shared file(s)
-        for file_entry in self.shared_file_data.iter_mut() {
+        for file_entry in self.shared_file_data.iter() {
             let file_name = &file_entry.key;
-            let file_path = format!("{shared_dir}/{file_name}");
+            // this catches anything the lexical check missed, eg: symlinks.
+            let file_path = resolve_shared_file_path(shared_dir, file_name)
+                .map_err(|e| compiler_error(&format!("Wasm module '{wasm_module_name}' attempted to output a shared file outside the shared file directory {:?}\n{e}", file_name)))?;
+            if let Some(parent) = file_path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| compiler_error(&format!("Failed to create directory while running module '{wasm_module_name}' {:?}\nError:\n{:?}", parent, e)))?;
+            }
             let mut out_f = std::fs::File::create(&file_path)
                 .map_err(|e| compiler_error(&format!("Failed to create/open file while running module '{wasm_module_name}' {:?}\nError:\n{:?}", file_path, e)))?;
 
@@ -315,40 +790,94 @@ impl HiraConfig {
         Ok(())
     }
 
+    /// returns the RUSTFLAGS, the arguments to pass to cargo, and the location of the compiled
+    /// binary relative to the target directory, excluding the crate name.
+    pub fn get_build_runtime_args(meta: &RuntimeMeta, runtime_name: &str, target_dir: &str) -> (String, Vec<String>, String) {
+        let profile = if meta.profile.is_empty() { "dev" } else { meta.profile.as_str() };
+        let mut rustflags = format!("--cfg {runtime_name} -C strip=symbols");
+        for feature in meta.features.iter() {
+            rustflags.push_str(&format!(" --cfg {feature}"));
+        }
+        let mut args = if meta.cargo_lambda {
+            vec![
+                "lambda".to_string(), "build".to_string(),
+                "--bin".to_string(), runtime_name.to_string(),
+                "--profile".to_string(), profile.to_string(),
+                "--target-dir".to_string(), target_dir.to_string(),
+                "--lambda-dir".to_string(), format!("{target_dir}/lambda"),
+            ]
+        } else {
+            vec![
+                "rustc".to_string(), "--crate-type=bin".to_string(),
+                "--profile".to_string(), profile.to_string(),
+                "--target-dir".to_string(), target_dir.to_string(),
+            ]
+        };
+        let mut target_location = "".to_string();
+        if !meta.target.is_empty() {
+            args.push("--target".to_string());
+            args.push(meta.target.to_string());
+            target_location = format!("{}/", meta.target);
+        }
+        if !meta.cargo_features.is_empty() {
+            args.push("--features".to_string());
+            args.push(meta.cargo_features.join(","));
+        }
+        if meta.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        if meta.cargo_lambda {
+            return (rustflags, args, format!("lambda/{runtime_name}"));
+        }
+        // cargo outputs the dev profile into the debug directory
+        let location = if profile == "dev" { "debug" } else { profile };
+        (rustflags, args, format!("{target_location}{location}"))
+    }
+
+    /// whether the runtime is compiled into a windows executable, which has an `.exe` suffix
+    pub fn is_windows_target(meta: &RuntimeMeta) -> bool {
+        if meta.target.is_empty() { cfg!(windows) } else { meta.target.contains("windows") }
+    }
+
+    /// the file name of the executable that the build outputs into the location returned by `get_build_runtime_args`
+    pub fn get_built_executable_name(meta: &RuntimeMeta, crate_name: &str) -> String {
+        if meta.cargo_lambda {
+            "bootstrap".to_string()
+        } else if Self::is_windows_target(meta) {
+            format!("{crate_name}.exe")
+        } else {
+            crate_name.to_string()
+        }
+    }
+
+    /// where the runtime's executable is copied to after it is built. must match `L0RuntimeCreator::get_full_runtime_path`
+    pub fn get_runtime_output_path(runtime_directory: &str, runtime_name: &str, meta: &RuntimeMeta) -> String {
+        if meta.cargo_lambda {
+            format!("{runtime_directory}/lambda/{runtime_name}/bootstrap")
+        } else if Self::is_windows_target(meta) {
+            format!("{runtime_directory}/{runtime_name}.exe")
+        } else {
+            format!("{runtime_directory}/{runtime_name}")
+        }
+    }
+
     #[cfg(feature = "wasm")]
     pub fn run_build_runtime_cmd(
         meta: &RuntimeMeta,
         runtime_name: &str,
         target_dir: &str, crate_name: &str,
         output_file: &str,
+        env: &[(String, String)],
         output_wrapper: Option<fn(String)>,
     ) -> Result<(), String> {
         use std::{process::{Command, Stdio}, io::BufRead};
 
         let cargo_cmd = if meta.cargo_cmd.is_empty() { "cargo" } else { meta.cargo_cmd.as_str() };
-        let profile = if meta.profile.is_empty() { "dev" } else { meta.profile.as_str() };
-
-        // let mut a
-        let rustflags = format!("--cfg {runtime_name} -C strip=symbols");
-        let mut args = vec![
-            "rustc", "--crate-type=bin",
-            "--profile", profile,
-            "--target-dir", target_dir,
-        ];
-        let mut target_location = "".to_string();
-        if !meta.target.is_empty() {
-            args.push("--target");
-            args.push(&meta.target);
-            target_location = format!("{}/", meta.target);
-        }
-        let location = if meta.profile.is_empty() {
-            "debug"
-        } else {
-            meta.profile.as_str()
-        };
+        let (rustflags, args, location) = Self::get_build_runtime_args(meta, runtime_name, target_dir);
         let mut cmd_out = Command::new(cargo_cmd)
             .env("RUSTFLAGS", &rustflags)
             .env("CARGO_WASMTYPEGEN_FILEOPS", "0")
+            .envs(env.iter().map(|(k, v)| (k, v)))
             .stdout(Stdio::null())
             .stderr(Stdio::piped())
             .args(args).spawn()
@@ -371,7 +900,10 @@ impl HiraConfig {
             return Err(err_str);
         }
 
-        let src_path = format!("{target_dir}/{target_location}{location}/{crate_name}");
+        let src_path = format!("{target_dir}/{location}/{}", Self::get_built_executable_name(meta, crate_name));
+        if let Some(parent) = std::path::Path::new(output_file).parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
         std::fs::copy(&src_path, output_file)
             .map_err(|e| format!("Failed to output runtime {} to proper location {}\n{:?}", runtime_name, output_file, e))?;
         Ok(())
@@ -382,24 +914,45 @@ impl HiraConfig {
         meta: &RuntimeMeta,
         runtime_name: &str, path: &str,
         target_dir: &str, crate_name: &str,
-        output_file: &str
+        output_file: &str,
+        env: &[(String, String)],
+        shell: BuildShell,
     ) -> Result<(), TokenStream> {
-        let mut f = std::fs::File::options().create(true).append(true).open(path)
-            .map_err(|e| compiler_error(&format!("Failed to open {}\n{:?}", path, e)))?;
+        let cmd = Self::get_build_script_cmd(meta, runtime_name, target_dir, crate_name, output_file, env, shell);
+        Self::write_build_script_block(path, &format!("runtime {runtime_name}"), &cmd, false)
+    }
+
+    /// replaces the block of the given name in the build script at `path`, or adds it if it doesn't exist yet.
+    /// see `upsert_build_script_block`
+    #[cfg(feature = "wasm")]
+    fn write_build_script_block(path: &str, name: &str, contents: &str, at_start: bool) -> Result<(), TokenStream> {
+        let existing = std::fs::read_to_string(path).unwrap_or_default();
+        let out = upsert_build_script_block(&existing, name, contents, at_start);
+        std::fs::write(path, out).map_err(|e| compiler_error(&format!("Failed to write to {}\n{:?}", path, e)))
+    }
+
+    #[cfg(feature = "wasm")]
+    fn get_build_script_cmd(
+        meta: &RuntimeMeta,
+        runtime_name: &str,
+        target_dir: &str, crate_name: &str,
+        output_file: &str,
+        env: &[(String, String)],
+        shell: BuildShell,
+    ) -> String {
         let cargo = if meta.cargo_cmd.is_empty() { "cargo" } else { meta.cargo_cmd.as_str() };
-        let profile = if meta.profile.is_empty() { "$profile" } else {
+        // the header defines the profile and $location. in PowerShell, $profile is already a builtin variable
+        let profile_var = match shell {
+            BuildShell::Bash => "$profile",
+            BuildShell::PowerShell => "$hiraProfile",
+        };
+        let profile = if meta.profile.is_empty() { profile_var } else {
             if meta.profile == "debug" {
                 "dev"
             } else {
                 meta.profile.as_str()
             }
         };
-        let mut cmd = format!("CARGO_WASMTYPEGEN_FILEOPS=\"0\" RUSTFLAGS=\"--cfg {runtime_name} -C strip=symbols\" {cargo} rustc \\\n    --crate-type=bin \\\n    --profile {profile} \\\n");
-        let mut target_location = "".to_string();
-        if !meta.target.is_empty() {
-            cmd.push_str(&format!("    --target {} \\\n", meta.target));
-            target_location = format!("{}/", meta.target);
-        }
         let location = if meta.profile.is_empty() { "$location" } else {
             if meta.profile == "dev" {
                 "debug"
@@ -407,52 +960,188 @@ impl HiraConfig {
                 meta.profile.as_str()
             }
         };
-        cmd.push_str(&format!("    --target-dir {target_dir}\n"));
-        cmd.push_str(&format!("cp {target_dir}/{target_location}{location}/{crate_name} {output_file}\n"));
-        f.write_all(cmd.as_bytes()).map_err(|e| compiler_error(&format!("Failed to write to {}\n{:?}", path, e)))?;
-        Ok(())
+        let mut cfgs = format!("--cfg {runtime_name}");
+        for feature in meta.features.iter() {
+            cfgs.push_str(&format!(" --cfg {feature}"));
+        }
+        let rustflags = format!("{cfgs} -C strip=symbols");
+        let (subcommand, mut args) = if meta.cargo_lambda {
+            ("lambda build", vec![format!("--bin {runtime_name}"), format!("--profile {profile}")])
+        } else {
+            ("rustc", vec!["--crate-type=bin".to_string(), format!("--profile {profile}")])
+        };
+        let mut target_location = "".to_string();
+        if !meta.target.is_empty() {
+            args.push(format!("--target {}", meta.target));
+            target_location = format!("{}/", meta.target);
+        }
+        if !meta.cargo_features.is_empty() {
+            args.push(format!("--features {}", meta.cargo_features.join(",")));
+        }
+        if meta.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        args.push(format!("--target-dir {target_dir}"));
+        let src_path = if meta.cargo_lambda {
+            args.push(format!("--lambda-dir {target_dir}/lambda"));
+            format!("{target_dir}/lambda/{runtime_name}/bootstrap")
+        } else {
+            format!("{target_dir}/{target_location}{location}/{}", Self::get_built_executable_name(meta, crate_name))
+        };
+        // cargo lambda's layout puts the executable in a directory of its own
+        let output_dir = std::path::Path::new(output_file).parent()
+            .filter(|_| meta.cargo_lambda)
+            .map(|x| x.to_string_lossy().to_string());
+
+        match shell {
+            BuildShell::Bash => {
+                let mut env_prefix = String::new();
+                for (key, value) in env {
+                    // single quoted such that the value is never expanded by the shell
+                    env_prefix.push_str(&format!("{key}='{}' ", value.replace('\'', "'\\''")));
+                }
+                let mut cmd = format!("{env_prefix}CARGO_WASMTYPEGEN_FILEOPS=\"0\" RUSTFLAGS=\"{rustflags}\" {cargo} {subcommand}");
+                for arg in args {
+                    cmd.push_str(&format!(" \\\n    {arg}"));
+                }
+                cmd.push('\n');
+                if let Some(output_dir) = output_dir {
+                    cmd.push_str(&format!("mkdir -p {output_dir}\n"));
+                }
+                cmd.push_str(&format!("cp {src_path} {output_file}\n"));
+                cmd
+            }
+            BuildShell::PowerShell => {
+                // powershell has no per command env vars, so they are set
+                // before the command, and removed afterwards such that they don't leak into other runtimes.
+                let mut cmd = String::new();
+                for (key, value) in env {
+                    // single quoted such that the value is never expanded by the shell
+                    cmd.push_str(&format!("$env:{key} = '{}'\n", value.replace('\'', "''")));
+                }
+                cmd.push_str("$env:CARGO_WASMTYPEGEN_FILEOPS = \"0\"\n");
+                cmd.push_str(&format!("$env:RUSTFLAGS = \"{rustflags}\"\n"));
+                cmd.push_str(&format!("{cargo} {subcommand}"));
+                for arg in args {
+                    cmd.push_str(&format!(" `\n    {arg}"));
+                }
+                cmd.push('\n');
+                for key in env.iter().map(|(k, _)| k.as_str()).chain(["CARGO_WASMTYPEGEN_FILEOPS", "RUSTFLAGS"]) {
+                    cmd.push_str(&format!("Remove-Item Env:{key}\n"));
+                }
+                if let Some(output_dir) = output_dir {
+                    cmd.push_str(&format!("New-Item -ItemType Directory -Force \"{output_dir}\" | Out-Null\n"));
+                }
+                cmd.push_str(&format!("Copy-Item -Force \"{src_path}\" \"{output_file}\"\n"));
+                cmd
+            }
+        }
     }
 
     /// forms the main entrypoint tokens for the runtime.
     /// returns (tokens, file name of the runtime statements, file name of the runtime data)
-    fn generate_runtime_entrypoint(runtime_name: &str, directory: &str, no_tokio: bool) -> Result<(TokenStream, String, String), TokenStream> {
+    fn generate_runtime_entrypoint(runtime_name: &str, directory: &str, meta: &RuntimeMeta) -> Result<(TokenStream, String, String), TokenStream> {
         let runtime_include_file = format!("{}/{}.rs.txt", directory, runtime_name);
         let runtime_data_include_file = format!("{}/{}_data.rs.txt", directory, runtime_name);
-        let main_line = if no_tokio {
-            "fn main() {"
+        let tokens = if let Some(builder) = &meta.runtime_builder {
+            if meta.no_tokio_async_runtime {
+                return Err(compiler_error(&format!("Runtime {} cannot have both a runtime_builder and no_tokio_async_runtime", runtime_name)));
+            }
+            if !builder.contains("hira_runtime_body") {
+                return Err(compiler_error(&format!("Runtime {} has a runtime_builder that never runs `hira_runtime_body`", runtime_name)));
+            }
+            if let Err(e) = syn::parse_str::<syn::Block>(&format!("{{ {builder} }}")) {
+                return Err(compiler_error(&format!("Runtime {} has an invalid runtime_builder\n{}\n{:?}", runtime_name, builder, e)));
+            }
+            format!(r#"
+#[cfg({runtime_name})]
+#[allow(incomplete_include)]
+fn main() {{
+    let d: &[&'static str] = &include!("{runtime_data_include_file}");
+    let mut runtime_data: Vec<String> = d.iter().map(|x| x.to_string()).collect();
+    let hira_runtime_body = async move {{
+        include!("{runtime_include_file}");
+    }};
+    {builder};
+}}"#)
         } else {
-            "#[tokio::main]\nasync fn main() {"
-        };
-        let tokens = format!(r#"
+            let main_line = if meta.no_tokio_async_runtime {
+                "fn main() {"
+            } else {
+                "#[tokio::main]\nasync fn main() {"
+            };
+            format!(r#"
 #[cfg({runtime_name})]
 #[allow(incomplete_include)]
 {main_line}
     let d: &[&'static str] = &include!("{runtime_data_include_file}");
     let mut runtime_data: Vec<String> = d.iter().map(|x| x.to_string()).collect();
     include!("{runtime_include_file}");
-}}"#).parse::<TokenStream>()
+}}"#)
+        };
+        let tokens = tokens.parse::<TokenStream>()
             .map_err(|e| compiler_error(&format!("Failed to output runtime {}: {:?}", runtime_name, e)))?;
         Ok((tokens, runtime_include_file, runtime_data_include_file))
     }
 
+    /// runtimes without an async runtime have a plain `fn main()`, so their code cannot `.await`.
+    fn validate_runtime_code(runtime_name: &str, meta: &RuntimeMeta, code: &[String]) -> Result<(), TokenStream> {
+        if !meta.no_tokio_async_runtime {
+            return Ok(());
+        }
+        // the lines are only valid as a whole, eg: one line can open a block that a later line closes.
+        // if they don't parse, rustc reports a better error once the runtime is compiled.
+        let found = match parsing::find_await_in_fn_body(&code.join("\n")) {
+            Ok(found) => found,
+            Err(_) => return Ok(()),
+        };
+        if let Some(expr) = found {
+            return Err(compiler_error(&format!("Runtime {} sets no_tokio_async_runtime, but its code uses `.await`:\n{}", runtime_name, expr)));
+        }
+        Ok(())
+    }
+
+    /// returns `value` as a raw string literal, eg: `r#"value"#`. if the value contains `"#`,
+    /// more hashes are used, such that the value cannot end the literal early.
+    pub fn to_raw_string_literal(value: &str) -> String {
+        let mut hashes = "#".to_string();
+        while value.contains(&format!("\"{hashes}")) {
+            hashes.push('#');
+        }
+        format!("r{hashes}\"{value}\"{hashes}")
+    }
+
     fn output_include_files(
         runtime_include_file: &str,
         runtime_data_include_file: &str,
         code: &Vec<String>,
         data: &Vec<String>,
+        variables: &[(String, String)],
     ) -> Result<(), TokenStream> {
-        let mut out_s = "[".to_string();
+        // the code is an array expression. if there are variables,
+        // we wrap it in a block such that the code can reference the variables.
+        let mut out_s = String::new();
+        if !variables.is_empty() {
+            out_s.push_str("{\n");
+            for (name, value) in variables {
+                out_s.push_str(&format!("let {name} = {};\n", Self::to_raw_string_literal(value)));
+            }
+        }
+        out_s.push('[');
         for line in code {
             out_s.push_str(line);
             out_s.push(',');
             out_s.push('\n');
         }
         out_s.push(']');
+        if !variables.is_empty() {
+            out_s.push_str("\n}");
+        }
         std::fs::write(&runtime_include_file, out_s)
             .map_err(|e| compiler_error(&format!("Failed to write runtime file {}\n{:?}", runtime_include_file, e)))?;
         let mut out_s = "[\n".to_string();
         for line in data {
-            out_s.push_str(&format!("    r#\"{line}\"#,\n"));
+            out_s.push_str(&format!("    {},\n", Self::to_raw_string_literal(line)));
         }
         out_s.push(']');
         std::fs::write(&runtime_data_include_file, out_s)
@@ -461,63 +1150,166 @@ impl HiraConfig {
     }
 
     #[cfg(feature = "wasm")]
-    fn output_runtimes(&mut self, stream: &mut TokenStream) -> Result<(), TokenStream> {
-        print_debug(&self.logfile, format!("Outputting runtimes. should_do_file_ops={}, should_output_build_script={}\n", self.should_do_file_ops, self.should_output_build_script));
-        if !self.has_deleted_build_script && self.should_do_file_ops {
-            let _ = std::fs::remove_file(&self.build_script_path);
-            let _ = std::fs::create_dir_all(&self.runtime_directory);
-            self.has_deleted_build_script = true;
-            let out = format!(r#"
+    fn write_build_script_header(settings: &HiraSettings, path: &str) -> Result<(), TokenStream> {
+        let build_shell = settings.build_shell();
+        let header = match build_shell {
+            BuildShell::Bash => {
+                let offline = if settings.offline { "export CARGO_NET_OFFLINE=true" } else { "" };
+                format!(r#"{offline}
 profile="${{1:-dev}}"
 location=$profile
 if [[ $profile == "dev" ]]; then
     location="debug"
 fi
-"#);
+"#)
+            }
+            BuildShell::PowerShell => {
+                let offline = if settings.offline { "$env:CARGO_NET_OFFLINE = \"true\"" } else { "" };
+                format!(r#"{offline}
+$hiraProfile = if ($args.Count -gt 0) {{ $args[0] }} else {{ "dev" }}
+$location = $hiraProfile
+if ($hiraProfile -eq "dev") {{
+    $location = "debug"
+}}
+"#)
+            }
+        };
+        let existing = std::fs::read_to_string(path).unwrap_or_default();
+        // the shebang has to be the first line, so it is kept outside of the header block
+        let existing = match existing.strip_prefix("#!").and_then(|_| existing.split_once('\n')) {
+            Some((_, rest)) => rest,
+            None => existing.as_str(),
+        };
+        // PowerShell scripts are ran with powershell, and have no shebang
+        let mut out = match &settings.shell {
+            Some(shell) if build_shell == BuildShell::Bash => format!("#!/usr/bin/env {shell}\n"),
+            _ => "".to_string(),
+        };
+        out.push_str(&upsert_build_script_block(existing, "header", &header, true));
+        std::fs::write(path, out)
+            .map_err(|e| compiler_error(&format!("Failed to create build script at {}\n{:?}", path, e)))
+    }
+
+    #[cfg(feature = "wasm")]
+    fn output_runtimes(&mut self, stream: &mut TokenStream) -> Result<(), TokenStream> {
+        print_debug(&self.logfile, format!("Outputting runtimes. should_do_file_ops={}, should_output_build_script={}\n", self.should_do_file_ops, self.should_output_build_script));
+        if !self.has_deleted_build_script && self.should_do_file_ops {
+            // when preserving the build script, only hira's blocks get replaced.
+            // every runtime of this pass is written again, so the blocks of runtimes that no longer exist are removed.
+            if !self.settings.preserve_build_script {
+                let _ = std::fs::remove_file(&self.build_script_path);
+            } else if let Ok(existing) = std::fs::read_to_string(&self.build_script_path) {
+                let pruned = remove_build_script_blocks(&existing, "runtime ");
+                std::fs::write(&self.build_script_path, pruned)
+                    .map_err(|e| compiler_error(&format!("Failed to write to {}\n{:?}", self.build_script_path, e)))?;
+            }
+            let _ = std::fs::create_dir_all(&self.runtime_directory);
+            self.has_deleted_build_script = true;
             if self.should_output_build_script {
-                std::fs::write(&self.build_script_path, out)
-                    .map_err(|e| compiler_error(&format!("Failed to create build script at {}\n{:?}", self.build_script_path, e)))?;
+                Self::write_build_script_header(&self.settings, &self.build_script_path)?;
             }
         }
+        let mut wrote_build_script = false;
         for (runtime_name, (already_output, meta, code, data)) in self.runtimes.iter_mut() {
-            let (tokens, runtime_include_file, runtime_data_include_file) = Self::generate_runtime_entrypoint(runtime_name, &self.wasm_directory, meta.no_tokio_async_runtime)?;
-            if !*already_output {
-                // write out the runtime main function to the stream:
-                stream.extend(tokens);
-                *already_output = true;
+            Self::validate_runtime_code(runtime_name, meta, code)?;
+            let (tokens, runtime_include_file, runtime_data_include_file) = Self::generate_runtime_entrypoint(runtime_name, &self.wasm_directory, meta)?;
+            let env = self.runtime_env.get(runtime_name).map(|x| x.as_slice()).unwrap_or_default();
+            // env vars can be added by later modules, so those runtimes have their commands rewritten
+            if !*already_output || !env.is_empty() {
+                if !*already_output {
+                    // write out the runtime main function to the stream:
+                    stream.extend(tokens);
+                    *already_output = true;
+                }
                 let target_dir = format!("{}/target_{}", self.wasm_directory, runtime_name);
-                let hira_runtime_output_path = format!("{}/{}", self.runtime_directory, runtime_name);
+                let hira_runtime_output_path = Self::get_runtime_output_path(&self.runtime_directory, runtime_name, meta);
                 if self.should_output_build_script {
-                    Self::append_to_build_script(meta, runtime_name, &self.build_script_path, &target_dir, &self.crate_name, &hira_runtime_output_path)?;
+                    Self::append_to_build_script(meta, runtime_name, &self.build_script_path, &target_dir, &self.crate_name, &hira_runtime_output_path, env, self.settings.build_shell())?;
+                    wrote_build_script = true;
                 }
             }
             if self.should_do_file_ops {
-                Self::output_include_files(&runtime_include_file, &runtime_data_include_file, &code, &data)?;
+                let variables = self.runtime_variables.get(runtime_name).map(|x| x.as_slice()).unwrap_or_default();
+                Self::output_include_files(&runtime_include_file, &runtime_data_include_file, &code, &data, variables)?;
             }
         }
+        if wrote_build_script && self.should_do_file_ops && self.settings.build_shell() == BuildShell::Bash {
+            check_shell_syntax(&self.build_script_path).map_err(|e| compiler_error(&e))?;
+        }
         Ok(())
     }
 
     fn set_directories(&mut self) {
-        let base_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or(".".into());
-        let target_dir = std::env::var("CARGO_HOME").unwrap_or(".".into());
-        let crate_name = std::env::var("CARGO_CRATE_NAME").unwrap_or("UNKNOWN_CRATE_NAME".into());
+        self.set_directories_ex(|name| std::env::var(name).ok());
+    }
+
+    /// every directory defaults to being inside of `{CARGO_MANIFEST_DIR}/hira`, but can be overridden:
+    /// - `HIRA_DIR`: the hira directory itself. when set, the module cache is also placed in it, so that
+    ///   multiple crates can share one build area. The build script, wasm, generated and runtime directories are placed in
+    ///   `{HIRA_DIR}/{crate name}` so that the crates sharing it don't overwrite each other's builds.
+    /// - `HIRA_MODULES_DIR`, `HIRA_WASM_DIR`, `HIRA_GEN_DIR`, `HIRA_RUNTIMES_DIR`: the full path of that directory.
+    fn set_directories_ex(&mut self, get_env: impl Fn(&str) -> Option<String>) {
+        let base_dir = get_env("CARGO_MANIFEST_DIR").unwrap_or(".".into());
+        let target_dir = get_env("CARGO_HOME").unwrap_or(".".into());
+        let crate_name = resolve_crate_name(
+            get_env("CARGO_BIN_NAME"),
+            get_env("CARGO_CRATE_NAME"),
+            None,
+        ).unwrap_or(UNKNOWN_CRATE_NAME.into());
         self.cargo_directory = base_dir;
-        self.hira_directory = format!("{}/{HIRA_DIR_NAME}", self.cargo_directory);
-        self.modules_directory = format!("{}/{HIRA_MODULES_DIR_NAME}", self.hira_directory);
-        self.wasm_directory = format!("{}/{HIRA_WASM_DIR_NAME}", self.hira_directory);
-        self.gen_directory = format!("{}/{HIRA_GEN_DIR_NAME}", self.hira_directory);
-        self.module_cache_directory = format!("{}/{HIRA_DIR_NAME}/cached_modules", target_dir);
-        self.build_script_path = format!("{}/build.sh", self.cargo_directory);
+        self.hira_directory = get_hira_directory(&get_env);
+        // the directory of the files that belong to this crate only
+        let crate_dir = match get_env("HIRA_DIR") {
+            Some(_) => {
+                self.module_cache_directory = format!("{}/cached_modules", self.hira_directory);
+                self.build_script_path = format!("{}/{crate_name}/build.sh", self.hira_directory);
+                format!("{}/{crate_name}", self.hira_directory)
+            }
+            None => {
+                self.module_cache_directory = format!("{}/{HIRA_DIR_NAME}/cached_modules", target_dir);
+                self.build_script_path = format!("{}/build.sh", self.cargo_directory);
+                self.hira_directory.clone()
+            }
+        };
+        let sub_dir = |env_name: &str, parent: &str, default_name: &str| {
+            get_env(env_name).unwrap_or_else(|| format!("{parent}/{default_name}"))
+        };
+        self.modules_directory = sub_dir("HIRA_MODULES_DIR", &self.hira_directory, HIRA_MODULES_DIR_NAME);
+        self.wasm_directory = sub_dir("HIRA_WASM_DIR", &crate_dir, HIRA_WASM_DIR_NAME);
+        self.gen_directory = get_gen_directory(&get_env);
+        self.runtime_directory = sub_dir("HIRA_RUNTIMES_DIR", &crate_dir, HIRA_RUNTIMES_DIR_NAME);
         self.logfile = format!("{}/hira.log", self.wasm_directory);
-        self.runtime_directory = format!("{}/{HIRA_RUNTIMES_DIR_NAME}", self.hira_directory);
         self.crate_name = crate_name;
     }
 
-    #[cfg(feature = "wasm")]
-    fn load_cargo_toml(&mut self) {
-        let file_path = format!("{}/Cargo.toml", self.cargo_directory);
-        let cargo_file_str = if let Ok(file_str) = std::fs::read_to_string(file_path) {
+    /// reads hira.toml from the cargo directory and applies the settings
+    /// that affect the config itself.
+    pub fn load_settings(&mut self, get_env: impl Fn(&str) -> Option<String>) {
+        let file_path = format!("{}/{HIRA_TOML_FILE_NAME}", self.cargo_directory);
+        let toml_str = std::fs::read_to_string(file_path).ok();
+        match HiraSettings::load(toml_str.as_deref(), get_env) {
+            Ok(settings) => {
+                if let Some(dir) = &settings.module_cache_dir {
+                    self.module_cache_directory = dir.to_string();
+                }
+                // the directory of the build script is decided in set_directories, only the file name depends on the shell
+                let script_dir = match self.build_script_path.rsplit_once('/') {
+                    Some((dir, _)) => dir.to_string(),
+                    None => self.cargo_directory.clone(),
+                };
+                self.build_script_path = format!("{script_dir}/{}", settings.build_shell().script_file_name());
+                self.settings = settings;
+            }
+            Err(e) => {
+                self.settings_error = Some(e);
+            }
+        }
+    }
+
+    #[cfg(feature = "wasm")]
+    fn load_cargo_toml(&mut self) {
+        let file_path = format!("{}/Cargo.toml", self.cargo_directory);
+        let cargo_file_str = if let Ok(file_str) = std::fs::read_to_string(file_path) {
             file_str
         } else {
             return
@@ -531,39 +1323,92 @@ fi
                 }
             }
         }
-        if self.crate_name == "UNKNOWN_CRATE_NAME" {
-            if let Some(package) = value.get("package") {
-                if let Some(toml::Value::String(s)) = package.get("name") {
-                    self.crate_name = s.to_string();
-                }
+        if self.crate_name == UNKNOWN_CRATE_NAME {
+            if let Ok(name) = resolve_crate_name(None, None, get_package_name(&value)) {
+                self.crate_name = name;
             }
         }
         self.known_cargo_dependencies = dependencies;
     }
 }
 
-static mut PERSISTED_DATA: Mutex<Option<HiraConfig>> = Mutex::new(None);
+/// the hira directory: `HIRA_DIR` if set, otherwise `{CARGO_MANIFEST_DIR}/hira`.
+/// See `HiraConfig::set_directories_ex`
+pub fn get_hira_directory(get_env: impl Fn(&str) -> Option<String>) -> String {
+    match get_env("HIRA_DIR") {
+        Some(dir) => dir,
+        None => {
+            let base_dir = get_env("CARGO_MANIFEST_DIR").unwrap_or(".".into());
+            format!("{base_dir}/{HIRA_DIR_NAME}")
+        }
+    }
+}
+
+/// the directory of generated files: `HIRA_GEN_DIR` if set, otherwise the `generated` directory
+/// in the hira directory. Like the other per crate directories, it is placed in `{HIRA_DIR}/{crate name}`
+/// when `HIRA_DIR` is set. Runtimes can use this to find the files hira generated for them.
+pub fn get_gen_directory(get_env: impl Fn(&str) -> Option<String>) -> String {
+    if let Some(dir) = get_env("HIRA_GEN_DIR") {
+        return dir;
+    }
+    match get_env("HIRA_DIR") {
+        Some(dir) => {
+            let crate_name = resolve_crate_name(
+                get_env("CARGO_BIN_NAME"),
+                get_env("CARGO_CRATE_NAME"),
+                None,
+            ).unwrap_or(UNKNOWN_CRATE_NAME.into());
+            format!("{dir}/{crate_name}/{HIRA_GEN_DIR_NAME}")
+        }
+        None => format!("{}/{HIRA_GEN_DIR_NAME}", get_hira_directory(get_env)),
+    }
+}
+
+/// a map of crate names to the config of that crate. The proc macro process
+/// can be reused across several crates (eg: by an IDE, or a workspace with multiple binaries)
+/// so state such as which runtimes were already output must be tracked per crate.
+static mut PERSISTED_DATA: Mutex<Option<HashMap<String, HiraConfig>>> = Mutex::new(None);
+
+pub fn use_hira_config(cb: impl FnMut(&mut HiraConfig)) {
+    let crate_name = std::env::var("CARGO_CRATE_NAME").unwrap_or_default();
+    use_hira_config_for(&crate_name, cb)
+}
+
+pub fn use_hira_config_for(crate_name: &str, mut cb: impl FnMut(&mut HiraConfig)) {
+    unsafe {
+        if let Ok(mut lock) = PERSISTED_DATA.lock() {
+            let configs = lock.get_or_insert_with(HashMap::new);
+            let config = configs.entry(crate_name.to_string()).or_insert_with(HiraConfig::new);
+            cb(config);
+        }
+    }
+}
 
-pub fn use_hira_config(mut cb: impl FnMut(&mut HiraConfig)) {
+/// drops the persisted config of every crate, such that the next `use_hira_config`
+/// starts with a fresh config. Meant for test binaries that invoke hira many times.
+/// The config is process-global: this is thread safe, but other threads that are
+/// in the middle of several `use_hira_config` calls (eg: tests running in parallel)
+/// will see their state disappear between calls. Tests that rely on persisted state
+/// should not run concurrently with tests that reset it.
+pub fn reset_config() {
     unsafe {
         if let Ok(mut lock) = PERSISTED_DATA.lock() {
-            if lock.is_none() {
-                *lock = Some(HiraConfig::new());
-            }
-            if let Some(config) = lock.as_mut() {
-                cb(config);
-            }
+            *lock = None;
         }
     }
 }
 
+/// helpers to expand hira modules end to end, the same way the hira macro does.
+/// Enabled by the `e2e` feature so that crates with lvl2 modules can test their modules end to end.
 #[cfg(feature = "wasm")]
-#[cfg(test)]
-pub mod e2e_tests {
+#[cfg(any(test, feature = "e2e"))]
+pub mod e2e {
     use std::str::FromStr;
     use proc_macro2::TokenStream;
-    use crate::module_loading::{hira_mod2_inner, ModuleLevel};
-    use super::*;
+    use quote::ToTokens;
+    use crate::module_loading::{hira_mod2_inner, parse_module_from_stream, order_by_priority_and_run_after};
+    use crate::parsing::{compiler_error, iter_hira_modules, get_ident_string};
+    use super::HiraConfig;
 
     pub fn assert_contains_str<Q: AsRef<str>, S: AsRef<str>>(search: Q, contains: S) {
         let search = search.as_ref();
@@ -576,7 +1421,7 @@ pub mod e2e_tests {
         assert!(contains_true);
     }
 
-    fn e2e_module2_run(
+    pub fn e2e_module2_run(
         module_code: &[&str],
         conf_cb: impl Fn(&mut HiraConfig),
     ) -> Result<HiraConfig, TokenStream> {
@@ -584,9 +1429,37 @@ pub mod e2e_tests {
         Ok(res.0)
     }
 
-    fn e2e_module2_run_with_token_stream(
+    /// same as `e2e_module2_run`, but first orders the modules by priority and `after`
+    /// the same way the CLI does. `e2e_module2_run` processes them in source order, like cargo does.
+    pub fn e2e_module2_run_ordered(
+        module_code: &[&str],
+        conf_cb: impl Fn(&mut HiraConfig),
+    ) -> Result<HiraConfig, TokenStream> {
+        let mut modules = vec![];
+        for code in module_code {
+            let code = TokenStream::from_str(code).expect("Failed to parse test case code");
+            let module = parse_module_from_stream(code.clone())?;
+            modules.push((module.name, module.run_after, (module.priority, code)));
+        }
+        let modules = order_by_priority_and_run_after(modules, |x| x.0).map_err(|e| compiler_error(&e))?;
+        let codes = modules.into_iter().map(|(_, _, (_, code))| code).collect();
+        let res = e2e_module2_run_streams(codes, conf_cb)?;
+        Ok(res.0)
+    }
+
+    pub fn e2e_module2_run_with_token_stream(
         module_code: &[&str],
         conf_cb: impl Fn(&mut HiraConfig),
+    ) -> Result<(HiraConfig, TokenStream), TokenStream> {
+        let codes = module_code.iter()
+            .map(|code| TokenStream::from_str(code).expect("Failed to parse test case code"))
+            .collect();
+        e2e_module2_run_streams(codes, conf_cb)
+    }
+
+    pub fn e2e_module2_run_streams(
+        codes: Vec<TokenStream>,
+        conf_cb: impl Fn(&mut HiraConfig),
     ) -> Result<(HiraConfig, TokenStream), TokenStream> {
         let mut conf = HiraConfig::default();
         #[cfg(feature = "wasm")]
@@ -611,9 +1484,8 @@ pub mod e2e_tests {
 
         conf_cb(&mut conf);
         let mut stream = TokenStream::new();
-        for code in module_code {
-            let code = TokenStream::from_str(code).expect("Failed to parse test case code");
-            let out = hira_mod2_inner(&mut conf, code);
+        for code in codes {
+            let out = hira_mod2_inner(&mut conf, code, TokenStream::new());
             match out {
                 Ok(s) => {
                     stream = s;
@@ -626,6 +1498,359 @@ pub mod e2e_tests {
         Ok((conf, stream))
     }
 
+    /// returns the source of the module called `name` in `file_contents`, without its attributes.
+    /// useful to expand a crate's own lvl2 module, eg: `get_hira_module_source(include_str!("lib.rs"), "my_module")`
+    pub fn get_hira_module_source(file_contents: &str, name: &str) -> String {
+        let mut out = None;
+        iter_hira_modules(file_contents, &mut |mut m| {
+            if get_ident_string(&m.ident) != name {
+                return Ok(true);
+            }
+            m.attrs.clear();
+            out = Some(m.to_token_stream().to_string());
+            Ok(false)
+        }).expect("Failed to parse file");
+        out.unwrap_or_else(|| panic!("Failed to find module '{name}'"))
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[cfg(test)]
+pub mod e2e_tests {
+    use std::str::FromStr;
+    use proc_macro2::TokenStream;
+    use crate::module_loading::{hira_mod2_inner, ModuleLevel, DOCUMENTATION_SUMMARY_HEADING};
+    use crate::e2e::*;
+    use super::*;
+
+    #[test]
+    fn crate_name_falls_back_to_package_name() {
+        let cargo_toml = "[package]\nname = \"my-package\"\nversion = \"0.1.0\"\n".parse::<Table>().unwrap();
+        let package_name = get_package_name(&cargo_toml);
+        assert_eq!(package_name.as_deref(), Some("my-package"));
+        assert_eq!(resolve_crate_name(None, None, package_name.clone()).unwrap(), "my-package");
+        assert_eq!(resolve_crate_name(None, Some("".into()), package_name.clone()).unwrap(), "my-package");
+        assert_eq!(resolve_crate_name(None, Some("my_crate".into()), package_name).unwrap(), "my_crate");
+        assert_eq!(get_package_name(&"[workspace]\nmembers = []\n".parse::<Table>().unwrap()), None);
+        assert_eq!(resolve_crate_name(Some("my-bin".into()), Some("my_crate".into()), None).unwrap(), "my-bin");
+        assert!(resolve_crate_name(None, None, None).is_err());
+    }
+
+    #[test]
+    fn export_formats_can_be_set_by_file_or_env_vars() {
+        let toml = "export_formats = [\"cfn-json\", \"tf-json\"]\n";
+        let settings = HiraSettings::load(Some(toml), |_| None).expect("Failed to load settings");
+        assert_eq!(settings.export_formats, vec![ExportFormat::CfnJson, ExportFormat::TfJson]);
+        let env = |name: &str| if name == "HIRA_EXPORT_FORMATS" { Some("cfn-yaml".to_string()) } else { None };
+        let settings = HiraSettings::load(Some(toml), env).expect("Failed to load settings");
+        assert_eq!(settings.export_formats, vec![ExportFormat::CfnYaml]);
+        assert!(HiraSettings::load(Some("export_formats = [\"xml\"]"), |_| None).is_err());
+        assert!(HiraSettings::load(None, |_| Some("cfn-json,xml".to_string())).is_err());
+    }
+
+    #[test]
+    fn iam_settings_can_be_set_by_file_or_env_vars() {
+        let toml = "iam_name_prefix = \"team-\"\n";
+        let settings = HiraSettings::load(Some(toml), |_| None).expect("Failed to load settings");
+        assert_eq!(settings.iam_name_prefix, "team-");
+        assert_eq!(settings.iam_permissions_boundary, None);
+
+        let env = |name: &str| match name {
+            "HIRA_IAM_PERMISSIONS_BOUNDARY" => Some("arn:aws:iam::123:policy/boundary".to_string()),
+            _ => None,
+        };
+        let settings = HiraSettings::load(Some(toml), env).expect("Failed to load settings");
+        assert_eq!(settings.iam_name_prefix, "team-");
+        assert_eq!(settings.iam_permissions_boundary.as_deref(), Some("arn:aws:iam::123:policy/boundary"));
+        assert!(HiraSettings::load(Some("iam_permissions_boundary = 1"), |_| None).is_err());
+    }
+
+    #[test]
+    fn hira_toml_settings_can_be_overridden_by_env_vars() {
+        let toml = "deny_warnings = true\nstack_name_prefix = \"dev-\"\nmodule_cache_dir = \"/tmp/hiracache\"\n";
+        let no_env = |_: &str| None;
+        let settings = HiraSettings::load(Some(toml), no_env).expect("Failed to load settings");
+        assert!(settings.deny_warnings);
+        assert_eq!(settings.stack_name_prefix, "dev-");
+        assert!(!settings.offline);
+
+        let env = |name: &str| match name {
+            "HIRA_DENY_WARNINGS" => Some("false".to_string()),
+            "HIRA_STACK_NAME_PREFIX" => Some("prod-".to_string()),
+            _ => None,
+        };
+        let settings = HiraSettings::load(Some(toml), env).expect("Failed to load settings");
+        assert!(!settings.deny_warnings);
+        assert_eq!(settings.stack_name_prefix, "prod-");
+        assert_eq!(settings.module_cache_dir.as_deref(), Some("/tmp/hiracache"));
+
+        assert!(HiraSettings::load(Some("deny_warnings = \"yes\""), no_env).is_err());
+        assert!(HiraSettings::load(Some("unknown = 1"), no_env).is_err());
+        assert!(HiraSettings::load(None, |_| Some("maybe".to_string())).is_err());
+
+        // the file is read from the crate root
+        let dir = std::path::PathBuf::from("./test_out/hira_toml_settings");
+        let _ = std::fs::create_dir_all(&dir);
+        std::fs::write(dir.join(HIRA_TOML_FILE_NAME), toml).expect("Failed to write hira.toml");
+        let mut conf = HiraConfig::default();
+        conf.cargo_directory = dir.to_string_lossy().to_string();
+        conf.load_settings(no_env);
+        assert!(conf.settings_error.is_none());
+        assert!(conf.settings.deny_warnings);
+        assert_eq!(conf.module_cache_directory, "/tmp/hiracache");
+        conf.load_settings(env);
+        assert!(!conf.settings.deny_warnings);
+    }
+
+    #[test]
+    fn hira_directories_can_be_overridden_by_env_vars() {
+        let mut conf = HiraConfig::default();
+        conf.set_directories_ex(|name| match name {
+            "CARGO_MANIFEST_DIR" => Some("/repo/mycrate".to_string()),
+            "CARGO_HOME" => Some("/cargo".to_string()),
+            _ => None,
+        });
+        assert_eq!(conf.hira_directory, "/repo/mycrate/hira");
+        assert_eq!(conf.wasm_directory, "/repo/mycrate/hira/wasm_out");
+        assert_eq!(conf.gen_directory, "/repo/mycrate/hira/generated");
+        assert_eq!(conf.module_cache_directory, "/cargo/hira/cached_modules");
+        assert_eq!(conf.build_script_path, "/repo/mycrate/build.sh");
+
+        let mut conf = HiraConfig::default();
+        conf.set_directories_ex(|name| match name {
+            "CARGO_MANIFEST_DIR" => Some("/repo/mycrate".to_string()),
+            "CARGO_HOME" => Some("/cargo".to_string()),
+            "CARGO_CRATE_NAME" => Some("mycrate".to_string()),
+            "HIRA_DIR" => Some("/repo/build/hira".to_string()),
+            "HIRA_GEN_DIR" => Some("/repo/build/generated".to_string()),
+            _ => None,
+        });
+        assert_eq!(conf.cargo_directory, "/repo/mycrate");
+        assert_eq!(conf.hira_directory, "/repo/build/hira");
+        assert_eq!(conf.modules_directory, "/repo/build/hira/modules");
+        // crates that share a HIRA_DIR must not share their builds
+        assert_eq!(conf.wasm_directory, "/repo/build/hira/mycrate/wasm_out");
+        assert_eq!(conf.logfile, "/repo/build/hira/mycrate/wasm_out/hira.log");
+        assert_eq!(conf.gen_directory, "/repo/build/generated");
+        assert_eq!(conf.runtime_directory, "/repo/build/hira/mycrate/runtimes");
+        assert_eq!(conf.module_cache_directory, "/repo/build/hira/cached_modules");
+        assert_eq!(conf.build_script_path, "/repo/build/hira/mycrate/build.sh");
+
+        // the build shell only changes the file name of the build script
+        conf.load_settings(|name| if name == "HIRA_SHELL" { Some("powershell".to_string()) } else { None });
+        assert_eq!(conf.build_script_path, "/repo/build/hira/mycrate/build.ps1");
+
+        let mut conf = HiraConfig::default();
+        conf.set_directories_ex(|name| match name {
+            "CARGO_MANIFEST_DIR" => Some("/repo/mycrate".to_string()),
+            "CARGO_CRATE_NAME" => Some("mycrate".to_string()),
+            "HIRA_DIR" => Some("/repo/build/hira".to_string()),
+            _ => None,
+        });
+        assert_eq!(conf.gen_directory, "/repo/build/hira/mycrate/generated");
+
+        let env = |name: &str| match name {
+            "CARGO_MANIFEST_DIR" => Some("/repo/mycrate".to_string()),
+            "CARGO_CRATE_NAME" => Some("mycrate".to_string()),
+            "HIRA_DIR" => Some("/repo/build/hira".to_string()),
+            _ => None,
+        };
+        assert_eq!(get_gen_directory(env), "/repo/build/hira/mycrate/generated");
+        // the bin name is preferred, same as for the other per crate directories
+        let env = |name: &str| match name {
+            "CARGO_BIN_NAME" => Some("mybin".to_string()),
+            "CARGO_CRATE_NAME" => Some("mycrate".to_string()),
+            "HIRA_DIR" => Some("/repo/build/hira".to_string()),
+            _ => None,
+        };
+        assert_eq!(get_gen_directory(env), "/repo/build/hira/mybin/generated");
+        assert_eq!(get_gen_directory(|name| if name == "CARGO_MANIFEST_DIR" { Some("/repo/mycrate".to_string()) } else { None }), "/repo/mycrate/hira/generated");
+    }
+
+    #[test]
+    fn malformed_shell_scripts_fail_syntax_check() {
+        if std::process::Command::new("bash").arg("--version").output().is_err() {
+            return;
+        }
+        let dir = std::path::PathBuf::from("./test_out/shell_syntax");
+        let _ = std::fs::create_dir_all(&dir);
+        let good = dir.join("good.sh");
+        std::fs::write(&good, "profile=\"${1:-dev}\"\nif [[ $profile == \"dev\" ]]; then\n    echo \"hi\"\nfi\n").expect("Failed to write script");
+        check_shell_syntax(&good.to_string_lossy()).expect("Expected well formed script to pass");
+        let bad = dir.join("bad.sh");
+        std::fs::write(&bad, "RUSTFLAGS=\"--cfg deploy cargo build\nif [[ 1 ]]; then\n").expect("Failed to write script");
+        let err = check_shell_syntax(&bad.to_string_lossy()).err().expect("Expected malformed script to fail");
+        assert!(err.contains("bad.sh"));
+    }
+
+    #[test]
+    fn domain_names_are_validated() {
+        for valid in ["mysite.com", "www.mysite.com", "multiple.sub.domains.my-site.com", "xn--bcher-kva.example", "a1.b2"] {
+            assert!(validate_domain_name(valid, false).is_ok(), "{valid} should be valid");
+        }
+        assert!(validate_domain_name("*.mysite.com", true).is_ok());
+        assert_contains_str(&validate_domain_name("*.mysite.com", false).unwrap_err(), "must not contain a wildcard");
+        assert_contains_str(&validate_domain_name("a.*.mysite.com", true).unwrap_err(), "first label");
+        assert_contains_str(&validate_domain_name("*.*.mysite.com", true).unwrap_err(), "first label");
+        assert_contains_str(&validate_domain_name("*mysite.com", true).unwrap_err(), "invalid character '*'");
+        assert_contains_str(&validate_domain_name("mysite.com.", false).unwrap_err(), "must not end with a '.'");
+        assert_contains_str(&validate_domain_name("my_site.com", false).unwrap_err(), "invalid character '_'");
+        assert_contains_str(&validate_domain_name("https://mysite.com", false).unwrap_err(), "invalid character ':'");
+        assert_contains_str(&validate_domain_name("-mysite.com", false).unwrap_err(), "starts or ends with '-'");
+        assert_contains_str(&validate_domain_name("my..site.com", false).unwrap_err(), "empty label");
+        assert_contains_str(&validate_domain_name("localhost", false).unwrap_err(), "fully qualified");
+        assert!(validate_domain_name("", false).is_err());
+        let long_label = "a".repeat(64);
+        assert_contains_str(&validate_domain_name(&format!("{long_label}.com"), false).unwrap_err(), "longer than 63");
+        let long_name = vec!["a".repeat(63); 5].join(".");
+        assert_contains_str(&validate_domain_name(&long_name, false).unwrap_err(), "longer than 253");
+    }
+
+    #[test]
+    fn preserved_build_script_keeps_user_lines() {
+        let settings = HiraSettings::load(None, |name| if name == "HIRA_PRESERVE_BUILD_SCRIPT" { Some("1".to_string()) } else { None })
+            .expect("Failed to load settings");
+        assert!(settings.preserve_build_script);
+        assert!(HiraSettings::load(Some("preserve_build_script = \"yes\""), |_| None).is_err());
+
+        let dir = std::path::PathBuf::from("./test_out/preserve_build_script");
+        let _ = std::fs::create_dir_all(&dir);
+        let path = dir.join("build.sh").to_string_lossy().to_string();
+        let _ = std::fs::remove_file(&path);
+        let meta = RuntimeMeta::default();
+        HiraConfig::write_build_script_header(&settings, &path).expect("Failed to write header");
+        HiraConfig::append_to_build_script(&meta, "myruntime", &path, "target", "mycrate", "out/myruntime", &[], BuildShell::Bash).expect("Failed to write runtime");
+        let mut script = std::fs::read_to_string(&path).expect("Failed to read build script");
+        script.push_str("echo \"my own line\"\n");
+        std::fs::write(&path, &script).expect("Failed to write build script");
+
+        // regenerating with different settings only replaces hira's blocks
+        let mut settings = settings;
+        settings.shell = Some("bash".to_string());
+        settings.offline = true;
+        HiraConfig::write_build_script_header(&settings, &path).expect("Failed to write header");
+        HiraConfig::append_to_build_script(&meta, "myruntime", &path, "target2", "mycrate", "out/myruntime", &[], BuildShell::Bash).expect("Failed to write runtime");
+        let script = std::fs::read_to_string(&path).expect("Failed to read build script");
+        assert!(script.starts_with("#!/usr/bin/env bash\n# hira:begin header\nexport CARGO_NET_OFFLINE=true\n"));
+        assert!(script.ends_with("echo \"my own line\"\n"));
+        assert_eq!(script.matches("# hira:begin runtime myruntime").count(), 1);
+        assert!(script.contains("--target-dir target2\n"));
+        assert!(!script.contains("--target-dir target\n"));
+        check_shell_syntax(&path).expect("Expected preserved script to be valid");
+
+        // the next pass removes the runtimes that no longer exist
+        HiraConfig::append_to_build_script(&meta, "oldruntime", &path, "target", "mycrate", "out/oldruntime", &[], BuildShell::Bash).expect("Failed to write runtime");
+        let mut conf = HiraConfig::default();
+        conf.settings = settings;
+        conf.build_script_path = path.clone();
+        conf.runtime_directory = dir.join("runtimes").to_string_lossy().to_string();
+        conf.should_do_file_ops = true;
+        conf.output_runtimes(&mut TokenStream::new()).expect("Failed to output runtimes");
+        let script = std::fs::read_to_string(&path).expect("Failed to read build script");
+        assert!(!script.contains("# hira:begin runtime"));
+        assert!(!script.contains("oldruntime"));
+        assert!(script.starts_with("#!/usr/bin/env bash\n# hira:begin header\n"));
+        assert!(script.ends_with("echo \"my own line\"\n"));
+    }
+
+    #[test]
+    fn powershell_build_script_can_be_generated() {
+        let settings = HiraSettings::load(Some("shell = \"pwsh\""), |_| None).expect("Failed to load settings");
+        assert_eq!(settings.build_shell(), BuildShell::PowerShell);
+        let settings = HiraSettings::load(None, |name| if name == "HIRA_SHELL" { Some("zsh".to_string()) } else { None })
+            .expect("Failed to load settings");
+        assert_eq!(settings.build_shell(), BuildShell::Bash);
+        let settings = HiraSettings::load(None, |name| if name == "HIRA_SHELL" { Some("powershell".to_string()) } else { None })
+            .expect("Failed to load settings");
+        assert_eq!(settings.build_shell(), BuildShell::PowerShell);
+
+        let dir = std::path::PathBuf::from("./test_out/powershell_build_script");
+        let _ = std::fs::create_dir_all(&dir);
+        let path = dir.join(settings.build_shell().script_file_name()).to_string_lossy().to_string();
+        assert!(path.ends_with("build.ps1"));
+        let _ = std::fs::remove_file(&path);
+        let meta = RuntimeMeta { cargo_features: vec!["a".to_string()], target: "x86_64-unknown-linux-gnu".to_string(), ..Default::default() };
+        let env = [("MY_VAR".to_string(), "it's $HOME".to_string())];
+        HiraConfig::write_build_script_header(&settings, &path).expect("Failed to write header");
+        HiraConfig::append_to_build_script(&meta, "myruntime", &path, "target", "mycrate", "out/myruntime", &env, settings.build_shell()).expect("Failed to write runtime");
+        let script = std::fs::read_to_string(&path).expect("Failed to read build script");
+        // $profile is a builtin PowerShell variable
+        assert!(!script.contains("$profile"));
+        assert!(!script.starts_with("#!"));
+        assert_contains_str(&script, "$hiraProfile = if ($args.Count -gt 0) { $args[0] } else { \"dev\" }");
+        assert_contains_str(&script, "$env:MY_VAR = 'it''s $HOME'\n");
+        assert_contains_str(&script, "$env:RUSTFLAGS = \"--cfg myruntime -C strip=symbols\"\n");
+        assert_contains_str(&script, "cargo rustc `\n    --crate-type=bin `\n    --profile $hiraProfile `\n    --target x86_64-unknown-linux-gnu `\n    --features a `\n    --target-dir target\n");
+        assert_contains_str(&script, "Remove-Item Env:MY_VAR\n");
+        assert_contains_str(&script, "Copy-Item -Force \"target/x86_64-unknown-linux-gnu/$location/mycrate\" \"out/myruntime\"\n");
+        assert!(!script.contains("cp "));
+
+        // windows executables have an .exe suffix
+        let meta = RuntimeMeta { target: "x86_64-pc-windows-msvc".to_string(), ..Default::default() };
+        let output_file = HiraConfig::get_runtime_output_path("out", "myruntime", &meta);
+        assert_eq!(output_file, "out/myruntime.exe");
+        let cmd = HiraConfig::get_build_script_cmd(&meta, "myruntime", "target", "mycrate", &output_file, &[], BuildShell::PowerShell);
+        assert_contains_str(&cmd, "Copy-Item -Force \"target/x86_64-pc-windows-msvc/$location/mycrate.exe\" \"out/myruntime.exe\"\n");
+    }
+
+    #[test]
+    fn cargo_lambda_runtimes_are_output_in_its_layout() {
+        let meta = RuntimeMeta {
+            cargo_cmd: "cargo".to_string(), profile: "release".to_string(),
+            target: "aarch64-unknown-linux-musl".to_string(), cargo_lambda: true, ..Default::default()
+        };
+        let output_file = HiraConfig::get_runtime_output_path("out", "myfn", &meta);
+        assert_eq!(output_file, "out/lambda/myfn/bootstrap");
+        let cmd = HiraConfig::get_build_script_cmd(&meta, "myfn", "target", "mycrate", &output_file, &[], BuildShell::Bash);
+        assert_eq!(cmd, r#"CARGO_WASMTYPEGEN_FILEOPS="0" RUSTFLAGS="--cfg myfn -C strip=symbols" cargo lambda build \
+    --bin myfn \
+    --profile release \
+    --target aarch64-unknown-linux-musl \
+    --target-dir target \
+    --lambda-dir target/lambda
+mkdir -p out/lambda/myfn
+cp target/lambda/myfn/bootstrap out/lambda/myfn/bootstrap
+"#);
+    }
+
+    /// held by tests that depend on, or reset, the persisted config.
+    static PERSISTED_CONFIG_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn hira_config_is_persisted_per_crate() {
+        let _guard = PERSISTED_CONFIG_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        use_hira_config_for("test_crate_a", |conf| {
+            assert!(!conf.has_deleted_build_script);
+            conf.has_deleted_build_script = true;
+            conf.runtimes.insert("myruntime".to_string(), (true, RuntimeMeta::default(), vec![], vec![]));
+        });
+        use_hira_config_for("test_crate_a", |conf| {
+            assert!(conf.has_deleted_build_script);
+        });
+        // a different crate must output its own build script and runtimes
+        use_hira_config_for("test_crate_b", |conf| {
+            assert!(!conf.has_deleted_build_script);
+            assert!(!conf.runtimes.contains_key("myruntime"));
+        });
+    }
+
+    #[test]
+    fn reset_config_yields_fresh_config() {
+        let _guard = PERSISTED_CONFIG_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        use_hira_config_for("test_crate_reset", |conf| {
+            conf.has_deleted_build_script = true;
+            conf.runtimes.insert("myruntime".to_string(), (true, RuntimeMeta::default(), vec![], vec![]));
+        });
+        use_hira_config_for("test_crate_reset", |conf| {
+            assert!(conf.runtimes.contains_key("myruntime"));
+        });
+        reset_config();
+        use_hira_config_for("test_crate_reset", |conf| {
+            assert!(!conf.has_deleted_build_script);
+            assert!(conf.runtimes.is_empty());
+        });
+    }
+
     #[test]
     fn mod2_outputs_work() {
         let code = [
@@ -746,6 +1971,60 @@ pub mod e2e_tests {
     }
 
 
+    #[test]
+    fn mod2_output_or_falls_back_if_output_not_set() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0Core;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub region: String,
+                    }
+                    pub mod outputs {
+                        pub const REGION: &str = "";
+                        pub const BUCKET: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                        l0core.set_output("REGION", input.region.as_str());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod1 {
+                    use super::lvl2mod;
+                    pub mod outputs {
+                        pub use lvl2mod::outputs::*;
+                    }
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.region = "us-east-2".to_string();
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod2 {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        let bucket = output_or!(mylevel3mod1::BUCKET, "fallback-bucket");
+                        let region = output_or!(super::mylevel3mod1::outputs::REGION, "fallback-region");
+                        if bucket != "fallback-bucket" {
+                            panic!("Expected bucket to fall back. Instead got {bucket}");
+                        }
+                        if region != "us-east-2" {
+                            panic!("Expected region to be us east 2. Instead got {region}");
+                        }
+                        input.region = format!("{bucket},{region}");
+                    }
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code, |_| {}).expect("Failed to compile");
+        let module = conf.get_mod2("mylevel3mod2").expect("Failed to find mylevel3mod2");
+        assert!(!module.contents.contains("output_or"));
+        assert_contains_str(&module.contents, "\"fallback-bucket\"");
+    }
+
+
     #[test]
     fn mod2_individual_outputs_can_be_renamed() {
         let code = [
@@ -826,6 +2105,50 @@ pub mod e2e_tests {
         assert_contains_str(err_str, "this module did not specify such an output");
     }
 
+    fn only_valid_regions(_module: &str, key: &str, value: &str) -> Result<(), String> {
+        if key.ends_with("REGION") && !value.contains('-') {
+            return Err(format!("{key} must look like a region, but found '{value}'"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn mod2_output_validator_can_reject_outputs() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0Core;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub region: String,
+                    }
+                    pub mod outputs {
+                        pub const REGION: &str = "us-east-1";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                        l0core.set_output("REGION", input.region.as_str());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub mod outputs {
+                        pub use lvl2mod::outputs::*;
+                    }
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.region = "useast1".to_string();
+                    }
+                }
+            ),
+        ];
+        let err = e2e_module2_run(&code, |c| {
+            c.output_validator = Some(only_valid_regions);
+        }).err().expect("Expected output validator to fail the build");
+        assert_contains_str(err.to_string(), "REGION must look like a region, but found 'useast1'");
+        assert_contains_str(err.to_string(), "mylevel3mod");
+    }
+
     #[test]
     fn mod2_outputs_get_defaulted_if_not_set() {
         let code = [
@@ -916,81 +2239,1558 @@ pub mod e2e_tests {
     }
 
     #[test]
-    fn mod2_can_output_shared_file_data() {
+    fn mod2_runtime_can_use_outputs() {
         let code = [
             stringify!(
                 pub mod lvl2mod {
-                    use super::L0AppendFile;
-
-                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["hello.txt"])];
-
+                    use super::L0Core;
                     #[derive(Default)]
                     pub struct Input {
-                        pub _unused: bool,
+                        pub region: String,
                     }
-                    pub fn config(input: &mut Input, l0core: &mut L0AppendFile) {
-                        l0core.append_to_file("hello.txt", "b", "line1".to_string());
-                        l0core.append_to_file("hello.txt", "b", "line2".to_string());
-                        l0core.append_to_file("hello.txt", "a", "line3".to_string());
-                        l0core.append_to_file("hello.txt", "a", "line4".to_string());
+                    pub mod outputs {
+                        pub const REGION: &str = "dsa";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                        l0core.set_output("REGION", input.region.as_str());
                     }
                 }
             ),
             stringify!(
-                pub mod mylevel3mod {
+                pub mod lvl2runtime {
+                    use super::L0RuntimeCreator;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub module: String,
+                    }
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("RUNTIME", &[""])];
+                    pub fn config(input: &mut Input, l0: &mut L0RuntimeCreator) {
+                        l0.add_output_to_runtime("output_var_runtime", &input.module, "REGION", "region");
+                        l0.add_to_runtime("output_var_runtime", "println!(\"{}\", region)".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod1 {
                     use super::lvl2mod;
-                    pub fn config(input: &mut lvl2mod::Input) {}
+                    pub mod outputs {
+                        pub use lvl2mod::outputs::*;
+                    }
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.region = "us-east-2".to_string();
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod2 {
+                    use super::lvl2runtime;
+                    pub fn config(input: &mut lvl2runtime::Input) {
+                        input.module = "mylevel3mod1".to_string();
+                    }
                 }
             ),
         ];
-        let res = e2e_module2_run(&code,|_| {});
-        let mut conf = res.ok().unwrap();
-        let data = conf.get_shared_file_data("hello.txt").expect("Failed to find hello.txt");
-        assert_eq!(data, "a\nline3\nline4\nb\nline1\nline2\n");
+        let conf = e2e_module2_run(&code, |c| {
+            c.should_do_file_ops = true;
+        }).expect("Failed to compile");
+        assert_eq!(conf.runtime_variables["output_var_runtime"], vec![("region".to_string(), "us-east-2".to_string())]);
+        let include_file = format!("{}/output_var_runtime.rs.txt", conf.wasm_directory);
+        let contents = std::fs::read_to_string(include_file).expect("Failed to read runtime include file");
+        assert_contains_str(&contents, "let region = r#\"us-east-2\"#;");
+        assert_contains_str(&contents, "println!(\"{}\", region)");
     }
 
+    #[test]
+    fn mod2_runtime_names_of_cli_subcommands_are_rejected() {
+        let code = [
+            stringify!(
+                pub mod lvl2runtime {
+                    use super::L0RuntimeCreator;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub name: String,
+                    }
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("RUNTIME", &[""])];
+                    pub fn config(input: &mut Input, l0: &mut L0RuntimeCreator) {
+                        l0.add_to_runtime(&input.name, "println!(\"hello\")".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2runtime;
+                    pub fn config(input: &mut lvl2runtime::Input) {
+                        input.name = "check".to_string();
+                    }
+                }
+            ),
+        ];
+        let err = e2e_module2_run(&code, |_| {}).err().expect("Expected check to be a reserved runtime name");
+        assert_contains_str(err.to_string(), "added runtime 'check', but that name is reserved by the hira CLI");
+    }
+
+    #[test]
+    fn mod2_runtime_env_is_set_in_build_script() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0RuntimeCreator;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub region: String,
+                    }
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("RUNTIME", &[""])];
+                    pub fn config(input: &mut Input, l0r: &mut L0RuntimeCreator) {
+                        l0r.add_to_runtime("env_runtime", "println!(\"hi\")".to_string());
+                        l0r.add_runtime_env("env_runtime", "AWS_REGION", "us-east-1");
+                        l0r.add_runtime_env("env_runtime", "AWS_REGION", &input.region);
+                        l0r.add_runtime_env("env_runtime", "GREETING", "it's me");
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.region = "us-west-2".to_string();
+                    }
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code, |c| {
+            c.should_do_file_ops = true;
+            c.should_output_build_script = true;
+            c.build_script_path = format!("{}/runtime_env_build.sh", c.wasm_directory);
+        }).expect("Failed to compile");
+        assert_eq!(conf.runtime_env["env_runtime"], vec![
+            ("AWS_REGION".to_string(), "us-west-2".to_string()),
+            ("GREETING".to_string(), "it's me".to_string()),
+        ]);
+        let script = std::fs::read_to_string(&conf.build_script_path).expect("Failed to read build script");
+        assert_contains_str(&script, "AWS_REGION='us-west-2' GREETING='it'\\''s me' CARGO_WASMTYPEGEN_FILEOPS=\"0\"");
+        assert!(!script.contains("us-east-1"));
+
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0RuntimeCreator;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("RUNTIME", &[""])];
+                    pub fn config(input: &mut Input, l0r: &mut L0RuntimeCreator) {
+                        l0r.add_to_runtime("env_runtime", "println!(\"hi\")".to_string());
+                        l0r.add_runtime_env("env_runtime", "AWS REGION", "us-east-1");
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let err = e2e_module2_run(&code, |_| {}).err().expect("Expected invalid env var name to fail").to_string();
+        assert_contains_str(&err, "invalid env var name 'AWS REGION'");
+    }
+
+    #[test]
+    fn mod2_runtime_cargo_features_are_passed_to_cargo() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::{L0RuntimeCreator, RuntimeMeta};
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub features: Vec<String>,
+                    }
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("RUNTIME", &[""])];
+                    pub fn config(input: &mut Input, l0r: &mut L0RuntimeCreator) {
+                        let meta = RuntimeMeta { profile: "release".to_string(), cargo_features: input.features.clone(), no_default_features: input.features.is_empty(), ..Default::default() };
+                        l0r.add_to_runtime_ex("features_runtime", "println!(\"hi\")".to_string(), meta);
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod1 {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.features = vec!["a".to_string(), "b".to_string()];
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod2 {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code, |c| {
+            c.should_do_file_ops = true;
+            c.should_output_build_script = true;
+            c.build_script_path = format!("{}/cargo_features_build.sh", c.wasm_directory);
+        }).expect("Failed to compile");
+        let meta = &conf.runtimes["features_runtime"].1;
+        assert_eq!(meta.cargo_features, vec!["a", "b"]);
+        assert!(meta.no_default_features);
+        let (_, args, _) = HiraConfig::get_build_runtime_args(meta, "features_runtime", "target_dir");
+        assert_eq!(args[args.len() - 3..], ["--features", "a,b", "--no-default-features"]);
+
+        let script = std::fs::read_to_string(&conf.build_script_path).expect("Failed to read build script");
+        let expected = format!(r#"CARGO_WASMTYPEGEN_FILEOPS="0" RUSTFLAGS="--cfg features_runtime -C strip=symbols" cargo rustc \
+    --crate-type=bin \
+    --profile release \
+    --features a,b \
+    --no-default-features \
+    --target-dir {0}/target_features_runtime
+cp {0}/target_features_runtime/release/{1} {2}/features_runtime
+"#, conf.wasm_directory, conf.crate_name, conf.runtime_directory);
+        assert_contains_str(&script, &expected);
+
+        // features are written into the build script, so they can't contain shell syntax
+        let mut code = code;
+        code[1] = stringify!(
+            pub mod mylevel3mod1 {
+                use super::lvl2mod;
+                pub fn config(input: &mut lvl2mod::Input) {
+                    input.features = vec!["a; rm -rf ~".to_string()];
+                }
+            }
+        );
+        let err = e2e_module2_run(&code, |_| {}).err().expect("Expected the feature to be rejected");
+        assert_contains_str(err.to_string(), "added runtime 'features_runtime' with invalid cargo features");
+    }
+
+    #[test]
+    fn mod2_runtime_code_must_be_valid_tokens() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0RuntimeCreator;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[
+                        ("RUNTIME", &[""]),
+                    ];
+                    pub fn config(input: &mut Input, l0: &mut L0RuntimeCreator) {
+                        l0.add_to_runtime("hello", "if true { world()".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let err = e2e_module2_run(&code, |_| {}).err().expect("Expected unbalanced brace to be rejected");
+        assert_contains_str(err.to_string(), "Module 'lvl2mod' added invalid code to runtime hello");
+        assert_contains_str(err.to_string(), "if true { world()");
+    }
+
+    #[test]
+    fn mod2_runtime_output_must_exist() {
+        let code = [
+            stringify!(
+                pub mod lvl2runtime {
+                    use super::L0RuntimeCreator;
+                    #[derive(Default)]
+                    pub struct Input {}
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("RUNTIME", &[""])];
+                    pub fn config(input: &mut Input, l0: &mut L0RuntimeCreator) {
+                        l0.add_output_to_runtime("output_var_runtime2", "doesnt_exist", "REGION", "region");
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2runtime;
+                    pub fn config(input: &mut lvl2runtime::Input) {}
+                }
+            ),
+        ];
+        let err = e2e_module2_run(&code, |_| {}).expect_err("Expected error");
+        assert_contains_str(err.to_string(), "but that output does not exist");
+    }
+
+    #[test]
+    fn mod2_can_output_shared_file_data() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0AppendFile;
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["hello.txt"])];
+
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0AppendFile) {
+                        l0core.append_to_file("hello.txt", "b", "line1".to_string());
+                        l0core.append_to_file("hello.txt", "b", "line2".to_string());
+                        l0core.append_to_file("hello.txt", "a", "line3".to_string());
+                        l0core.append_to_file("hello.txt", "a", "line4".to_string());
+                        l0core.prepend_to_file("hello.txt", "a", "line0".to_string());
+                        l0core.prepend_to_file("hello.txt", "a", "line0.5".to_string());
+                        l0core.append_to_line("hello.txt", "c", "world".to_string(), "!".to_string());
+                        l0core.prepend_to_line("hello.txt", "c", "world".to_string(), "hello ".to_string());
+                        l0core.prepend_to_line("hello.txt", "c", "friend".to_string(), "my ".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let res = e2e_module2_run(&code,|_| {});
+        let mut conf = res.ok().unwrap();
+        let data = conf.get_shared_file_data("hello.txt").expect("Failed to find hello.txt");
+        assert_eq!(data, "a\nline0\nline0.5\nline3\nline4\nb\nline1\nline2\nc\nhello world!\nmy friend\n");
+    }
+
+    #[test]
+    fn indexed_shared_files_match_linear_scan() {
+        // the straightforward implementation that shared files used to use. only supports appends.
+        fn linear_scan(data: &[(String, String, String)]) -> Vec<(String, String)> {
+            let mut files: Vec<(String, Vec<(String, Vec<String>)>)> = vec![];
+            for (file, label, line) in data {
+                let labels = match files.iter_mut().find(|x| &x.0 == file) {
+                    Some(f) => &mut f.1,
+                    None => {
+                        files.push((file.clone(), vec![]));
+                        &mut files.last_mut().unwrap().1
+                    }
+                };
+                match labels.iter_mut().find(|x| &x.0 == label) {
+                    Some(l) => l.1.push(line.clone()),
+                    None => labels.push((label.clone(), vec![line.clone()])),
+                }
+            }
+            files.into_iter().map(|(file, mut labels)| {
+                labels.sort_by(|a, b| a.0.cmp(&b.0));
+                let out: String = labels.iter().map(|(label, lines)| format!("{label}\n{}", lines.iter().map(|x| format!("{x}\n")).collect::<String>())).collect();
+                (file, out)
+            }).collect()
+        }
+
+        let mut data = vec![];
+        for i in 0..20000 {
+            data.push((format!("file{}.txt", i % 7), format!("label{}", (i * 31) % 53), format!("line{i}")));
+        }
+        let mut conf = HiraConfig::default();
+        // merged in chunks, and read in between, to ensure reading doesn't invalidate the indices
+        for chunk in data.chunks(5000) {
+            let entries = chunk.iter().map(|(file, label, line)| SharedOutputEntry {
+                filename: file.clone(), label: label.clone(), line: line.clone(), unique: false,
+                after: None, prepend: false, remove: false, creator: "mod".to_string(),
+            }).collect();
+            conf.merge_shared_files("mod", crate::wasm_types::to_map_entry(entries)).expect("Failed to merge shared files");
+            assert!(conf.get_shared_file_data("file0.txt").is_some());
+        }
+
+        let expected = linear_scan(&data);
+        assert_eq!(conf.shared_file_data.len(), expected.len());
+        for (i, (file, contents)) in expected.iter().enumerate() {
+            assert_eq!(&conf.shared_file_data[i].key, file);
+            assert_eq!(&conf.get_shared_file_data(file).expect("Missing file"), contents);
+        }
+    }
+
+    #[test]
+    fn mod2_can_remove_and_replace_shared_file_lines() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0AppendFile;
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["hello.txt"])];
+
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0: &mut L0AppendFile) {
+                        l0.append_to_file("hello.txt", "a", "keep".to_string());
+                        l0.append_to_file("hello.txt", "a", "retracted".to_string());
+                        l0.append_to_file("hello.txt", "a", "old".to_string());
+                        l0.remove_from_file("hello.txt", "a", "retracted".to_string());
+                        l0.remove_from_file("hello.txt", "a", "doesnt exist".to_string());
+                        l0.replace_in_file("hello.txt", "a", "old".to_string(), "new".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let mut conf = e2e_module2_run(&code, |_| {}).expect("Failed to compile");
+        let data = conf.get_shared_file_data("hello.txt").expect("Failed to find hello.txt");
+        assert_eq!(data, "a\nkeep\nnew\n");
+
+        let mut lines = vec!["a".to_string(), "b".to_string(), "a".to_string()];
+        assert!(HiraConfig::remove_shared_line(&mut lines, "a", None));
+        assert_eq!(lines, vec!["b".to_string()]);
+        assert!(!HiraConfig::remove_shared_line(&mut lines, "a", None));
+        assert!(!HiraConfig::remove_shared_line(&mut lines, "c", Some("d".to_string())));
+        assert!(HiraConfig::remove_shared_line(&mut lines, "b", Some("d".to_string())));
+        assert_eq!(lines, vec!["d".to_string()]);
+    }
+
+    #[test]
+    fn mod2_can_output_shared_files_to_subdirectories() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0AppendFile;
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["config/app.json"])];
+
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0: &mut L0AppendFile) {
+                        l0.append_to_file("config/app.json", "a", "{}".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code, |c| {
+            c.should_do_file_ops = true;
+            c.gen_directory = format!("{}/nested_gen", c.wasm_directory);
+        }).expect("Failed to compile");
+        let contents = std::fs::read_to_string(format!("{}/config/app.json", conf.gen_directory)).expect("Failed to read nested shared file");
+        assert_eq!(contents, "a\n{}\n");
+
+        assert!(validate_shared_file_path("config/app.json").is_ok());
+        assert!(validate_shared_file_path("/etc/passwd").is_err());
+        assert!(validate_shared_file_path("C:\\escape.txt").is_err());
+        assert!(validate_shared_file_path("config/../../escape.txt").is_err());
+        assert!(validate_shared_file_path("config//app.json").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_files_cannot_be_written_through_symlinks() {
+        let dir = std::path::PathBuf::from("./test_out/shared_symlinks");
+        let _ = std::fs::remove_dir_all(&dir);
+        let shared_dir = dir.join("gen");
+        let outside = dir.join("outside");
+        std::fs::create_dir_all(shared_dir.join("config")).expect("Failed to create shared dir");
+        std::fs::create_dir_all(&outside).expect("Failed to create outside dir");
+        std::os::unix::fs::symlink(outside.canonicalize().unwrap(), shared_dir.join("link")).expect("Failed to create symlink");
+        std::os::unix::fs::symlink(outside.canonicalize().unwrap().join("target.txt"), shared_dir.join("file.txt")).expect("Failed to create symlink");
+
+        assert_eq!(resolve_shared_file_path(&shared_dir, "config/app.json").unwrap(), shared_dir.join("config/app.json"));
+        assert_eq!(resolve_shared_file_path(&shared_dir, "new/nested/app.json").unwrap(), shared_dir.join("new/nested/app.json"));
+        let err = resolve_shared_file_path(&shared_dir, "link/nested/escape.txt").expect_err("Expected symlinked directory to be rejected");
+        assert_contains_str(err, "symlink");
+        // dangling symlinks are rejected as well, as writing to them would create the file they point to
+        assert!(resolve_shared_file_path(&shared_dir, "file.txt").is_err());
+        assert!(!outside.join("nested").exists());
+        assert!(!outside.join("target.txt").exists());
+    }
+
+    #[test]
+    fn mod2_cannot_output_shared_files_outside_gen_directory() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0AppendFile;
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["../escape.txt"])];
+
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0: &mut L0AppendFile) {
+                        l0.append_to_file("../escape.txt", "a", "escaped".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let err = e2e_module2_run(&code, |c| {
+            c.should_do_file_ops = true;
+            c.gen_directory = format!("{}/escape_gen", c.wasm_directory);
+        }).expect_err("Expected error");
+        assert_contains_str(err.to_string(), "outside the shared file directory");
+        assert!(!std::path::Path::new("test_out/escape.txt").exists());
+    }
+
+    #[test]
+    fn mod2_warns_if_multiple_modules_write_same_file_label() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod_a {
+                    use super::L0AppendFile;
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["hello.txt"])];
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0: &mut L0AppendFile) {
+                        l0.append_to_file("hello.txt", "a", "from_a".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod lvl2mod_b {
+                    use super::L0AppendFile;
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["hello.txt"])];
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0: &mut L0AppendFile) {
+                        l0.append_to_file("hello.txt", "a", "from_b".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod1 {
+                    use super::lvl2mod_a;
+                    pub fn config(input: &mut lvl2mod_a::Input) {}
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod2 {
+                    use super::lvl2mod_b;
+                    pub fn config(input: &mut lvl2mod_b::Input) {}
+                }
+            ),
+        ];
+        let (conf, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
+        assert_contains_str(stream.to_string(), "Multiple modules are appending to the same file label");
+        assert_contains_str(stream.to_string(), "lvl2mod_a");
+        assert_eq!(conf.shared_file_contributors[&("hello.txt".to_string(), "a".to_string())].len(), 2);
+    }
+
+    #[test]
+    fn mod2_doesnt_warn_if_multiple_modules_write_different_labels() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod_a {
+                    use super::L0AppendFile;
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["hello.txt"])];
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0: &mut L0AppendFile) {
+                        l0.append_to_file("hello.txt", "a", "from_a".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod lvl2mod_b {
+                    use super::L0AppendFile;
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["hello.txt"])];
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0: &mut L0AppendFile) {
+                        l0.append_to_file("hello.txt", "b", "from_b".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod1 {
+                    use super::lvl2mod_a;
+                    pub fn config(input: &mut lvl2mod_a::Input) {}
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod2 {
+                    use super::lvl2mod_b;
+                    pub fn config(input: &mut lvl2mod_b::Input) {}
+                }
+            ),
+        ];
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
+        assert!(!stream.to_string().contains("Multiple modules are appending to the same file label"));
+    }
+
+    #[test]
+    fn runtime_entrypoint_can_use_custom_builder() {
+        let mut meta = RuntimeMeta::default();
+        let (tokens, _, _) = HiraConfig::generate_runtime_entrypoint("myruntime", "dir", &meta).expect("Failed to generate entrypoint");
+        assert_contains_str(tokens.to_string(), "tokio :: main");
+
+        meta.runtime_builder = Some("tokio::runtime::Builder::new_multi_thread().worker_threads(2).thread_name(\"myworker\").enable_all().build().unwrap().block_on(hira_runtime_body)".to_string());
+        let (tokens, _, _) = HiraConfig::generate_runtime_entrypoint("myruntime", "dir", &meta).expect("Failed to generate entrypoint");
+        let tokens = tokens.to_string();
+        assert!(!tokens.contains("tokio :: main"));
+        assert_contains_str(&tokens, "worker_threads (2)");
+        assert_contains_str(&tokens, "let hira_runtime_body = async move");
+
+        meta.runtime_builder = Some("this is not ( valid rust".to_string());
+        assert!(HiraConfig::generate_runtime_entrypoint("myruntime", "dir", &meta).is_err());
+        meta.runtime_builder = Some("tokio::runtime::Runtime::new().unwrap()".to_string());
+        assert!(HiraConfig::generate_runtime_entrypoint("myruntime", "dir", &meta).is_err());
+    }
+
+    #[test]
+    fn sync_runtime_entrypoint_includes_the_same_files() {
+        let mut meta = RuntimeMeta::default();
+        let (async_tokens, async_code_file, async_data_file) = HiraConfig::generate_runtime_entrypoint("myruntime", "dir", &meta).expect("Failed to generate entrypoint");
+        meta.no_tokio_async_runtime = true;
+        let (sync_tokens, sync_code_file, sync_data_file) = HiraConfig::generate_runtime_entrypoint("myruntime", "dir", &meta).expect("Failed to generate entrypoint");
+        assert_eq!(async_code_file, sync_code_file);
+        assert_eq!(async_data_file, sync_data_file);
+
+        let sync_tokens = sync_tokens.to_string();
+        assert!(!sync_tokens.contains("tokio"));
+        assert!(!sync_tokens.contains("async"));
+        assert!(!sync_tokens.contains("await"));
+        assert_contains_str(&sync_tokens, "fn main ()");
+        // only the main function header differs
+        let async_tokens = async_tokens.to_string().replace("# [tokio :: main] async fn main ()", "fn main ()");
+        assert_eq!(async_tokens, sync_tokens);
+    }
+
+    #[test]
+    fn mod2_scratch_dir_is_module_specific() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::{L0Core, L0Scratch};
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("SCRATCH", &[""])];
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub mod outputs {
+                        pub const SCRATCH: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core, scratch: &mut L0Scratch) {
+                        l0core.set_output("SCRATCH", &scratch.scratch_dir().to_string_lossy());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod1 {
+                    use super::lvl2mod;
+                    pub mod outputs {
+                        pub use lvl2mod::outputs::*;
+                    }
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod2 {
+                    use super::lvl2mod;
+                    pub mod outputs {
+                        pub use lvl2mod::outputs::*;
+                    }
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code, |c| {
+            c.hira_directory = c.wasm_directory.clone();
+            c.should_do_file_ops = true;
+        }).expect("Failed to compile");
+        let tmp_dir = format!("{}/{HIRA_TMP_DIR_NAME}", conf.hira_directory);
+        let scratch1 = &conf.get_mod2("mylevel3mod1").expect("Failed to find mylevel3mod1").resolved_outputs["SCRATCH"];
+        let scratch2 = &conf.get_mod2("mylevel3mod2").expect("Failed to find mylevel3mod2").resolved_outputs["SCRATCH"];
+        assert_eq!(*scratch1, format!("{tmp_dir}/mylevel3mod1"));
+        assert_eq!(*scratch2, format!("{tmp_dir}/mylevel3mod2"));
+        assert!(std::path::Path::new(scratch1).is_dir());
+        assert!(std::path::Path::new(scratch2).is_dir());
+    }
+
+    #[test]
+    fn mod2_after_attribute_orders_modules() {
+        let code = [
+            stringify!(
+                pub mod seeder {
+                    use super::L0KvReader;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub val: String,
+                    }
+                    pub fn config(input: &mut Input, kv: &mut L0KvReader) {
+                        kv.insert("seeded".to_string(), input.val.clone());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod reader {
+                    use super::{L0Core, L0KvReader};
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub mod outputs {
+                        pub const SEEDED: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core, kv: &mut L0KvReader) {
+                        match kv.get("seeded") {
+                            Some(val) => l0core.set_output("SEEDED", &val),
+                            None => l0core.compiler_error("seeded was not set"),
+                        }
+                    }
+                }
+            ),
+            // declared before myseeder, but must be processed after it
+            stringify!(
+                #[hira(after = "myseeder")]
+                pub mod myreader {
+                    use super::reader;
+                    pub mod outputs {
+                        pub use reader::outputs::*;
+                    }
+                    pub fn config(input: &mut reader::Input) {}
+                }
+            ),
+            stringify!(
+                pub mod myseeder {
+                    use super::seeder;
+                    pub fn config(input: &mut seeder::Input) {
+                        input.val = "hello".to_string();
+                    }
+                }
+            ),
+        ];
+        let conf = e2e_module2_run_ordered(&code, |_| {}).expect("Failed to compile");
+        let module = conf.get_mod2("myreader").expect("Failed to find myreader");
+        assert_eq!(module.resolved_outputs["SEEDED"], "hello");
+        // cargo expands the modules in source order, so myreader is expanded before myseeder exists
+        let err = e2e_module2_run(&code, |_| {}).err().expect("Expected myseeder to be missing");
+        assert_contains_str(err.to_string(), "must be processed after 'myseeder'");
+    }
+
+    #[test]
+    fn dotenv_diagnostics_are_collected() {
+        let contents = "# comment\nA=1\n\nnot a key value\nB=2\nA=3\n1BAD=x\nC = spaced\n";
+        let (map, diagnostics) = L0Core::parse_dotenv(contents);
+        assert_eq!(map["A"], "3");
+        assert_eq!(map["B"], "2");
+        assert_eq!(map["C"], " spaced");
+        assert_eq!(map.len(), 3);
+        assert_eq!(diagnostics, vec![
+            "line 4: expected KEY=value, found 'not a key value'".to_string(),
+            "line 6: duplicate key 'A' (previously set on line 2). The last value is used".to_string(),
+            "line 7: invalid key '1BAD'. Keys can only contain letters, digits, and underscores, and cannot start with a digit".to_string(),
+        ]);
+        let (_, diagnostics) = L0Core::parse_dotenv("A=1\nB=2\n");
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn dotenv_quoted_values_are_unescaped() {
+        let contents = [
+            r#"A="say \"hi\" to \$USER""#,
+            r#"B="a\nb\\c" # comment"#,
+            "C='literal \\n $x'",
+            "D=\"a=b#c\"",
+            "E=\"unterminated",
+            "F=\"a\" b",
+        ].join("\n");
+        let (map, diagnostics) = L0Core::parse_dotenv(&contents);
+        assert_eq!(map["A"], r#"say "hi" to $USER"#);
+        assert_eq!(map["B"], "a\nb\\c");
+        assert_eq!(map["C"], "literal \\n $x");
+        assert_eq!(map["D"], "a=b#c");
+        assert_eq!(map.len(), 4);
+        assert_eq!(diagnostics, vec![
+            "line 5: missing the closing quote of \"unterminated".to_string(),
+            "line 6: unexpected characters after the closing quote: 'b'".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn mod2_priority_attribute_orders_modules() {
+        let code = [
+            stringify!(
+                pub mod seeder {
+                    use super::L0KvReader;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub val: String,
+                    }
+                    pub fn config(input: &mut Input, kv: &mut L0KvReader) {
+                        kv.insert("seeded".to_string(), input.val.clone());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod reader {
+                    use super::{L0Core, L0KvReader};
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub mod outputs {
+                        pub const SEEDED: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core, kv: &mut L0KvReader) {
+                        match kv.get("seeded") {
+                            Some(val) => l0core.set_output("SEEDED", &val),
+                            None => l0core.compiler_error("seeded was not set"),
+                        }
+                    }
+                }
+            ),
+            // declared first, but has a higher priority number so it is processed last
+            stringify!(
+                #[hira(priority = 2)]
+                pub mod myreader {
+                    use super::reader;
+                    pub mod outputs {
+                        pub use reader::outputs::*;
+                    }
+                    pub fn config(input: &mut reader::Input) {}
+                }
+            ),
+            stringify!(
+                #[hira(priority = 1)]
+                pub mod myseeder {
+                    use super::seeder;
+                    pub fn config(input: &mut seeder::Input) {
+                        input.val = "hello".to_string();
+                    }
+                }
+            ),
+        ];
+        let conf = e2e_module2_run_ordered(&code, |_| {}).expect("Failed to compile");
+        let module = conf.get_mod2("myreader").expect("Failed to find myreader");
+        assert_eq!(module.resolved_outputs["SEEDED"], "hello");
+        assert_eq!(module.priority, Some(2));
+    }
+
+    #[test]
+    fn mod2_priority_is_verified_in_source_order() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0Core;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {}
+                }
+            ),
+            stringify!(
+                #[hira(priority = 1)]
+                pub mod first {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+            stringify!(
+                #[hira(priority = 2)]
+                pub mod second {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let mut conf = e2e_module2_run(&code, |_| {}).expect("Failed to compile");
+        // expanding the crate again (eg: by an IDE) must not compare against the previous expansion
+        for code in code.iter().skip(1) {
+            let code = TokenStream::from_str(code).expect("Failed to parse test case code");
+            hira_mod2_inner(&mut conf, code, TokenStream::new()).expect("Re-expanding should not error");
+        }
+
+        let out_of_order = [code[0], code[2], code[1]];
+        let err = e2e_module2_run(&out_of_order, |_| {}).err().expect("Expected priority to be out of order");
+        assert_contains_str(err.to_string(), "Module 'first' has priority 1, so it must be processed before 'second'");
+        e2e_module2_run_ordered(&out_of_order, |_| {}).expect("The CLI orders modules by priority");
+
+        // modules without a priority are verified the same way they are sorted, ie: with the default priority
+        let unset = stringify!(
+            pub mod unset {
+                use super::lvl2mod;
+                pub fn config(input: &mut lvl2mod::Input) {}
+            }
+        );
+        let out_of_order = [code[0], code[1], unset];
+        let err = e2e_module2_run(&out_of_order, |_| {}).err().expect("Expected priority to be out of order");
+        assert_contains_str(err.to_string(), "Module 'unset' has priority 0, so it must be processed before 'first'");
+        e2e_module2_run_ordered(&out_of_order, |_| {}).expect("The CLI orders modules by priority");
+    }
+
+    #[test]
+    fn mod2_priority_must_be_an_integer() {
+        let mut conf = HiraConfig::default();
+        let code = TokenStream::from_str(stringify!(
+            pub mod a {
+                use super::lvl2mod;
+                pub fn config(input: &mut lvl2mod::Input) {}
+            }
+        )).expect("Failed to parse test case code");
+        let err = hira_mod2_inner(&mut conf, code, TokenStream::from_str("priority = \"high\"").unwrap()).err().expect("Expected priority to be invalid");
+        assert_contains_str(err.to_string(), "Expected an integer");
+        for invalid in ["1.5", "a", "-\"1\""] {
+            let err = crate::parsing::parse_hira_attr_args(TokenStream::from_str(&format!("priority = {invalid}")).unwrap()).err().expect("Expected priority to be invalid");
+            assert_contains_str(err, "Expected an integer");
+        }
+        assert_eq!(crate::parsing::parse_hira_attr_args(TokenStream::from_str("priority = -3").unwrap()).unwrap().priority, Some(-3));
+        assert_eq!(crate::parsing::parse_hira_attr_args(TokenStream::from_str("priority = 10").unwrap()).unwrap().priority, Some(10));
+    }
+
+    #[test]
+    fn mod2_can_see_sibling_modules() {
+        let code = [
+            stringify!(
+                pub mod summary {
+                    use super::L0Core;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub mod outputs {
+                        pub const SIBLINGS: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                        let siblings = l0core.sibling_modules().join(",");
+                        l0core.set_output("SIBLINGS", &siblings);
+                    }
+                }
+            ),
+            stringify!(
+                pub mod first {
+                    use super::summary;
+                    pub mod outputs {
+                        pub use summary::outputs::*;
+                    }
+                    pub fn config(input: &mut summary::Input) {}
+                }
+            ),
+            stringify!(
+                pub mod second {
+                    use super::summary;
+                    pub fn config(input: &mut summary::Input) {}
+                }
+            ),
+            stringify!(
+                #[hira(after = "first", after = "second")]
+                pub mod mysummary {
+                    use super::summary;
+                    pub mod outputs {
+                        pub use summary::outputs::*;
+                    }
+                    pub fn config(input: &mut summary::Input) {}
+                }
+            ),
+        ];
+        let mut conf = e2e_module2_run(&code, |_| {}).expect("Failed to compile");
+        let module = conf.get_mod2("mysummary").expect("Failed to find mysummary");
+        assert_eq!(module.resolved_outputs["SIBLINGS"], "first,second,summary");
+        let module = conf.get_mod2("first").expect("Failed to find first");
+        assert_eq!(module.resolved_outputs["SIBLINGS"], "summary");
+
+        // expanding the crate again (eg: by an IDE) must not see the modules from the previous expansion
+        for code in code.iter() {
+            let code = TokenStream::from_str(code).expect("Failed to parse test case code");
+            hira_mod2_inner(&mut conf, code, TokenStream::new()).expect("Re-expanding should not error");
+        }
+        let module = conf.get_mod2("first").expect("Failed to find first");
+        assert_eq!(module.resolved_outputs["SIBLINGS"], "summary");
+        let module = conf.get_mod2("mysummary").expect("Failed to find mysummary");
+        assert_eq!(module.resolved_outputs["SIBLINGS"], "first,second,summary");
+    }
+
+    #[test]
+    fn mod2_source_file_is_recorded() {
+        let code = [
+            stringify!(
+                pub mod whereami {
+                    use super::L0Core;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub mod outputs {
+                        pub const FILE: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                        let file = l0core.module_span_info().file;
+                        l0core.set_output("FILE", &file);
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mymod {
+                    use super::whereami;
+                    pub mod outputs {
+                        pub use whereami::outputs::*;
+                    }
+                    pub fn config(input: &mut whereami::Input) {}
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code, |conf| {
+            conf.source_file = "src/mymod.rs".to_string();
+        }).expect("Failed to compile");
+        let module = conf.get_mod2("mymod").expect("Failed to find mymod");
+        assert_eq!(module.source_file, "src/mymod.rs");
+        assert_eq!(module.resolved_outputs["FILE"], "src/mymod.rs");
+    }
+
+    #[test]
+    fn mod2_duplicate_module_names_across_files_error() {
+        let code = stringify!(
+            pub mod lvl2mod {
+                use super::L0Core;
+                #[derive(Default)]
+                pub struct Input {
+                    pub _unused: bool,
+                }
+                pub fn config(input: &mut Input, l0core: &mut L0Core) {}
+            }
+        );
+        let mut conf = HiraConfig::default();
+        conf.dont_cache_modules = true;
+        let expand = |conf: &mut HiraConfig, file: &str| {
+            conf.source_file = file.to_string();
+            let code = TokenStream::from_str(code).expect("Failed to parse test case code");
+            hira_mod2_inner(conf, code, TokenStream::new())
+        };
+        expand(&mut conf, "src/a.rs").expect("Failed to compile");
+        // expanding the crate again (eg: by an IDE) is not a duplicate
+        expand(&mut conf, "src/a.rs").expect("Re-expanding should not error");
+        let err = expand(&mut conf, "src/b.rs").err().expect("Expected a duplicate name error");
+        assert_contains_str(err.to_string(), r#"Module 'lvl2mod' is defined in both \"src/a.rs\" and \"src/b.rs\""#);
+    }
+
+    #[test]
+    fn mod2_after_attribute_cycles_error() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0Core;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {}
+                }
+            ),
+            stringify!(
+                #[hira(after = "b")]
+                pub mod a {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+            stringify!(
+                #[hira(after = "a")]
+                pub mod b {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let err = e2e_module2_run_ordered(&code, |_| {}).err().expect("Expected cycle to error");
+        assert_contains_str(err.to_string(), "cycle");
+    }
+
+    #[test]
+    fn mod2_after_attribute_requires_module_to_be_loaded() {
+        let mut conf = HiraConfig::default();
+        let code = TokenStream::from_str(stringify!(
+            pub mod a {
+                use super::lvl2mod;
+                pub fn config(input: &mut lvl2mod::Input) {}
+            }
+        )).expect("Failed to parse test case code");
+        let err = hira_mod2_inner(&mut conf, code, TokenStream::from_str("after = \"b\"").unwrap()).err().expect("Expected b to be missing");
+        assert_contains_str(err.to_string(), "must be processed after 'b'");
+    }
+
+    #[test]
+    fn mod2_kv_data_is_visible_to_later_modules() {
+        let code = [
+            stringify!(
+                pub mod fakelambda {
+                    use super::L0KvReader;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub path: String,
+                    }
+                    pub fn config(input: &mut Input, kv: &mut L0KvReader) {
+                        kv.insert(format!("desired_path:{}", input.path), input.path.clone());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod fakedistr {
+                    use super::{L0Core, L0KvReader};
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub mod outputs {
+                        pub const PATHS: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core, kv: &mut L0KvReader) {
+                        let paths: Vec<String> = kv.get_with_prefix("desired_path:").into_iter().map(|(_, v)| v).collect();
+                        l0core.set_output("PATHS", &paths.join(","));
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylambda1 {
+                    use super::fakelambda;
+                    pub fn config(input: &mut fakelambda::Input) {
+                        input.path = "/b".to_string();
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylambda2 {
+                    use super::fakelambda;
+                    pub fn config(input: &mut fakelambda::Input) {
+                        input.path = "/a".to_string();
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mydistr {
+                    use super::fakedistr;
+                    pub mod outputs {
+                        pub use fakedistr::outputs::*;
+                    }
+                    pub fn config(input: &mut fakedistr::Input) {}
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code, |_| {}).expect("Failed to compile");
+        let module = conf.get_mod2("mydistr").expect("Failed to find mydistr");
+        assert_eq!(module.resolved_outputs["PATHS"], "/a,/b");
+        assert_eq!(conf.shared_data.len(), 2);
+    }
+
+    #[test]
+    fn mod2_kv_data_cant_be_overwritten() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0KvReader;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub val: String,
+                    }
+                    pub fn config(input: &mut Input, kv: &mut L0KvReader) {
+                        kv.insert("mykey".to_string(), input.val.clone());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod1 {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.val = "a".to_string();
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod2 {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.val = "b".to_string();
+                    }
+                }
+            ),
+        ];
+        let err = e2e_module2_run(&code, |_| {}).expect_err("Expected error");
+        assert_contains_str(err.to_string(), "attempted to change shared data key 'mykey'");
+    }
+
+
+    #[test]
+    fn mod2_can_output_compiler_errors() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0Core;
+
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                        l0core.compiler_error("this is a custom error");
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Test case compilation failed");
+        let stream_text = stream.to_string();
+        assert_contains_str(stream_text, "this is a custom error");
+    }
+
+    #[test]
+    fn mod2_can_output_compiler_warnings() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0Core;
+
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                        l0core.compiler_warning("this is a custom warning");
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Test case compilation failed");
+        let stream_text = stream.to_string();
+        assert_contains_str(stream_text, "this is a custom warning");
+    }
+
+    #[test]
+    fn runtime_data_with_raw_string_delimiters_round_trips() {
+        use quote::ToTokens;
+        let dir = std::path::PathBuf::from("./test_out/raw_string_include");
+        let _ = std::fs::create_dir_all(&dir);
+        let include_file = dir.join("runtime.rs.txt").to_string_lossy().to_string();
+        let data_include_file = dir.join("runtime_data.rs.txt").to_string_lossy().to_string();
+        let data = vec![
+            "plain".to_string(),
+            r###"{"key":"ends with quote hash"#}"###.to_string(),
+            r###"two "## hashes"###.to_string(),
+        ];
+        let variables = vec![("myvar".to_string(), r##"a"#b"##.to_string())];
+        HiraConfig::output_include_files(&include_file, &data_include_file, &vec!["myvar".to_string()], &data, &variables)
+            .expect("Failed to write include files");
+
+        let contents = std::fs::read_to_string(&data_include_file).expect("Failed to read data include file");
+        let array: syn::ExprArray = syn::parse_str(&contents).expect("Data include file should be a valid array expression");
+        let parsed: Vec<String> = array.elems.iter().map(|x| match x {
+            syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(s), .. }) => s.value(),
+            _ => panic!("Expected a string literal, found {}", x.to_token_stream()),
+        }).collect();
+        assert_eq!(parsed, data);
+        assert_contains_str(&contents, "r#\"plain\"#");
+
+        let contents = std::fs::read_to_string(&include_file).expect("Failed to read include file");
+        let block: syn::Block = syn::parse_str(&contents).expect("Include file should be a valid block");
+        assert_contains_str(block.to_token_stream().to_string(), "r##\"a\"#b\"##");
+    }
+
+    #[test]
+    fn mod2_structured_result_has_outputs_and_warnings() {
+        let lvl2 = stringify!(
+            pub mod lvl2mod {
+                use super::L0Core;
+                #[derive(Default)]
+                pub struct Input {
+                    pub region: String,
+                }
+                pub mod outputs {
+                    pub const REGION: &str = "";
+                }
+                pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                    l0core.set_output("REGION", input.region.as_str());
+                    l0core.compiler_warning("region is hardcoded");
+                }
+            }
+        );
+        let lvl3 = stringify!(
+            pub mod mylevel3mod {
+                use super::lvl2mod;
+                pub mod outputs {
+                    pub use lvl2mod::outputs::*;
+                }
+                pub fn config(input: &mut lvl2mod::Input) {
+                    input.region = "us-east-2".to_string();
+                }
+            }
+        );
+        let mut conf = e2e_module2_run(&[lvl2], |_| {}).expect("Failed to compile");
+        let code = TokenStream::from_str(lvl3).expect("Failed to parse test case code");
+        let result = crate::module_loading::hira_mod2_structured(&mut conf, code, TokenStream::new()).expect("Failed to compile");
+        assert_eq!(result.name, "mylevel3mod");
+        assert_eq!(result.resolved_outputs["REGION"], "us-east-2");
+        assert_eq!(result.warnings, vec!["region is hardcoded"]);
+        assert_contains_str(result.stream.to_string(), "region is hardcoded");
+        assert_eq!(conf.get_mod2("mylevel3mod").expect("Failed to find mylevel3mod").warnings, result.warnings);
+    }
+
+    #[test]
+    fn mod2_can_write_functions_outside_of_the_module() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0CodeWriter;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: u32,
+                    }
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("CODE_WRITE", &["fn_global:main"])];
+
+                    pub fn config(input: &mut Input, l0writer: &mut L0CodeWriter) {
+                        l0writer.write_global_fn("pub fn main()".to_string(), "".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
+        let stream_str = stream.to_string();
+        assert_contains_str(stream_str, "pub fn main ()");
+    }
+
+    #[test]
+    fn mod2_can_write_functions_inside_of_the_module() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0CodeWriter;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: u32,
+                    }
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("CODE_WRITE", &["fn_module:heyo"])];
+
+                    pub fn config(input: &mut Input, l0writer: &mut L0CodeWriter) {
+                        l0writer.write_internal_fn("pub fn heyo()".to_string(), "".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
+        let stream_str = stream.to_string();
+        // we add an extra bracket to check if this is truly inside the module.
+        // ie: if its the last function in the module then we expect to see something like:
+        // pub mod ... {
+        // ...
+        // pub fn heyo() { }
+        // }
+        // ^ we are looking for this
+        assert_contains_str(stream_str, "pub fn heyo () { } }");
+    }
+
+    #[test]
+    fn mod2_can_write_structs_and_impls() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0CodeWriter;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: u32,
+                    }
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("CODE_WRITE", &["struct_global:MyError", "impl_global:MyError", "struct_module:Inner"])];
+
+                    pub fn config(input: &mut Input, l0writer: &mut L0CodeWriter) {
+                        l0writer.write_global_struct("#[derive(Debug)] pub enum MyError".to_string(), "Io(std::io::Error)".to_string());
+                        l0writer.write_global_impl("impl From<std::io::Error> for MyError".to_string(), "fn from(e: std::io::Error) -> Self { MyError::Io(e) }".to_string());
+                        l0writer.write_internal_struct("pub struct Inner".to_string(), "pub a: u32".to_string());
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
+        let stream_str = stream.to_string();
+        // the internal struct is the last item inside the module
+        assert_contains_str(&stream_str, "pub struct Inner { pub a : u32 } }");
+        assert_contains_str(&stream_str, "# [derive (Debug)] pub enum MyError { Io (std :: io :: Error) }");
+        assert_contains_str(&stream_str, "impl From < std :: io :: Error > for MyError { fn from (e : std :: io :: Error) -> Self { MyError :: Io (e) } }");
+
+        // without the capability for this specific name, it fails
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::L0CodeWriter;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: u32,
+                    }
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("CODE_WRITE", &["struct_global:MyError"])];
+
+                    pub fn config(input: &mut Input, l0writer: &mut L0CodeWriter) {
+                        l0writer.write_global_struct("pub struct OtherStruct".to_string(), "".to_string());
+                    }
+                }
+            ),
+            code[1],
+        ];
+        let err = e2e_module2_run_with_token_stream(&code, |_| {}).err().expect("Expected missing capability to error");
+        assert_contains_str(err.to_string(), "no struct_global:OtherStruct capability was defined");
+    }
+
+    #[test]
+    fn mod2_can_write_output_consts() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::{L0Core, L0CodeWriter};
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub name: String,
+                    }
+                    pub mod outputs {
+                        pub const BUCKET_NAME: &str = "UNDEFINED";
+                        pub const OTHER: &str = "UNDEFINED";
+                    }
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("CODE_WRITE", &["const_module:BUCKET_NAME", "const_module:OTHER"])];
+
+                    pub fn config(input: &mut Input, l0core: &mut L0Core, l0writer: &mut L0CodeWriter) {
+                        if !input.name.is_empty() {
+                            l0core.set_output("BUCKET_NAME", &format!("hiragen{}", input.name));
+                            l0writer.write_output_const("BUCKET_NAME");
+                        } else {
+                            l0writer.write_output_const("OTHER");
+                        }
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub mod outputs {
+                        pub use lvl2mod::outputs::*;
+                    }
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.name = "mybucket".to_string();
+                    }
+                }
+            ),
+        ];
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
+        assert_contains_str(stream.to_string(), "pub const BUCKET_NAME : & str = \"hiragenmybucket\" ;");
+
+        // OTHER is never set, so it still has its placeholder value
+        let code = [code[0], stringify!(
+            pub mod mylevel3mod {
+                use super::lvl2mod;
+                pub mod outputs {
+                    pub use lvl2mod::outputs::*;
+                }
+                pub fn config(input: &mut lvl2mod::Input) {}
+            }
+        )];
+        let err = e2e_module2_run_with_token_stream(&code, |_| {}).err().expect("Expected unset output to error");
+        assert_contains_str(err.to_string(), "placeholder value");
+    }
 
     #[test]
-    fn mod2_can_output_compiler_errors() {
+    fn mod2_can_write_static_asserts() {
         let code = [
             stringify!(
                 pub mod lvl2mod {
-                    use super::L0Core;
-
+                    use super::L0CodeWriter;
                     #[derive(Default)]
                     pub struct Input {
-                        pub _unused: bool,
+                        pub expr: String,
                     }
-                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
-                        l0core.compiler_error("this is a custom error");
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("CODE_WRITE", &["assert"])];
+                    pub fn config(input: &mut Input, l0writer: &mut L0CodeWriter) {
+                        l0writer.write_static_assert(input.expr.clone());
                     }
                 }
             ),
             stringify!(
                 pub mod mylevel3mod {
                     use super::lvl2mod;
-                    pub fn config(input: &mut lvl2mod::Input) {}
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.expr = "std::mem::size_of::<u32>() <= 8".to_string();
+                    }
                 }
             ),
         ];
-        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Test case compilation failed");
-        let stream_text = stream.to_string();
-        assert_contains_str(stream_text, "this is a custom error");
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
+        assert_contains_str(stream.to_string(), "const _ : () = assert ! (std :: mem :: size_of :: < u32 > () <= 8) ;");
+
+        let code = [code[0], stringify!(
+            pub mod mylevel3mod {
+                use super::lvl2mod;
+                pub fn config(input: &mut lvl2mod::Input) {
+                    input.expr = "this is not ( valid".to_string();
+                }
+            }
+        )];
+        let err = e2e_module2_run_with_token_stream(&code, |_| {}).err().expect("Expected invalid expression to error");
+        assert_contains_str(err.to_string(), "invalid static assertion expression");
     }
 
     #[test]
-    fn mod2_can_output_compiler_warnings() {
+    fn mod2_can_provide_requested_fn_signatures() {
         let code = [
             stringify!(
                 pub mod lvl2mod {
-                    use super::L0Core;
+                    use super::L0CodeReader;
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[
+                        ("CODE_READ", &["fn:hello_world"])
+                    ];
 
                     #[derive(Default)]
                     pub struct Input {
                         pub _unused: bool,
                     }
-                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
-                        l0core.compiler_warning("this is a custom warning");
+                    pub fn config(input: &mut Input, l0: &mut L0CodeReader) {
+                        // test will fail if this unwrap panics:
+                        l0.get_fn("hello_world").unwrap();
                     }
                 }
             ),
@@ -998,29 +3798,35 @@ pub mod e2e_tests {
                 pub mod mylevel3mod {
                     use super::lvl2mod;
                     pub fn config(input: &mut lvl2mod::Input) {}
+
+                    pub fn hello_world() {}
                 }
             ),
         ];
-        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Test case compilation failed");
-        let stream_text = stream.to_string();
-        assert_contains_str(stream_text, "this is a custom warning");
+        let res = e2e_module2_run(&code,|_| {});
+        assert!(res.is_ok());
     }
 
+
     #[test]
-    fn mod2_can_write_functions_outside_of_the_module() {
+    fn mod2_can_list_all_fns() {
         let code = [
             stringify!(
                 pub mod lvl2mod {
-                    use super::L0CodeWriter;
+                    use super::L0CodeReader;
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[
+                        ("CODE_READ", &["fn:*"])
+                    ];
+
                     #[derive(Default)]
                     pub struct Input {
-                        pub _unused: u32,
+                        pub _unused: bool,
                     }
-
-                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("CODE_WRITE", &["fn_global:main"])];
-
-                    pub fn config(input: &mut Input, l0writer: &mut L0CodeWriter) {
-                        l0writer.write_global_fn("pub fn main()".to_string(), "".to_string());
+                    pub fn config(input: &mut Input, l0: &mut L0CodeReader) {
+                        // test will fail if any of these panic:
+                        assert_eq!(l0.list_fns(), &["config".to_string(), "get_user".to_string(), "post_user".to_string()]);
+                        assert_eq!(l0.get_fn("post_user").unwrap().inputs.len(), 1);
                     }
                 }
             ),
@@ -1028,60 +3834,50 @@ pub mod e2e_tests {
                 pub mod mylevel3mod {
                     use super::lvl2mod;
                     pub fn config(input: &mut lvl2mod::Input) {}
+
+                    pub fn get_user() {}
+                    pub fn post_user(body: String) {}
                 }
             ),
         ];
-        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
-        let stream_str = stream.to_string();
-        assert_contains_str(stream_str, "pub fn main ()");
-    }
+        let res = e2e_module2_run(&code,|_| {});
+        assert!(res.is_ok());
 
-    #[test]
-    fn mod2_can_write_functions_inside_of_the_module() {
+        // without the wildcard, nothing is listed
         let code = [
             stringify!(
                 pub mod lvl2mod {
-                    use super::L0CodeWriter;
+                    use super::L0CodeReader;
+
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[
+                        ("CODE_READ", &["fn:get_user"])
+                    ];
+
                     #[derive(Default)]
                     pub struct Input {
-                        pub _unused: u32,
+                        pub _unused: bool,
                     }
-
-                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("CODE_WRITE", &["fn_module:heyo"])];
-
-                    pub fn config(input: &mut Input, l0writer: &mut L0CodeWriter) {
-                        l0writer.write_internal_fn("pub fn heyo()".to_string(), "".to_string());
+                    pub fn config(input: &mut Input, l0: &mut L0CodeReader) {
+                        assert!(l0.list_fns().is_empty());
+                        assert!(l0.get_fn("get_user").is_some());
                     }
                 }
             ),
-            stringify!(
-                pub mod mylevel3mod {
-                    use super::lvl2mod;
-                    pub fn config(input: &mut lvl2mod::Input) {}
-                }
-            ),
+            code[1],
         ];
-        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
-        let stream_str = stream.to_string();
-        // we add an extra bracket to check if this is truly inside the module.
-        // ie: if its the last function in the module then we expect to see something like:
-        // pub mod ... {
-        // ...
-        // pub fn heyo() { }
-        // }
-        // ^ we are looking for this
-        assert_contains_str(stream_str, "pub fn heyo () { } }");
+        let res = e2e_module2_run(&code,|_| {});
+        assert!(res.is_ok());
     }
 
     #[test]
-    fn mod2_can_provide_requested_fn_signatures() {
+    fn mod2_can_provide_requested_struct_definitions() {
         let code = [
             stringify!(
                 pub mod lvl2mod {
                     use super::L0CodeReader;
 
                     pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[
-                        ("CODE_READ", &["fn:hello_world"])
+                        ("CODE_READ", &["struct:User"])
                     ];
 
                     #[derive(Default)]
@@ -1089,8 +3885,17 @@ pub mod e2e_tests {
                         pub _unused: bool,
                     }
                     pub fn config(input: &mut Input, l0: &mut L0CodeReader) {
-                        // test will fail if this unwrap panics:
-                        l0.get_fn("hello_world").unwrap();
+                        // test will fail if any of these panic:
+                        let user = l0.get_struct("User").unwrap();
+                        assert_eq!(user.documentation, "a user");
+                        assert_eq!(user.fields.len(), 2);
+                        assert_eq!(user.fields[0].name, "id");
+                        assert_eq!(user.fields[0].ty, "u64");
+                        assert_eq!(user.fields[0].documentation, "unique id");
+                        assert_eq!(user.fields[1].name, "name");
+                        assert_eq!(user.fields[1].ty, "Option < String >");
+                        // not requested:
+                        assert!(l0.get_struct("Other").is_none());
                     }
                 }
             ),
@@ -1099,7 +3904,13 @@ pub mod e2e_tests {
                     use super::lvl2mod;
                     pub fn config(input: &mut lvl2mod::Input) {}
 
-                    pub fn hello_world() {}
+                    /// a user
+                    pub struct User {
+                        /// unique id
+                        pub id: u64,
+                        pub name: Option<String>,
+                    }
+                    pub struct Other {}
                 }
             ),
         ];
@@ -1107,7 +3918,6 @@ pub mod e2e_tests {
         assert!(res.is_ok());
     }
 
-
     #[test]
     fn mod2_can_use_hiracfg_safely() {
         let code = [
@@ -1454,6 +4264,49 @@ pub mod e2e_tests {
         assert_contains_str(err_str, "requested to use runtime hello but no RUNTIME capability was found");
     }
 
+    #[test]
+    fn mod2_can_only_use_granted_runtimes() {
+        let make_code = |third_runtime: &str| {
+            let lvl2 = stringify!(
+                pub mod lvl2mod {
+                    use super::L0RuntimeCreator;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub use_third: bool,
+                    }
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("RUNTIME", &["deploy", "migrate"])];
+                    pub fn config(input: &mut Input, l0r: &mut L0RuntimeCreator) {
+                        l0r.add_to_runtime("migrate", "migrate();".to_string());
+                        l0r.add_to_runtime("deploy", "deploy();".to_string());
+                        if input.use_third {
+                            l0r.add_to_runtime("THIRD", "third();".to_string());
+                        }
+                    }
+                }
+            ).replace("THIRD", third_runtime);
+            let lvl3 = stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.use_third = true;
+                    }
+                }
+            ).to_string();
+            [lvl2, lvl3]
+        };
+        // re-using a granted runtime is fine
+        let code = make_code("deploy");
+        let code: Vec<&str> = code.iter().map(|x| x.as_str()).collect();
+        let conf = e2e_module2_run(&code, |_| {}).expect("Failed to compile");
+        assert!(conf.runtimes.contains_key("deploy"));
+        assert!(conf.runtimes.contains_key("migrate"));
+
+        let code = make_code("cleanup");
+        let code: Vec<&str> = code.iter().map(|x| x.as_str()).collect();
+        let err = e2e_module2_run(&code, |_| {}).err().expect("Expected ungranted runtime to fail");
+        assert_contains_str(err.to_string(), "requested to use runtime cleanup but its RUNTIME capability only grants: deploy, migrate");
+    }
+
     #[cfg(feature = "wasm")]
     #[test]
     fn mod2_can_set_runtimes() {
@@ -1484,6 +4337,65 @@ pub mod e2e_tests {
         assert_eq!(conf.runtimes["hello"].2[0], "world();");
     }
 
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn mod2_sync_runtimes_dont_use_tokio() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::{L0RuntimeCreator, RuntimeMeta};
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub echo: String,
+                    }
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("RUNTIME", &[""])];
+                    pub fn config(input: &mut Input, l0r: &mut L0RuntimeCreator) {
+                        let meta = RuntimeMeta { no_tokio_async_runtime: true, ..Default::default() };
+                        l0r.add_to_runtime_ex("hello", format!("println!(r#\"{}\"#)", input.echo), meta);
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {
+                        input.echo = "hi".into();
+                    }
+                }
+            ),
+        ];
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to compile");
+        let stream = stream.to_string();
+        assert_contains_str(&stream, "fn main ()");
+        assert!(!stream.contains("tokio :: main"));
+        assert!(!stream.contains("async fn main"));
+
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    use super::{L0RuntimeCreator, RuntimeMeta};
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub _unused: bool,
+                    }
+                    pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("RUNTIME", &[""])];
+                    pub fn config(input: &mut Input, l0r: &mut L0RuntimeCreator) {
+                        let meta = RuntimeMeta { no_tokio_async_runtime: true, ..Default::default() };
+                        l0r.add_to_runtime_ex("hello", "something() . await;".to_string(), meta);
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let err = e2e_module2_run(&code, |_| {}).err().expect("Expected compile error");
+        assert_contains_str(err.to_string(), "sets no_tokio_async_runtime, but its code uses `.await`");
+    }
+
     #[cfg(feature = "wasm")]
     #[test]
     fn mod2_can_set_runtimes_with_unique_code() {
@@ -1688,6 +4600,103 @@ pub mod e2e_tests {
         assert_eq!(module.resolved_outputs["A2"], "lvlv2moda2");
     }
 
+    #[test]
+    fn mod2_inner_modules_can_read_input_sources() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod_a {
+                    use super::{L0Core, InputSource};
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub name: String,
+                        pub count: u32,
+                    }
+                    pub mod outputs {
+                        pub const NAME_SOURCE: &str = "";
+                        pub const COUNT_SOURCE: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core) {
+                        if l0core.input_source("name") == InputSource::Wrapper {
+                            l0core.set_output("NAME_SOURCE", &format!("{:?}", l0core.input_source("name")));
+                        }
+                        l0core.set_output("COUNT_SOURCE", &format!("{:?}", l0core.input_source("count")));
+                    }
+                }
+            ),
+            stringify!(
+                pub mod lvl2mod_c {
+                    use super::L0Core;
+                    use super::lvl2mod_a;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub greeting: String,
+                        pub unset: bool,
+                    }
+                    pub mod outputs {
+                        pub use lvl2mod_a::outputs::*;
+                        pub const GREETING_SOURCE: &str = "";
+                        pub const UNSET_SOURCE: &str = "";
+                    }
+                    pub fn config(input: &mut Input, l0core: &mut L0Core, ainp: &mut lvl2mod_a::Input) {
+                        ainp.name = input.greeting.clone();
+                        l0core.set_output("GREETING_SOURCE", &format!("{:?}", l0core.input_source("greeting")));
+                        l0core.set_output("UNSET_SOURCE", &format!("{:?}", l0core.input_source("unset")));
+                    }
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod_c;
+                    pub mod outputs {
+                        pub use lvl2mod_c::outputs::*;
+                    }
+                    pub fn config(input: &mut lvl2mod_c::Input) {
+                        input.greeting = "hello".to_string();
+                    }
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code, |_| {}).expect("Failed to compile");
+        let module = conf.get_mod2("mylevel3mod").expect("Failed to find mylevel3mod");
+        assert_eq!(module.resolved_outputs["GREETING_SOURCE"], "User");
+        assert_eq!(module.resolved_outputs["UNSET_SOURCE"], "Default");
+        assert_eq!(module.resolved_outputs["NAME_SOURCE"], "Wrapper");
+        assert_eq!(module.resolved_outputs["COUNT_SOURCE"], "Default");
+    }
+
+    #[test]
+    fn mod2_input_field_attributes_are_recorded() {
+        let code = [
+            stringify!(
+                pub mod lvl2mod {
+                    #[derive(Default)]
+                    pub struct Input {
+                        /// docs are not attributes
+                        pub new: bool,
+                        #[allow(dead_code)]
+                        #[deprecated = "use new instead"]
+                        pub old: bool,
+                    }
+                    pub fn config(input: &mut Input) {}
+                }
+            ),
+            stringify!(
+                pub mod mylevel3mod {
+                    use super::lvl2mod;
+                    pub fn config(input: &mut lvl2mod::Input) {}
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code,|_| {}).expect("Failed to compile");
+        let module = conf.get_mod2("lvl2mod").expect("Failed to find lvl2mod");
+        assert!(module.input_definition["new"].attributes.is_empty());
+        assert_eq!(module.input_definition["old"].ty, "bool");
+        assert_eq!(module.input_definition["old"].attributes, vec![
+            ("allow".to_string(), Some("dead_code".to_string())),
+            ("deprecated".to_string(), Some("use new instead".to_string())),
+        ]);
+    }
+
     #[test]
     fn mod2_can_parse_documentation() {
         let code = [
@@ -1747,4 +4756,73 @@ pub mod e2e_tests {
         assert_eq!(outputs["A"].documentation, "doc for A");
         assert_eq!(outputs["A"].default, "A");
     }
+
+    #[test]
+    fn mod2_documentation_can_be_split_into_sections() {
+        let code = [
+            stringify!(
+                /// summarizes the module
+                /// in two lines.
+                /// # Inputs
+                /// set `name` to something.
+                ///
+                /// ## Example
+                /// ```
+                /// # this is a hidden line, not a heading
+                /// let x = 2;
+                /// ```
+                pub mod lvl2mod {
+                    use super::L0CodeReader;
+                    #[derive(Default)]
+                    pub struct Input {
+                        pub name: String,
+                    }
+                    pub fn config(input: &mut Input, l0: &mut L0CodeReader) {}
+                }
+            ),
+            stringify!(
+                /// # Only a heading
+                /// no summary here
+                pub mod othermod {
+                    use super::L0CodeReader;
+                    #[derive(Default)]
+                    pub struct Input {}
+                    pub fn config(input: &mut Input, l0: &mut L0CodeReader) {}
+                }
+            ),
+        ];
+        let conf = e2e_module2_run(&code,|_| {}).expect("Failed to compile");
+        let module = conf.get_mod2("lvl2mod").expect("Failed to find lvl2mod");
+        let sections = module.documentation_sections();
+        let headings: Vec<&str> = sections.iter().map(|(h, _)| h.as_str()).collect();
+        assert_eq!(headings, vec![DOCUMENTATION_SUMMARY_HEADING, "Inputs", "Example"]);
+        assert_eq!(sections[0].1, "summarizes the module\nin two lines.");
+        assert_eq!(sections[1].1, "set `name` to something.");
+        assert_contains_str(&sections[2].1, "# this is a hidden line, not a heading");
+        assert_contains_str(&sections[2].1, "let x = 2;");
+
+        let module = conf.get_mod2("othermod").expect("Failed to find othermod");
+        assert_eq!(module.documentation_sections(), vec![("Only a heading".to_string(), "no summary here".to_string())]);
+
+        let code = [
+            "/**
+            summarizes the module
+# Inputs
+set `name` to something.
+*/
+            pub mod blockmod {
+                use super::L0CodeReader;
+                #[derive(Default)]
+                pub struct Input {}
+                pub fn config(input: &mut Input, l0: &mut L0CodeReader) {}
+            }",
+        ];
+        let conf = e2e_module2_run(&code,|_| {}).expect("Failed to compile");
+        let module = conf.get_mod2("blockmod").expect("Failed to find blockmod");
+        let sections = module.documentation_sections();
+        let headings: Vec<&str> = sections.iter().map(|(h, _)| h.as_str()).collect();
+        assert_eq!(headings, vec![DOCUMENTATION_SUMMARY_HEADING, "Inputs"]);
+        assert_eq!(sections[0].1.trim(), "summarizes the module");
+        assert_eq!(sections[1].1, "set `name` to something.");
+    }
 }
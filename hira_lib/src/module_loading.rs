@@ -10,7 +10,7 @@ use syn::spanned::Spanned;
 #[cfg(feature = "wasm")]
 use wasm_type_gen::WasmIncludeString;
 
-use crate::parsing::{remove_surrounding_quotes, parse_as_module_item, iterate_mod_def, get_ident_string, iterate_item_tree, parse_module_name_from_use_tree, iterate_tuples, is_public, has_derive, parse_module_name_from_use_names, has_comment, parse_documentation_from_attributes, iter_fields, Hiracfg, extract_hiracfgs};
+use crate::parsing::{parse_hira_attr_args, HiraAttrArgs, remove_surrounding_quotes, parse_as_module_item, iterate_mod_def, get_ident_string, iterate_item_tree, parse_module_name_from_use_tree, iterate_tuples, is_public, has_derive, parse_module_name_from_use_names, has_comment, parse_documentation_from_attributes, parse_documentation_lines_from_attributes, iter_fields, Hiracfg, extract_hiracfgs, has_derive_or_cfg_derive, get_item_name, get_span_location};
 #[cfg(feature = "wasm")]
 use crate::{wasm_types::*, level0::*};
 
@@ -26,6 +26,7 @@ pub const REQUIRED_HIRA_MODS_NAME: &'static str = "REQUIRED_HIRA_MODULES";
 pub const HIRA_MOD_NAME_NAME: &'static str = "HIRA_MODULE_NAME";
 pub const EXPORT_ITEM_NAME: &'static str = "ExportType";
 pub const CAPABILITY_PARAMS_NAME: &'static str = "CAPABILITY_PARAMS";
+pub const OUTPUT_OR_MACRO_NAME: &'static str = "output_or";
 
 
 #[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
@@ -88,6 +89,10 @@ pub enum OutputType {
 pub struct InputDef {
     pub ty: String,
     pub documentation: String,
+    /// all non-doc attributes of the field, as (name, value).
+    /// see `parsing::parse_attributes` for how the value is formatted.
+    #[serde(default)]
+    pub attributes: Vec<(String, Option<String>)>,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -95,11 +100,18 @@ pub struct HiraModule2 {
     pub name: String,
     /// the documentation comment above the module.
     pub documentation: String,
+    /// the lines of the documentation comment above the module. see `documentation_sections`
+    #[serde(default)]
+    pub documentation_lines: Vec<String>,
     pub contents: String,
     pub config_fn_is_pub: bool,
     pub config_fn_signature_inputs: Vec<String>,
     pub is_pub: bool,
     pub input_struct_has_default: bool,
+    /// true if the Input struct derives `schemars::JsonSchema`, either directly or behind a `cfg_attr`.
+    /// such modules get a `schema_for_input` function, see `add_schema_for_input`
+    #[serde(default)]
+    pub input_struct_derives_schema: bool,
     pub input_struct: String,
     pub input_documentation: String,
     pub input_definition: HashMap<String, InputDef>,
@@ -173,9 +185,68 @@ pub struct HiraModule2 {
     pub extra_parsed_items: Vec<(u32, String)>,
     pub extra_parsed_config_idents: Vec<String>,
     pub extra_parsed_config_body: (u32, String),
+
+    /// the (line, column) of where this module was defined. line is 1-based.
+    pub span: (u32, u32),
+    /// the (line, column) of every named item inside this module.
+    pub item_spans: HashMap<String, (u32, u32)>,
+    /// path of the file this module was defined in. empty if unknown.
+    pub source_file: String,
+
+    /// names of modules that must be processed before this module.
+    /// set via `#[hira(after = "other_module")]`
+    pub run_after: Vec<String>,
+
+    /// modules with a lower priority are processed first.
+    /// set via `#[hira(priority = N)]`. Modules without a priority have `DEFAULT_PRIORITY`
+    #[serde(default)]
+    pub priority: Option<i64>,
+
+    /// the warnings that were emitted while evaluating this module.
+    /// only relevant to the current build, so it is not cached.
+    #[serde(skip)]
+    pub warnings: Vec<String>,
 }
 
+/// the heading of the documentation before the first markdown heading
+pub const DOCUMENTATION_SUMMARY_HEADING: &str = "summary";
+
 impl HiraModule2 {
+    /// splits the documentation on markdown headings (eg: `# Example`), and returns the (heading, body) of each section.
+    /// the text before the first heading is in a section called `DOCUMENTATION_SUMMARY_HEADING`.
+    /// lines within code blocks are never headings, as `# ` hides a line of a rust code example.
+    pub fn documentation_sections(&self) -> Vec<(String, String)> {
+        if self.documentation_lines.is_empty() {
+            // modules that were cached before the lines were stored
+            if self.documentation.is_empty() {
+                return vec![];
+            }
+            return vec![(DOCUMENTATION_SUMMARY_HEADING.to_string(), self.documentation.clone())];
+        }
+        let mut sections = vec![(DOCUMENTATION_SUMMARY_HEADING.to_string(), vec![])];
+        let mut in_code_block = false;
+        for line in self.documentation_lines.iter() {
+            // doc comments are usually written as `/// text`
+            let line = line.strip_prefix(' ').unwrap_or(line);
+            let trimmed = line.trim();
+            if trimmed.starts_with("```") {
+                in_code_block = !in_code_block;
+            }
+            let heading = trimmed.trim_start_matches('#');
+            let is_heading = !in_code_block && heading.len() < trimmed.len() && heading.starts_with(' ');
+            if is_heading {
+                sections.push((heading.trim().to_string(), vec![]));
+            } else if let Some((_, body)) = sections.last_mut() {
+                body.push(line.trim_end());
+            }
+        }
+        sections.into_iter().enumerate()
+            .map(|(i, (heading, body))| (i, heading, body.join("\n").trim_matches('\n').to_string()))
+            .filter(|(i, _, body)| *i != 0 || !body.is_empty())
+            .map(|(_, heading, body)| (heading, body))
+            .collect()
+    }
+
     pub fn get_cached_json_path(module_name: &str, cache_dir: &str) -> String {
         format!("{}/{}.json", cache_dir, module_name)
     }
@@ -350,6 +421,90 @@ impl HiraModule2 {
         Ok(())
     }
 
+    /// runs the config's output validator (if any) over every resolved output of this module
+    pub fn validate_resolved_outputs(&self, conf: &HiraConfig) -> Result<(), TokenStream> {
+        let validator = match conf.output_validator {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let mut outputs: Vec<_> = self.resolved_outputs.iter().collect();
+        outputs.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, val) in outputs {
+            if let Err(e) = validator(&self.name, key, val) {
+                return Err(compiler_error(&format!("Output '{}' of module '{}' failed validation: {}", key, self.name, e)));
+            }
+        }
+        Ok(())
+    }
+
+    /// errors if the module's name cannot be used as a hira module name. Module names are also
+    /// used as the names of the crates that get compiled to wasm, so only ascii identifiers are allowed,
+    /// and names that hira uses for its own crates are reserved.
+    pub fn verify_name(&self) -> Result<(), TokenStream> {
+        let is_ascii_ident = self.name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_ascii_ident || syn::parse_str::<syn::Ident>(&self.name).is_err() {
+            return Err(compiler_error(&format!("Module name '{}' is invalid. Module names must be ascii identifiers, and cannot be raw identifiers or keywords", self.name)));
+        }
+        if RESERVED_MODULE_NAMES.contains(&self.name.as_str()) || self.name.starts_with(RESERVED_MODULE_NAME_PREFIX) {
+            return Err(compiler_error(&format!("Module name '{}' is reserved by hira. Module names cannot be any of {:?}, and cannot start with '{RESERVED_MODULE_NAME_PREFIX}'", self.name, RESERVED_MODULE_NAMES)));
+        }
+        Ok(())
+    }
+
+    /// errors if a module with the same name from a different file was already expanded in this pass.
+    /// Modules are stored by name, so the second one would silently replace the first.
+    /// The same module being expanded again is a re-expansion of the crate, which is not an error.
+    /// Nothing is checked if the file of either module is unknown.
+    pub fn verify_unique_name(&self, conf: &HiraConfig) -> Result<(), TokenStream> {
+        if self.source_file.is_empty() || !conf.expanded_modules.iter().any(|(x, _)| *x == self.name) {
+            return Ok(());
+        }
+        let existing = match conf.get_mod2(&self.name) {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        if existing.source_file.is_empty() || existing.source_file == self.source_file {
+            return Ok(());
+        }
+        Err(compiler_error(&format!("Module '{}' is defined in both {:?} and {:?}. Module names must be unique", self.name, existing.source_file, self.source_file)))
+    }
+
+    /// errors if a module with a higher priority number was already expanded in this pass, ie: this module
+    /// should've been processed before it. Priorities are compared the same way that
+    /// `order_by_priority_and_run_after` sorts them, see `get_effective_priority`.
+    pub fn verify_priority(&self, conf: &HiraConfig) -> Result<(), TokenStream> {
+        let priority = get_effective_priority(self.priority);
+        let mut later: Vec<(&String, i64)> = conf.expanded_modules.iter()
+            .filter(|(name, _)| *name != self.name)
+            .map(|(name, p)| (name, get_effective_priority(*p)))
+            .filter(|(_, p)| *p > priority)
+            .collect();
+        later.sort();
+        if let Some((other, other_priority)) = later.first() {
+            return Err(compiler_error(
+                &format!("Module '{}' has priority {priority}, so it must be processed before '{other}' which has priority {other_priority}. If compiling with cargo, ensure that '{}' is defined prior to '{other}'", self.name, self.name)
+            ));
+        }
+        Ok(())
+    }
+
+    /// errors if any of the modules this module must be processed after
+    /// has not been processed yet.
+    pub fn verify_run_after(&self, conf: &HiraConfig) -> Result<(), TokenStream> {
+        for other in self.run_after.iter() {
+            if *other == self.name {
+                return Err(compiler_error(&format!("Module '{}' cannot be processed after itself", self.name)));
+            }
+            if conf.get_mod2(other).is_none() {
+                return Err(compiler_error(
+                    &format!("Module '{}' must be processed after '{}', but that module has not been loaded yet. If compiling with cargo, ensure that '{}' is defined prior to '{}'", self.name, other, other, self.name)
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn use_dep_is_in_compile_dependencies(&self, use_dep: &str) -> bool {
         for compile_dep in self.compile_dependencies.iter() {
             match compile_dep {
@@ -364,6 +519,41 @@ impl HiraModule2 {
         false
     }
 
+    /// replaces every `output_or!(some_module::OUTPUT_NAME, "fallback")` in this module's contents
+    /// with a string literal. The literal is the evaluated output value, or the fallback
+    /// if the output was never set (ie: it still equals the default that `some_module` declared).
+    pub fn insert_output_or_values(&mut self, conf: &HiraConfig) -> Result<(), TokenStream> {
+        if !self.contents.contains(OUTPUT_OR_MACRO_NAME) {
+            return Ok(());
+        }
+        let stream = self.contents.parse::<TokenStream>()
+            .map_err(|e| compiler_error(&format!("Failed to parse contents of module '{}'\n{:?}", self.name, e)))?;
+        self.contents = self.replace_output_or_values(conf, stream)?.to_string();
+        Ok(())
+    }
+
+    /// see `insert_output_or_values`. This operates on an arbitrary stream
+    /// so that the code that rustc sees can be substituted as well.
+    pub fn replace_output_or_values(&self, conf: &HiraConfig, stream: TokenStream) -> Result<TokenStream, TokenStream> {
+        let my_name = &self.name;
+        replace_macro_invocations(stream, OUTPUT_OR_MACRO_NAME, &mut |args| {
+            let (mod_name, output_name, fallback) = parse_output_or_args(args)?;
+            let mod_conf = conf.get_mod2(&mod_name).ok_or_else(|| compiler_error(
+                &format!("Module '{}' used {}! on outputs from dependency module '{}', but that module has not been loaded yet. If compiling with cargo, ensure that '{}' is defined prior to '{}'", my_name, OUTPUT_OR_MACRO_NAME, mod_name, mod_name, my_name)
+            ))?;
+            let mut all_outputs = HashMap::new();
+            mod_conf.get_all_output_docs(conf, &mut all_outputs)?;
+            let declared = all_outputs.get(&output_name).ok_or_else(|| compiler_error(
+                &format!("Module '{}' used {}! on output '{}' from '{}' but no such output was found", my_name, OUTPUT_OR_MACRO_NAME, output_name, mod_name)
+            ))?;
+            let val = match mod_conf.resolved_outputs.get(&output_name) {
+                Some(val) if *val != declared.default => val.to_string(),
+                _ => fallback,
+            };
+            Ok(proc_macro2::Literal::string(&val).into_token_stream())
+        })
+    }
+
     pub fn insert_evaluated_output_const(contents: &mut String, mod_name: &str, key: &String, val: &String) {
         // this is hacky as we search for a string, but converting back to tokens and back again
         // seems expensive.
@@ -390,6 +580,7 @@ impl HiraModule2 {
     /// prior to compilation, we add in `const {OUTPUT_NAME}: &str = {OUTPUT_VAL};`
     /// for each output that this lvl3 module depends on
     pub fn insert_evaluated_outputs(&mut self, conf: &HiraConfig) -> Result<(), TokenStream> {
+        self.insert_output_or_values(conf)?;
         for output in self.fill_outputs.iter() {
             match output {
                 OutputType::AllFromModule(mod_name) => {
@@ -718,16 +909,19 @@ pub fn print_debug<S: AsRef<str>>(out_f: &str, contents: S) {
     let _ = out_f.write_all(contents.as_ref().as_bytes());
 }
 
-/// corresponds to the main hira_mod! macro
+/// corresponds to the main hira_mod! macro.
+/// `source_file` is the file that the macro was invoked in, or empty if unknown.
 #[cfg(feature = "wasm")]
-pub fn hira_mod2(mut stream: TokenStream, mut _attr: TokenStream) -> TokenStream {
+pub fn hira_mod2(mut stream: TokenStream, mut attr: TokenStream, source_file: String) -> TokenStream {
     let mut out = Err(default_stream());
     let out_ref = &mut out;
+    let mut source_file = source_file;
     use_hira_config(|conf| {
         // print_debug_stuff();
         let stream = std::mem::take(&mut stream);
-        // let attr = std::mem::take(&mut attr);
-        *out_ref = hira_mod2_inner(conf, stream);
+        let attr = std::mem::take(&mut attr);
+        conf.source_file = std::mem::take(&mut source_file);
+        *out_ref = hira_mod2_structured(conf, stream, attr).map(|x| x.stream);
     });
     match out {
         Ok(o) => o,
@@ -735,6 +929,65 @@ pub fn hira_mod2(mut stream: TokenStream, mut _attr: TokenStream) -> TokenStream
     }
 }
 
+/// the priority of modules that dont set `#[hira(priority = N)]`
+pub const DEFAULT_PRIORITY: i64 = 0;
+
+/// the priority that a module is ordered by
+pub fn get_effective_priority(priority: Option<i64>) -> i64 {
+    priority.unwrap_or(DEFAULT_PRIORITY)
+}
+/// crate names hira uses when compiling modules to wasm.
+pub const RESERVED_MODULE_NAMES: &[&str] = &["hira_base"];
+/// the dependencies of a level3 module get compiled as a crate called `{prefix}{level3 module name}`
+pub const RESERVED_MODULE_NAME_PREFIX: &str = "dependencies_";
+
+/// sorts the items by priority (lower first) and then orders them by `order_by_run_after`.
+/// modules with the same priority keep their original order, unless `after` requires otherwise.
+pub fn order_by_priority_and_run_after<T>(
+    items: Vec<(String, Vec<String>, T)>,
+    get_priority: impl Fn(&T) -> Option<i64>,
+) -> Result<Vec<(String, Vec<String>, T)>, String> {
+    let mut items = items;
+    items.sort_by_key(|x| get_effective_priority(get_priority(&x.2)));
+    order_by_run_after(items)
+}
+
+/// orders items of (module name, names of modules it must come after, T) such that every module comes after
+/// the modules it lists. Otherwise the original order is preserved. Names that aren't part of `items`
+/// are ignored, as those modules are expected to already be loaded. Errors if there is a cycle.
+pub fn order_by_run_after<T>(items: Vec<(String, Vec<String>, T)>) -> Result<Vec<(String, Vec<String>, T)>, String> {
+    let names: HashSet<String> = items.iter().map(|x| x.0.clone()).collect();
+    let mut done: HashSet<String> = HashSet::new();
+    let mut remaining = items;
+    let mut out = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let is_ready = |after: &Vec<String>| after.iter().all(|x| !names.contains(x) || done.contains(x));
+        if let Some(index) = remaining.iter().position(|x| is_ready(&x.1)) {
+            let item = remaining.remove(index);
+            done.insert(item.0.clone());
+            out.push(item);
+            continue;
+        }
+        // every remaining module is waiting on another remaining module.
+        // follow the chain from the first one until a name repeats.
+        let mut chain = vec![remaining[0].0.clone()];
+        loop {
+            let current = &chain[chain.len() - 1];
+            let next = remaining.iter()
+                .find(|x| &x.0 == current)
+                .and_then(|x| x.1.iter().find(|a| names.contains(*a) && !done.contains(*a)))
+                .cloned()
+                .unwrap_or_default();
+            let cycle_start = chain.iter().position(|x| *x == next);
+            chain.push(next);
+            if let Some(start) = cycle_start {
+                return Err(format!("Detected a cycle in hira `after` attributes: {}", chain[start..].join(" -> ")));
+            }
+        }
+    }
+    Ok(out)
+}
+
 pub fn get_all_extern_crates(conf: &mut HiraConfig, module: &mut HiraModule2) -> Vec<String> {
     let mut all_externs = HashSet::new();
     for ext in module.extern_crates.iter() {
@@ -760,8 +1013,27 @@ pub fn should_compile() -> bool {
     true
 }
 
+/// the result of processing a single module via `hira_mod2_structured`.
+/// host tools (eg: the CLI) can use this instead of inspecting the token stream.
+#[derive(Debug, Default)]
+pub struct ModuleResult {
+    pub name: String,
+    /// the module's code, plus everything that hira generated for it
+    pub stream: TokenStream,
+    /// the warnings that were emitted while evaluating the module.
+    /// these are also part of the stream, as deprecation notes.
+    pub warnings: Vec<String>,
+    /// the outputs of the module after it was evaluated
+    pub resolved_outputs: HashMap<String, String>,
+}
+
+#[cfg(feature = "wasm")]
+pub fn hira_mod2_inner(conf: &mut HiraConfig, stream: TokenStream, attr: TokenStream) -> Result<TokenStream, TokenStream> {
+    hira_mod2_structured(conf, stream, attr).map(|x| x.stream)
+}
+
 #[cfg(feature = "wasm")]
-pub fn hira_mod2_inner(conf: &mut HiraConfig, stream: TokenStream) -> Result<TokenStream, TokenStream> {
+pub fn hira_mod2_structured(conf: &mut HiraConfig, stream: TokenStream, attr: TokenStream) -> Result<ModuleResult, TokenStream> {
     // originally i had the idea that itd be nice to get compiler errors
     // as you type in your editor, so you can get a quicker feedback loop.
     // this means on every file save, your typehint program would run cargo check
@@ -773,39 +1045,78 @@ pub fn hira_mod2_inner(conf: &mut HiraConfig, stream: TokenStream) -> Result<Tok
     // but a quick/dirty way is to check if we have RUST_BACKTRACE=full or not (cargo build
     // uses full, whereas cargo check uses short by default)
     let should_compile = should_compile();
-    hira_mod2_inner_ex(conf, stream, should_compile, false, None, None)
+    // when invoked as a macro, the #[hira(...)] attribute is not part of the stream
+    let attr_args = parse_hira_attr_args(attr).map_err(|e| compiler_error(&e))?;
+    hira_mod2_structured_ex(conf, stream, should_compile, false, None, None, attr_args)
 }
 
 #[cfg(feature = "wasm")]
 pub fn hira_mod2_inner_ex(
     conf: &mut HiraConfig,
-    mut stream: TokenStream,
+    stream: TokenStream,
     should_compile: bool,
     dont_run_wasm: bool,
     custom_codegen_opts: Option<Vec<&str>>,
     compile_log: Option<fn (&str)>,
+    attr_args: HiraAttrArgs,
 ) -> Result<TokenStream, TokenStream> {
+    hira_mod2_structured_ex(conf, stream, should_compile, dont_run_wasm, custom_codegen_opts, compile_log, attr_args)
+        .map(|x| x.stream)
+}
+
+#[cfg(feature = "wasm")]
+pub fn hira_mod2_structured_ex(
+    conf: &mut HiraConfig,
+    mut stream: TokenStream,
+    should_compile: bool,
+    dont_run_wasm: bool,
+    custom_codegen_opts: Option<Vec<&str>>,
+    compile_log: Option<fn (&str)>,
+    attr_args: HiraAttrArgs,
+) -> Result<ModuleResult, TokenStream> {
+    if let Some(e) = &conf.settings_error {
+        return Err(compiler_error(e));
+    }
     let mut module = parse_module_from_stream(stream.clone())?;
+    module.source_file = conf.source_file.clone();
+    module.run_after.extend(attr_args.after);
+    if attr_args.priority.is_some() {
+        module.priority = attr_args.priority;
+    }
+    module.verify_name()?;
+    module.verify_run_after(conf)?;
+    module.verify_unique_name(conf)?;
+    conf.add_expanded_module(&module.name, module.priority);
+    module.verify_priority(conf)?;
     module.verify_config_signature(conf)?;
 
     // only level3 modules get compiled into wasm
     // all other modules get compiled as dependencies for a level3 module
     // but theres no point to compile them all individually
     if module.level != ModuleLevel::Level3 {
+        if module.input_struct_derives_schema {
+            stream = add_schema_for_input(stream)?;
+            module.contents = add_schema_for_input(module.contents.parse().map_err(|e| compiler_error(&format!("Failed to parse contents of module '{}'\n{:?}", module.name, e)))?)?.to_string();
+        }
         // cache it in case this module is needed as a dependency
         // in another crate
-        module.cache_to_disk(&conf.module_cache_directory);
+        if !conf.dont_cache_modules {
+            module.cache_to_disk(&conf.module_cache_directory);
+        }
+        let name = module.name.clone();
         conf.modules2.insert(module.name.clone(), module);
-        return Ok(stream);
+        return Ok(ModuleResult { name, stream, ..Default::default() });
     }
     if !should_compile {
-        return Ok(stream);
+        return Ok(ModuleResult { name: module.name, stream, ..Default::default() });
     }
 
     if let Some(log_fn) = &compile_log {
         log_fn(&module.name);
     }
     module.insert_evaluated_outputs(conf)?;
+    // output_or! only exists for hira, so rustc must also see the substituted values
+    stream = module.replace_output_or_values(conf, stream)?;
     // we also want to insert evaluated outputs for wrapper modules:
     if let Some(wrapper_map) = &module.use_wrappers {
         for (_, wrapper_names) in wrapper_map {
@@ -832,8 +1143,32 @@ pub fn hira_mod2_inner_ex(
         print_debug(&conf.logfile, format!("not applying library obj changes from {} because dont_run_wasm = true\n", module.name));
     }
 
+    let result = ModuleResult {
+        name: module.name.clone(),
+        stream,
+        warnings: module.warnings.clone(),
+        resolved_outputs: module.resolved_outputs.clone(),
+    };
     conf.modules2.insert(module.name.clone(), module);
-    Ok(stream)
+    Ok(result)
+}
+
+/// adds a `schema_for_input` function to the module, which returns the JSON schema of its Input.
+/// only compiled with the `web` feature, like the serde derives of Input types.
+pub fn add_schema_for_input(stream: TokenStream) -> Result<TokenStream, TokenStream> {
+    let mut mod_def = parse_as_module_item(stream)?;
+    let schema_fn: syn::Item = syn::parse_quote! {
+        /// the JSON schema of this module's Input. Generated by hira because Input derives `schemars::JsonSchema`
+        #[cfg(feature = "web")]
+        pub fn schema_for_input() -> schemars::schema::RootSchema {
+            schemars::schema_for!(Input)
+        }
+    };
+    match &mut mod_def.content {
+        Some((_, items)) => items.push(schema_fn),
+        None => return Err(compiler_error(&format!("Module '{}' has no body", mod_def.ident))),
+    }
+    Ok(mod_def.to_token_stream())
 }
 
 pub fn set_config_fn_sig(module: &mut HiraModule2, item: &mut syn::ItemFn) {
@@ -899,8 +1234,9 @@ pub fn set_input_item_struct(module: &mut HiraModule2, item: &mut syn::ItemStruc
     if item.attrs.iter().any(|att| has_derive(&att.meta, "Default")) {
         module.input_struct_has_default = true;
     }
-    iter_fields(&item.fields, &mut |name, ty, documentation| {
-        module.input_definition.insert(name, InputDef { ty, documentation });
+    module.input_struct_derives_schema = item.attrs.iter().any(|att| has_derive_or_cfg_derive(&att.meta, "JsonSchema"));
+    iter_fields(&item.fields, &mut |name, ty, documentation, attributes| {
+        module.input_definition.insert(name, InputDef { ty, documentation, attributes });
     });
     module.input_struct = item.to_token_stream().to_string();
 }
@@ -1019,6 +1355,62 @@ pub fn set_use_dependencies(module: &mut HiraModule2, item: &mut syn::ItemUse) {
     module.use_dependencies = deps;
 }
 
+/// recursively finds every `{macro_name}!(...)` in the stream, and replaces it with
+/// whatever `cb` returns when given the arguments of that invocation.
+pub fn replace_macro_invocations(
+    stream: TokenStream,
+    macro_name: &str,
+    cb: &mut impl FnMut(TokenStream) -> Result<TokenStream, TokenStream>,
+) -> Result<TokenStream, TokenStream> {
+    let tokens: Vec<proc_macro2::TokenTree> = stream.into_iter().collect();
+    let mut out = TokenStream::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        if let (proc_macro2::TokenTree::Ident(id), Some(proc_macro2::TokenTree::Punct(p)), Some(proc_macro2::TokenTree::Group(g))) = (&tokens[i], tokens.get(i + 1), tokens.get(i + 2)) {
+            if id == macro_name && p.as_char() == '!' {
+                out.extend(cb(g.stream())?);
+                i += 3;
+                continue;
+            }
+        }
+        match &tokens[i] {
+            proc_macro2::TokenTree::Group(g) => {
+                let inner = replace_macro_invocations(g.stream(), macro_name, cb)?;
+                let mut new_group = proc_macro2::Group::new(g.delimiter(), inner);
+                new_group.set_span(g.span());
+                out.extend([proc_macro2::TokenTree::Group(new_group)]);
+            }
+            x => out.extend([x.clone()]),
+        }
+        i += 1;
+    }
+    Ok(out)
+}
+
+/// parses `some_module::OUTPUT_NAME, "fallback"` into (some_module, OUTPUT_NAME, fallback).
+/// leading `super`/`crate`/`self` segments, and an `outputs` segment are allowed, eg:
+/// `super::some_module::outputs::OUTPUT_NAME`
+pub fn parse_output_or_args(args: TokenStream) -> Result<(String, String, String), TokenStream> {
+    let parser = |input: syn::parse::ParseStream| -> syn::Result<(syn::Path, syn::LitStr)> {
+        let path: syn::Path = input.parse()?;
+        input.parse::<syn::Token![,]>()?;
+        let fallback: syn::LitStr = input.parse()?;
+        let _ = input.parse::<Option<syn::Token![,]>>()?;
+        Ok((path, fallback))
+    };
+    let usage_err = || compiler_error(&format!("Invalid usage of {}!. Expected `{}!(some_module::OUTPUT_NAME, \"default value\")`", OUTPUT_OR_MACRO_NAME, OUTPUT_OR_MACRO_NAME));
+    let (path, fallback) = syn::parse::Parser::parse2(parser, args).map_err(|_| usage_err())?;
+    let names: Vec<String> = path.segments.iter()
+        .map(|x| get_ident_string(&x.ident))
+        .filter(|x| x != "super" && x != "crate" && x != "self")
+        .collect();
+    match names.as_slice() {
+        [mod_name, output_name] => Ok((mod_name.to_string(), output_name.to_string(), fallback.value())),
+        [mod_name, outputs, output_name] if outputs == "outputs" => Ok((mod_name.to_string(), output_name.to_string(), fallback.value())),
+        _ => Err(usage_err()),
+    }
+}
+
 pub fn set_extern_crates(module: &mut HiraModule2, item: &mut syn::ItemExternCrate) {
     let convert_underscore = has_comment(&item.attrs, "underscore_to_dash");
     let mut name = get_ident_string(&item.ident);
@@ -1099,6 +1491,15 @@ pub fn parse_module_from_stream(stream: TokenStream) -> Result<HiraModule2, Toke
     let mut hira_mod = HiraModule2::default();
     let doc = parse_documentation_from_attributes(&mod_def.attrs);
     hira_mod.documentation = doc;
+    hira_mod.documentation_lines = parse_documentation_lines_from_attributes(&mod_def.attrs);
+    hira_mod.span = get_span_location(mod_def.span());
+    if let Some((_, items)) = &mod_def.content {
+        for item in items.iter() {
+            if let Some(name) = get_item_name(item) {
+                hira_mod.item_spans.insert(name, get_span_location(item.span()));
+            }
+        }
+    }
     iterate_mod_def(
         &mut hira_mod,
         &mut mod_def,
@@ -1198,6 +1599,40 @@ mod tests {
         assert_eq!(module.extern_crates[0], "some_dependency");
     }
 
+    #[test]
+    fn mod2_span_info_is_stored() {
+        let code = r#"
+        pub mod hello_world {
+            #[derive(Default)]
+            pub struct Input { pub a: u32 }
+
+            pub fn config(input: &mut Input) {}
+        }
+        "#;
+        let stream = TokenStream::from_str(code).expect("Failed to parse test case as token stream");
+        let module = parse_module_from_stream(stream).expect("failed to parse test case as module");
+        assert_eq!(module.span.0, 2);
+        assert_eq!(module.item_spans["Input"].0, 3);
+        assert_eq!(module.item_spans["config"].0, 6);
+    }
+
+    #[test]
+    fn mod2_names_must_be_valid_and_not_reserved() {
+        let mut module = HiraModule2::default();
+        for valid in ["hello_world", "_private", "a1"] {
+            module.name = valid.to_string();
+            assert!(module.verify_name().is_ok(), "{valid} should be valid");
+        }
+        for invalid in ["", "1abc", "r#type", "self", "héllo"] {
+            module.name = invalid.to_string();
+            assert!(module.verify_name().unwrap_err().to_string().contains("is invalid"), "{invalid} should be invalid");
+        }
+        for reserved in ["hira_base", "dependencies_mymod"] {
+            module.name = reserved.to_string();
+            assert!(module.verify_name().unwrap_err().to_string().contains("is reserved"), "{reserved} should be reserved");
+        }
+    }
+
     #[test]
     fn mod2_file_permissions_get_set_correctly() {
         let code = r#"pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[("FILES", &["hello.txt"])];"#;
@@ -1490,6 +1925,36 @@ mod tests {
         assert_eq!(module.input_struct_has_default, true);
     }
 
+    #[test]
+    fn mod2_lvl2_input_schema_fn_is_generated_for_schema_derives() {
+        let code = r#"
+        pub mod hello_world {
+            #[derive(Default)]
+            #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+            pub struct Input {}
+            pub fn config(input: &mut Input) {}
+        }
+        "#;
+        let stream = TokenStream::from_str(code).expect("Failed to parse test case as token stream");
+        let module = parse_module_from_stream(stream.clone()).expect("failed to parse test case as module");
+        assert!(module.input_struct_derives_schema);
+        let out = add_schema_for_input(stream).expect("Failed to add schema fn").to_string();
+        assert_contains_str(&out, "# [cfg (feature = \"web\")] pub fn schema_for_input () -> schemars :: schema :: RootSchema");
+        assert_contains_str(&out, "schemars :: schema_for ! (Input)");
+
+        let code = r#"
+        pub mod hello_world {
+            #[derive(Default)]
+            #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+            pub struct Input {}
+            pub fn config(input: &mut Input) {}
+        }
+        "#;
+        let stream = TokenStream::from_str(code).expect("Failed to parse test case as token stream");
+        let module = parse_module_from_stream(stream).expect("failed to parse test case as module");
+        assert!(!module.input_struct_derives_schema);
+    }
+
     #[test]
     fn mod2_lvl2_input_struct_must_be_referenced() {
         let code = r#"
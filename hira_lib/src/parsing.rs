@@ -45,6 +45,32 @@ pub struct FunctionSignature {
     pub is_const: bool,
     pub inputs: Vec<UserInput>,
     pub return_ty: String,
+    /// if the return type is a `Result<T, E>`, this is `T`. otherwise it is the same as `return_ty`.
+    pub return_ok_ty: String,
+    /// the generic parameters including the angle brackets, eg: `< 'a , E >`. empty if not generic.
+    pub generics: String,
+    /// the where clause including the `where` keyword, eg: `where E : Send`. empty if there is none.
+    pub where_clause: String,
+}
+
+#[cfg_attr(feature = "wasm", derive(WasmTypeGen, Debug))]
+#[derive(Default)]
+#[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructField {
+    /// for tuple structs, every field is named `a`
+    pub name: String,
+    pub ty: String,
+    pub documentation: String,
+}
+
+#[cfg_attr(feature = "wasm", derive(WasmTypeGen, Debug))]
+#[derive(Default)]
+#[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructDefinition {
+    pub name: String,
+    pub documentation: String,
+    /// the fields in the order they are defined.
+    pub fields: Vec<StructField>,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -90,6 +116,19 @@ pub fn iterate_tuples(expr: &Expr, cb: &mut impl FnMut(String, &Expr)) {
     }
 }
 
+/// parses the fields the same way as the fields of a module's `Input` struct. see `iter_fields`
+pub fn parse_struct_definition(item: &ItemStruct) -> StructDefinition {
+    let mut fields = vec![];
+    iter_fields(&item.fields, &mut |name, ty, documentation, _| {
+        fields.push(StructField { name, ty, documentation });
+    });
+    StructDefinition {
+        name: get_ident_string(&item.ident),
+        documentation: parse_documentation_from_attributes(&item.attrs),
+        fields,
+    }
+}
+
 pub fn parse_fn_signature(item: &ItemFn) -> FunctionSignature {
     let mut name = item.sig.ident.to_string();
     remove_surrounding_quotes(&mut name);
@@ -111,10 +150,16 @@ pub fn parse_fn_signature(item: &ItemFn) -> FunctionSignature {
         };
         inputs.push(usr_field);
     }
-    let return_ty = match &item.sig.output {
-        syn::ReturnType::Default => "".into(),
-        syn::ReturnType::Type(_, b) => b.to_token_stream().to_string(),
+    let (return_ty, return_ok_ty) = match &item.sig.output {
+        syn::ReturnType::Default => ("".into(), "".into()),
+        syn::ReturnType::Type(_, b) => {
+            let ok_ty = get_result_ok_type(b).unwrap_or(b);
+            (b.to_token_stream().to_string(), ok_ty.to_token_stream().to_string())
+        }
     };
+    // syn outputs the where clause separately from the rest of the generics
+    let generics = item.sig.generics.to_token_stream().to_string();
+    let where_clause = item.sig.generics.where_clause.as_ref().map(|x| x.to_token_stream().to_string()).unwrap_or_default();
 
     FunctionSignature {
         name,
@@ -127,9 +172,59 @@ pub fn parse_fn_signature(item: &ItemFn) -> FunctionSignature {
         is_const: item.sig.constness.is_some(),
         inputs,
         return_ty,
+        return_ok_ty,
+        generics,
+        where_clause,
     }
 }
 
+/// returns the `T` of a `Result<T, E>`, or None if `ty` isn't a Result.
+pub fn get_result_ok_type(ty: &syn::Type) -> Option<&syn::Type> {
+    let path = match ty {
+        syn::Type::Path(p) => &p.path,
+        _ => return None,
+    };
+    let last = path.segments.last()?;
+    if last.ident != "Result" {
+        return None;
+    }
+    match &last.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.first()? {
+            syn::GenericArgument::Type(ok) => Some(ok),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// parses `code` as the body of a function and returns the first `.await` expression
+/// that would run in that function. awaits inside of async blocks and async closures
+/// are not returned, as those don't require the function itself to be async.
+pub fn find_await_in_fn_body(code: &str) -> Result<Option<String>, String> {
+    struct AwaitFinder {
+        found: Option<String>,
+    }
+    impl<'ast> syn::visit::Visit<'ast> for AwaitFinder {
+        fn visit_expr_await(&mut self, node: &'ast syn::ExprAwait) {
+            if self.found.is_none() {
+                self.found = Some(node.to_token_stream().to_string());
+            }
+        }
+        fn visit_expr_async(&mut self, _node: &'ast syn::ExprAsync) {}
+        fn visit_expr_closure(&mut self, node: &'ast syn::ExprClosure) {
+            if node.asyncness.is_none() {
+                syn::visit::visit_expr_closure(self, node);
+            }
+        }
+        fn visit_item(&mut self, _node: &'ast Item) {}
+    }
+    let block: syn::Block = syn::parse_str(&format!("{{\n{code}\n}}"))
+        .map_err(|e| format!("Failed to parse as a function body: {e}"))?;
+    let mut finder = AwaitFinder { found: None };
+    syn::visit::Visit::visit_block(&mut finder, &block);
+    Ok(finder.found)
+}
+
 /// in a few places in hira we let the module writer specify some array of values
 /// which we parse out the strings. This function is generic over that iteration
 /// and calls the callback with anytime we find a string
@@ -324,6 +419,7 @@ pub fn iterate_mod_def(
     );
     let cfgs = extract_hiracfgs(&mut mod_def.attrs, None);
     extract_wrappers(&mod_def.attrs, module);
+    extract_run_after(&mod_def.attrs, module);
     module.hiracfgs.extend(cfgs);
     // remove attributes, since we dont want to try to compile #[hira]
     mod_def.attrs.clear();
@@ -344,6 +440,95 @@ pub fn extract_wrappers(attributes: &Vec<Attribute>, module: &mut HiraModule2) {
     }
 }
 
+/// finds `#[hira(after = "other_module", priority = N)]` in the attributes, and adds `other_module`
+/// to the modules that this module must be processed after.
+pub fn extract_run_after(attributes: &Vec<Attribute>, module: &mut HiraModule2) {
+    match get_hira_attr_args(attributes) {
+        Ok(args) => {
+            module.run_after.extend(args.after);
+            if args.priority.is_some() {
+                module.priority = args.priority;
+            }
+        }
+        Err(e) => module.errors_during_parsing.push(e),
+    }
+}
+
+/// the arguments of `#[hira(...)]`
+#[derive(Debug, Default, PartialEq)]
+pub struct HiraAttrArgs {
+    /// names of the modules that must be processed first
+    pub after: Vec<String>,
+    /// modules with a lower priority are processed first.
+    pub priority: Option<i64>,
+}
+
+/// returns the names of all modules listed in `#[hira(after = "...")]` attributes
+pub fn get_run_after(attributes: &[Attribute]) -> Result<Vec<String>, String> {
+    Ok(get_hira_attr_args(attributes)?.after)
+}
+
+/// returns the combined arguments of all `#[hira(...)]` attributes
+pub fn get_hira_attr_args(attributes: &[Attribute]) -> Result<HiraAttrArgs, String> {
+    let mut out = HiraAttrArgs::default();
+    for attr in attributes.iter() {
+        if let Meta::List(l) = &attr.meta {
+            if attr_ends_in(attr, "hira") {
+                let args = parse_hira_attr_args(l.tokens.clone())?;
+                out.after.extend(args.after);
+                if args.priority.is_some() {
+                    if out.priority.is_some() {
+                        return Err("hira attribute argument 'priority' can only be set once".to_string());
+                    }
+                    out.priority = args.priority;
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// parses the arguments of the hira attribute, eg: `after = "a", after = "b", priority = 1`
+pub fn parse_hira_attr_args(args: TokenStream) -> Result<HiraAttrArgs, String> {
+    const EXPECTED: &str = "Expected `after = \"other_module\"` or `priority = N`";
+    let mut out = HiraAttrArgs::default();
+    if args.is_empty() {
+        return Ok(out);
+    }
+    let parser = syn::punctuated::Punctuated::<syn::MetaNameValue, syn::Token![,]>::parse_terminated;
+    let pairs = syn::parse::Parser::parse2(parser, args)
+        .map_err(|e| format!("Failed to parse hira attribute arguments: {e}. {EXPECTED}"))?;
+    for pair in pairs {
+        let key = pair.path.to_token_stream().to_string();
+        match (key.as_str(), &pair.value) {
+            ("after", Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(s), .. })) => out.after.push(s.value()),
+            ("priority", value) => {
+                if out.priority.is_some() {
+                    return Err("hira attribute argument 'priority' can only be set once".to_string());
+                }
+                let priority = parse_priority(value)
+                    .ok_or_else(|| format!("Invalid value for hira attribute argument 'priority': {}. Expected an integer", value.to_token_stream()))?;
+                out.priority = Some(priority);
+            }
+            ("after", x) => return Err(format!("Invalid value for hira attribute argument 'after': {}. {EXPECTED}", x.to_token_stream())),
+            _ => return Err(format!("Unknown hira attribute argument '{key}'. {EXPECTED}")),
+        }
+    }
+    Ok(out)
+}
+
+/// parses an integer literal, optionally negated, eg: `2` or `-1`
+fn parse_priority(value: &Expr) -> Option<i64> {
+    match value {
+        Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(i), .. }) => i.base10_parse::<i64>().ok(),
+        Expr::Unary(syn::ExprUnary { op: syn::UnOp::Neg(_), expr, .. }) => match expr.as_ref() {
+            Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(i), .. }) => i.base10_parse::<i64>().ok().and_then(|x| x.checked_neg()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 pub fn extract_hirawrapmod(list: &MetaList, module: &mut HiraModule2) {
     let mut tokens_iter = list.tokens.clone().into_iter();
     let wrap_module = if let Some(TokenTree::Ident(token)) = tokens_iter.next() {
@@ -548,6 +733,24 @@ pub fn has_derive(meta: &Meta, name: &str) -> bool {
     false
 }
 
+/// like `has_derive`, but also finds derives that are behind a `cfg_attr`,
+/// eg: `#[cfg_attr(feature = "web", derive(schemars::JsonSchema))]`
+pub fn has_derive_or_cfg_derive(meta: &Meta, name: &str) -> bool {
+    if has_derive(meta, name) {
+        return true;
+    }
+    let list = match meta {
+        Meta::List(l) if l.path.is_ident("cfg_attr") => l,
+        _ => return false,
+    };
+    let nested = match list.parse_args_with(syn::punctuated::Punctuated::<Meta, syn::Token![,]>::parse_terminated) {
+        Ok(n) => n,
+        Err(_) => return false,
+    };
+    // the first item is the cfg predicate
+    nested.iter().skip(1).any(|x| has_derive_or_cfg_derive(x, name))
+}
+
 pub fn is_public(vis: &Visibility) -> bool {
     match vis {
         Visibility::Public(_) => true,
@@ -586,10 +789,106 @@ pub fn convert_to_snake_case(field: &str) -> String {
     out
 }
 
+/// returns the (line, column) of the start of the span.
+/// line is 1-based, column is 1-based to match what editors display.
+pub fn get_span_location(span: Span) -> (u32, u32) {
+    let start = span.start();
+    (start.line as u32, start.column as u32 + 1)
+}
+
+/// returns the name of the item if it has one
+pub fn get_item_name(item: &Item) -> Option<String> {
+    let id = match item {
+        Item::Const(x) => &x.ident,
+        Item::Enum(x) => &x.ident,
+        Item::ExternCrate(x) => &x.ident,
+        Item::Fn(x) => &x.sig.ident,
+        Item::Mod(x) => &x.ident,
+        Item::Static(x) => &x.ident,
+        Item::Struct(x) => &x.ident,
+        Item::Trait(x) => &x.ident,
+        Item::Type(x) => &x.ident,
+        Item::Union(x) => &x.ident,
+        _ => return None,
+    };
+    Some(get_ident_string(id))
+}
+
+#[derive(Debug)]
+pub enum ModuleParseError {
+    /// found a token that cannot start a module definition
+    UnexpectedToken { expected: &'static str, found: TokenTree },
+    /// the token stream ended before finding a module
+    UnexpectedEnd { expected: &'static str },
+    /// the tokens look like a module, but syn failed to parse it
+    Syn(syn::Error),
+}
+
+impl ModuleParseError {
+    pub fn to_message(&self) -> String {
+        match self {
+            ModuleParseError::UnexpectedToken { expected, found } => {
+                let (line, column) = get_span_location(found.span());
+                format!("Hira can only be applied to rust modules. Expected {expected}, found `{found}` at line {line}, column {column}")
+            }
+            ModuleParseError::UnexpectedEnd { expected } => {
+                format!("Hira can only be applied to rust modules. Expected {expected}, but reached the end of the token stream")
+            }
+            ModuleParseError::Syn(e) => {
+                let (line, column) = get_span_location(e.span());
+                format!("Failed to parse module at line {line}, column {column}\n{e}")
+            }
+        }
+    }
+}
+
+/// walks past the attributes and visibility of an item to find
+/// what kind of item it is. Used to give a better error message when
+/// the stream is not a module.
+pub fn find_non_module_token(stream: TokenStream) -> Result<(), ModuleParseError> {
+    const EXPECTED: &str = "`mod` keyword";
+    let mut iter = stream.into_iter().peekable();
+    loop {
+        let token = match iter.next() {
+            Some(t) => t,
+            None => return Err(ModuleParseError::UnexpectedEnd { expected: EXPECTED }),
+        };
+        match &token {
+            // attribute: `#` followed by `[...]`
+            TokenTree::Punct(p) if p.as_char() == '#' => {
+                if let Some(TokenTree::Group(_)) = iter.peek() {
+                    iter.next();
+                    continue;
+                }
+                return Err(ModuleParseError::UnexpectedToken { expected: EXPECTED, found: token });
+            }
+            TokenTree::Ident(i) if i == "pub" => {
+                // optional visibility restriction, eg: pub(crate)
+                if let Some(TokenTree::Group(g)) = iter.peek() {
+                    if g.delimiter() == proc_macro2::Delimiter::Parenthesis {
+                        iter.next();
+                    }
+                }
+                continue;
+            }
+            TokenTree::Ident(i) if i == "mod" => return Ok(()),
+            _ => return Err(ModuleParseError::UnexpectedToken { expected: EXPECTED, found: token }),
+        }
+    }
+}
+
+pub fn parse_as_module_item_ex(stream: TokenStream) -> Result<ItemMod, ModuleParseError> {
+    match syn::parse2::<ItemMod>(stream.clone()) {
+        Ok(mod_def) => Ok(mod_def),
+        Err(e) => {
+            find_non_module_token(stream)?;
+            Err(ModuleParseError::Syn(e))
+        }
+    }
+}
+
 pub fn parse_as_module_item(stream: TokenStream) -> Result<ItemMod, TokenStream> {
-    let mod_def = syn::parse2::<ItemMod>(stream)
-        .map_err(|e| compiler_error(&format!("Failed to parse as ItemMod. Hira expects modules to be only applied to rust modules\n{:?}", e)))?;
-    Ok(mod_def)
+    parse_as_module_item_ex(stream).map_err(|e| compiler_error(&e.to_message()))
 }
 
 #[derive(PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
@@ -610,10 +909,17 @@ pub struct DependencyConfig {
     pub level: ModuleLevel,
     pub deps: Vec<DependencyType>,
     pub wrapper_mod_names: Vec<String>,
+    /// names of the public fields of the module's Input. used to track
+    /// who set each field. see `L0Core::input_source`
+    pub input_fields: Vec<String>,
+    /// true if the module has a `schema_for_input` function. see `HiraModule2::input_struct_derives_schema`
+    pub has_input_schema: bool,
 }
 
 impl DependencyConfig {
-    pub fn config_calling_code(&self, first_config_ident: Ident) -> TokenStream {
+    /// `set_by_wrapper` is true if `first_config_ident` was filled by a wrapping lvl2 module's config,
+    /// or false if it was filled by the user's lvl3 module.
+    pub fn config_calling_code(&self, first_config_ident: Ident, set_by_wrapper: bool) -> TokenStream {
         let item_name = &self.name;
         let item_name_ident = format_ident!("{}", item_name);
         let mut config_lets = vec![];
@@ -626,7 +932,7 @@ impl DependencyConfig {
                     let x_name = format_ident!("{}", x.name);
                     config_lets.push(quote!{ let mut #conf_name = #x_name::Input::default(); });
                     config_pass.push(quote!{ &mut #conf_name, });
-                    recursive.push(x.config_calling_code(conf_name));
+                    recursive.push(x.config_calling_code(conf_name, true));
                 }
                 DependencyType::Library(x) => {
                     let x_field_name = convert_to_snake_case(x);
@@ -641,9 +947,49 @@ impl DependencyConfig {
             let wrapper_modname_ident = format_ident!("{}", wrapper);
             wrappers.push(quote!{ #wrapper_modname_ident::config(&mut #first_config_ident); });
         }
+        let set_by = if set_by_wrapper { format_ident!("Wrapper") } else { format_ident!("User") };
+        let input_fields: Vec<Ident> = self.input_fields.iter().map(|x| format_ident!("{}", x)).collect();
+        let input_field_names = &self.input_fields;
+        // fields that differ from the default before the wrappers run were set by whoever filled the input.
+        // any other field that differs after the wrappers run was set by a wrapper.
+        let track_sources = if input_fields.is_empty() { quote!{} } else {
+            quote! {
+                let hira_input_default = #item_name_ident::Input::default();
+                let mut hira_input_sources = std::collections::HashMap::new();
+                #[allow(deprecated)]
+                let _ = {#(
+                    hira_input_sources.insert(#input_field_names.to_string(), match (&HiraInputProbe(&#first_config_ident.#input_fields, &hira_input_default.#input_fields)).hira_changed() {
+                        Some(true) => hira_base::InputSource::#set_by,
+                        Some(false) => hira_base::InputSource::Default,
+                        None => hira_base::InputSource::Unknown,
+                    });
+                )*};
+            }
+        };
+        let track_wrapper_sources = if input_fields.is_empty() || wrappers.is_empty() { quote!{} } else {
+            quote! {
+                #[allow(deprecated)]
+                let _ = {#(
+                    if hira_input_sources.get(#input_field_names) == Some(&hira_base::InputSource::Default) {
+                        if let Some(true) = (&HiraInputProbe(&#first_config_ident.#input_fields, &hira_input_default.#input_fields)).hira_changed() {
+                            hira_input_sources.insert(#input_field_names.to_string(), hira_base::InputSource::Wrapper);
+                        }
+                    }
+                )*};
+            }
+        };
+        // always set, such that the sources of a previous module are not visible to this module
+        let set_sources = if input_fields.is_empty() {
+            quote!{ library_obj.l0_core.set_input_sources(Default::default()); }
+        } else {
+            quote!{ library_obj.l0_core.set_input_sources(hira_input_sources); }
+        };
         quote! {
             #(#config_lets)*
+            #track_sources
             #(#wrappers)*
+            #track_wrapper_sources
+            #set_sources
             #item_name_ident::config(&mut #first_config_ident, #(#config_pass)*);
 
             #(#recursive)*
@@ -677,7 +1023,13 @@ pub fn ident_contains(id: &Ident, match_str: &str) -> bool {
 }
 
 pub fn parse_documentation_from_attributes(attrs: &[Attribute]) -> String {
-    let mut out = "".to_string();
+    parse_documentation_lines_from_attributes(attrs).concat().trim().to_string()
+}
+
+/// returns every line of the doc comment separately, as is. eg: `/// a` -> `" a"`.
+/// the lines of block doc comments are also returned separately, eg: `/** a\n b */` -> `" a"`, `" b "`
+pub fn parse_documentation_lines_from_attributes(attrs: &[Attribute]) -> Vec<String> {
+    let mut out = vec![];
     for att in attrs.iter() {
         if let Meta::NameValue(nv) = &att.meta {
             if !nv.path.segments.iter().any(|s| ident_contains(&s.ident, "doc")) {
@@ -685,18 +1037,52 @@ pub fn parse_documentation_from_attributes(attrs: &[Attribute]) -> String {
             }
             if let Expr::Lit(l) = &nv.value {
                 if let syn::Lit::Str(s) = &l.lit {
-                    let mut st = s.token().to_string();
-                    remove_surrounding_quotes(&mut st);
-                    out.push_str(&st);
+                    // a block doc comment (`/** ... */`) is a single attribute containing all of its lines
+                    let value = s.value();
+                    if value.contains('\n') {
+                        for line in value.split('\n') {
+                            let mut st = proc_macro2::Literal::string(line).to_string();
+                            remove_surrounding_quotes(&mut st);
+                            out.push(st);
+                        }
+                    } else {
+                        let mut st = s.token().to_string();
+                        remove_surrounding_quotes(&mut st);
+                        out.push(st);
+                    }
                 }
             }
         }
     }
-    out.trim().to_string()
+    out
+}
+
+/// returns the name and value of every non-doc attribute. eg:
+/// `#[a]` -> `("a", None)`, `#[a = "b"]` -> `("a", Some("b"))`, `#[a(b, c)]` -> `("a", Some("b , c"))`
+pub fn parse_attributes(attrs: &[Attribute]) -> Vec<(String, Option<String>)> {
+    let mut out = vec![];
+    for att in attrs.iter() {
+        let path = att.path();
+        let name = path.to_token_stream().to_string().replace(' ', "");
+        if path.is_ident("doc") {
+            continue;
+        }
+        let value = match &att.meta {
+            Meta::Path(_) => None,
+            Meta::List(l) => Some(l.tokens.to_string()),
+            Meta::NameValue(nv) => {
+                let mut val = nv.value.to_token_stream().to_string();
+                remove_surrounding_quotes(&mut val);
+                Some(val)
+            }
+        };
+        out.push((name, value));
+    }
+    out
 }
 
-/// callback takes: field name, field type, field documentation
-pub fn iter_fields(fields: &Fields, cb: &mut impl FnMut(String, String, String)) {
+/// callback takes: field name, field type, field documentation, field attributes
+pub fn iter_fields(fields: &Fields, cb: &mut impl FnMut(String, String, String, Vec<(String, Option<String>)>)) {
     let default_ident = Ident::new("a", Span::call_site());
     for field in fields.iter() {
         let ident = field.ident.as_ref().unwrap_or(&default_ident);
@@ -704,10 +1090,23 @@ pub fn iter_fields(fields: &Fields, cb: &mut impl FnMut(String, String, String))
         let mut typ = field.ty.to_token_stream().to_string();
         remove_surrounding_quotes(&mut typ);
         let doc = parse_documentation_from_attributes(&field.attrs);
-        cb(name, typ, doc);
+        let attributes = parse_attributes(&field.attrs);
+        cb(name, typ, doc, attributes);
     }
 }
 
+/// returns the names of the `pub` named fields of the struct. empty if `struct_def` is not a struct.
+pub fn get_public_field_names(struct_def: &str) -> Vec<String> {
+    let item = match syn::parse_str::<ItemStruct>(struct_def) {
+        Ok(i) => i,
+        Err(_) => return vec![],
+    };
+    item.fields.iter()
+        .filter(|f| matches!(f.vis, Visibility::Public(_)))
+        .filter_map(|f| f.ident.as_ref().map(get_ident_string))
+        .collect()
+}
+
 pub fn fill_dependency_config(
     hira_conf: &HiraConfig,
     name: &str,
@@ -721,6 +1120,8 @@ pub fn fill_dependency_config(
         level: dep_module.level,
         deps: vec![],
         wrapper_mod_names: vec![],
+        input_fields: get_public_field_names(&dep_module.input_struct),
+        has_input_schema: dep_module.input_struct_derives_schema,
     };
     if let Some(list) = use_wrappers.get(name) {
         // for each wrapper module name, find its module contents and att it to the dep contents:
@@ -762,10 +1163,77 @@ pub fn parse_module_from_string<S: AsRef<str>>(s: S) -> Result<HiraModule2, Toke
     parse_module_from_stream(stream)
 }
 
+/// convenience function for testing. parses the string as a function, and calls `parse_fn_signature`
+pub fn parse_fn_signature_from_string<S: AsRef<str>>(s: S) -> Result<FunctionSignature, String> {
+    let item = syn::parse_str::<ItemFn>(s.as_ref())
+        .map_err(|e| format!("Failed to parse string as function {:?}", e))?;
+    Ok(parse_fn_signature(&item))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_as_module_errors_on_struct() {
+        let tokens = TokenStream::from_str("#[derive(Default)]\npub struct Input { a: u32 }").unwrap();
+        let err = parse_as_module_item_ex(tokens).err().expect("Expected parse error");
+        match &err {
+            ModuleParseError::UnexpectedToken { found, .. } => assert_eq!(found.to_string(), "struct"),
+            e => panic!("Unexpected error {:?}", e),
+        }
+        assert_eq!(err.to_message(), "Hira can only be applied to rust modules. Expected `mod` keyword, found `struct` at line 2, column 5");
+    }
+
+    #[test]
+    fn parse_as_module_errors_on_fn() {
+        let tokens = TokenStream::from_str("pub(crate) fn config() {}").unwrap();
+        let err = parse_as_module_item_ex(tokens).err().expect("Expected parse error");
+        assert_eq!(err.to_message(), "Hira can only be applied to rust modules. Expected `mod` keyword, found `fn` at line 1, column 12");
+        let err = parse_as_module_item(TokenStream::from_str("pub").unwrap()).err().expect("Expected parse error");
+        assert!(err.to_string().contains("reached the end of the token stream"));
+    }
+
+    #[test]
+    fn fn_signature_return_ok_type_handles_nested_generics() {
+        let item = syn::parse_str::<ItemFn>("fn a() -> std::result::Result<HashMap<String, Vec<(A, B)>>, BoxError> { todo!() }").unwrap();
+        assert_eq!(parse_fn_signature(&item).return_ok_ty, "HashMap < String , Vec < (A , B) > >");
+        let item = syn::parse_str::<ItemFn>("fn a() -> HashMap<String, Vec<(A, B)>> { todo!() }").unwrap();
+        assert_eq!(parse_fn_signature(&item).return_ok_ty, "HashMap < String , Vec < (A , B) > >");
+        let item = syn::parse_str::<ItemFn>("fn a() {}").unwrap();
+        assert_eq!(parse_fn_signature(&item).return_ok_ty, "");
+    }
+
+    #[test]
+    fn await_is_only_found_in_code() {
+        assert_eq!(find_await_in_fn_body("let x = something() . await;"), Ok(Some("something () . await".to_string())));
+        let code = "// we don't use .await here\nlet s = \"x.await\";\nlet f = async { other().await };";
+        assert_eq!(find_await_in_fn_body(code), Ok(None));
+        assert!(find_await_in_fn_body("if x {").is_err());
+    }
+
+    #[test]
+    fn fn_signature_captures_generics_and_where_clause() {
+        let item = syn::parse_str::<ItemFn>("pub async fn handler<'a, E: std::fmt::Debug>(x: &'a str) -> Result<(), E> where E: Send { todo!() }").unwrap();
+        let sig = parse_fn_signature(&item);
+        assert!(sig.is_async);
+        assert_eq!(sig.generics, "< 'a , E : std :: fmt :: Debug >");
+        assert_eq!(sig.where_clause, "where E : Send");
+        assert_eq!(sig.inputs[0].ty, "& 'a str");
+        assert_eq!(sig.return_ty, "Result < () , E >");
+        assert_eq!(sig.return_ok_ty, "()");
+        // the signature can be rebuilt from its parts
+        let inputs: Vec<String> = sig.inputs.iter().map(|x| format!("{}: {}", x.name, x.ty)).collect();
+        let rebuilt = format!("pub async fn {}{}({}) -> {} {} {{ todo!() }}", sig.name, sig.generics, inputs.join(", "), sig.return_ty, sig.where_clause);
+        let rebuilt = syn::parse_str::<ItemFn>(&rebuilt).unwrap();
+        assert_eq!(rebuilt.to_token_stream().to_string(), item.to_token_stream().to_string());
+
+        let item = syn::parse_str::<ItemFn>("fn plain(a: u32) {}").unwrap();
+        let sig = parse_fn_signature(&item);
+        assert_eq!(sig.generics, "");
+        assert_eq!(sig.where_clause, "");
+    }
+
     #[test]
     fn to_snakecase_works() {
         let field_ty = "L0KvReader";
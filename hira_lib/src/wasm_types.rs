@@ -25,21 +25,21 @@ pub struct FileOut {
     pub data: Vec<u8>,
 }
 
-pub fn to_map_entry(data: Vec<SharedOutputEntry>) -> Vec<MapEntry<MapEntry<(bool, String, Option<String>)>>> {
-    let mut map_entries: Vec<MapEntry<MapEntry<(bool, String, Option<String>)>>> = vec![];
+pub fn to_map_entry(data: Vec<SharedOutputEntry>) -> Vec<MapEntry<MapEntry<SharedOutputEntry>>> {
+    let mut map_entries: Vec<MapEntry<MapEntry<SharedOutputEntry>>> = vec![];
+    // index of every file in map_entries, and of every label within its file
+    let mut file_indices: HashMap<String, (usize, HashMap<String, usize>)> = HashMap::new();
     for d in data {
-        if let Some(m) = map_entries.iter_mut().find(|x| x.key == d.filename) {
-            if let Some(m) = m.lines.iter_mut().find(|x| x.key == d.label) {
-                m.lines.push((d.unique, d.line, d.after));
-            } else {
-                m.lines.push(MapEntry { key: d.label, lines: vec![(d.unique, d.line, d.after)] });
-            }
-        } else {
-            map_entries.push(MapEntry { key: d.filename, lines: vec![MapEntry {
-                key: d.label,
-                lines: vec![(d.unique, d.line, d.after)],
-            }] })
-        }
+        let (file_index, label_indices) = file_indices.entry(d.filename.clone()).or_insert_with(|| {
+            map_entries.push(MapEntry { key: d.filename.clone(), lines: vec![] });
+            (map_entries.len() - 1, HashMap::new())
+        });
+        let labels = &mut map_entries[*file_index].lines;
+        let label_index = *label_indices.entry(d.label.clone()).or_insert_with(|| {
+            labels.push(MapEntry { key: d.label.clone(), lines: vec![] });
+            labels.len() - 1
+        });
+        labels[label_index].lines.push(d);
     }
     map_entries
 }
@@ -133,6 +133,29 @@ pub fn get_wasm_code_to_compile2(
     ])
 }
 
+/// used by the config calling code to check if an input field differs from its default.
+/// fields whose type does not implement PartialEq can't be compared, and so `hira_changed` returns None for them.
+fn input_probe_code() -> TokenStream {
+    quote! {
+        #[allow(dead_code)]
+        struct HiraInputProbe<'a, T>(&'a T, &'a T);
+        #[allow(dead_code)]
+        trait HiraInputChanged {
+            fn hira_changed(&self) -> Option<bool>;
+        }
+        impl<'a, T: PartialEq> HiraInputChanged for HiraInputProbe<'a, T> {
+            fn hira_changed(&self) -> Option<bool> { Some(self.0 != self.1) }
+        }
+        #[allow(dead_code)]
+        trait HiraInputChangedFallback {
+            fn hira_changed(&self) -> Option<bool>;
+        }
+        impl<'a, T> HiraInputChangedFallback for &HiraInputProbe<'a, T> {
+            fn hira_changed(&self) -> Option<bool> { None }
+        }
+    }
+}
+
 pub fn get_wasm_code_to_compile_lvl3(
     lvl3module_name: String,
     lvl3module_def: String,
@@ -144,10 +167,24 @@ pub fn get_wasm_code_to_compile_lvl3(
     let conf0 = format_ident!("conf_0");
     let dep_crate_name = format_ident!("{}", dependency_crate_name);
 
-    let mod2_calling_code = lvl2module.config_calling_code(conf0.clone());
+    let mod2_calling_code = lvl2module.config_calling_code(conf0.clone(), false);
+    let input_probe = input_probe_code();
     let lvl3mod_tokens = TokenStream::from_str(&lvl3module_def).expect("Failed to parse lvl3 module def as tokens");
 
     if cfg!(feature = "web") {
+        let input_schema_code = if lvl2module.has_input_schema {
+            quote! {
+                #[no_mangle]
+                pub extern "C" fn get_input_schema() -> sapp_jsutils::JsObject {
+                    match serde_json::to_string(&#mod2name::schema_for_input()) {
+                        Ok(s) => create_js_obj("ok", s),
+                        Err(e) => create_js_obj("err", format!("Failed to serialize Input schema\n{:?}", e)),
+                    }
+                }
+            }
+        } else {
+            quote! {}
+        };
         return quote! {
             extern crate hira_base;
             extern crate serde_json;
@@ -158,6 +195,8 @@ pub fn get_wasm_code_to_compile_lvl3(
 
             #lvl3mod_tokens
 
+            #input_probe
+
             fn create_js_obj(typ: &str, err: String) -> sapp_jsutils::JsObject {
                 let mut obj = serde_json::Map::new();
                 obj.insert(typ.to_string(), serde_json::Value::String(err));
@@ -179,6 +218,8 @@ pub fn get_wasm_code_to_compile_lvl3(
                 }
             }
 
+            #input_schema_code
+
             #[no_mangle]
             pub extern "C" fn run_module_config(lib_obj: sapp_jsutils::JsObject, conf0data: sapp_jsutils::JsObject) -> sapp_jsutils::JsObject {
                 let mut lib_obj_str = String::new();
@@ -224,6 +265,8 @@ pub fn get_wasm_code_to_compile_lvl3(
 
         #lvl3mod_tokens
 
+        #input_probe
+
         #[no_mangle]
         pub fn wasm_main(library_obj: &mut LibraryObj) {
             let mut #conf0 = #mod2name::Input::default();
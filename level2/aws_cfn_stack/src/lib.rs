@@ -1,20 +1,29 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use hira_lib::level0::*;
 use aws_config;
-use aws_sdk_cloudformation::{self, types::{Stack, Capability, OnFailure, StackResourceSummary}};
+use aws_sdk_cloudformation::{self, types::{Stack, Capability, ChangeSetType, OnFailure, StackResourceSummary, Tag}};
 
 use crate::aws_cfn_stack::SavedTemplate;
 
-pub async fn runtime_main(data: &Vec<String>) {
-    // // TODO: allow user to customize region.
-    let shared_config = aws_config::from_env().load().await;
-    let client = aws_sdk_cloudformation::Client::new(&shared_config);
-    let mut stack_map: HashMap<String, Vec<(String, aws_cfn_stack::SavedTemplate)>> = HashMap::new();
+/// a map of stack names to the tags of that stack
+type StackTags = HashMap<String, Vec<(String, String)>>;
+
+/// stacks are sorted by name so that every runtime iterates them in the same order.
+fn get_stack_map(data: &Vec<String>) -> (BTreeMap<String, Vec<(String, aws_cfn_stack::SavedTemplate)>>, usize, StackTags) {
+    let mut stack_map: BTreeMap<String, Vec<(String, aws_cfn_stack::SavedTemplate)>> = BTreeMap::new();
+    let mut stack_tags: StackTags = HashMap::new();
     let mut num_resources = 0;
     for stack_str in data {
         let stack: aws_cfn_stack::SavedStack = cfn_resources::serde_json::from_str(&stack_str).expect("Failed to deserialize generated json file");
         for (stack_name, (mod_name, template)) in stack.template {
+            let tags = stack_tags.entry(stack_name.clone()).or_default();
+            // conflicting values are a compile error, see `register_stack_tags`
+            for (key, val) in stack.tags.iter() {
+                if !tags.iter().any(|(k, _)| k == key) {
+                    tags.push((key.to_string(), val.to_string()));
+                }
+            }
             num_resources += template.resources.len();
             if let Some(existing) = stack_map.get_mut(&stack_name) {
                 existing.push((mod_name, template));
@@ -25,16 +34,217 @@ pub async fn runtime_main(data: &Vec<String>) {
             break;
         }
     }
+    (stack_map, num_resources, stack_tags)
+}
+
+static TEMPLATE_TRANSFORMERS: std::sync::Mutex<Vec<fn(&mut SavedTemplate)>> = std::sync::Mutex::new(Vec::new());
+
+/// registers a function that modifies every stack's final template before it gets deployed.
+/// transformers run in the order they were registered. This is usually not called directly, but
+/// rather via the `template_transformers` field of `aws_cfn_stack::Input`.
+pub fn register_template_transformer(transformer: fn(&mut SavedTemplate)) {
+    let mut transformers = TEMPLATE_TRANSFORMERS.lock().expect("Template transformers lock poisoned");
+    if !transformers.contains(&transformer) {
+        transformers.push(transformer);
+    }
+}
+
+fn apply_template_transformers(template: &mut SavedTemplate) {
+    let transformers = TEMPLATE_TRANSFORMERS.lock().expect("Template transformers lock poisoned");
+    for transformer in transformers.iter() {
+        transformer(template);
+    }
+}
+
+/// converts the tags of a stack to the tags that get sent to cloudformation.
+/// returns None if there are no tags, as an empty list would remove the existing tags of the stack.
+pub fn to_sdk_tags(tags: &[(String, String)]) -> Option<Vec<Tag>> {
+    if tags.is_empty() {
+        return None;
+    }
+    Some(tags.iter().map(|(key, value)| {
+        Tag::builder().key(key).value(value).build()
+    }).collect())
+}
+
+/// the part of `aws_config::ConfigLoader` that selects a named profile.
+/// this is a trait so that tests can record which profile gets selected.
+pub trait AwsProfileSelector: Sized {
+    fn with_profile_name(self, name: &str) -> Self;
+}
+
+impl AwsProfileSelector for aws_config::ConfigLoader {
+    fn with_profile_name(self, name: &str) -> Self {
+        self.profile_name(name)
+    }
+}
+
+/// selects the profile if there is one, otherwise the loader uses the default credentials chain.
+pub fn select_aws_profile<L: AwsProfileSelector>(loader: L, profile: Option<&str>) -> L {
+    match profile {
+        Some(name) => loader.with_profile_name(name),
+        None => loader,
+    }
+}
+
+/// returns the aws profile of every stack, where None means the default credentials chain.
+/// Different stacks can use different profiles, eg: to deploy to multiple accounts. But a stack is deployed
+/// with one set of credentials, so every module of a stack must use the same profile. This includes modules
+/// that dont set a profile when other modules of the stack do, as those would otherwise
+/// silently be deployed with the profile of the other modules.
+pub fn get_aws_profiles(data: &Vec<String>) -> Result<BTreeMap<String, Option<String>>, String> {
+    // stack name -> (profile, name of the module that set it)
+    let mut profiles: BTreeMap<String, (Option<String>, String)> = BTreeMap::new();
+    let describe = |profile: &Option<String>| match profile {
+        Some(name) => format!("uses aws profile '{name}'"),
+        None => "doesn't set an aws profile".to_string(),
+    };
+    for stack_str in data {
+        let stack: aws_cfn_stack::SavedStack = cfn_resources::serde_json::from_str(&stack_str)
+            .map_err(|e| format!("Failed to deserialize generated json file\n{:?}", e))?;
+        for (stack_name, (mod_name, _)) in stack.template {
+            match profiles.get(&stack_name) {
+                Some((existing, existing_mod)) if *existing != stack.aws_profile => {
+                    return Err(format!("Module {existing_mod} of stack {stack_name} {}, but module {mod_name} {}. All modules of a stack must use the same profile", describe(existing), describe(&stack.aws_profile)));
+                }
+                Some(_) => {}
+                None => {
+                    profiles.insert(stack_name, (stack.aws_profile.clone(), mod_name));
+                }
+            }
+        }
+    }
+    Ok(profiles.into_iter().map(|(stack_name, (profile, _))| (stack_name, profile)).collect())
+}
+
+/// returns true if `name` is a profile in the contents of the aws config file (`[profile name]`, or `[default]`)
+/// or the aws credentials file (`[name]`).
+pub fn aws_profile_exists(name: &str, config_file: Option<&str>, credentials_file: Option<&str>) -> bool {
+    let has_section = |contents: Option<&str>, section: &str| {
+        contents.map(|x| x.lines().any(|line| line.trim() == section)).unwrap_or(false)
+    };
+    let config_section = if name == "default" { "[default]".to_string() } else { format!("[profile {name}]") };
+    has_section(config_file, &config_section) || has_section(credentials_file, &format!("[{name}]"))
+}
+
+/// reads the aws file from the path in `env_var`, or from `~/.aws/{default_name}`
+fn read_aws_file(env_var: &str, default_name: &str) -> Option<String> {
+    let path = match std::env::var(env_var) {
+        Ok(p) => std::path::PathBuf::from(p),
+        Err(_) => {
+            let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")).ok()?;
+            std::path::PathBuf::from(home).join(".aws").join(default_name)
+        }
+    };
+    std::fs::read_to_string(path).ok()
+}
+
+/// the aws configs that the stacks are deployed with. A config is loaded once
+/// for every profile used by the stacks, see `get_aws_profiles`.
+pub struct AwsConfigs {
+    /// stack name -> profile
+    profiles: BTreeMap<String, Option<String>>,
+    configs: HashMap<Option<String>, aws_config::SdkConfig>,
+}
+
+impl AwsConfigs {
+    /// the profile of the stack, or None if it uses the default credentials chain
+    pub fn profile(&self, stack_name: &str) -> Option<String> {
+        self.profiles.get(stack_name).cloned().flatten()
+    }
+
+    /// the config of the stack's profile
+    pub fn get(&self, stack_name: &str) -> &aws_config::SdkConfig {
+        let profile = self.profile(stack_name);
+        match self.configs.get(&profile) {
+            Some(c) => c,
+            None => panic!("No aws config was loaded for stack {stack_name}"),
+        }
+    }
+
+    pub fn cloudformation_client(&self, stack_name: &str) -> aws_sdk_cloudformation::Client {
+        aws_sdk_cloudformation::Client::new(self.get(stack_name))
+    }
+}
+
+/// loads the aws configs that are used to deploy the stacks,
+/// using the `aws_profile` of the stacks if they set one.
+pub async fn load_aws_configs(data: &Vec<String>) -> AwsConfigs {
+    let profiles = match get_aws_profiles(data) {
+        Ok(p) => p,
+        Err(e) => panic!("{e}"),
+    };
+    let mut configs = HashMap::new();
+    for profile in profiles.values() {
+        if !configs.contains_key(profile) {
+            configs.insert(profile.clone(), load_aws_config(profile.as_deref()).await);
+        }
+    }
+    AwsConfigs { profiles, configs }
+}
+
+/// loads the aws config of the named profile, or of the default credentials chain if there is no profile.
+pub async fn load_aws_config(profile: Option<&str>) -> aws_config::SdkConfig {
+    if let Some(name) = profile {
+        let config_file = read_aws_file("AWS_CONFIG_FILE", "config");
+        let credentials_file = read_aws_file("AWS_SHARED_CREDENTIALS_FILE", "credentials");
+        if !aws_profile_exists(name, config_file.as_deref(), credentials_file.as_deref()) {
+            panic!("AWS profile '{name}' was not found in the aws config or credentials file");
+        }
+        println!("Using AWS profile {name}");
+    }
+    select_aws_profile(aws_config::from_env(), profile).load().await
+}
+
+/// when compiled with `--cfg hira_preview`, nothing is deployed. instead we print
+/// what would be deployed.
+#[cfg(hira_preview)]
+pub async fn runtime_main(data: &Vec<String>) {
+    preview::runtime_main(data).await
+}
+
+#[cfg(not(hira_preview))]
+pub async fn runtime_main(data: &Vec<String>) {
+    // // TODO: allow user to customize region.
+    let aws_configs = load_aws_configs(data).await;
+    let (mut stack_map, num_resources, stack_tags) = get_stack_map(data);
+    let dotenv_paths = get_dotenv_paths(data);
+    let nested_stacks = get_nested_stacks(data);
+    // only created if a stack has nested stacks. each profile
+    // can be a different account, so they each get their own bucket.
+    let mut artifact_buckets: HashMap<Option<String>, String> = HashMap::new();
+    let mode = DeployMode::from_args(std::env::args());
+    let deploy_state_path = get_deploy_state_path();
+    let mut deploy_state = load_deploy_state(&deploy_state_path);
+    let deploy_order = start_deploy(mode, stack_map.keys().cloned().collect(), &mut deploy_state);
+    if let Err(e) = save_deploy_state(&deploy_state_path, &deploy_state) {
+        println!("Warning: {e}");
+    }
+    let mut dotenv_files: BTreeMap<String, Vec<(String, String)>> = Default::default();
     println!("\nDeploying {} resource(s)", num_resources);
     println!("Across {} stack(s)", stack_map.len());
 
-    for (stack_name, templates) in stack_map {
-        println!("\nAbout to deploy stack: {stack_name}");
-        let mut final_template = SavedTemplate::default();
+    let mut stacks = vec![];
+    for stack_name in deploy_order {
+        let templates = match stack_map.remove(&stack_name) {
+            Some(t) => t,
+            None => continue,
+        };
+        let client = aws_configs.cloudformation_client(&stack_name);
         let mut module_resources: HashMap<String, (ModResourceCounts, Vec<(bool, String)>)> = HashMap::new();
-        for (mod_name, template) in templates {
-            for (resource_name, _) in template.resources.iter() {
-                if let Some((_, existing)) = module_resources.get_mut(&mod_name) {
+        let mut has_nested_stacks = false;
+        for (mod_name, template) in templates.iter() {
+            // the resources of a nested stack are not resources of this stack,
+            // so we track the nested stack itself instead.
+            let resource_names: Vec<&String> = match nested_stacks.get(&(stack_name.clone(), mod_name.clone())) {
+                Some(nested_stack) => {
+                    has_nested_stacks = true;
+                    vec![nested_stack]
+                }
+                None => template.resources.keys().collect(),
+            };
+            for resource_name in resource_names {
+                if let Some((_, existing)) = module_resources.get_mut(mod_name) {
                     existing.push((false, resource_name.to_string()));
                 } else {
                     let mod_resource_counts = ModResourceCounts {
@@ -44,21 +254,954 @@ pub async fn runtime_main(data: &Vec<String>) {
                     module_resources.insert(mod_name.to_string(), (mod_resource_counts, vec![(false, resource_name.to_string())]));
                 }
             }
-            final_template.resources.extend(template.resources);
-            final_template.outputs.extend(template.outputs);
         }
-        // we make it pretty so if a user needs to look at the stack in Cfn console, it looks nice
-        let template_body = cfn_resources::serde_json::to_string_pretty(&final_template).expect("Failed to serialize template");
-        if let Err(e) = create_or_update_stack(&client, &stack_name, &template_body).await {
-            panic!("Failed to create stack {stack_name}\n{e}");
+        let profile = aws_configs.profile(&stack_name);
+        let bucket = match (artifact_buckets.get(&profile), has_nested_stacks) {
+            (_, false) => String::new(),
+            (Some(b), true) => b.clone(),
+            (None, true) => {
+                let b = match get_or_create_artifact_bucket(&client).await {
+                    Ok(b) => b,
+                    Err(e) => panic!("{e}"),
+                };
+                artifact_buckets.insert(profile, b.clone());
+                b
+            }
+        };
+        let (mut final_template, nested_templates) = match split_nested_stacks(&stack_name, templates, &nested_stacks, &bucket) {
+            Ok(x) => x,
+            Err(e) => panic!("Failed to create nested stacks of stack {stack_name}\n{e}"),
+        };
+        apply_template_transformers(&mut final_template);
+        // we make it pretty so if a user needs to look at the stack in Cfn console, it looks nice
+        let template_body = cfn_resources::serde_json::to_string_pretty(&final_template).expect("Failed to serialize template");
+        let tags = stack_tags.get(&stack_name).map(|x| x.as_slice()).unwrap_or_default();
+        let hash = get_stack_hash(&template_body, tags);
+        let status = match get_stack_status(&client, &stack_name).await {
+            Ok(s) => s,
+            Err(e) => panic!("Failed to get status of stack {stack_name}\n{e}"),
+        };
+        stacks.push((stack_name, template_body, hash, status, module_resources, nested_templates));
+    }
+    let decisions: Vec<(&str, &str, Option<&str>)> = stacks.iter()
+        .map(|(name, _, hash, status, _, _)| (name.as_str(), hash.as_str(), status.as_deref())).collect();
+    let decisions = get_resume_decisions(mode, &decisions, &deploy_state);
+    if let Some(resume_index) = decisions.iter().position(|skip| !skip) {
+        if resume_index > 0 && deploy_state.is_resuming() {
+            println!("\n{resume_index} stack(s) were already deployed by the previous deploy. Resuming from stack {} (pass {REDEPLOY_ALL_FLAG} to start over)", stacks[resume_index].0);
+        }
+    }
+
+    for ((stack_name, template_body, hash, _, mut module_resources, nested_templates), skip) in stacks.into_iter().zip(decisions) {
+        println!("\nAbout to deploy stack: {stack_name}");
+        let client = aws_configs.cloudformation_client(&stack_name);
+        let tags = stack_tags.get(&stack_name).map(|x| x.as_slice()).unwrap_or_default();
+        let outputs = if skip {
+            match mode {
+                DeployMode::Force => println!("Stack {stack_name} was already deployed by the previous deploy. Skipping (pass {REDEPLOY_ALL_FLAG} to deploy it anyway)"),
+                _ => println!("Stack {stack_name} is unchanged since the last deploy. Skipping (pass {FORCE_DEPLOY_FLAG} to deploy anyway)"),
+            }
+            wait_for_output(&client, &stack_name, None).await
+        } else {
+            if let Some(bucket) = artifact_buckets.get(&aws_configs.profile(&stack_name)).filter(|_| !nested_templates.is_empty()) {
+                let s3_client = aws_sdk_s3::Client::new(aws_configs.get(&stack_name));
+                if let Err(e) = upload_nested_templates(&s3_client, bucket, &nested_templates).await {
+                    panic!("{e}");
+                }
+            }
+            match create_or_update_stack(&client, &stack_name, &template_body, tags).await {
+                Ok(_) => wait_for_output(&client, &stack_name, Some(&mut module_resources)).await,
+                Err(e) => Err(e),
+            }
+        };
+        let mut outputs = match outputs {
+            Err(e) => {
+                // a failed stack is never skipped on the next deploy, regardless of its status.
+                deploy_state.deployed.remove(&stack_name);
+                if let Err(e) = save_deploy_state(&deploy_state_path, &deploy_state) {
+                    println!("Warning: {e}");
+                }
+                panic!("Failed to create stack {stack_name}\n{e}");
+            }
+            Ok(o) => o,
+        };
+        // saved after every stack so that a failure in a later stack
+        // doesnt cause the earlier stacks to be redeployed next time.
+        deploy_state.complete_stack(&stack_name, hash);
+        if let Err(e) = save_deploy_state(&deploy_state_path, &deploy_state) {
+            println!("Warning: {e}");
+        }
+        let mut outputs: Vec<(String, String)> = outputs.drain().map(|(k, v)| {
+            (k, v)
+        }).collect();
+        outputs.sort_by(|a, b| a.0.cmp(&b.0));
+        for path in dotenv_paths.get(&stack_name).map(|x| x.as_slice()).unwrap_or_default() {
+            dotenv_files.entry(path.to_string()).or_default().extend(outputs.iter().cloned());
+        }
+        if !outputs.is_empty() {
+            println!("\nOutputs:");
+            for (key, val) in outputs {
+                println!("- {}:\n  {}", key, val);
+            }
+        }
+    }
+
+    deploy_state.interrupted = None;
+    if let Err(e) = save_deploy_state(&deploy_state_path, &deploy_state) {
+        println!("Warning: {e}");
+    }
+
+    for (path, outputs) in dotenv_files {
+        if let Err(e) = write_file_atomically(&path, &to_dotenv(&outputs)) {
+            panic!("Failed to write outputs to {path}\n{e}");
+        }
+        println!("\nWrote outputs to {path}");
+    }
+}
+
+/// returns the paths that each stack's outputs should be written to as dotenv files
+fn get_dotenv_paths(data: &Vec<String>) -> HashMap<String, Vec<String>> {
+    let mut out: HashMap<String, Vec<String>> = HashMap::new();
+    for stack_str in data {
+        let stack: aws_cfn_stack::SavedStack = cfn_resources::serde_json::from_str(&stack_str).expect("Failed to deserialize generated json file");
+        let path = match stack.dotenv_path {
+            Some(p) => p,
+            None => continue,
+        };
+        for stack_name in stack.template.keys() {
+            let paths = out.entry(stack_name.to_string()).or_default();
+            if !paths.contains(&path) {
+                paths.push(path.clone());
+            }
+        }
+    }
+    out
+}
+
+/// serializes the outputs as `KEY=value` lines. Values that contain anything other
+/// than a conservative set of characters are double quoted and escaped such that
+/// shells and dotenv parsers read back the original value.
+pub fn to_dotenv(outputs: &[(String, String)]) -> String {
+    let mut out = String::new();
+    for (key, val) in outputs {
+        let is_plain = val.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:@,+=".contains(c));
+        if is_plain {
+            out.push_str(&format!("{key}={val}\n"));
+            continue;
+        }
+        let mut escaped = String::with_capacity(val.len());
+        for c in val.chars() {
+            match c {
+                '\\' | '"' | '$' | '`' => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c),
+            }
+        }
+        out.push_str(&format!("{key}=\"{escaped}\"\n"));
+    }
+    out
+}
+
+/// writes to a temporary file next to `path` and then renames it, so that readers
+/// never see a partially written file.
+fn write_file_atomically(path: &str, contents: &str) -> Result<(), String> {
+    let tmp_path = format!("{path}.tmp");
+    std::fs::write(&tmp_path, contents)
+        .map_err(|e| format!("Failed to write {tmp_path}\n{:?}", e))?;
+    std::fs::rename(&tmp_path, path)
+        .map_err(|e| format!("Failed to rename {tmp_path} to {path}\n{:?}", e))
+}
+
+/// the stack that contains the artifact bucket that is shared by every module
+/// that needs to upload something before deploying, eg: lambda code, or nested stack templates.
+pub const ARTIFACT_BUCKET_STACK_NAME: &str = "hira-gen-lambda-artifact-bucket";
+
+/// the prefix of the keys that nested stack templates are uploaded to in the artifact bucket.
+pub const NESTED_STACK_KEY_PREFIX: &str = "hira-nested-stacks";
+
+/// returns the name of the artifact bucket, and creates the stack that contains it if it doesnt exist yet.
+pub async fn get_or_create_artifact_bucket(client: &aws_sdk_cloudformation::Client) -> Result<String, String> {
+    // check if this stack already exists.
+    // if it does, then just return the name of the s3 bucket output
+    match wait_for_output(client, ARTIFACT_BUCKET_STACK_NAME, None).await {
+        Ok(o) => match o.get("BucketName") {
+            Some(s) => return Ok(s.to_string()),
+            None => return Err(format!("Stack {ARTIFACT_BUCKET_STACK_NAME} already exists, but is missing a BucketName output")),
+        },
+        Err(_) => {
+            // assume it doesnt exist, try to create it:
+        }
+    }
+
+    let mut template = SavedTemplate::default();
+    template.resources.insert("S3ArtifactBucket".to_string(), aws_cfn_stack::SavedResource {
+        ty: "AWS::S3::Bucket".to_string(),
+        properties: cfn_resources::serde_json::Value::Object(Default::default()),
+        metadata: None,
+        depends_on: vec![],
+    });
+    template.outputs.insert("BucketName".to_string(), aws_cfn_stack::ResourceOutput {
+        description: "name of bucket created".to_string(),
+        value: cfn_resources::serde_json::json!({ "Ref": "S3ArtifactBucket" }),
+    });
+    let template_body = cfn_resources::serde_json::to_string(&template).expect("Failed to serialize template");
+    create_or_update_stack(client, ARTIFACT_BUCKET_STACK_NAME, &template_body, &[]).await
+        .map_err(|e| format!("Failed to create {ARTIFACT_BUCKET_STACK_NAME} stack\n{e}"))?;
+    let outputs = wait_for_output(client, ARTIFACT_BUCKET_STACK_NAME, None).await
+        .map_err(|e| format!("Failed to get outputs for {ARTIFACT_BUCKET_STACK_NAME}\n{e}"))?;
+    match outputs.get("BucketName") {
+        Some(s) => Ok(s.to_string()),
+        None => Err(format!("Failed to get BucketName output for {ARTIFACT_BUCKET_STACK_NAME}")),
+    }
+}
+
+/// returns the nested stack of every module that deploys into one, keyed by (stack name, module name)
+fn get_nested_stacks(data: &Vec<String>) -> HashMap<(String, String), String> {
+    let mut out = HashMap::new();
+    for stack_str in data {
+        let stack: aws_cfn_stack::SavedStack = cfn_resources::serde_json::from_str(&stack_str).expect("Failed to deserialize generated json file");
+        let nested_stack = match stack.nested_stack {
+            Some(n) => n,
+            None => continue,
+        };
+        for (stack_name, (mod_name, _)) in stack.template {
+            out.insert((stack_name, mod_name), nested_stack.clone());
+        }
+    }
+    out
+}
+
+/// the plan and change set runtimes operate on the parent templates directly,
+/// and therefore dont support nested stacks yet.
+fn reject_nested_stacks(data: &Vec<String>, runtime_name: &str) {
+    let nested_stacks = get_nested_stacks(data);
+    if let Some(((stack_name, mod_name), nested_stack)) = nested_stacks.iter().next() {
+        panic!("The {runtime_name} runtime does not support nested stacks yet, but module {mod_name} deploys to nested stack {nested_stack} of stack {stack_name}. Use the deploy runtime instead");
+    }
+}
+
+/// the template of a nested stack, and the key in the artifact bucket it gets uploaded to
+pub struct NestedTemplate {
+    pub logical_name: String,
+    pub object_key: String,
+    pub body: String,
+}
+
+/// collects the logical names that are referenced via `Ref`, `Fn::GetAtt` or `${Name}` in an `Fn::Sub` anywhere within `value`.
+/// pseudo parameters such as `AWS::Region` are not included, and neither are the variables an `Fn::Sub` defines itself.
+pub fn get_referenced_names(value: &cfn_resources::serde_json::Value, out: &mut std::collections::BTreeSet<String>) {
+    use cfn_resources::serde_json::Value;
+    match value {
+        Value::Object(map) => {
+            for (key, val) in map.iter() {
+                let name = match (key.as_str(), val) {
+                    ("Ref", Value::String(name)) => Some(name.as_str()),
+                    ("Fn::GetAtt", Value::Array(arr)) => arr.first().and_then(|x| x.as_str()),
+                    ("Fn::GetAtt", Value::String(s)) => s.split('.').next(),
+                    ("Fn::Sub", Value::String(s)) => {
+                        out.extend(get_sub_names(s).into_iter().map(|x| x.to_string()));
+                        continue;
+                    }
+                    ("Fn::Sub", Value::Array(arr)) => {
+                        let vars = arr.get(1).and_then(|x| x.as_object());
+                        if let Some(s) = arr.first().and_then(|x| x.as_str()) {
+                            let names = get_sub_names(s).into_iter()
+                                .filter(|x| !vars.map(|v| v.contains_key(*x)).unwrap_or(false));
+                            out.extend(names.map(|x| x.to_string()));
+                        }
+                        if let Some(vars) = vars {
+                            vars.values().for_each(|x| get_referenced_names(x, out));
+                        }
+                        continue;
+                    }
+                    _ => None,
+                };
+                match name {
+                    Some(name) if !name.starts_with("AWS::") => { out.insert(name.to_string()); }
+                    _ => get_referenced_names(val, out),
+                }
+            }
+        }
+        Value::Array(arr) => arr.iter().for_each(|x| get_referenced_names(x, out)),
+        _ => {}
+    }
+}
+
+/// the names in `${Name}` or `${Name.Attribute}` of an `Fn::Sub` string.
+/// `${!Literal}` is not a reference, and neither are pseudo parameters such as `${AWS::Region}`.
+fn get_sub_names(s: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        rest = &rest[start + 2..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        let var = rest[..end].trim();
+        rest = &rest[end + 1..];
+        if var.starts_with('!') || var.starts_with("AWS::") {
+            continue;
+        }
+        let name = var.split('.').next().unwrap_or(var);
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// merges the templates of every module deploying to `stack_name`. The templates of modules that deploy to
+/// a nested stack are instead merged into that nested stack's template, which the parent references via
+/// an `AWS::CloudFormation::Stack` resource whose template is at `object_key` of `bucket`.
+/// The outputs of a nested stack are also outputs of the parent. Errors if a resource references a resource
+/// on the other side of a nested stack boundary, as cloudformation cannot resolve those.
+pub fn split_nested_stacks(
+    stack_name: &str,
+    templates: Vec<(String, SavedTemplate)>,
+    nested_stacks: &HashMap<(String, String), String>,
+    bucket: &str,
+) -> Result<(SavedTemplate, Vec<NestedTemplate>), String> {
+    let mut final_template = SavedTemplate::default();
+    let mut nested_templates: std::collections::BTreeMap<String, SavedTemplate> = Default::default();
+    for (mod_name, template) in templates {
+        let target = match nested_stacks.get(&(stack_name.to_string(), mod_name)) {
+            Some(nested_stack) => nested_templates.entry(nested_stack.clone()).or_default(),
+            None => &mut final_template,
+        };
+        target.resources.extend(template.resources);
+        target.outputs.extend(template.outputs);
+    }
+
+    let mut nested_resource_names = std::collections::BTreeSet::new();
+    let mut out = vec![];
+    for (logical_name, mut template) in nested_templates {
+        apply_template_transformers(&mut template);
+        let mut referenced = std::collections::BTreeSet::new();
+        for resource in template.resources.values() {
+            get_referenced_names(&resource.properties, &mut referenced);
+        }
+        for output in template.outputs.values() {
+            get_referenced_names(&output.value, &mut referenced);
+        }
+        let outside: Vec<&String> = referenced.iter().filter(|x| !template.resources.contains_key(*x)).collect();
+        if !outside.is_empty() {
+            return Err(format!("Nested stack {logical_name} references {:?}, which are not in the nested stack. Resources in a nested stack can only reference resources in the same nested stack", outside));
+        }
+        nested_resource_names.extend(template.resources.keys().map(|x| (x.to_string(), logical_name.clone())));
+
+        for (output_name, output) in template.outputs.iter() {
+            if final_template.outputs.contains_key(output_name) {
+                return Err(format!("Output {output_name} of nested stack {logical_name} conflicts with an output of stack {stack_name}"));
+            }
+            final_template.outputs.insert(output_name.clone(), aws_cfn_stack::ResourceOutput {
+                description: output.description.clone(),
+                value: cfn_resources::serde_json::json!({ "Fn::GetAtt": [logical_name, format!("Outputs.{output_name}")] }),
+            });
+        }
+        let body = cfn_resources::serde_json::to_string_pretty(&template).expect("Failed to serialize template");
+        // the key changes whenever the template does, so that cloudformation updates the nested stack.
+        let object_key = format!("{NESTED_STACK_KEY_PREFIX}/{stack_name}/{logical_name}-{}.json", aws_cfn_stack::content_hash(&body));
+        final_template.resources.insert(logical_name.clone(), aws_cfn_stack::SavedResource {
+            ty: "AWS::CloudFormation::Stack".to_string(),
+            // the region and domain of the bucket depend on where the stack is deployed
+            properties: cfn_resources::serde_json::json!({
+                "TemplateURL": { "Fn::Sub": format!("https://{bucket}.s3.${{AWS::Region}}.${{AWS::URLSuffix}}/{object_key}") }
+            }),
+            metadata: None,
+            depends_on: vec![],
+        });
+        out.push(NestedTemplate { logical_name, object_key, body });
+    }
+
+    let mut referenced = std::collections::BTreeSet::new();
+    for resource in final_template.resources.values() {
+        get_referenced_names(&resource.properties, &mut referenced);
+    }
+    for output in final_template.outputs.values() {
+        get_referenced_names(&output.value, &mut referenced);
+    }
+    for (name, logical_name) in nested_resource_names {
+        if referenced.contains(&name) {
+            return Err(format!("Stack {stack_name} references {name}, which is in nested stack {logical_name}. Use the outputs of the nested stack instead"));
+        }
+    }
+    Ok((final_template, out))
+}
+
+/// uploads the templates of nested stacks to the artifact bucket
+pub async fn upload_nested_templates(client: &aws_sdk_s3::Client, bucket: &str, templates: &[NestedTemplate]) -> Result<(), String> {
+    for template in templates {
+        println!("Uploading template of nested stack {}", template.logical_name);
+        client.put_object()
+            .bucket(bucket)
+            .key(&template.object_key)
+            .body(aws_sdk_s3::primitives::ByteStream::from(template.body.clone().into_bytes()))
+            .send().await
+            .map_err(|e| format!("Failed to upload template of nested stack {} to {bucket}\n{:#?}", template.logical_name, e))?;
+    }
+    Ok(())
+}
+
+/// passing this to the deploy runtime deploys every stack, even the ones that are unchanged.
+/// if the previous deploy failed mid-way, the stacks that it already deployed are still skipped.
+pub const FORCE_DEPLOY_FLAG: &str = "--force";
+
+/// passing this to the deploy runtime ignores the progress of a previous,
+/// partially failed deploy and deploys every stack from the beginning.
+pub const REDEPLOY_ALL_FLAG: &str = "--redeploy-all";
+
+/// name of the file in `hira/generated` that stores the hash of each stack
+/// as of its last successful deploy, and the progress of an unfinished deploy.
+pub const DEPLOY_STATE_FILE_NAME: &str = "deployed_stacks.json";
+
+/// the hash of everything we send to cloudformation for a stack. If this
+/// hash is the same as the last deploy, there is nothing to update.
+pub fn get_stack_hash(template_body: &str, tags: &[(String, String)]) -> String {
+    let mut data = template_body.to_string();
+    for (key, val) in tags {
+        data.push_str(&format!("\n{key}={val}"));
+    }
+    aws_cfn_stack::content_hash(&data)
+}
+
+/// which stacks the deploy runtime skips.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeployMode {
+    /// stacks that are unchanged since their last deploy are skipped.
+    Default,
+    /// see `FORCE_DEPLOY_FLAG`
+    Force,
+    /// see `REDEPLOY_ALL_FLAG`
+    RedeployAll,
+}
+
+impl DeployMode {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Self {
+        let mut mode = DeployMode::Default;
+        for arg in args {
+            if arg == REDEPLOY_ALL_FLAG {
+                return DeployMode::RedeployAll;
+            }
+            if arg == FORCE_DEPLOY_FLAG {
+                mode = DeployMode::Force;
+            }
+        }
+        mode
+    }
+}
+
+/// the deploy state that is persisted between deploys
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DeployState {
+    /// the hash of each stack as of its last successful deploy
+    #[serde(default)]
+    pub deployed: HashMap<String, String>,
+    /// the progress of the last deploy if it did not finish
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interrupted: Option<InterruptedDeploy>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct InterruptedDeploy {
+    /// every stack of the deploy, in the order they are deployed
+    pub order: Vec<String>,
+    /// the stacks that were successfully deployed before the deploy stopped
+    pub completed: Vec<String>,
+}
+
+impl DeployState {
+    /// true if the current deploy continues a previous deploy that did not finish
+    pub fn is_resuming(&self) -> bool {
+        self.interrupted.as_ref().map(|x| !x.completed.is_empty()).unwrap_or(false)
+    }
+
+    pub fn complete_stack(&mut self, stack_name: &str, hash: String) {
+        self.deployed.insert(stack_name.to_string(), hash);
+        if let Some(interrupted) = self.interrupted.as_mut() {
+            if !interrupted.completed.iter().any(|x| x == stack_name) {
+                interrupted.completed.push(stack_name.to_string());
+            }
+        }
+    }
+}
+
+/// returns the order that the stacks get deployed in, and records it in the state so that
+/// a deploy that fails mid-way is resumed in the same order. Stacks are deployed sorted by name,
+/// unless a previous deploy did not finish, in which case its order is kept and new stacks are deployed last.
+pub fn start_deploy(mode: DeployMode, mut stack_names: Vec<String>, state: &mut DeployState) -> Vec<String> {
+    stack_names.sort();
+    let previous = match (mode, state.interrupted.take()) {
+        (DeployMode::RedeployAll, _) | (_, None) => InterruptedDeploy::default(),
+        (_, Some(previous)) => previous,
+    };
+    let mut order: Vec<String> = previous.order.into_iter().filter(|x| stack_names.contains(x)).collect();
+    for name in stack_names {
+        if !order.contains(&name) {
+            order.push(name);
+        }
+    }
+    let completed = previous.completed.into_iter().filter(|x| order.contains(x)).collect();
+    state.interrupted = Some(InterruptedDeploy { order: order.clone(), completed });
+    order
+}
+
+/// a stack is only skipped if it was previously deployed with the exact same hash,
+/// and it is currently in a successful state. stacks that were rolled back or are
+/// otherwise not complete are always deployed.
+pub fn should_skip_deploy(force: bool, previous_hash: Option<&str>, new_hash: &str, status: Option<&str>) -> bool {
+    if force || previous_hash != Some(new_hash) {
+        return false;
+    }
+    matches!(status, Some("CREATE_COMPLETE") | Some("UPDATE_COMPLETE") | Some("IMPORT_COMPLETE"))
+}
+
+/// given every stack that is about to be deployed as (name, hash, current status), in deploy order,
+/// returns whether each stack should be skipped. After a deploy that failed mid-way,
+/// the stacks that it completed are skipped even with `DeployMode::Force`, so that the deploy resumes from the failed stack.
+pub fn get_resume_decisions(mode: DeployMode, stacks: &[(&str, &str, Option<&str>)], state: &DeployState) -> Vec<bool> {
+    let completed = state.interrupted.as_ref().map(|x| x.completed.as_slice()).unwrap_or_default();
+    stacks.iter().map(|(name, hash, status)| {
+        let unchanged = should_skip_deploy(false, state.deployed.get(*name).map(|x| x.as_str()), hash, *status);
+        match mode {
+            DeployMode::Default => unchanged,
+            DeployMode::Force => unchanged && completed.iter().any(|x| x == name),
+            DeployMode::RedeployAll => false,
+        }
+    }).collect()
+}
+
+/// the state is stored in the generated directory, which honors `HIRA_DIR` and `HIRA_GEN_DIR`.
+/// with `HIRA_DIR`, the directory is per crate, so runtimes launched outside of cargo should set `HIRA_GEN_DIR`.
+fn get_deploy_state_path() -> std::path::PathBuf {
+    let gen_dir = hira_lib::get_gen_directory(|name| std::env::var(name).ok());
+    std::path::PathBuf::from(gen_dir).join(DEPLOY_STATE_FILE_NAME)
+}
+
+/// a missing or invalid state file is treated the same as no previous deploys.
+fn load_deploy_state(path: &std::path::Path) -> DeployState {
+    std::fs::read_to_string(path).ok()
+        .and_then(|x| cfn_resources::serde_json::from_str(&x).ok())
+        .unwrap_or_default()
+}
+
+fn save_deploy_state(path: &std::path::Path, state: &DeployState) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {:?}\n{:?}", parent, e))?;
+    }
+    let data = cfn_resources::serde_json::to_string_pretty(state)
+        .map_err(|e| format!("Failed to serialize deploy state\n{:?}", e))?;
+    write_file_atomically(&path.to_string_lossy(), &data)
+}
+
+/// compares the templates that would be deployed against the templates
+/// of the currently deployed stacks, and prints what would change. Does not deploy anything.
+pub async fn plan_main(data: &Vec<String>) {
+    reject_nested_stacks(data, "plan");
+    let aws_configs = load_aws_configs(data).await;
+    let (stack_map, _, _) = get_stack_map(data);
+    let mut stack_names: Vec<&String> = stack_map.keys().collect();
+    stack_names.sort();
+    for stack_name in stack_names {
+        let client = aws_configs.cloudformation_client(stack_name);
+        let final_template = merge_stack_templates(&stack_map[stack_name]);
+        let new_template = cfn_resources::serde_json::to_value(&final_template).expect("Failed to serialize template");
+        let old_template = match get_deployed_template(&client, stack_name).await {
+            Ok(o) => o,
+            Err(e) => panic!("Failed to get deployed template for stack {stack_name}\n{e}"),
+        };
+        println!("\nStack: {stack_name}");
+        let old_template = match old_template {
+            Some(t) => t,
+            None => {
+                println!("  (stack does not exist yet, all resources will be created)");
+                cfn_resources::serde_json::Value::Null
+            }
+        };
+        let diff = diff_templates(&old_template, &new_template);
+        if diff.is_empty() {
+            println!("  No changes");
+            continue;
+        }
+        diff.resources.print("Resources");
+        diff.outputs.print("Outputs");
+    }
+}
+
+/// returns the template body of the currently deployed stack,
+/// or None if the stack does not exist.
+pub async fn get_deployed_template(client: &aws_sdk_cloudformation::Client, name: &str) -> Result<Option<cfn_resources::serde_json::Value>, String> {
+    if describe_stack(client, name).await?.is_none() {
+        return Ok(None);
+    }
+    let resp = client.get_template().stack_name(name).send().await.map_err(|e| format!("{:#?}", e))?;
+    let body = match resp.template_body() {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    let template = cfn_resources::serde_json::from_str(body)
+        .map_err(|e| format!("Failed to parse deployed template of stack {name} as json\n{:?}", e))?;
+    Ok(Some(template))
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SectionDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl SectionDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn print(&self, section: &str) {
+        if self.is_empty() {
+            return;
+        }
+        println!("  {section}:");
+        for name in self.added.iter() {
+            println!("    + {name}");
+        }
+        for name in self.removed.iter() {
+            println!("    - {name}");
+        }
+        for name in self.changed.iter() {
+            println!("    ~ {name}");
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TemplateDiff {
+    pub resources: SectionDiff,
+    pub outputs: SectionDiff,
+}
+
+impl TemplateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty() && self.outputs.is_empty()
+    }
+}
+
+/// structural diff of two cloudformation templates, keyed by logical id.
+/// a missing template (eg: `Value::Null`) is treated as a template with no resources/outputs.
+pub fn diff_templates(old: &cfn_resources::serde_json::Value, new: &cfn_resources::serde_json::Value) -> TemplateDiff {
+    TemplateDiff {
+        resources: diff_section(old.get("Resources"), new.get("Resources")),
+        outputs: diff_section(old.get("Outputs"), new.get("Outputs")),
+    }
+}
+
+fn diff_section(old: Option<&cfn_resources::serde_json::Value>, new: Option<&cfn_resources::serde_json::Value>) -> SectionDiff {
+    let empty = cfn_resources::serde_json::Map::new();
+    let old = old.and_then(|x| x.as_object()).unwrap_or(&empty);
+    let new = new.and_then(|x| x.as_object()).unwrap_or(&empty);
+    let mut diff = SectionDiff::default();
+    for (key, new_val) in new.iter() {
+        match old.get(key) {
+            Some(old_val) => if old_val != new_val {
+                diff.changed.push(key.to_string());
+            }
+            None => diff.added.push(key.to_string()),
+        }
+    }
+    for key in old.keys() {
+        if !new.contains_key(key) {
+            diff.removed.push(key.to_string());
+        }
+    }
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort();
+    diff
+}
+
+/// merges the templates of every module deploying to the same stack, and applies the template transformers.
+fn merge_stack_templates(templates: &[(String, SavedTemplate)]) -> SavedTemplate {
+    let mut final_template = SavedTemplate::default();
+    for (_, template) in templates.iter() {
+        for (key, val) in template.resources.iter() {
+            final_template.resources.insert(key.clone(), aws_cfn_stack::SavedResource { ty: val.ty.clone(), properties: val.properties.clone(), metadata: val.metadata.clone(), depends_on: val.depends_on.clone() });
+        }
+        final_template.outputs.extend(template.outputs.clone());
+    }
+    apply_template_transformers(&mut final_template);
+    final_template
+}
+
+/// returns the merged template body of a stack (see `merge_stack_templates`)
+/// exactly as the deploy runtime would send it to cloudformation.
+fn get_stack_template_body(templates: &[(String, SavedTemplate)]) -> String {
+    let final_template = merge_stack_templates(templates);
+    cfn_resources::serde_json::to_string_pretty(&final_template).expect("Failed to serialize template")
+}
+
+/// change set names contain the time they were created at, so that re-running the `changeset`
+/// runtime doesn't collide with a change set that was created previously.
+pub fn get_change_set_name(template_body: &str, tags: &[(String, String)], created_at: u64) -> String {
+    format!("hira-{}-{created_at}", get_stack_hash(template_body, tags))
+}
+
+/// name of the file in `hira/generated` that stores the change sets created by the `changeset`
+/// runtime, so that the `execute-changeset` runtime knows which change sets to execute.
+pub const PENDING_CHANGE_SETS_FILE_NAME: &str = "pending_change_sets.json";
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PendingChangeSet {
+    pub change_set_name: String,
+    /// the hash of the stack as of when the change set was created. see `get_stack_hash`
+    pub stack_hash: String,
+}
+
+fn get_pending_change_sets_path() -> std::path::PathBuf {
+    get_deploy_state_path().with_file_name(PENDING_CHANGE_SETS_FILE_NAME)
+}
+
+fn load_pending_change_sets(path: &std::path::Path) -> HashMap<String, PendingChangeSet> {
+    std::fs::read_to_string(path).ok()
+        .and_then(|x| cfn_resources::serde_json::from_str(&x).ok())
+        .unwrap_or_default()
+}
+
+fn save_pending_change_sets(path: &std::path::Path, pending: &HashMap<String, PendingChangeSet>) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {:?}\n{:?}", parent, e))?;
+    }
+    let data = cfn_resources::serde_json::to_string_pretty(pending)
+        .map_err(|e| format!("Failed to serialize pending change sets\n{:?}", e))?;
+    write_file_atomically(&path.to_string_lossy(), &data)
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ResourceChangeSummary {
+    pub action: String,
+    pub logical_id: String,
+    pub resource_type: String,
+    /// only set for modifications. one of `True`, `False`, `Conditional`
+    pub replacement: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ChangeSetDescription {
+    /// eg: `CREATE_PENDING`, `CREATE_COMPLETE`, `FAILED`
+    pub status: String,
+    pub status_reason: Option<String>,
+    pub changes: Vec<ResourceChangeSummary>,
+}
+
+pub type BoxFuture<'a, T> = std::pin::Pin<Box<dyn std::future::Future<Output = T> + 'a>>;
+
+/// the subset of the cloudformation api used for change sets. implemented
+/// for the sdk client, and can be implemented by a mock in tests.
+pub trait ChangeSetClient {
+    fn create_change_set<'a>(&'a self, stack_name: &'a str, change_set_name: &'a str, body: &'a str, tags: &'a [(String, String)]) -> BoxFuture<'a, Result<(), String>>;
+    fn describe_change_set<'a>(&'a self, stack_name: &'a str, change_set_name: &'a str) -> BoxFuture<'a, Result<ChangeSetDescription, String>>;
+    fn delete_change_set<'a>(&'a self, stack_name: &'a str, change_set_name: &'a str) -> BoxFuture<'a, Result<(), String>>;
+}
+
+impl ChangeSetClient for aws_sdk_cloudformation::Client {
+    fn create_change_set<'a>(&'a self, stack_name: &'a str, change_set_name: &'a str, body: &'a str, tags: &'a [(String, String)]) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let change_set_type = if does_stack_exist(self, stack_name).await? {
+                ChangeSetType::Update
+            } else {
+                ChangeSetType::Create
+            };
+            self.create_change_set()
+                .change_set_type(change_set_type)
+                .capabilities(Capability::CapabilityNamedIam)
+                .capabilities(Capability::CapabilityIam)
+                .stack_name(stack_name)
+                .change_set_name(change_set_name)
+                .template_body(body)
+                .set_tags(to_sdk_tags(tags))
+                .send()
+                .await.map_err(|e| format!("Failed to create change set:\n{:#?}", e))?;
+            Ok(())
+        })
+    }
+
+    fn describe_change_set<'a>(&'a self, stack_name: &'a str, change_set_name: &'a str) -> BoxFuture<'a, Result<ChangeSetDescription, String>> {
+        Box::pin(async move {
+            let resp = self.describe_change_set()
+                .stack_name(stack_name)
+                .change_set_name(change_set_name)
+                .send()
+                .await.map_err(|e| format!("Failed to describe change set:\n{:#?}", e))?;
+            let mut out = ChangeSetDescription {
+                status: resp.status().map(|x| x.as_str().to_string()).unwrap_or_default(),
+                status_reason: resp.status_reason().map(|x| x.to_string()),
+                changes: vec![],
+            };
+            for change in resp.changes().unwrap_or_default() {
+                let rc = match change.resource_change() {
+                    Some(rc) => rc,
+                    None => continue,
+                };
+                out.changes.push(ResourceChangeSummary {
+                    action: rc.action().map(|x| x.as_str().to_string()).unwrap_or_default(),
+                    logical_id: rc.logical_resource_id().unwrap_or_default().to_string(),
+                    resource_type: rc.resource_type().unwrap_or_default().to_string(),
+                    replacement: rc.replacement().map(|x| x.as_str().to_string()),
+                });
+            }
+            Ok(out)
+        })
+    }
+
+    fn delete_change_set<'a>(&'a self, stack_name: &'a str, change_set_name: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            self.delete_change_set()
+                .stack_name(stack_name)
+                .change_set_name(change_set_name)
+                .send()
+                .await.map_err(|e| format!("Failed to delete change set:\n{:#?}", e))?;
+            Ok(())
+        })
+    }
+}
+
+/// returns human readable lines describing the change set
+pub fn format_change_set_summary(stack_name: &str, change_set_name: &str, desc: &ChangeSetDescription) -> Vec<String> {
+    let mut out = vec![format!("Stack {stack_name}: change set {change_set_name}")];
+    if desc.changes.is_empty() {
+        out.push("  No changes".to_string());
+        return out;
+    }
+    for change in desc.changes.iter() {
+        let mut line = format!("  {} {} ({})", change.action, change.logical_id, change.resource_type);
+        if let Some(replacement) = &change.replacement {
+            line.push_str(&format!(" replacement: {replacement}"));
+        }
+        out.push(line);
+    }
+    out
+}
+
+#[derive(Debug)]
+pub struct ChangeSetSummary {
+    /// false if the change set contained no changes. such change sets are deleted, as they cannot be executed.
+    pub has_changes: bool,
+    pub lines: Vec<String>,
+}
+
+/// creates the change set and waits for cloudformation to compute its changes.
+/// cloudformation fails change sets that contain no changes, which is reported as an empty summary.
+pub async fn create_change_set_summary(client: &impl ChangeSetClient, stack_name: &str, change_set_name: &str, body: &str, tags: &[(String, String)]) -> Result<ChangeSetSummary, String> {
+    client.create_change_set(stack_name, change_set_name, body, tags).await?;
+    loop {
+        let mut desc = client.describe_change_set(stack_name, change_set_name).await?;
+        match desc.status.as_str() {
+            "CREATE_COMPLETE" => {
+                let lines = format_change_set_summary(stack_name, change_set_name, &desc);
+                return Ok(ChangeSetSummary { has_changes: true, lines });
+            }
+            "FAILED" => {
+                let reason = desc.status_reason.take().unwrap_or_default();
+                if reason.contains("didn't contain changes") || reason.contains("No updates are to be performed") {
+                    client.delete_change_set(stack_name, change_set_name).await?;
+                    desc.changes.clear();
+                    let lines = format_change_set_summary(stack_name, change_set_name, &desc);
+                    return Ok(ChangeSetSummary { has_changes: false, lines });
+                }
+                return Err(format!("Failed to create change set {change_set_name} for stack {stack_name}\n{reason}"));
+            }
+            _ => {
+                tokio::time::sleep(tokio::time::Duration::from_millis(700)).await;
+            }
+        }
+    }
+}
+
+/// creates a change set for every stack and prints what it would change. Does not execute them.
+/// the change sets can then be executed with the `execute-changeset` runtime.
+/// Like the deploy runtime, this runs the `run_before` steps first, eg: to upload lambda code.
+pub async fn changeset_main(data: &Vec<String>) {
+    reject_nested_stacks(data, "changeset");
+    let aws_configs = load_aws_configs(data).await;
+    let (stack_map, _, stack_tags) = get_stack_map(data);
+    let pending_path = get_pending_change_sets_path();
+    let mut pending = load_pending_change_sets(&pending_path);
+    let created_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs()).unwrap_or_default();
+    let mut stack_names: Vec<&String> = stack_map.keys().collect();
+    stack_names.sort();
+    for stack_name in stack_names {
+        let client = aws_configs.cloudformation_client(stack_name);
+        let body = get_stack_template_body(&stack_map[stack_name]);
+        let tags = stack_tags.get(stack_name).map(|x| x.as_slice()).unwrap_or_default();
+        let change_set_name = get_change_set_name(&body, tags, created_at);
+        let summary = match create_change_set_summary(&client, stack_name, &change_set_name, &body, tags).await {
+            Ok(s) => s,
+            Err(e) => panic!("{e}"),
+        };
+        for line in summary.lines {
+            println!("{line}");
+        }
+        if summary.has_changes {
+            pending.insert(stack_name.clone(), PendingChangeSet { change_set_name, stack_hash: get_stack_hash(&body, tags) });
+        } else {
+            // a previously created change set is outdated now that there is nothing to change
+            pending.remove(stack_name);
+        }
+        if let Err(e) = save_pending_change_sets(&pending_path, &pending) {
+            println!("Warning: {e}");
+        }
+    }
+}
+
+/// executes the change sets created by `changeset_main`. Stacks that had no changes
+/// when the change sets were created are skipped.
+pub async fn execute_changeset_main(data: &Vec<String>) {
+    reject_nested_stacks(data, "execute-changeset");
+    let aws_configs = load_aws_configs(data).await;
+    let (stack_map, _, _) = get_stack_map(data);
+    let deploy_state_path = get_deploy_state_path();
+    let mut deploy_state = load_deploy_state(&deploy_state_path);
+    let pending_path = get_pending_change_sets_path();
+    let mut pending = load_pending_change_sets(&pending_path);
+    let mut stack_names: Vec<&String> = stack_map.keys().collect();
+    stack_names.sort();
+    for stack_name in stack_names {
+        let PendingChangeSet { change_set_name, stack_hash } = match pending.get(stack_name) {
+            Some(p) => p.clone(),
+            None => {
+                println!("\nNo pending change set for stack {stack_name}. Skipping");
+                continue;
+            }
+        };
+        println!("\nExecuting change set {change_set_name} of stack {stack_name}");
+        let client = aws_configs.cloudformation_client(stack_name);
+        let res = client.execute_change_set()
+            .stack_name(stack_name)
+            .change_set_name(&change_set_name)
+            .send().await;
+        if let Err(e) = res {
+            let e_str = format!("{:#?}", e);
+            if e_str.contains("does not exist") {
+                panic!("Change set {change_set_name} of stack {stack_name} not found. Run the changeset command again\n{e_str}");
+            }
+            panic!("Failed to execute change set {change_set_name} of stack {stack_name}\n{e_str}");
         }
-        let mut outputs = match wait_for_output(&client, &stack_name, Some(&mut module_resources)).await {
-            Err(e) => panic!("Failed to create stack {stack_name}\n{e}"),
+        let outputs = match wait_for_output(&client, stack_name, None).await {
+            Err(e) => panic!("Failed to execute change set of stack {stack_name}\n{e}"),
             Ok(o) => o,
         };
-        let mut outputs: Vec<(String, String)> = outputs.drain().map(|(k, v)| {
-            (k, v)
-        }).collect();
+        deploy_state.deployed.insert(stack_name.clone(), stack_hash);
+        if let Err(e) = save_deploy_state(&deploy_state_path, &deploy_state) {
+            println!("Warning: {e}");
+        }
+        pending.remove(stack_name);
+        if let Err(e) = save_pending_change_sets(&pending_path, &pending) {
+            println!("Warning: {e}");
+        }
+        let mut outputs: Vec<(String, String)> = outputs.into_iter().collect();
         outputs.sort_by(|a, b| a.0.cmp(&b.0));
         if !outputs.is_empty() {
             println!("\nOutputs:");
@@ -67,9 +1210,69 @@ pub async fn runtime_main(data: &Vec<String>) {
             }
         }
     }
+}
+
+/// stubbed versions of the functions that would otherwise call AWS.
+/// these are used instead of the real functions when the deploy runtime is compiled with
+/// `--cfg hira_preview` (see the `preview` field of `aws_cfn_stack::Input`).
+pub mod preview {
+    use std::collections::HashMap;
+    use super::{get_stack_map, get_nested_stacks, ModResourceCounts};
+
+    pub async fn runtime_main(data: &Vec<String>) {
+        for line in get_preview_summary(data) {
+            println!("{line}");
+        }
+    }
+
+    /// returns a human readable summary of the stacks and resources that would be deployed
+    pub fn get_preview_summary(data: &Vec<String>) -> Vec<String> {
+        let (stack_map, num_resources, stack_tags) = get_stack_map(data);
+        let nested_stacks = get_nested_stacks(data);
+        let mut out = vec![
+            "Preview mode: nothing will be deployed".to_string(),
+            format!("Would deploy {} resource(s) across {} stack(s)", num_resources, stack_map.len()),
+        ];
+        let mut stack_names: Vec<&String> = stack_map.keys().collect();
+        stack_names.sort();
+        for stack_name in stack_names {
+            out.push(format!("Stack {stack_name}:"));
+            for (key, val) in stack_tags.get(stack_name).map(|x| x.as_slice()).unwrap_or_default() {
+                out.push(format!("  tag {key}={val}"));
+            }
+            let mut resources = vec![];
+            for (mod_name, template) in stack_map[stack_name].iter() {
+                let nested_stack = nested_stacks.get(&(stack_name.to_string(), mod_name.to_string()));
+                for (resource_name, resource) in template.resources.iter() {
+                    match nested_stack {
+                        Some(n) => resources.push(format!("  {mod_name}: {resource_name} ({}) in nested stack {n}", resource.ty)),
+                        None => resources.push(format!("  {mod_name}: {resource_name} ({})", resource.ty)),
+                    }
+                }
+            }
+            resources.sort();
+            out.extend(resources);
+        }
+        out
+    }
+
+    pub async fn create_or_update_stack(_client: &aws_sdk_cloudformation::Client, name: &str, body: &str, tags: &[(String, String)]) -> Result<(), String> {
+        println!("Preview: would create or update stack {name} ({} byte template, {} tag(s))", body.len(), tags.len());
+        Ok(())
+    }
 
+    pub async fn wait_for_output(
+        _client: &aws_sdk_cloudformation::Client, name: &str,
+        _module_resources: Option<&mut HashMap<String, (ModResourceCounts, Vec<(bool, String)>)>>,
+    ) -> Result<HashMap<String, String>, String> {
+        println!("Preview: would wait for outputs of stack {name}");
+        Ok(HashMap::new())
+    }
 }
 
+#[cfg(hira_preview)]
+pub use preview::{create_or_update_stack, wait_for_output};
+
 pub async fn does_stack_exist(client: &aws_sdk_cloudformation::Client, name: &str) -> Result<bool, String> {
     // does not exist
     match client.describe_stacks().stack_name(name).send().await {
@@ -84,6 +1287,22 @@ pub async fn does_stack_exist(client: &aws_sdk_cloudformation::Client, name: &st
     }
 }
 
+/// returns the status of the stack (eg: `UPDATE_COMPLETE`), or None if the stack does not exist
+pub async fn get_stack_status(client: &aws_sdk_cloudformation::Client, name: &str) -> Result<Option<String>, String> {
+    match client.describe_stacks().stack_name(name).send().await {
+        Ok(d) => {
+            let status = d.stacks().and_then(|s| s.first()).and_then(|s| s.stack_status());
+            Ok(status.map(|s| s.as_str().to_string()))
+        }
+        Err(e) => {
+            let e_str = format!("{:#?}", e);
+            if e_str.contains("does not exist") {
+                return Ok(None);
+            }
+            Err(e_str)
+        }
+    }
+}
 
 pub async fn describe_stack(client: &aws_sdk_cloudformation::Client, name: &str) -> Result<Option<Stack>, String> {
     match client.describe_stacks().stack_name(name).send().await {
@@ -197,6 +1416,7 @@ impl ModResourceCounts {
     }
 }
 
+#[cfg(not(hira_preview))]
 pub async fn wait_for_output(
     client: &aws_sdk_cloudformation::Client, name: &str,
     mut module_resources: Option<&mut HashMap<String, (ModResourceCounts, Vec<(bool, String)>)>>,
@@ -281,17 +1501,21 @@ pub async fn wait_for_output(
     }
 }
 
-pub async fn create_or_update_stack(client: &aws_sdk_cloudformation::Client, name: &str, body: &str) -> Result<(), String> {
+#[cfg(not(hira_preview))]
+pub async fn create_or_update_stack(client: &aws_sdk_cloudformation::Client, name: &str, body: &str, tags: &[(String, String)]) -> Result<(), String> {
     let exists = does_stack_exist(client, name).await?;
+    let tags = to_sdk_tags(tags);
     if exists {
         println!("Updating {name} ...");
-        // update
+        // update. note that the tags we set replace all existing stack tags,
+        // unless we dont set any.
         match client
             .update_stack()
             .capabilities(Capability::CapabilityNamedIam)
             .capabilities(Capability::CapabilityIam)
             .stack_name(name)
             .template_body(body)
+            .set_tags(tags)
             .send()
             .await
         {
@@ -314,6 +1538,7 @@ pub async fn create_or_update_stack(client: &aws_sdk_cloudformation::Client, nam
             .capabilities(Capability::CapabilityIam)
             .stack_name(name)
             .template_body(body)
+            .set_tags(tags)
             .send()
             .await.map_err(|e| format!("Failed to create:\n{:#?}", e))?;
     }
@@ -329,7 +1554,19 @@ pub async fn create_or_update_stack(client: &aws_sdk_cloudformation::Client, nam
 pub mod aws_cfn_stack {
     extern crate cfn_resources;
     use super::L0Core;
+    use super::L0KvReader;
     use super::L0RuntimeCreator;
+    use super::RuntimeMeta;
+
+    /// prefix of the shared data keys used to detect logical name collisions.
+    /// the full key is `{prefix}{stack name}:{sanitized logical name}` and the value
+    /// is `{module name}:{original logical name}`
+    pub const LOGICAL_NAME_KEY_PREFIX: &str = "hira_cfn_logical_name:";
+
+    /// prefix of the shared data keys used to detect conflicting stack tags.
+    /// the full key is `{prefix}{stack name}:{tag key}` and the value
+    /// is `{module name}:{tag value}`
+    pub const STACK_TAG_KEY_PREFIX: &str = "hira_cfn_stack_tag:";
 
     pub const CAPABILITY_PARAMS: &[(&str, &[&str])] = &[
         ("RUNTIME", &[""]),
@@ -346,6 +1583,37 @@ pub mod aws_cfn_stack {
         pub ty: String,
         #[serde(rename = "Properties")]
         pub properties: cfn_resources::serde_json::Value,
+        #[serde(rename = "Metadata", default, skip_serializing_if = "Option::is_none")]
+        pub metadata: Option<cfn_resources::serde_json::Value>,
+        #[serde(rename = "DependsOn", default, skip_serializing_if = "Vec::is_empty")]
+        pub depends_on: Vec<String>,
+    }
+
+    /// FNV-1a. used instead of the std hasher because the output
+    /// must be stable across rust versions.
+    pub fn content_hash(data: &str) -> String {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for b in data.as_bytes() {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        format!("{:016x}", hash)
+    }
+
+    /// sets `Metadata.hira` of the resource to contain the name of the module that generated it,
+    /// and a hash of the resource's type and properties. This does not affect deployments, but
+    /// can be used by tools to detect resources that drifted from what hira generated.
+    pub fn add_hira_metadata(resource: &mut SavedResource, module: &str) {
+        let content = format!("{}{}", resource.ty, resource.properties);
+        let mut hira = cfn_resources::serde_json::Map::new();
+        hira.insert("module".to_string(), cfn_resources::serde_json::Value::String(module.to_string()));
+        hira.insert("hash".to_string(), cfn_resources::serde_json::Value::String(content_hash(&content)));
+        let mut metadata = match resource.metadata.take() {
+            Some(cfn_resources::serde_json::Value::Object(map)) => map,
+            _ => cfn_resources::serde_json::Map::new(),
+        };
+        metadata.insert("hira".to_string(), cfn_resources::serde_json::Value::Object(hira));
+        resource.metadata = Some(cfn_resources::serde_json::Value::Object(metadata));
     }
 
     #[derive(Debug, cfn_resources::serde::Serialize, cfn_resources::serde::Deserialize)]
@@ -383,10 +1651,22 @@ pub mod aws_cfn_stack {
         /// we structure it this way so that we can separate the stack name
         /// from the template
         pub template: std::collections::HashMap<String, (String, SavedTemplate)>,
+        /// tags of the stack itself. these are propagated to all resources in the stack.
+        #[serde(default)]
+        pub tags: Vec<(String, String)>,
+        /// if set, the outputs of the stack are written to this path as a dotenv file after deploying.
+        #[serde(default)]
+        pub dotenv_path: Option<String>,
+        /// if set, the stack is deployed with the credentials of this named aws profile.
+        #[serde(default)]
+        pub aws_profile: Option<String>,
+        /// if set, the template is deployed as part of this nested stack of the stack.
+        #[serde(default)]
+        pub nested_stack: Option<String>,
     }
 
     #[derive(Default)]
-    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
     pub struct Input {
         /// if left empty (default), we set the stack name to `hira-gen-default-stack`
         /// Optionally, provide a stack name of your own. You can group resources into 1 stack by ensuring
@@ -397,28 +1677,216 @@ pub mod aws_cfn_stack {
         /// a list of function invocations that should be ran
         /// prior to deploying the stack.
         pub run_before: Vec<String>,
+        /// paths to functions of the form `fn(&mut aws_cfn_stack::SavedTemplate)`, eg: `my_crate::add_team_tag`.
+        /// Every stack's final template is passed through all transformers (across all modules) right before
+        /// it gets deployed, in the order they were added. Useful for org-wide conventions like required tags.
+        pub template_transformers: Vec<String>,
+        /// if true, the deploy runtime is compiled with `--cfg hira_preview`
+        /// which prints what would be deployed instead of calling AWS.
+        /// if any stack sets this, the entire deploy runtime is in preview mode.
+        pub preview: bool,
+        #[cfg_attr(feature = "web", schemars(with = "std::collections::HashMap<String, serde_json::Value>"))]
         pub outputs: std::collections::HashMap<String, ResourceOutput>,
+        /// optionally override the description of outputs, keyed by output name.
+        /// Useful to document outputs created by other modules, eg: `("LambdaFunctionArnmyfn", "ARN of the api")`.
+        /// it is an error to provide a description for an output that does not exist.
+        pub output_descriptions: std::collections::HashMap<String, String>,
+        /// tags to set on the cloudformation stack. cloudformation
+        /// propagates these to all of the resources in the stack.
+        /// if multiple modules deploy to the same stack, their tags are merged.
+        pub stack_tags: Vec<(String, String)>,
+        /// if true, each resource gets a `Metadata.hira` block containing the name of
+        /// the module that generated it and a hash of its contents. Useful for drift detection tooling.
+        pub emit_hira_metadata: bool,
+        /// if true, after deploying, the outputs of this stack are written to
+        /// a dotenv file as `KEY=value` lines so that local tools can source them.
+        pub write_outputs_dotenv: bool,
+        /// where to write the dotenv file if `write_outputs_dotenv` is true.
+        /// Defaults to `.env.generated`. Relative paths are relative to where the deploy runtime is ran.
+        pub dotenv_path: String,
+        /// optionally deploy with the credentials of a named aws profile (from `~/.aws/config` or `~/.aws/credentials`)
+        /// instead of the default credentials. The profile must exist when deploying.
+        /// different stacks can use different profiles, eg: to deploy to multiple accounts,
+        /// but every module that deploys to the same stack must set the same profile.
+        /// Note: this only applies to the deploy, plan, changeset, and execute-changeset runtimes of this module.
+        pub aws_profile: String,
+        /// optionally deploy this module's resources and outputs in a nested stack (`AWS::CloudFormation::Stack`)
+        /// of `stack_name`, with this logical name. Useful for stacks that would otherwise exceed the cloudformation
+        /// limit of 500 resources. Modules with the same stack_name and nested_stack share the nested stack.
+        /// The nested template is uploaded to the artifact bucket (the `hira-gen-lambda-artifact-bucket` stack)
+        /// when deploying, and the outputs of the nested stack are also outputs of the parent stack.
+        /// Resources in a nested stack can only reference resources in the same nested stack.
+        /// Note: nested stacks are only supported by the deploy runtime, not the plan or change set runtimes.
+        pub nested_stack: String,
+        /// resources that are inserted into the template as is, keyed by logical name.
+        /// Use `add_raw_resource` to add to this.
+        #[cfg_attr(feature = "web", serde(skip))]
+        pub raw_resources: Vec<(String, SavedResource)>,
+        /// pairs of (resource, resource that it depends on), which are added to the `DependsOn`
+        /// of the first resource. Only needed if the dependency isn't implied by a `Ref` or `Fn::GetAtt`.
+        /// The first resource must be one of this module's resources. Use `add_depends_on` to add to this.
+        pub depends_on: Vec<(String, String)>,
+        /// (resource, key, value) entries that are added to the `Metadata` of the resource.
+        /// Metadata does not affect deployments, but can be read by the `run_before` steps.
+        /// The resource must be one of this module's resources. Use `add_resource_metadata` to add to this.
+        pub resource_metadata: Vec<(String, String, String)>,
+    }
+
+    impl Input {
+        /// makes cloudformation create `depends_on` before `logical_name`.
+        pub fn add_depends_on(&mut self, logical_name: &str, depends_on: &str) {
+            self.depends_on.push((logical_name.to_string(), depends_on.to_string()));
+        }
+
+        /// sets `Metadata.{key}` of the resource `logical_name` to `value`.
+        pub fn add_resource_metadata(&mut self, logical_name: &str, key: &str, value: &str) {
+            self.resource_metadata.push((logical_name.to_string(), key.to_string(), value.to_string()));
+        }
+
+        /// adds a resource from its raw cloudformation json, eg:
+        /// `{ "Type": "AWS::SNS::Topic", "Properties": { ... } }`.
+        /// This is useful for resources that don't have a typed representation in hira.
+        /// The json must be an object with a string `Type`. `Properties` and `Metadata` are optional.
+        /// Other resource attributes (eg: `DependsOn`) are not supported and result in an error.
+        pub fn add_raw_resource(&mut self, logical_name: &str, json: cfn_resources::serde_json::Value) -> Result<(), String> {
+            let mut map = match json {
+                cfn_resources::serde_json::Value::Object(map) => map,
+                _ => return Err(format!("Raw resource '{logical_name}' must be a json object")),
+            };
+            let ty = match map.remove("Type") {
+                Some(cfn_resources::serde_json::Value::String(ty)) if !ty.is_empty() => ty,
+                _ => return Err(format!("Raw resource '{logical_name}' must have a non-empty string 'Type' key")),
+            };
+            let properties = map.remove("Properties")
+                .unwrap_or(cfn_resources::serde_json::Value::Object(Default::default()));
+            let metadata = map.remove("Metadata");
+            if let Some(key) = map.keys().next() {
+                return Err(format!("Raw resource '{logical_name}' has unsupported key '{key}'. Only 'Type', 'Properties', and 'Metadata' are supported"));
+            }
+            self.raw_resources.push((logical_name.to_string(), SavedResource { ty, properties, metadata, depends_on: vec![] }));
+            Ok(())
+        }
+    }
+
+    /// validates the tags against the cloudformation tag constraints
+    pub fn validate_stack_tags(tags: &Vec<(String, String)>) -> Result<(), String> {
+        if tags.len() > 50 {
+            return Err(format!("Stacks can have at most 50 tags, found {}", tags.len()));
+        }
+        for (i, (key, value)) in tags.iter().enumerate() {
+            if key.is_empty() || key.chars().count() > 128 {
+                return Err(format!("Invalid stack tag key '{key}'. Must be between 1 and 128 characters"));
+            }
+            if key.to_lowercase().starts_with("aws:") {
+                return Err(format!("Invalid stack tag key '{key}'. Keys cannot start with 'aws:'"));
+            }
+            if value.chars().count() > 256 {
+                return Err(format!("Invalid value for stack tag '{key}'. Must be at most 256 characters"));
+            }
+            if tags[..i].iter().any(|(k, _)| k == key) {
+                return Err(format!("Duplicate stack tag key '{key}'"));
+            }
+        }
+        Ok(())
+    }
+
+    /// cloudformation limits output descriptions to 1024 characters.
+    pub const MAX_OUTPUT_DESCRIPTION_LEN: usize = 1024;
+
+    /// sets the description of every output in `descriptions`.
+    /// errors if an output does not exist, or if the description is too long.
+    pub fn apply_output_descriptions(
+        outputs: &mut std::collections::HashMap<String, ResourceOutput>,
+        descriptions: &std::collections::HashMap<String, String>,
+    ) -> Result<(), String> {
+        for (name, description) in descriptions {
+            if description.chars().count() > MAX_OUTPUT_DESCRIPTION_LEN {
+                return Err(format!("Description of output '{name}' is longer than {MAX_OUTPUT_DESCRIPTION_LEN} characters"));
+            }
+            let output = match outputs.get_mut(name) {
+                Some(o) => o,
+                None => {
+                    let mut existing: Vec<&String> = outputs.keys().collect();
+                    existing.sort();
+                    return Err(format!("Provided a description for output '{name}', but this stack has no such output. Outputs are: {:?}", existing));
+                }
+            };
+            output.description = description.clone();
+        }
+        Ok(())
     }
 
-    fn validate_resources_to_template(resources: &Vec<Resource>, outputs: &std::collections::HashMap<String, ResourceOutput>) -> Result<SavedTemplate, String> {
+    pub fn validate_resources_to_template(resources: &Vec<Resource>, raw_resources: &Vec<(String, SavedResource)>, outputs: &std::collections::HashMap<String, ResourceOutput>, metadata_module: Option<&str>) -> Result<SavedTemplate, String> {
         let mut out_template = SavedTemplate::default();
         for resource in resources.iter() {
             if let Err(e) = resource.properties.validate() {
                 return Err(format!("Validation failed on resource '{}'\n{e}", resource.name));
             }
-            let saved_resource = SavedResource {
+            let mut saved_resource = SavedResource {
                 ty: resource.properties.type_string().to_string(),
                 properties: resource.properties.properties(),
+                metadata: None,
+                depends_on: vec![],
             };
+            if let Some(module) = metadata_module {
+                add_hira_metadata(&mut saved_resource, module);
+            }
             out_template.resources.insert(resource.name.clone(), saved_resource);
         }
+        for (name, raw) in raw_resources.iter() {
+            let mut saved_resource = SavedResource {
+                ty: raw.ty.clone(),
+                properties: raw.properties.clone(),
+                metadata: raw.metadata.clone(),
+                depends_on: raw.depends_on.clone(),
+            };
+            if let Some(module) = metadata_module {
+                add_hira_metadata(&mut saved_resource, module);
+            }
+            out_template.resources.insert(name.clone(), saved_resource);
+        }
         out_template.outputs = outputs.clone();
         Ok(out_template)
     }
 
-    fn get_serialized_stack_json(user_mod_name: String, stack_name: &String, template: SavedTemplate) -> Result<String, String> {
+    /// adds the dependencies (see `Input::depends_on`) to the resources of the template.
+    pub fn apply_depends_on(template: &mut SavedTemplate, depends_on: &[(String, String)]) -> Result<(), String> {
+        for (name, dependency) in depends_on {
+            let resource = match template.resources.get_mut(name) {
+                Some(r) => r,
+                None => return Err(format!("Resource '{name}' depends on '{dependency}', but '{name}' is not a resource of this module")),
+            };
+            if !resource.depends_on.contains(dependency) {
+                resource.depends_on.push(dependency.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// adds the metadata entries (see `Input::resource_metadata`) to the resources of the template.
+    pub fn apply_resource_metadata(template: &mut SavedTemplate, resource_metadata: &[(String, String, String)]) -> Result<(), String> {
+        for (name, key, value) in resource_metadata {
+            let resource = match template.resources.get_mut(name) {
+                Some(r) => r,
+                None => return Err(format!("Metadata '{key}' was added to '{name}', but '{name}' is not a resource of this module")),
+            };
+            let mut metadata = match resource.metadata.take() {
+                Some(cfn_resources::serde_json::Value::Object(map)) => map,
+                _ => cfn_resources::serde_json::Map::new(),
+            };
+            metadata.insert(key.clone(), cfn_resources::serde_json::Value::String(value.clone()));
+            resource.metadata = Some(cfn_resources::serde_json::Value::Object(metadata));
+        }
+        Ok(())
+    }
+
+    fn get_serialized_stack_json(user_mod_name: String, stack_name: &String, template: SavedTemplate, tags: Vec<(String, String)>, dotenv_path: Option<String>, aws_profile: Option<String>, nested_stack: Option<String>) -> Result<String, String> {
         let mut stack = SavedStack::default();
         stack.template.insert(stack_name.clone(), (user_mod_name, template));
+        stack.tags = tags;
+        stack.dotenv_path = dotenv_path;
+        stack.aws_profile = aws_profile;
+        stack.nested_stack = nested_stack;
         match cfn_resources::serde_json::to_string(&stack) {
             Err(e) => {
                 Err(format!("Failed to serialize template\n{:#?}", e))
@@ -427,12 +1895,96 @@ pub mod aws_cfn_stack {
         }
     }
 
-    fn validate_stack_name(_user_mod_name: &str, current_stack_name: &str) -> Result<String, String> {
-        let stack_name = if current_stack_name.is_empty() {
-            "hira-gen-default-stack".to_string()
+    /// profile names are used as section names of the aws config files, so they cannot contain whitespace or brackets
+    pub fn validate_aws_profile(name: &str) -> Result<(), String> {
+        if name.chars().any(|c| c.is_whitespace() || c == '[' || c == ']') {
+            return Err(format!("Invalid aws_profile '{name}'. Profile names cannot contain whitespace or brackets"));
+        }
+        Ok(())
+    }
+
+    /// cloudformation logical ids can only contain alphanumeric characters,
+    /// so names such as `my-fn` and `my_fn` would both end up as `myfn`.
+    pub fn sanitize_logical_name(name: &str) -> String {
+        name.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
+    }
+
+    /// registers the sanitized logical names of the resources in the given stack,
+    /// and errors if any of them collides with a name registered by this or a previous module.
+    pub fn register_logical_names(l0kv: &mut L0KvReader, stack_name: &str, user_mod_name: &str, resource_names: &[&str]) -> Result<(), String> {
+        for (i, name) in resource_names.iter().enumerate() {
+            let sanitized = sanitize_logical_name(name);
+            if let Some(other) = resource_names[..i].iter().find(|x| sanitize_logical_name(x) == sanitized) {
+                return Err(format!("Logical resource names '{other}' and '{name}' of module '{user_mod_name}' both become '{sanitized}' in stack '{stack_name}'. Logical resource names must be unique after removing non-alphanumeric characters"));
+            }
+            let key = format!("{LOGICAL_NAME_KEY_PREFIX}{stack_name}:{sanitized}");
+            let val = format!("{user_mod_name}:{name}");
+            if let Some(existing) = l0kv.get(&key) {
+                if existing != val {
+                    let (existing_mod, existing_name) = existing.split_once(':').unwrap_or(("", &existing));
+                    return Err(format!("Logical resource name '{name}' of module '{user_mod_name}' conflicts with '{existing_name}' of module '{existing_mod}'. Both become '{sanitized}' in stack '{stack_name}'. Logical resource names must be unique after removing non-alphanumeric characters"));
+                }
+                continue;
+            }
+            l0kv.insert(key, val);
+        }
+        Ok(())
+    }
+
+    /// the stack tags of every module of a stack are merged into the tags of that stack.
+    /// errors if another module already set one of the tags to a different value.
+    pub fn register_stack_tags(l0kv: &mut L0KvReader, stack_name: &str, user_mod_name: &str, tags: &[(String, String)]) -> Result<(), String> {
+        for (key, val) in tags {
+            let kv_key = format!("{STACK_TAG_KEY_PREFIX}{stack_name}:{key}");
+            if let Some(existing) = l0kv.get(&kv_key) {
+                let (existing_mod, existing_val) = existing.split_once(':').unwrap_or(("", &existing));
+                if existing_val != val {
+                    return Err(format!("Stack tag '{key}' of module '{user_mod_name}' is '{val}', but module '{existing_mod}' set it to '{existing_val}'. Stack '{stack_name}' can only have one value per tag"));
+                }
+                continue;
+            }
+            l0kv.insert(kv_key, format!("{user_mod_name}:{val}"));
+        }
+        Ok(())
+    }
+
+    /// the module that nested stacks are registered as in `register_nested_stack`
+    pub const NESTED_STACK_OWNER: &str = "<nested stack>";
+
+    /// nested stacks are resources of the parent stack, so their names must be valid logical names that
+    /// dont collide with any resource of the parent stack. Unlike resources, multiple modules can register the same nested stack.
+    pub fn register_nested_stack(l0kv: &mut L0KvReader, stack_name: &str, nested_stack: &str) -> Result<(), String> {
+        if nested_stack.is_empty() || nested_stack.len() > 255 || sanitize_logical_name(nested_stack) != nested_stack {
+            return Err(format!("Invalid nested_stack '{nested_stack}'. Must only consist of alphanumeric characters, and be between 1 and 255 characters"));
+        }
+        register_logical_names(l0kv, stack_name, NESTED_STACK_OWNER, &[nested_stack])
+    }
+
+    /// returns the name that the stack is deployed as, given the `stack_name` of the Input
+    /// and the `stack_name_prefix` of the L0Core.
+    pub fn get_full_stack_name(current_stack_name: &str, prefix: &str) -> String {
+        if current_stack_name.is_empty() {
+            format!("{prefix}hira-gen-default-stack")
         } else {
-            current_stack_name.to_string()
-        };
+            format!("{prefix}{current_stack_name}")
+        }
+    }
+
+    /// returns a name for a resource that must be unique within the account and region
+    /// (eg: a lambda function name), by prefixing the logical name with the stack name.
+    /// Names longer than `max_len` are truncated, and end in a hash of the full name so that they remain unique.
+    pub fn get_physical_name(stack_name: &str, logical_name: &str, max_len: usize) -> String {
+        let name = format!("{stack_name}-{logical_name}");
+        if name.len() <= max_len {
+            return name;
+        }
+        let hash = &content_hash(&name)[..8];
+        let keep = max_len.saturating_sub(hash.len() + 1);
+        format!("{}-{hash}", &name[..keep])
+    }
+
+    fn validate_stack_name(_user_mod_name: &str, current_stack_name: &str, prefix: &str) -> Result<String, String> {
+        let stack_name = get_full_stack_name(current_stack_name, prefix);
         // A stack name can contain only alphanumeric characters (case sensitive) and hyphens.
         // It must start with an alphabetical character and can't be longer than 128 characters.
         let restricion = "Must only consist of alphanumeric characters and hyphens, Must start with an alphabetical character, and cannot be longer than 128 characters.";
@@ -452,31 +2004,772 @@ pub mod aws_cfn_stack {
         Ok(stack_name)
     }
 
-    pub fn config(input: &mut Input, core: &mut L0Core, runtimer: &mut L0RuntimeCreator) {
-        let out_template = match validate_resources_to_template(&input.resources, &input.outputs) {
+    pub fn config(input: &mut Input, core: &mut L0Core, runtimer: &mut L0RuntimeCreator, l0kv: &mut L0KvReader) {
+        let user_mod_name = core.users_module_name();
+        let metadata_module = if input.emit_hira_metadata { Some(user_mod_name.as_str()) } else { None };
+        if let Err(e) = apply_output_descriptions(&mut input.outputs, &input.output_descriptions) {
+            return core.compiler_error(&e);
+        }
+        let mut out_template = match validate_resources_to_template(&input.resources, &input.raw_resources, &input.outputs, metadata_module) {
             Ok(t) => t,
             Err(e) => {
                 return core.compiler_error(&e);
             }
         };
-        let user_mod_name = core.users_module_name();
-        let stack_name = match validate_stack_name(&user_mod_name, &input.stack_name) {
+        if let Err(e) = apply_depends_on(&mut out_template, &input.depends_on) {
+            return core.compiler_error(&e);
+        }
+        if let Err(e) = apply_resource_metadata(&mut out_template, &input.resource_metadata) {
+            return core.compiler_error(&e);
+        }
+        let stack_name = match validate_stack_name(&user_mod_name, &input.stack_name, &core.stack_name_prefix()) {
             Ok(s) => s,
             Err(e) => {
                 return core.compiler_error(&e);
             }
         };
-        let output = match get_serialized_stack_json(user_mod_name, &stack_name, out_template) {
+        if let Err(e) = validate_stack_tags(&input.stack_tags) {
+            return core.compiler_error(&e);
+        }
+        let resource_names: Vec<&str> = input.resources.iter().map(|x| x.name.as_str())
+            .chain(input.raw_resources.iter().map(|(name, _)| name.as_str()))
+            .collect();
+        if let Err(e) = register_logical_names(l0kv, &stack_name, &user_mod_name, &resource_names) {
+            return core.compiler_error(&e);
+        }
+        if let Err(e) = register_stack_tags(l0kv, &stack_name, &user_mod_name, &input.stack_tags) {
+            return core.compiler_error(&e);
+        }
+        let dotenv_path = if !input.write_outputs_dotenv {
+            None
+        } else if input.dotenv_path.is_empty() {
+            Some(".env.generated".to_string())
+        } else {
+            Some(input.dotenv_path.clone())
+        };
+        if let Err(e) = validate_aws_profile(&input.aws_profile) {
+            return core.compiler_error(&e);
+        }
+        let aws_profile = if input.aws_profile.is_empty() { None } else { Some(input.aws_profile.clone()) };
+        let nested_stack = if input.nested_stack.is_empty() { None } else { Some(input.nested_stack.clone()) };
+        if let Some(nested_stack) = &nested_stack {
+            if let Err(e) = register_nested_stack(l0kv, &stack_name, nested_stack) {
+                return core.compiler_error(&e);
+            }
+        }
+        let output = match get_serialized_stack_json(user_mod_name, &stack_name, out_template, input.stack_tags.clone(), dotenv_path, aws_profile, nested_stack) {
             Ok(s) => s,
             Err(e) => {
                 return core.compiler_error(&e);
             }
         };
 
+        let features = if input.preview { vec!["hira_preview".to_string()] } else { vec![] };
+        let meta = RuntimeMeta { features, ..Default::default() };
         for code in input.run_before.iter() {
-            runtimer.add_to_runtime_unique_beginning("deploy", code.to_string());
+            runtimer.add_to_runtime_ex_unique_beginning("deploy", code.to_string(), meta.clone());
+            // the change sets must contain the same values as a deploy would, eg: the location of uploaded code
+            runtimer.add_to_runtime_unique_beginning("changeset", code.to_string());
+        }
+        for transformer in input.template_transformers.iter() {
+            let code = format!("::aws_cfn_stack::register_template_transformer({transformer});");
+            runtimer.add_to_runtime_ex_unique_beginning("deploy", code.clone(), meta.clone());
+            for runtime_name in ["plan", "changeset", "execute-changeset"] {
+                runtimer.add_to_runtime_unique_beginning(runtime_name, code.clone());
+            }
+        }
+        runtimer.add_to_runtime_ex_unique_end("deploy", "::aws_cfn_stack::runtime_main(&runtime_data).await".to_string(), meta);
+        runtimer.add_data_to_runtime("deploy", output.clone());
+        // the plan runtime only compares the generated templates against
+        // the deployed stacks, so it doesnt need the run_before steps.
+        runtimer.add_to_runtime_unique_end("plan", "::aws_cfn_stack::plan_main(&runtime_data).await".to_string());
+        runtimer.add_data_to_runtime("plan", output.clone());
+        // for approval workflows: `changeset` creates change sets without executing them,
+        // and `execute-changeset` executes them.
+        runtimer.add_to_runtime_unique_end("changeset", "::aws_cfn_stack::changeset_main(&runtime_data).await".to_string());
+        runtimer.add_data_to_runtime("changeset", output.clone());
+        runtimer.add_to_runtime_unique_end("execute-changeset", "::aws_cfn_stack::execute_changeset_main(&runtime_data).await".to_string());
+        runtimer.add_data_to_runtime("execute-changeset", output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cfn_resources::serde_json::json;
+    use hira_lib::e2e::{assert_contains_str, e2e_module2_run_with_token_stream, get_hira_module_source};
+
+    #[test]
+    fn diff_templates_works() {
+        let old = json!({
+            "Resources": {
+                "unchanged": { "Type": "AWS::S3::Bucket", "Properties": { "BucketName": "a" } },
+                "changed": { "Type": "AWS::S3::Bucket", "Properties": { "BucketName": "b" } },
+                "removed": { "Type": "AWS::S3::Bucket", "Properties": {} },
+            },
+            "Outputs": {
+                "out1": { "Description": "", "Value": "x" },
+            }
+        });
+        let new = json!({
+            "Resources": {
+                "unchanged": { "Type": "AWS::S3::Bucket", "Properties": { "BucketName": "a" } },
+                "changed": { "Type": "AWS::S3::Bucket", "Properties": { "BucketName": "c" } },
+                "added2": { "Type": "AWS::S3::Bucket", "Properties": {} },
+                "added1": { "Type": "AWS::S3::Bucket", "Properties": {} },
+            },
+            "Outputs": {
+                "out1": { "Description": "", "Value": "x" },
+                "out2": { "Description": "", "Value": "y" },
+            }
+        });
+        let diff = diff_templates(&old, &new);
+        assert_eq!(diff.resources.added, vec!["added1", "added2"]);
+        assert_eq!(diff.resources.removed, vec!["removed"]);
+        assert_eq!(diff.resources.changed, vec!["changed"]);
+        assert_eq!(diff.outputs.added, vec!["out2"]);
+        assert!(diff.outputs.removed.is_empty());
+        assert!(diff.outputs.changed.is_empty());
+        assert!(diff_templates(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn preview_summary_lists_resources() {
+        let data = vec![
+            r#"{"template":{"stack-a":["mod1",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"mybucket":{"Type":"AWS::S3::Bucket","Properties":{}}},"Outputs":{}}]}}"#.to_string(),
+            r#"{"template":{"stack-b":["mod2",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"myfn":{"Type":"AWS::Lambda::Function","Properties":{}}},"Outputs":{}}]}}"#.to_string(),
+        ];
+        let summary = preview::get_preview_summary(&data);
+        assert_eq!(summary[1], "Would deploy 2 resource(s) across 2 stack(s)");
+        assert_eq!(&summary[2..], &[
+            "Stack stack-a:".to_string(),
+            "  mod1: mybucket (AWS::S3::Bucket)".to_string(),
+            "Stack stack-b:".to_string(),
+            "  mod2: myfn (AWS::Lambda::Function)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn nested_stacks_are_split_from_parent_template() {
+        let data = vec![
+            r#"{"template":{"stack-a":["mod1",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"mybucket":{"Type":"AWS::S3::Bucket","Properties":{}}},"Outputs":{}}]}}"#.to_string(),
+            r#"{"template":{"stack-a":["mod2",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"myfn":{"Type":"AWS::Lambda::Function","Properties":{"Role":{"Fn::GetAtt":["myrole","Arn"]}}},"myrole":{"Type":"AWS::IAM::Role","Properties":{}}},"Outputs":{"FnArn":{"Description":"arn of myfn","Value":{"Fn::GetAtt":["myfn","Arn"]}}}}]},"nested_stack":"functions"}"#.to_string(),
+        ];
+        let (mut stack_map, _, _) = get_stack_map(&data);
+        let nested_stacks = get_nested_stacks(&data);
+        let templates = stack_map.remove("stack-a").unwrap();
+        let (parent, nested) = split_nested_stacks("stack-a", templates, &nested_stacks, "artifacts").expect("Failed to split nested stacks");
+        let parent = cfn_resources::serde_json::to_value(&parent).unwrap();
+        let mut resources: Vec<&String> = parent["Resources"].as_object().unwrap().keys().collect();
+        resources.sort();
+        assert_eq!(resources, vec!["functions", "mybucket"]);
+        assert_eq!(parent["Resources"]["functions"]["Type"], "AWS::CloudFormation::Stack");
+        assert_eq!(nested.len(), 1);
+        assert_eq!(nested[0].logical_name, "functions");
+        assert!(nested[0].object_key.starts_with(&format!("{NESTED_STACK_KEY_PREFIX}/stack-a/functions-")));
+        assert_eq!(
+            parent["Resources"]["functions"]["Properties"]["TemplateURL"],
+            json!({ "Fn::Sub": format!("https://artifacts.s3.${{AWS::Region}}.${{AWS::URLSuffix}}/{}", nested[0].object_key) })
+        );
+        // outputs of the nested stack are propagated to the parent
+        assert_eq!(parent["Outputs"]["FnArn"]["Value"], json!({ "Fn::GetAtt": ["functions", "Outputs.FnArn"] }));
+        assert_eq!(parent["Outputs"]["FnArn"]["Description"], "arn of myfn");
+
+        let nested_template: cfn_resources::serde_json::Value = cfn_resources::serde_json::from_str(&nested[0].body).unwrap();
+        let mut resources: Vec<&String> = nested_template["Resources"].as_object().unwrap().keys().collect();
+        resources.sort();
+        assert_eq!(resources, vec!["myfn", "myrole"]);
+        assert_eq!(nested_template["Outputs"]["FnArn"]["Value"], json!({ "Fn::GetAtt": ["myfn", "Arn"] }));
+
+        let summary = preview::get_preview_summary(&data);
+        assert!(summary.contains(&"  mod2: myfn (AWS::Lambda::Function) in nested stack functions".to_string()));
+    }
+
+    #[test]
+    fn references_across_nested_stacks_are_errors() {
+        let data = vec![
+            r#"{"template":{"stack-a":["mod1",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"myrole":{"Type":"AWS::IAM::Role","Properties":{}}},"Outputs":{}}]}}"#.to_string(),
+            r#"{"template":{"stack-a":["mod2",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"myfn":{"Type":"AWS::Lambda::Function","Properties":{"Role":{"Fn::GetAtt":"myrole.Arn"},"Region":{"Ref":"AWS::Region"}}}},"Outputs":{}}]},"nested_stack":"functions"}"#.to_string(),
+        ];
+        let (mut stack_map, _, _) = get_stack_map(&data);
+        let err = split_nested_stacks("stack-a", stack_map.remove("stack-a").unwrap(), &get_nested_stacks(&data), "artifacts")
+            .err().expect("Expected reference out of the nested stack to fail");
+        assert!(err.contains("myrole") && !err.contains("AWS::Region"));
+
+        let data = vec![
+            r#"{"template":{"stack-a":["mod1",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"myfn":{"Type":"AWS::Lambda::Function","Properties":{"Role":{"Ref":"myrole"}}}},"Outputs":{}}]}}"#.to_string(),
+            r#"{"template":{"stack-a":["mod2",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"myrole":{"Type":"AWS::IAM::Role","Properties":{}}},"Outputs":{}}]},"nested_stack":"roles"}"#.to_string(),
+        ];
+        let (mut stack_map, _, _) = get_stack_map(&data);
+        let err = split_nested_stacks("stack-a", stack_map.remove("stack-a").unwrap(), &get_nested_stacks(&data), "artifacts")
+            .err().expect("Expected reference into the nested stack to fail");
+        assert!(err.contains("myrole") && err.contains("roles"));
+
+        let data = vec![
+            r#"{"template":{"stack-a":["mod1",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"mybucket":{"Type":"AWS::S3::Bucket","Properties":{}}},"Outputs":{}}]}}"#.to_string(),
+            r#"{"template":{"stack-a":["mod2",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"myfn":{"Type":"AWS::Lambda::Function","Properties":{"Description":{"Fn::Sub":"reads ${mybucket.Arn} in ${AWS::Region}"}}}},"Outputs":{}}]},"nested_stack":"functions"}"#.to_string(),
+        ];
+        let (mut stack_map, _, _) = get_stack_map(&data);
+        let err = split_nested_stacks("stack-a", stack_map.remove("stack-a").unwrap(), &get_nested_stacks(&data), "artifacts")
+            .err().expect("Expected Fn::Sub reference out of the nested stack to fail");
+        assert!(err.contains("mybucket") && !err.contains("AWS::Region"));
+    }
+
+    #[test]
+    fn fn_sub_references_are_collected() {
+        let value = json!({
+            "A": { "Fn::Sub": "arn:${AWS::Partition}:s3:::${mybucket}/${!Literal}/${myfn.Arn}" },
+            "B": { "Fn::Sub": ["${Local}-${myrole}", { "Local": { "Ref": "mytable" } }] },
+        });
+        let mut names = std::collections::BTreeSet::new();
+        get_referenced_names(&value, &mut names);
+        assert_eq!(names.into_iter().collect::<Vec<_>>(), vec!["mybucket", "myfn", "myrole", "mytable"]);
+    }
+
+    #[tokio::test]
+    async fn preview_runtime_main_doesnt_need_aws() {
+        // no client or aws config is created in preview mode, so this
+        // succeeds without any credentials or network access.
+        let data = vec![
+            r#"{"template":{"stack-a":["mod1",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{},"Outputs":{}}]}}"#.to_string(),
+        ];
+        preview::runtime_main(&data).await;
+    }
+
+    #[test]
+    fn e2e_preview_builds_the_deploy_runtime_with_the_preview_cfg() {
+        let stack_module = get_hira_module_source(include_str!("lib.rs"), "aws_cfn_stack");
+        let preview_module = stringify!(
+            pub mod mypreview {
+                extern crate cfn_resources;
+                use super::aws_cfn_stack;
+                pub fn config(input: &mut aws_cfn_stack::Input) {
+                    input.stack_name = "my-app".to_string();
+                    input.preview = true;
+                    input.add_raw_resource("mytopic", cfn_resources::serde_json::json!({ "Type": "AWS::SNS::Topic" })).unwrap();
+                }
+            }
+        );
+        let (conf, stream) = e2e_module2_run_with_token_stream(&[&stack_module, preview_module], |_| {})
+            .expect("Failed to expand modules");
+        assert!(!stream.to_string().contains("compile_error"), "{}", stream);
+        let meta = &conf.runtimes["deploy"].1;
+        assert_eq!(meta.features, vec!["hira_preview"]);
+        let (rustflags, _, _) = hira_lib::HiraConfig::get_build_runtime_args(meta, "deploy", "target_dir");
+        assert_eq!(rustflags, "--cfg deploy -C strip=symbols --cfg hira_preview");
+        // the template that would be deployed is still passed to the runtime
+        let deploy_data = conf.runtimes["deploy"].3.join("\n");
+        assert_contains_str(&deploy_data, "mytopic");
+        let summary = preview::get_preview_summary(&conf.runtimes["deploy"].3);
+        assert_contains_str(summary.join("\n"), "mypreview: mytopic (AWS::SNS::Topic)");
+    }
+
+    /// only compiled with `RUSTFLAGS="--cfg hira_preview" cargo test`
+    #[cfg(hira_preview)]
+    #[tokio::test]
+    async fn preview_cfg_stubs_out_aws_calls() {
+        let data = vec![
+            r#"{"template":{"stack-a":["mod1",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{},"Outputs":{}}]}}"#.to_string(),
+        ];
+        // the real entry point of the deploy runtime doesnt load an aws config in preview mode
+        runtime_main(&data).await;
+        // constructing a client without credentials is fine, as long as it never sends a request
+        let sdk_config = aws_config::SdkConfig::builder().build();
+        let client = aws_sdk_cloudformation::Client::new(&sdk_config);
+        assert_eq!(create_or_update_stack(&client, "stack-a", "{}", &[]).await, Ok(()));
+        assert_eq!(wait_for_output(&client, "stack-a", None).await, Ok(HashMap::new()));
+    }
+
+    #[test]
+    fn stack_tags_convert_to_sdk_tags() {
+        // no tags leaves the existing tags of the stack as is
+        assert!(to_sdk_tags(&[]).is_none());
+        let tags = to_sdk_tags(&[
+            ("team".to_string(), "infra".to_string()),
+            ("env".to_string(), "".to_string()),
+        ]).expect("Expected tags");
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].key(), Some("team"));
+        assert_eq!(tags[0].value(), Some("infra"));
+        assert_eq!(tags[1].key(), Some("env"));
+        assert_eq!(tags[1].value(), Some(""));
+    }
+
+    #[test]
+    fn unchanged_complete_stacks_are_skipped() {
+        let tags = vec![("team".to_string(), "infra".to_string())];
+        let hash = get_stack_hash("{}", &tags);
+        assert_eq!(hash, get_stack_hash("{}", &tags));
+        assert_ne!(hash, get_stack_hash("{}", &[]));
+        assert_ne!(hash, get_stack_hash("{\"a\":1}", &tags));
+
+        assert!(should_skip_deploy(false, Some(&hash), &hash, Some("UPDATE_COMPLETE")));
+        assert!(should_skip_deploy(false, Some(&hash), &hash, Some("CREATE_COMPLETE")));
+        // forced
+        assert!(!should_skip_deploy(true, Some(&hash), &hash, Some("UPDATE_COMPLETE")));
+        // changed, or never deployed
+        assert!(!should_skip_deploy(false, Some("different"), &hash, Some("UPDATE_COMPLETE")));
+        assert!(!should_skip_deploy(false, None, &hash, Some("UPDATE_COMPLETE")));
+        // not in a successful state, or deleted
+        assert!(!should_skip_deploy(false, Some(&hash), &hash, Some("UPDATE_ROLLBACK_COMPLETE")));
+        assert!(!should_skip_deploy(false, Some(&hash), &hash, Some("UPDATE_IN_PROGRESS")));
+        assert!(!should_skip_deploy(false, Some(&hash), &hash, None));
+    }
+
+    #[test]
+    fn partially_failed_deploys_resume_from_the_failed_stack() {
+        let mut state = DeployState::default();
+        state.deployed.insert("first".to_string(), "a".to_string());
+        state.deployed.insert("second".to_string(), "b".to_string());
+        state.deployed.insert("third".to_string(), "c".to_string());
+        state.deployed.insert("fifth".to_string(), "old".to_string());
+        state.deployed.insert("sixth".to_string(), "f".to_string());
+        state.interrupted = Some(InterruptedDeploy {
+            order: ["first", "second", "third", "fourth", "fifth", "sixth"].map(String::from).to_vec(),
+            completed: vec!["first".to_string(), "second".to_string()],
+        });
+        let stacks = [
+            ("first", "a", Some("CREATE_COMPLETE")),
+            ("second", "b", Some("UPDATE_COMPLETE")),
+            // failed on the previous deploy
+            ("third", "c", Some("UPDATE_ROLLBACK_COMPLETE")),
+            // never got deployed
+            ("fourth", "d", None),
+            // deployed before, but has since changed
+            ("fifth", "new", Some("UPDATE_COMPLETE")),
+            // unchanged, but not reached by the previous deploy
+            ("sixth", "f", Some("UPDATE_COMPLETE")),
+        ];
+        assert_eq!(get_resume_decisions(DeployMode::Default, &stacks, &state), vec![true, true, false, false, false, true]);
+        assert_eq!(get_resume_decisions(DeployMode::Force, &stacks, &state), vec![true, true, false, false, false, false]);
+        assert_eq!(get_resume_decisions(DeployMode::RedeployAll, &stacks, &state), vec![false; 6]);
+    }
+
+    #[test]
+    fn deploy_order_is_sorted_and_persisted() {
+        let mut state = DeployState::default();
+        let names = || ["b", "c", "a"].map(String::from).to_vec();
+        assert_eq!(start_deploy(DeployMode::Default, names(), &mut state), vec!["a", "b", "c"]);
+        state.complete_stack("a", "hash".to_string());
+        assert!(state.is_resuming());
+
+        // the interrupted deploy is resumed in its own order. new stacks are deployed last
+        state.interrupted.as_mut().unwrap().order = ["c", "a", "b"].map(String::from).to_vec();
+        let mut resumed = state.clone();
+        let mut names_with_new = names();
+        names_with_new.push("0new".to_string());
+        assert_eq!(start_deploy(DeployMode::Force, names_with_new, &mut resumed), vec!["c", "a", "b", "0new"]);
+        assert_eq!(resumed.interrupted.as_ref().unwrap().completed, vec!["a"]);
+
+        let mut restarted = state.clone();
+        assert_eq!(start_deploy(DeployMode::RedeployAll, names(), &mut restarted), vec!["a", "b", "c"]);
+        assert!(!restarted.is_resuming());
+        assert_eq!(restarted.deployed, state.deployed);
+    }
+
+    #[test]
+    fn deploy_mode_is_parsed_from_args() {
+        let mode = |args: &[&str]| DeployMode::from_args(args.iter().map(|x| x.to_string()));
+        assert_eq!(mode(&["deploy"]), DeployMode::Default);
+        assert_eq!(mode(&["deploy", FORCE_DEPLOY_FLAG]), DeployMode::Force);
+        assert_eq!(mode(&[REDEPLOY_ALL_FLAG, FORCE_DEPLOY_FLAG]), DeployMode::RedeployAll);
+    }
+
+    struct MockChangeSetClient {
+        describe_responses: std::cell::RefCell<Vec<ChangeSetDescription>>,
+        created: std::cell::RefCell<Vec<(String, String)>>,
+        deleted: std::cell::RefCell<Vec<(String, String)>>,
+    }
+
+    impl ChangeSetClient for MockChangeSetClient {
+        fn create_change_set<'a>(&'a self, stack_name: &'a str, change_set_name: &'a str, _body: &'a str, _tags: &'a [(String, String)]) -> BoxFuture<'a, Result<(), String>> {
+            self.created.borrow_mut().push((stack_name.to_string(), change_set_name.to_string()));
+            Box::pin(async { Ok(()) })
+        }
+        fn describe_change_set<'a>(&'a self, _stack_name: &'a str, _change_set_name: &'a str) -> BoxFuture<'a, Result<ChangeSetDescription, String>> {
+            let resp = self.describe_responses.borrow_mut().remove(0);
+            Box::pin(async { Ok(resp) })
+        }
+        fn delete_change_set<'a>(&'a self, stack_name: &'a str, change_set_name: &'a str) -> BoxFuture<'a, Result<(), String>> {
+            self.deleted.borrow_mut().push((stack_name.to_string(), change_set_name.to_string()));
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn change_set_names_are_valid_and_unique() {
+        let name = get_change_set_name("{}", &[], 1700000000);
+        assert_eq!(name, get_change_set_name("{}", &[], 1700000000));
+        assert_ne!(name, get_change_set_name("{\"a\":1}", &[], 1700000000));
+        // re-running the changeset runtime creates a new change set
+        assert_ne!(name, get_change_set_name("{}", &[], 1700000001));
+        assert!(name.starts_with("hira-"));
+        assert!(name.len() <= 128);
+        assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+    }
+
+    #[tokio::test]
+    async fn change_set_summary_is_formatted() {
+        let client = MockChangeSetClient {
+            created: Default::default(),
+            deleted: Default::default(),
+            describe_responses: std::cell::RefCell::new(vec![
+                ChangeSetDescription { status: "CREATE_PENDING".into(), ..Default::default() },
+                ChangeSetDescription {
+                    status: "CREATE_COMPLETE".into(),
+                    status_reason: None,
+                    changes: vec![
+                        ResourceChangeSummary { action: "Add".into(), logical_id: "mybucket".into(), resource_type: "AWS::S3::Bucket".into(), replacement: None },
+                        ResourceChangeSummary { action: "Modify".into(), logical_id: "myfn".into(), resource_type: "AWS::Lambda::Function".into(), replacement: Some("False".into()) },
+                    ],
+                },
+            ]),
+        };
+        let name = get_change_set_name("{}", &[], 1);
+        let summary = create_change_set_summary(&client, "stack-a", &name, "{}", &[]).await.expect("Failed to create change set");
+        assert_eq!(client.created.borrow().as_slice(), &[("stack-a".to_string(), name.clone())]);
+        assert!(summary.has_changes);
+        assert!(client.deleted.borrow().is_empty());
+        assert_eq!(summary.lines, vec![
+            format!("Stack stack-a: change set {name}"),
+            "  Add mybucket (AWS::S3::Bucket)".to_string(),
+            "  Modify myfn (AWS::Lambda::Function) replacement: False".to_string(),
+        ]);
+
+        let client = MockChangeSetClient {
+            created: Default::default(),
+            deleted: Default::default(),
+            describe_responses: std::cell::RefCell::new(vec![
+                ChangeSetDescription { status: "FAILED".into(), status_reason: Some("The submitted information didn't contain changes.".into()), changes: vec![] },
+            ]),
+        };
+        let summary = create_change_set_summary(&client, "stack-a", &name, "{}", &[]).await.expect("Empty change sets should not error");
+        assert_eq!(summary.lines[1], "  No changes");
+        assert!(!summary.has_changes);
+        // change sets without changes cannot be executed, so they are a no-op and get cleaned up
+        assert_eq!(client.deleted.borrow().as_slice(), &[("stack-a".to_string(), name.clone())]);
+
+        let client = MockChangeSetClient {
+            created: Default::default(),
+            deleted: Default::default(),
+            describe_responses: std::cell::RefCell::new(vec![
+                ChangeSetDescription { status: "FAILED".into(), status_reason: Some("bad template".into()), changes: vec![] },
+            ]),
+        };
+        let err = create_change_set_summary(&client, "stack-a", &name, "{}", &[]).await.expect_err("Failed change sets should error");
+        assert!(err.contains("bad template"));
+    }
+
+    /// clears the global template transformers when dropped, so that
+    /// the transformers of a test dont outlive it, even if it fails.
+    struct TemplateTransformersGuard;
+
+    impl Drop for TemplateTransformersGuard {
+        fn drop(&mut self) {
+            TEMPLATE_TRANSFORMERS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+
+    /// only changes the templates of `template_transformers_are_applied_to_final_template`,
+    /// as other tests can build templates while it is registered.
+    fn add_team_tag(template: &mut SavedTemplate) {
+        if !template.resources.contains_key("transformertestbucket") {
+            return;
+        }
+        for resource in template.resources.values_mut() {
+            if let Some(props) = resource.properties.as_object_mut() {
+                props.insert("Tags".to_string(), json!([{ "Key": "team", "Value": "infra" }]));
+            }
+        }
+    }
+
+    #[test]
+    fn template_transformers_are_applied_to_final_template() {
+        let data = vec![
+            r#"{"template":{"stack-a":["mod1",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"transformertestbucket":{"Type":"AWS::S3::Bucket","Properties":{}}},"Outputs":{}}]}}"#.to_string(),
+            r#"{"template":{"stack-a":["mod2",{"AWSTemplateFormatVersion":"2010-09-09","Resources":{"transformertestfn":{"Type":"AWS::Lambda::Function","Properties":{}}},"Outputs":{}}]}}"#.to_string(),
+        ];
+        let _guard = TemplateTransformersGuard;
+        register_template_transformer(add_team_tag);
+        // registering the same transformer twice is a no-op
+        register_template_transformer(add_team_tag);
+        let (stack_map, _, _) = get_stack_map(&data);
+        let body = get_stack_template_body(&stack_map["stack-a"]);
+        let template: cfn_resources::serde_json::Value = cfn_resources::serde_json::from_str(&body).unwrap();
+        for name in ["transformertestbucket", "transformertestfn"] {
+            assert_eq!(template["Resources"][name]["Properties"]["Tags"], json!([{ "Key": "team", "Value": "infra" }]));
+        }
+        assert_eq!(TEMPLATE_TRANSFORMERS.lock().unwrap().len(), 1);
+    }
+
+    #[derive(Default)]
+    struct RecordingProfileLoader {
+        selected: Vec<String>,
+    }
+
+    impl AwsProfileSelector for RecordingProfileLoader {
+        fn with_profile_name(mut self, name: &str) -> Self {
+            self.selected.push(name.to_string());
+            self
+        }
+    }
+
+    #[test]
+    fn aws_profile_is_selected_from_stacks() {
+        let data = vec![
+            r#"{"template":{"stack-b":["mod2",{"Resources":{}}]},"aws_profile":"prod"}"#.to_string(),
+            r#"{"template":{"stack-c":["mod3",{"Resources":{}}]},"aws_profile":"prod"}"#.to_string(),
+        ];
+        let profiles = get_aws_profiles(&data).expect("Failed to get profiles");
+        assert_eq!(profiles["stack-b"].as_deref(), Some("prod"));
+        let loader = select_aws_profile(RecordingProfileLoader::default(), profiles["stack-b"].as_deref());
+        assert_eq!(loader.selected, vec!["prod"]);
+
+        let no_profile = vec![r#"{"template":{"stack-a":["mod1",{"Resources":{}}]}}"#.to_string()];
+        let profiles = get_aws_profiles(&no_profile).unwrap();
+        let loader = select_aws_profile(RecordingProfileLoader::default(), profiles["stack-a"].as_deref());
+        assert!(loader.selected.is_empty());
+
+        // stacks can be deployed with different profiles, eg: to different accounts
+        let mut multi_account = data.clone();
+        multi_account.extend(no_profile);
+        multi_account.push(r#"{"template":{"stack-d":["mod4",{"Resources":{}}]},"aws_profile":"dev"}"#.to_string());
+        let profiles = get_aws_profiles(&multi_account).expect("Failed to get profiles");
+        assert_eq!(profiles.into_iter().collect::<Vec<_>>(), vec![
+            ("stack-a".to_string(), None),
+            ("stack-b".to_string(), Some("prod".to_string())),
+            ("stack-c".to_string(), Some("prod".to_string())),
+            ("stack-d".to_string(), Some("dev".to_string())),
+        ]);
+
+        // but the modules of one stack cannot
+        let mut conflicting = data.clone();
+        conflicting.push(r#"{"template":{"stack-b":["mod4",{"Resources":{}}]},"aws_profile":"dev"}"#.to_string());
+        let err = get_aws_profiles(&conflicting).expect_err("Expected conflicting profiles");
+        assert_eq!(err, "Module mod2 of stack stack-b uses aws profile 'prod', but module mod4 uses aws profile 'dev'. All modules of a stack must use the same profile");
+        let mut mixed = data.clone();
+        mixed.push(r#"{"template":{"stack-b":["mod4",{"Resources":{}}]}}"#.to_string());
+        let err = get_aws_profiles(&mixed).expect_err("Expected modules without a profile to be an error");
+        assert_eq!(err, "Module mod2 of stack stack-b uses aws profile 'prod', but module mod4 doesn't set an aws profile. All modules of a stack must use the same profile");
+
+        let config = "[default]\nregion = us-east-1\n[profile prod]\nregion = us-west-2\n";
+        let credentials = "[staging]\naws_access_key_id = x\n";
+        assert!(aws_profile_exists("prod", Some(config), Some(credentials)));
+        assert!(aws_profile_exists("default", Some(config), None));
+        assert!(aws_profile_exists("staging", Some(config), Some(credentials)));
+        assert!(!aws_profile_exists("dev", Some(config), Some(credentials)));
+        assert!(!aws_profile_exists("prod", None, None));
+
+        assert!(aws_cfn_stack::validate_aws_profile("prod").is_ok());
+        assert!(aws_cfn_stack::validate_aws_profile("my profile").is_err());
+    }
+
+    #[test]
+    fn stack_tags_are_validated() {
+        assert!(aws_cfn_stack::validate_stack_tags(&vec![]).is_ok());
+        assert!(aws_cfn_stack::validate_stack_tags(&vec![("team".into(), "infra".into())]).is_ok());
+        assert!(aws_cfn_stack::validate_stack_tags(&vec![("".into(), "infra".into())]).is_err());
+        assert!(aws_cfn_stack::validate_stack_tags(&vec![("aws:team".into(), "infra".into())]).is_err());
+        assert!(aws_cfn_stack::validate_stack_tags(&vec![("a".into(), "b".into()), ("a".into(), "c".into())]).is_err());
+        assert!(aws_cfn_stack::validate_stack_tags(&vec![("a".into(), "b".repeat(257))]).is_err());
+    }
+
+    #[test]
+    fn dotenv_values_are_escaped() {
+        let outputs = [
+            ("PLAIN".to_string(), "https://abc.lambda-url.us-east-1.on.aws/".to_string()),
+            ("SPACES".to_string(), "hello world".to_string()),
+            ("QUOTES".to_string(), r#"say "hi" to $USER"#.to_string()),
+            ("NEWLINE".to_string(), "a\nb".to_string()),
+            ("EMPTY".to_string(), "".to_string()),
+        ];
+        let dotenv = to_dotenv(&outputs);
+        assert_eq!(dotenv, [
+            "PLAIN=https://abc.lambda-url.us-east-1.on.aws/",
+            r#"SPACES="hello world""#,
+            r#"QUOTES="say \"hi\" to \$USER""#,
+            r#"NEWLINE="a\nb""#,
+            "EMPTY=",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn dotenv_values_round_trip() {
+        let outputs = [
+            ("PLAIN".to_string(), "https://abc.lambda-url.us-east-1.on.aws/".to_string()),
+            ("QUOTES".to_string(), r#"say "hi" to 'you'"#.to_string()),
+            ("EQUALS".to_string(), "a=b==c".to_string()),
+            ("HASH".to_string(), "#not a comment # either".to_string()),
+            ("ESCAPES".to_string(), "a\\nb\n$USER `cmd` \r".to_string()),
+            ("EMPTY".to_string(), "".to_string()),
+        ];
+        let (map, diagnostics) = L0Core::parse_dotenv(&to_dotenv(&outputs));
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(map.len(), outputs.len());
+        for (key, val) in outputs.iter() {
+            assert_eq!(&map[key], val);
         }
-        runtimer.add_to_runtime_unique_end("deploy", "::aws_cfn_stack::runtime_main(&runtime_data).await".to_string());
-        runtimer.add_data_to_runtime("deploy", output);
+    }
+
+    #[test]
+    fn logical_name_collisions_across_modules_are_detected() {
+        let mut kv = L0KvReader::new();
+        aws_cfn_stack::register_logical_names(&mut kv, "my-app", "my_fn", &["hiragenmy-fn", "hiragenmyfnrole"])
+            .expect("first module should not collide");
+        // same names in a different stack are fine
+        aws_cfn_stack::register_logical_names(&mut kv, "myapp", "myfn", &["hiragenmy_fn"])
+            .expect("different stacks should not collide");
+        // re-registering the same module is fine
+        aws_cfn_stack::register_logical_names(&mut kv, "my-app", "my_fn", &["hiragenmy-fn"])
+            .expect("same module should not collide with itself");
+
+        let err = aws_cfn_stack::register_logical_names(&mut kv, "my-app", "myfn", &["hiragenmy_fn"])
+            .err().expect("expected collision");
+        assert!(err.contains("'hiragenmy_fn' of module 'myfn'"));
+        assert!(err.contains("'hiragenmy-fn' of module 'my_fn'"));
+        assert!(err.contains("'hiragenmyfn'"));
+
+        let err = aws_cfn_stack::register_logical_names(&mut kv, "other", "mod", &["a-b", "a_b"])
+            .err().expect("expected collision within a module");
+        assert!(err.contains("'a-b' and 'a_b'"));
+    }
+
+    #[test]
+    fn conflicting_stack_tags_are_detected() {
+        let mut kv = L0KvReader::new();
+        let tags = vec![("team".to_string(), "infra".to_string())];
+        aws_cfn_stack::register_stack_tags(&mut kv, "my-app", "first", &tags).expect("first module should not conflict");
+        // the same value, or a different stack is fine
+        aws_cfn_stack::register_stack_tags(&mut kv, "my-app", "second", &tags).expect("same values should not conflict");
+        aws_cfn_stack::register_stack_tags(&mut kv, "my-other-app", "second", &[("team".to_string(), "web".to_string())])
+            .expect("different stacks should not conflict");
+
+        let err = aws_cfn_stack::register_stack_tags(&mut kv, "my-app", "third", &[("team".to_string(), "web:frontend".to_string())])
+            .err().expect("expected conflict");
+        assert_eq!(err, "Stack tag 'team' of module 'third' is 'web:frontend', but module 'first' set it to 'infra'. Stack 'my-app' can only have one value per tag");
+    }
+
+    /// a lvl3 module that deploys a topic called `logical_name` to `stack_name`
+    fn topic_module(name: &str, stack_name: &str, logical_name: &str) -> String {
+        format!(r#"
+            pub mod {name} {{
+                extern crate cfn_resources;
+                use super::aws_cfn_stack;
+                pub fn config(input: &mut aws_cfn_stack::Input) {{
+                    input.stack_name = "{stack_name}".to_string();
+                    input.add_raw_resource("{logical_name}", cfn_resources::serde_json::json!({{ "Type": "AWS::SNS::Topic" }})).unwrap();
+                }}
+            }}
+        "#)
+    }
+
+    #[test]
+    fn e2e_logical_name_collisions_are_errors() {
+        let stack_module = get_hira_module_source(include_str!("lib.rs"), "aws_cfn_stack");
+        let first = topic_module("first", "my-app", "my-topic");
+        let second = topic_module("second", "my-app", "my_topic");
+        let (_, stream) = e2e_module2_run_with_token_stream(&[&stack_module, &first, &second], |_| {})
+            .expect("Failed to expand modules");
+        assert_contains_str(stream.to_string(), "Logical resource name 'my_topic' of module 'second' conflicts with 'my-topic' of module 'first'. Both become 'mytopic' in stack 'my-app'");
+
+        // the same names in different stacks don't collide
+        let second = topic_module("second", "my-other-app", "my_topic");
+        let (_, stream) = e2e_module2_run_with_token_stream(&[&stack_module, &first, &second], |_| {})
+            .expect("Failed to expand modules");
+        assert!(!stream.to_string().contains("conflicts with"));
+    }
+
+    #[test]
+    fn hira_metadata_is_serialized() {
+        let mut resource = aws_cfn_stack::SavedResource {
+            ty: "AWS::S3::Bucket".to_string(),
+            properties: json!({ "BucketName": "a" }),
+            metadata: None,
+            depends_on: vec![],
+        };
+        let serialized = cfn_resources::serde_json::to_value(&resource).unwrap();
+        assert!(serialized.get("Metadata").is_none());
+
+        aws_cfn_stack::add_hira_metadata(&mut resource, "my_module");
+        let serialized = cfn_resources::serde_json::to_value(&resource).unwrap();
+        assert_eq!(serialized["Metadata"]["hira"]["module"], "my_module");
+        let hash = serialized["Metadata"]["hira"]["hash"].as_str().expect("hash should be a string").to_string();
+        assert_eq!(hash.len(), 16);
+
+        // hash only depends on the contents
+        resource.properties = json!({ "BucketName": "b" });
+        aws_cfn_stack::add_hira_metadata(&mut resource, "my_module");
+        let serialized = cfn_resources::serde_json::to_value(&resource).unwrap();
+        assert_ne!(serialized["Metadata"]["hira"]["hash"], hash.as_str());
+    }
+
+    struct TestBucket;
+
+    impl cfn_resources::CfnResource for TestBucket {
+        fn type_string(&self) -> &'static str {
+            "AWS::S3::Bucket"
+        }
+        fn properties(&self) -> cfn_resources::serde_json::Value {
+            json!({ "BucketName": "typed" })
+        }
+    }
+
+    #[test]
+    fn raw_resources_are_merged_with_typed_resources() {
+        let mut input = aws_cfn_stack::Input::default();
+        input.resources.push(aws_cfn_stack::Resource { name: "typedbucket".to_string(), properties: Box::new(TestBucket) });
+        input.add_raw_resource("rawtopic", json!({
+            "Type": "AWS::SNS::Topic",
+            "Properties": { "TopicName": "raw" },
+        })).expect("valid raw resource");
+        input.add_raw_resource("rawqueue", json!({ "Type": "AWS::SQS::Queue" })).expect("properties are optional");
+
+        let template = aws_cfn_stack::validate_resources_to_template(&input.resources, &input.raw_resources, &input.outputs, Some("mymod"))
+            .expect("Failed to create template");
+        let template = cfn_resources::serde_json::to_value(&template).unwrap();
+        assert_eq!(template["Resources"]["typedbucket"]["Type"], "AWS::S3::Bucket");
+        assert_eq!(template["Resources"]["typedbucket"]["Properties"]["BucketName"], "typed");
+        assert_eq!(template["Resources"]["rawtopic"]["Type"], "AWS::SNS::Topic");
+        assert_eq!(template["Resources"]["rawtopic"]["Properties"]["TopicName"], "raw");
+        assert_eq!(template["Resources"]["rawtopic"]["Metadata"]["hira"]["module"], "mymod");
+        assert_eq!(template["Resources"]["rawqueue"]["Properties"], json!({}));
+
+        assert!(input.add_raw_resource("notype", json!({ "Properties": {} })).is_err());
+        assert!(input.add_raw_resource("notobject", json!("AWS::SNS::Topic")).is_err());
+        assert!(input.add_raw_resource("unsupported", json!({ "Type": "AWS::SNS::Topic", "DependsOn": "x" })).is_err());
+        assert_eq!(input.raw_resources.len(), 2);
+    }
+
+    #[test]
+    fn diff_templates_missing_old_template_adds_everything() {
+        let new = json!({
+            "Resources": { "a": { "Type": "AWS::S3::Bucket", "Properties": {} } },
+            "Outputs": {}
+        });
+        let diff = diff_templates(&cfn_resources::serde_json::Value::Null, &new);
+        assert_eq!(diff.resources.added, vec!["a"]);
+        assert!(diff.outputs.is_empty());
+    }
+
+    #[test]
+    fn output_descriptions_can_be_overridden() {
+        let mut outputs = std::collections::HashMap::new();
+        outputs.insert("BucketArn".to_string(), aws_cfn_stack::ResourceOutput {
+            description: "ARN of the bucket".to_string(),
+            value: json!({ "Fn::GetAtt": ["bucket", "Arn"] }),
+        });
+        let mut descriptions = std::collections::HashMap::new();
+        descriptions.insert("BucketArn".to_string(), "ARN of the uploads bucket".to_string());
+        aws_cfn_stack::apply_output_descriptions(&mut outputs, &descriptions).expect("Failed to apply descriptions");
+        assert_eq!(outputs["BucketArn"].description, "ARN of the uploads bucket");
+
+        descriptions.insert("Missing".to_string(), "abc".to_string());
+        let err = aws_cfn_stack::apply_output_descriptions(&mut outputs, &descriptions).expect_err("Expected missing output to error");
+        assert!(err.contains("no such output"));
+
+        let mut descriptions = std::collections::HashMap::new();
+        descriptions.insert("BucketArn".to_string(), "a".repeat(1025));
+        assert!(aws_cfn_stack::apply_output_descriptions(&mut outputs, &descriptions).is_err());
     }
 }
@@ -10,6 +10,7 @@ pub mod lambda_url_distribution {
 
     
     use super::L0Core;
+    use super::L0KvReader;
     use super::aws_cloudfront_distribution;
     use self::cfn_resources::ToOptStrVal;
 
@@ -38,19 +39,31 @@ pub mod lambda_url_distribution {
         pub function_url_id: String,
     }
 
+    /// prefix of the shared data keys that lambda functions use to register their desired path.
+    /// must match `h_aws_lambda::DESIRED_PATH_KEY_PREFIX`
+    pub const DESIRED_PATH_KEY_PREFIX: &str = "hira_lambda_desired_path:";
+
     #[derive(Default)]
     #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
     pub struct Input {
         /// at least one of your endpoints must have path = "/".
         /// this represents the default endpoint.
         /// all endpoints paths must be unique.
+        /// if left empty, the endpoints are collected from all lambda functions
+        /// that set a `desired_path`. Those lambda modules must be defined prior to this module.
         pub endpoints: Vec<LambdaApiEndpoint>,
 
         /// optionally provide settings to configure your distribution with a custom domain name + https cert
         pub custom_domain_settings: Option<CustomDomainSettings>,
     }
 
-    pub fn config(inp: &mut Input, distrinput: &mut aws_cloudfront_distribution::Input, l0core: &mut L0Core) {
+    pub fn config(inp: &mut Input, distrinput: &mut aws_cloudfront_distribution::Input, l0core: &mut L0Core, l0kv: &mut L0KvReader) {
+        if inp.endpoints.is_empty() {
+            for (key, function_url_id) in l0kv.get_with_prefix(DESIRED_PATH_KEY_PREFIX) {
+                let path = key.trim_start_matches(DESIRED_PATH_KEY_PREFIX).to_string();
+                inp.endpoints.push(LambdaApiEndpoint { path, function_url_id });
+            }
+        }
         let mut default = None;
         let mut other_endpoints: Vec<LambdaApiEndpoint> = vec![];
         for endpoint in inp.endpoints.drain(..) {
@@ -15,6 +15,7 @@ pub mod aws_cloudfront_distribution {
     extern crate cfn_resources;
 
     use super::L0Core;
+    use super::validate_domain_name;
     use super::aws_cfn_stack;
     use self::aws_cfn_stack::ResourceOutput;
     use self::cfn_resources::StrVal;
@@ -31,6 +32,7 @@ pub mod aws_cloudfront_distribution {
     pub use self::cloud_front::distribution::ViewerCertificateSslSupportMethodEnum;
     pub use self::cloud_front::distribution::ViewerCertificateMinimumProtocolVersionEnum;
     pub use self::cloud_front::distribution::ViewerCertificate;
+    pub use self::cloud_front::distribution::Logging;
 
     pub mod outputs {
         /// this is the logical name in cloudformation for your distribution.
@@ -59,8 +61,127 @@ pub mod aws_cloudfront_distribution {
         cfn_resources::StrVal::Val(select_domain)
     }
 
+    /// which edge locations serve your distribution. see
+    /// https://docs.aws.amazon.com/AmazonCloudFront/latest/DeveloperGuide/PriceClass.html
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub enum PriceClass {
+        /// North America and Europe only. the cheapest option.
+        PriceClass100,
+        /// everything in PriceClass100, plus Asia, the Middle East, and Africa.
+        PriceClass200,
+        /// all edge locations. this is cloudfront's default.
+        PriceClassAll,
+    }
+
+    impl PriceClass {
+        pub const ALLOWED: &'static [&'static str] = &["PriceClass_100", "PriceClass_200", "PriceClass_All"];
+
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                PriceClass::PriceClass100 => "PriceClass_100",
+                PriceClass::PriceClass200 => "PriceClass_200",
+                PriceClass::PriceClassAll => "PriceClass_All",
+            }
+        }
+    }
+
+    /// the highest HTTP version viewers can use to connect to your distribution.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub enum HttpVersion {
+        Http1_1,
+        /// this is cloudfront's default.
+        Http2,
+        Http3,
+        Http2And3,
+    }
+
+    impl HttpVersion {
+        pub const ALLOWED: &'static [&'static str] = &["http1.1", "http2", "http3", "http2and3"];
+
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                HttpVersion::Http1_1 => "http1.1",
+                HttpVersion::Http2 => "http2",
+                HttpVersion::Http3 => "http3",
+                HttpVersion::Http2And3 => "http2and3",
+            }
+        }
+    }
+
+    /// sets the price class and http version from the input (if provided) on the distribution config,
+    /// and verifies that the final values (which might have been set via `default_distribution_options`)
+    /// are values that cloudfront allows.
+    pub fn apply_price_class_and_http_version(inp: &Input, config: &mut DistributionConfig) -> Result<(), String> {
+        if let Some(price_class) = inp.price_class {
+            config.price_class = price_class.as_str().to_str_val();
+        }
+        if let Some(http_version) = inp.http_version {
+            config.http_version = http_version.as_str().to_str_val();
+        }
+        if let Some(StrVal::String(s)) = &config.price_class {
+            if !PriceClass::ALLOWED.contains(&s.as_str()) {
+                return Err(format!("Invalid price class '{s}'. Must be one of: {}", PriceClass::ALLOWED.join(", ")));
+            }
+        }
+        if let Some(StrVal::String(s)) = &config.http_version {
+            if !HttpVersion::ALLOWED.contains(&s.as_str()) {
+                return Err(format!("Invalid http version '{s}'. Must be one of: {}", HttpVersion::ALLOWED.join(", ")));
+            }
+        }
+        Ok(())
+    }
+
+    /// where cloudfront writes standard access logs. see
+    /// https://docs.aws.amazon.com/AmazonCloudFront/latest/DeveloperGuide/AccessLogs.html
+    #[derive(Clone, Default)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub struct LoggingConfig {
+        /// required. will error if not provided.
+        /// the domain name of the bucket to write logs to, eg: mylogs.s3.amazonaws.com
+        pub bucket_domain: String,
+        /// optionally prefix the names of the log files, eg: "myprefix/"
+        pub prefix: Option<String>,
+        /// whether cookies should be included in the logs.
+        pub include_cookies: bool,
+    }
+
+    /// sets the logging config from the input (if provided) on the distribution config.
+    pub fn apply_logging(inp: &Input, config: &mut DistributionConfig) -> Result<(), String> {
+        let logging = match &inp.logging {
+            Some(l) => l,
+            None => return Ok(()),
+        };
+        if logging.bucket_domain.trim().is_empty() {
+            return Err("Provided logging, but bucket_domain field is empty. This is required.".to_string());
+        }
+        config.logging = Some(Logging {
+            bucket: logging.bucket_domain.clone().into(),
+            prefix: logging.prefix.clone().map(|x| x.into()),
+            include_cookies: Some(logging.include_cookies),
+        });
+        Ok(())
+    }
+
+    /// sets the default root object from the input (if provided) on the distribution config.
+    pub fn apply_default_root_object(inp: &Input, config: &mut DistributionConfig) -> Result<(), String> {
+        let root = match &inp.default_root_object {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        if root.trim().is_empty() {
+            return Err("Provided default_root_object, but it is empty".to_string());
+        }
+        if root.starts_with('/') {
+            return Err(format!("default_root_object '{root}' must not start with a /"));
+        }
+        config.default_root_object = root.as_str().to_str_val();
+        Ok(())
+    }
+
     #[derive(Clone)]
-    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
     pub struct CustomDomainSettings {
         /// required. will error if not provided.
         pub acm_arn: String,
@@ -74,8 +195,10 @@ pub mod aws_cloudfront_distribution {
         pub domain_name: String,
         pub subdomain: Option<String>,
         /// by default we set this to sni-only
+        #[cfg_attr(feature = "web", schemars(with = "String"))]
         pub ssl_support_method: ViewerCertificateSslSupportMethodEnum,
         /// by default we set this to TLSv1.2_2021
+        #[cfg_attr(feature = "web", schemars(with = "String"))]
         pub minimum_protocol_version: ViewerCertificateMinimumProtocolVersionEnum,
         /// by default this is true, and a route53 record will be created
         /// that points from your domain_name to this cloudfront distribution.
@@ -96,7 +219,7 @@ pub mod aws_cloudfront_distribution {
         }
     }
 
-    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
     pub struct Input {
         /// by default we create the distribution enabled and ready to use.
         /// optionally set this field to true to create the distribution
@@ -104,6 +227,7 @@ pub mod aws_cloudfront_distribution {
         pub disabled: bool,
 
         /// By default set to allow-all.
+        #[cfg_attr(feature = "web", schemars(with = "String"))]
         pub viewer_protocol_policy: DefaultCacheBehaviorViewerProtocolPolicyEnum,
 
         /// by default this is left empty and that means your cloudfront distribution is
@@ -116,9 +240,27 @@ pub mod aws_cloudfront_distribution {
         /// to point to this distribution.
         pub custom_domain_settings: Option<CustomDomainSettings>,
 
+        /// optionally limit which edge locations serve your distribution to reduce cost.
+        /// if not provided, cloudfront uses all edge locations (PriceClass_All)
+        pub price_class: Option<PriceClass>,
+
+        /// optionally set the highest HTTP version viewers can use, eg: to enable HTTP/3.
+        /// if not provided, cloudfront uses http2
+        pub http_version: Option<HttpVersion>,
+
+        /// optionally enable standard logging of every request to an S3 bucket.
+        pub logging: Option<LoggingConfig>,
+
+        /// optionally set the object cloudfront returns when a viewer requests the root url,
+        /// eg: "index.html". This is useful for origins that don't support index documents themselves,
+        /// such as S3 REST endpoints (as opposed to S3 website endpoints).
+        /// Must not start with a /
+        pub default_root_object: Option<String>,
+
         /// the domain name of your default origin. If using an S3 bucket website
         /// this should be WebsiteUrl returned from your S3 bucket.
         /// see https://docs.aws.amazon.com/AmazonCloudFront/latest/DeveloperGuide/distribution-web-values-specify.html#DownloadDistValuesDomainName
+        #[cfg_attr(feature = "web", schemars(with = "serde_json::Value"))]
         pub default_origin_domain_name: StrVal,
 
         /// the policy cloudfront should use when making requests to your origin.
@@ -179,6 +321,10 @@ pub mod aws_cloudfront_distribution {
                 default_distribution_options: Default::default(),
                 extra_origins: Default::default(),
                 custom_domain_settings: Default::default(),
+                price_class: Default::default(),
+                http_version: Default::default(),
+                logging: Default::default(),
+                default_root_object: Default::default(),
             }
         }
     }
@@ -208,6 +354,10 @@ pub mod aws_cloudfront_distribution {
                 l0core.compiler_error(&format!("Provided custom_domain_settings, but domain_name field is empty. This is required."));
                 return;
             }
+            if let Err(e) = validate_domain_name(&settings.domain_name, false) {
+                l0core.compiler_error(&format!("Provided invalid custom_domain_settings.domain_name. {e}"));
+                return;
+            }
             let alias = match &settings.subdomain {
                 Some(a) => {
                     if a.ends_with(".") {
@@ -218,6 +368,10 @@ pub mod aws_cloudfront_distribution {
                 },
                 None => settings.domain_name.clone(),
             };
+            if let Err(e) = validate_domain_name(&alias, true) {
+                l0core.compiler_error(&format!("Provided invalid custom_domain_settings.subdomain. {e}"));
+                return;
+            }
             let route_53_resource = if settings.enable_route_53 {
                 let record_set = route53::record_set::CfnRecordSet {
                     alias_target: route53::record_set::AliasTarget {
@@ -241,7 +395,7 @@ pub mod aws_cloudfront_distribution {
                 let output_name = format!("Route53Record{}", user_mod_name);
                 let output_name = output_name.replace("_", "");
                 let output = ResourceOutput {
-                    description: "".to_string(),
+                    description: format!("Domain name of the route53 record pointing to the cloudfront distribution of module '{user_mod_name}'"),
                     value: get_ref(&logical_r53_resource_name),
                 };
                 stackinp.outputs.insert(output_name, output);
@@ -280,6 +434,19 @@ pub mod aws_cloudfront_distribution {
             ..Default::default()
         };
 
+        if let Err(e) = apply_price_class_and_http_version(myinput, &mut distribution.distribution_config) {
+            l0core.compiler_error(&e);
+            return;
+        }
+        if let Err(e) = apply_logging(myinput, &mut distribution.distribution_config) {
+            l0core.compiler_error(&e);
+            return;
+        }
+        if let Err(e) = apply_default_root_object(myinput, &mut distribution.distribution_config) {
+            l0core.compiler_error(&e);
+            return;
+        }
+
         let mut used_origin_ids = vec![default_origin_id.to_string()];
         for (origin, behavior) in myinput.extra_origins.drain(..) {
             if let StrVal::String(s) = &origin.id {
@@ -311,7 +478,8 @@ pub mod aws_cloudfront_distribution {
             let output_name = format!("CloudfrontDomainName{}", user_mod_name);
             let output_name = output_name.replace("_", "");
             let output = ResourceOutput {
-                description: "".to_string(),
+                // Ref of a distribution is its id, not its domain name
+                description: format!("Id of the cloudfront distribution of module '{user_mod_name}'"),
                 value: get_ref(&logical_distr_name),
             };
             stackinp.outputs.insert(output_name, output);
@@ -319,3 +487,84 @@ pub mod aws_cloudfront_distribution {
         l0core.set_output("LOGICAL_DISTR_NAME", &logical_distr_name);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_cloudfront_distribution::*;
+    use cfn_resources::{CfnResource, ToOptStrVal};
+
+    #[test]
+    fn price_class_and_http_version_are_set_on_distribution_config() {
+        let inp = Input {
+            price_class: Some(PriceClass::PriceClass100),
+            http_version: Some(HttpVersion::Http2And3),
+            ..Default::default()
+        };
+        let mut distribution = CfnDistribution::default();
+        apply_price_class_and_http_version(&inp, &mut distribution.distribution_config).expect("Failed to apply settings");
+        let props = distribution.properties();
+        assert_eq!(props["DistributionConfig"]["PriceClass"], "PriceClass_100");
+        assert_eq!(props["DistributionConfig"]["HttpVersion"], "http2and3");
+    }
+
+    #[test]
+    fn price_class_and_http_version_are_validated() {
+        let inp = Input::default();
+        let mut config = DistributionConfig::default();
+        assert!(apply_price_class_and_http_version(&inp, &mut config).is_ok());
+        config.price_class = "PriceClass_50".to_str_val();
+        assert!(apply_price_class_and_http_version(&inp, &mut config).is_err());
+        config.price_class = None;
+        config.http_version = "http4".to_str_val();
+        assert!(apply_price_class_and_http_version(&inp, &mut config).is_err());
+    }
+
+    #[test]
+    fn logging_is_set_on_distribution_config() {
+        let inp = Input {
+            logging: Some(LoggingConfig {
+                bucket_domain: "mylogs.s3.amazonaws.com".to_string(),
+                prefix: Some("cdn/".to_string()),
+                include_cookies: true,
+            }),
+            ..Default::default()
+        };
+        let mut distribution = CfnDistribution::default();
+        apply_logging(&inp, &mut distribution.distribution_config).expect("Failed to apply logging");
+        let props = distribution.properties();
+        assert_eq!(props["DistributionConfig"]["Logging"]["Bucket"], "mylogs.s3.amazonaws.com");
+        assert_eq!(props["DistributionConfig"]["Logging"]["Prefix"], "cdn/");
+        assert_eq!(props["DistributionConfig"]["Logging"]["IncludeCookies"], true);
+
+        let inp = Input {
+            logging: Some(LoggingConfig::default()),
+            ..Default::default()
+        };
+        let mut config = DistributionConfig::default();
+        assert!(apply_logging(&inp, &mut config).is_err());
+        assert!(apply_logging(&Input::default(), &mut config).is_ok());
+        assert!(config.logging.is_none());
+    }
+
+    #[test]
+    fn default_root_object_is_set_on_distribution_config() {
+        let inp = Input {
+            default_root_object: Some("index.html".to_string()),
+            ..Default::default()
+        };
+        let mut distribution = CfnDistribution::default();
+        apply_default_root_object(&inp, &mut distribution.distribution_config).expect("Failed to apply default root object");
+        let props = distribution.properties();
+        assert_eq!(props["DistributionConfig"]["DefaultRootObject"], "index.html");
+
+        let inp = Input {
+            default_root_object: Some("/index.html".to_string()),
+            ..Default::default()
+        };
+        let mut config = DistributionConfig::default();
+        assert!(apply_default_root_object(&inp, &mut config).is_err());
+        assert!(apply_default_root_object(&Input::default(), &mut config).is_ok());
+        assert!(config.default_root_object.is_none());
+    }
+}
@@ -4,7 +4,8 @@ use cfn_resources::CfnResource;
 use hira_lib::level0::*;
 use hira_lib::parsing::FunctionSignature;
 use aws_cfn_stack::aws_cfn_stack;
-use ::aws_cfn_stack::{aws_cfn_stack::{SavedResource, SavedTemplate, ResourceOutput}, create_or_update_stack, wait_for_output};
+use h_aws_lambda::ZipLevel;
+use ::aws_cfn_stack::{aws_cfn_stack::{SavedResource, SavedTemplate}, create_or_update_stack, wait_for_output, does_stack_exist, get_deployed_template, get_or_create_artifact_bucket, load_aws_configs};
 use cfn_resources::serde_json::Value;
 use tokio::io::AsyncReadExt;
 use zip::write::FileOptions;
@@ -19,66 +20,35 @@ pub fn get_ref(logical_name: &str) -> Value {
     serde_json::Value::Object(map)
 }
 
-pub async fn create_bucket_stack() -> String {
-    const STACK_NAME: &str = "hira-gen-lambda-artifact-bucket";
-    let sdk_config = aws_config::from_env().load().await;
-    let client = aws_sdk_cloudformation::Client::new(&sdk_config);
-
-    // check if this stack already exists.
-    // if it does, then just return the name of the s3 bucket output
-    match wait_for_output(&client, STACK_NAME, None).await {
-        Ok(o) => match o.get("BucketName") {
-            Some(s) => return s.to_string(),
-            None => panic!("Stack {} already exists, but is missing a BucketName output", STACK_NAME),
-        },
-        Err(_) => {
-            // assume it doesnt exist, try to create it:
-        }
-    }
-
-    let s3_bucket = s3::bucket::CfnBucket {
-        ..Default::default()
-    };
-    let resource = SavedResource {
-        ty: s3_bucket.type_string().to_string(),
-        properties: s3_bucket.properties(),
-    };
-    let mut template = SavedTemplate::default();
-    template.resources.insert("S3ArtifactBucket".to_string(), resource);
-    template.outputs.insert("BucketName".to_string(), ResourceOutput {
-        description: "name of bucket created".to_string(),
-        value: get_ref("S3ArtifactBucket"),
-    });
-    let template_body = cfn_resources::serde_json::to_string(&template).expect("Failed to serialize template");
-    if let Err(e) = create_or_update_stack(&client, STACK_NAME, &template_body).await {
-        panic!("Failed to create {STACK_NAME} stack\n{e}");
-    }
-    let outputs = match wait_for_output(&client, STACK_NAME, None).await {
-        Ok(o) => o,
-        Err(e) => panic!("Failed to get outputs for {STACK_NAME}\n{}", e),
-    };
-    match outputs.get("BucketName") {
-        Some(s) => s.to_string(),
-        None => panic!("Failed to get BucketName output for {STACK_NAME}"),
+/// `sdk_config` should be loaded with `load_aws_configs` so that the stacks' `aws_profile` is used.
+pub async fn create_bucket_stack(sdk_config: &aws_config::SdkConfig) -> String {
+    let client = aws_sdk_cloudformation::Client::new(sdk_config);
+    match get_or_create_artifact_bucket(&client).await {
+        Ok(b) => b,
+        Err(e) => panic!("{e}"),
     }
 }
 
-pub async fn set_bucket_arn(bucket_name: &mut String, bucket_location: &mut Option<String>) {
+pub async fn set_bucket_arn(sdk_config: &aws_config::SdkConfig, bucket_name: &mut String, bucket_location: &mut Option<String>) {
     if let Some(location) = bucket_location {
         *bucket_name = location.clone();
     } else {
-        let location = create_bucket_stack().await;
+        let location = create_bucket_stack(sdk_config).await;
         *bucket_location = Some(location.clone());
         *bucket_name = location;
     }
 }
 
-fn create_zip_archive(data: &[u8]) -> ZipResult<Vec<u8>> {
+fn create_zip_archive(data: &[u8], level: ZipLevel) -> ZipResult<Vec<u8>> {
     let mut zip_buffer = Cursor::new(Vec::new());
     let mut zip_writer = ZipWriter::new(&mut zip_buffer);
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .unix_permissions(0o755);
+    let options = match level {
+        ZipLevel::Stored => FileOptions::default().compression_method(zip::CompressionMethod::Stored),
+        ZipLevel::Deflated(level) => FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .compression_level(level),
+    };
+    let options = options.unix_permissions(0o755);
     zip_writer.start_file("bootstrap", options)?;
     zip_writer.write_all(data)?;
     zip_writer.finish()?;
@@ -94,7 +64,7 @@ pub fn basic_hash(data: &[u8]) -> String {
     format!("{:X}", hash)
 }
 
-pub async fn zip_and_upload_lambda_code(src_path: &str, dest_bucket: &str) -> String {
+pub async fn zip_and_upload_lambda_code(sdk_config: &aws_config::SdkConfig, src_path: &str, dest_bucket: &str, zip_level: ZipLevel) -> String {
     let mut file_data = vec![];
     match tokio::fs::File::open(&src_path).await {
         Ok(mut f) => match f.read_to_end(&mut file_data).await {
@@ -104,7 +74,7 @@ pub async fn zip_and_upload_lambda_code(src_path: &str, dest_bucket: &str) -> St
         Err(e) => panic!("Failed to read artifact file {src_path}\n{:?}", e),
     }
     let hash_str = basic_hash(&file_data);
-    let zipped_data = match create_zip_archive(&file_data) {
+    let zipped_data = match create_zip_archive(&file_data, zip_level) {
         Ok(d) => d,
         Err(e) => panic!("Failed to create zip archive for {src_path}\n{:?}", e),
     };
@@ -114,8 +84,7 @@ pub async fn zip_and_upload_lambda_code(src_path: &str, dest_bucket: &str) -> St
     };
     let obj_key = format!("{base_name}-{hash_str}.zip");
 
-    let sdk_config = aws_config::from_env().load().await;
-    let client = aws_sdk_s3::Client::new(&sdk_config);
+    let client = aws_sdk_s3::Client::new(sdk_config);
 
     let resp = client.put_object()
         .bucket(dest_bucket)
@@ -128,36 +97,170 @@ pub async fn zip_and_upload_lambda_code(src_path: &str, dest_bucket: &str) -> St
     obj_key
 }
 
+/// in preview mode we dont create a bucket or upload any artifacts.
+#[cfg(hira_preview)]
+pub async fn setup_lambda(data: &mut Vec<String>) {
+    for stack_str in data {
+        let stack: aws_cfn_stack::SavedStack = cfn_resources::serde_json::from_str(&stack_str).expect("Failed to deserialize generated json file");
+        for (_stack_name, (_, template)) in stack.template.iter() {
+            for (resource_name, resource) in template.resources.iter() {
+                if let Some((_, obj_key)) = get_function_code_location(resource) {
+                    println!("Preview: would zip and upload artifact {obj_key} for {resource_name}");
+                }
+            }
+        }
+    }
+}
+
+/// returns the name of the artifact bucket that is deployed as part of the given stack.
+/// if the stack or the bucket doesnt exist yet, the bucket gets deployed first, so that
+/// code can be uploaded to it before the stack's lambda functions are created.
+pub async fn create_in_stack_bucket(sdk_config: &aws_config::SdkConfig, stack_name: &str, bucket_logical_name: &str, tags: &[(String, String)]) -> String {
+    use crate::h_aws_lambda::ARTIFACT_BUCKET_OUTPUT_NAME;
+    let client = aws_sdk_cloudformation::Client::new(sdk_config);
+    let exists = match does_stack_exist(&client, stack_name).await {
+        Ok(e) => e,
+        Err(e) => panic!("Failed to check if stack {stack_name} exists\n{e}"),
+    };
+    let mut template = if exists {
+        let outputs = match wait_for_output(&client, stack_name, None).await {
+            Ok(o) => o,
+            Err(e) => panic!("Failed to get outputs for {stack_name}\n{e}"),
+        };
+        if let Some(name) = outputs.get(ARTIFACT_BUCKET_OUTPUT_NAME) {
+            return name.to_string();
+        }
+        match get_deployed_template(&client, stack_name).await {
+            Ok(Some(t)) => t,
+            Ok(None) => panic!("Failed to get deployed template of stack {stack_name}"),
+            Err(e) => panic!("Failed to get deployed template of stack {stack_name}\n{e}"),
+        }
+    } else {
+        cfn_resources::serde_json::to_value(SavedTemplate::default()).expect("Failed to serialize template")
+    };
+    // we only add the bucket to what is currently deployed. the rest of the stack
+    // gets updated by the actual deploy.
+    let bucket = s3::bucket::CfnBucket::default();
+    template["Resources"][bucket_logical_name] = serde_json::json!({
+        "Type": bucket.type_string(),
+        "Properties": bucket.properties(),
+    });
+    template["Outputs"][ARTIFACT_BUCKET_OUTPUT_NAME] = serde_json::json!({
+        "Description": "name of the artifact bucket of this stack",
+        "Value": get_ref(bucket_logical_name),
+    });
+    let template_body = cfn_resources::serde_json::to_string(&template).expect("Failed to serialize template");
+    println!("Creating artifact bucket in stack {stack_name}");
+    if let Err(e) = create_or_update_stack(&client, stack_name, &template_body, tags).await {
+        panic!("Failed to create artifact bucket in stack {stack_name}\n{e}");
+    }
+    let outputs = match wait_for_output(&client, stack_name, None).await {
+        Ok(o) => o,
+        Err(e) => panic!("Failed to get outputs for {stack_name}\n{}", e),
+    };
+    match outputs.get(ARTIFACT_BUCKET_OUTPUT_NAME) {
+        Some(s) => s.to_string(),
+        None => panic!("Failed to get {ARTIFACT_BUCKET_OUTPUT_NAME} output for {stack_name}"),
+    }
+}
+
+#[cfg(not(hira_preview))]
 pub async fn setup_lambda(data: &mut Vec<String>) {
-    use crate::h_aws_lambda::BUCKET_UNKNOWN;
-    let bucket_location = create_bucket_stack().await;
-    let bucket_location_ref = bucket_location.as_str();
+    use crate::h_aws_lambda::{BUCKET_UNKNOWN, get_in_stack_bucket};
+    // the global bucket is only created if a function uses it. each aws profile
+    // can be a different account, so they each get their own global bucket.
+    let mut global_buckets: std::collections::HashMap<Option<String>, String> = Default::default();
+    // stack name -> artifact bucket name
+    let mut in_stack_buckets: std::collections::HashMap<String, String> = Default::default();
 
+    // uses the same aws profiles as the deploy of the stacks
+    let aws_configs = load_aws_configs(data).await;
     println!("Uploading Lambdas Function Artifacts...");
     for stack_str in data {
         let mut stack: aws_cfn_stack::SavedStack = cfn_resources::serde_json::from_str(&stack_str).expect("Failed to deserialize generated json file");
-        for (_stack_name, (_, template)) in stack.template.iter_mut() {
+        for (stack_name, (_, template)) in stack.template.iter_mut() {
+            let sdk_config = aws_configs.get(stack_name);
             for (resource_name, resource) in template.resources.iter_mut() {
-                if let Some((mut bucket_name, mut obj_key)) = get_function_code_location(resource) {
+                if let Some((bucket_name, mut obj_key)) = get_function_code_location(resource) {
+                    let zip_level = match ZipLevel::from_env_or(get_function_zip_level(resource)) {
+                        Ok(l) => l,
+                        Err(e) => panic!("Failed to get zip level of {resource_name}\n{e}"),
+                    };
+                    if let Some(bucket_logical_name) = get_in_stack_bucket(&bucket_name) {
+                        let bucket_logical_name = bucket_logical_name.to_string();
+                        if !in_stack_buckets.contains_key(stack_name) {
+                            let name = create_in_stack_bucket(sdk_config, stack_name, &bucket_logical_name, &stack.tags).await;
+                            in_stack_buckets.insert(stack_name.to_string(), name);
+                        }
+                        println!("Zipping and uploading artifact for {resource_name}");
+                        obj_key = zip_and_upload_lambda_code(sdk_config, &obj_key, &in_stack_buckets[stack_name], zip_level).await;
+                        // referencing the bucket ensures cloudformation creates it before the function.
+                        reinsert_value(resource, get_ref(&bucket_logical_name), obj_key);
+                        continue;
+                    }
+                    let mut bucket_name = bucket_name;
+                    let profile = aws_configs.profile(stack_name);
+                    let bucket_location = match global_buckets.get(&profile) {
+                        Some(b) => b.clone(),
+                        None => {
+                            let b = create_bucket_stack(sdk_config).await;
+                            global_buckets.insert(profile, b.clone());
+                            b
+                        }
+                    };
                     // this lambda function doesnt have a bucket name yet, so we set it
                     if bucket_name == BUCKET_UNKNOWN {
-                        bucket_name = bucket_location_ref.to_string();
+                        bucket_name = bucket_location.clone();
                     }
                     // upload the file to the bucket location:
                     println!("Zipping and uploading artifact for {resource_name}");
-                    obj_key = zip_and_upload_lambda_code(&obj_key, bucket_location_ref).await;
+                    obj_key = zip_and_upload_lambda_code(sdk_config, &obj_key, &bucket_location, zip_level).await;
                     reinsert(resource, bucket_name, obj_key);
                 }
             }
         }
+        for (_, (_, template)) in stack.template.iter_mut() {
+            set_version_descriptions(template);
+        }
         // serialize it back and store in the string
         *stack_str = cfn_resources::serde_json::to_string(&stack).expect("Failed to serialize generated json");
     }
 }
 
+/// a lambda version is only published when the version resource is replaced. cloudformation replaces it
+/// when its description changes, so we set the description to a hash of the function it references.
+/// This must be called after uploading the code, so that the hash includes the key of the code (which contains the hash of the code).
+pub fn set_version_descriptions(template: &mut SavedTemplate) {
+    let version_ty = "AWS::Lambda::Version";
+    let mut descriptions = vec![];
+    for (name, resource) in template.resources.iter() {
+        if resource.ty != version_ty {
+            continue;
+        }
+        let function = resource.properties.get("FunctionName")
+            .and_then(|x| x.get("Ref"))
+            .and_then(|x| x.as_str())
+            .and_then(|x| template.resources.get(x));
+        if let Some(function) = function {
+            let hash = aws_cfn_stack::content_hash(&function.properties.to_string());
+            descriptions.push((name.clone(), format!("hira function hash {hash}")));
+        }
+    }
+    for (name, description) in descriptions {
+        if let Some(Value::Object(props)) = template.resources.get_mut(&name).map(|x| &mut x.properties) {
+            props.insert("Description".to_string(), Value::String(description));
+        }
+    }
+}
+
 pub fn reinsert(resource: &mut SavedResource, bucket_name: String, obj_key: String) {
+    reinsert_value(resource, Value::String(bucket_name), obj_key);
+}
+
+/// same as `reinsert`, but the bucket can be any value, eg: a `Ref` to a bucket in the same stack
+pub fn reinsert_value(resource: &mut SavedResource, bucket: Value, obj_key: String) {
     if let Some(Value::Object(code)) = resource.properties.get_mut("Code") {
-        code.insert("S3Bucket".to_string(), Value::String(bucket_name));
+        code.insert("S3Bucket".to_string(), bucket);
         code.insert("S3Key".to_string(), Value::String(obj_key));
     }
 }
@@ -184,6 +287,12 @@ pub fn get_function_code_location(resource: &SavedResource) -> Option<(String, S
     }
 }
 
+/// returns the zip level that was set in the `Metadata` of the function resource, if any.
+/// see `h_aws_lambda::ZIP_LEVEL_METADATA_KEY`
+pub fn get_function_zip_level(resource: &SavedResource) -> Option<&str> {
+    resource.metadata.as_ref()?.get(h_aws_lambda::ZIP_LEVEL_METADATA_KEY)?.as_str()
+}
+
 /// This is a higher level module for easily creating lambda functions.
 /// To use this module, your calling module must contain a function `lambda_main`.
 /// This module parses the function signature of `lambda_main` and generates corresponding
@@ -194,6 +303,9 @@ pub fn get_function_code_location(resource: &SavedResource) -> Option<(String, S
 pub mod h_aws_lambda {
     extern crate lambda;
     extern crate iam;
+    extern crate s3;
+    extern crate logs;
+    extern crate events;
     extern crate cfn_resources;
     use super::FunctionSignature;
     use super::aws_cfn_stack;
@@ -202,8 +314,10 @@ pub mod h_aws_lambda {
     use super::L0CodeWriter;
     use super::L0CodeReader;
     use super::L0Core;
+    use super::L0KvReader;
     use super::RuntimeMeta;
     use self::cfn_resources::get_att;
+    use self::cfn_resources::get_ref;
     use self::cfn_resources::ToOptStrVal;
     use self::cfn_resources::serde_json::Value;
     use self::cfn_resources::StrVal;
@@ -212,6 +326,75 @@ pub mod h_aws_lambda {
 
     pub const BUCKET_UNKNOWN: &str = "HIRA_GEN_BUCKET_UNKNOWN";
 
+    /// functions that use an artifact bucket in their own stack have their bucket
+    /// set to `{prefix}{logical id of the bucket}` until their code is uploaded.
+    pub const IN_STACK_BUCKET_PREFIX: &str = "HIRA_GEN_IN_STACK_BUCKET:";
+    pub const ARTIFACT_BUCKET_LOGICAL_NAME: &str = "hiragenartifactbucket";
+    /// the stack output that contains the name of a stack's own artifact bucket
+    pub const ARTIFACT_BUCKET_OUTPUT_NAME: &str = "HiraArtifactBucketName";
+    /// prefix of the shared data keys used to only add 1 artifact bucket per stack.
+    /// the full key is `{prefix}{stack name}`
+    pub const ARTIFACT_BUCKET_KEY_PREFIX: &str = "hira_lambda_artifact_bucket:";
+
+    /// the key in the `Metadata` of a function resource that contains the zip level of its artifact.
+    /// set from the `zip_level` of the function's `RuntimeMeta`
+    pub const ZIP_LEVEL_METADATA_KEY: &str = "HiraZipLevel";
+
+    /// the env var read by the deploy runtime to decide how lambda artifacts are compressed. see `ZipLevel::parse`.
+    /// if set, it takes precedence over the `zip_level` of the function's `RuntimeMeta`
+    pub const ZIP_LEVEL_ENV: &str = "HIRA_ZIP_LEVEL";
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ZipLevel {
+        /// no compression. fastest to create, useful for iterative builds.
+        Stored,
+        /// deflate at the given level (0-9), or the default level if None.
+        Deflated(Option<i32>),
+    }
+
+    impl Default for ZipLevel {
+        fn default() -> Self {
+            ZipLevel::Deflated(None)
+        }
+    }
+
+    impl ZipLevel {
+        /// `stored`, `deflated`, or a deflate level from 0 (fastest) to 9 (smallest).
+        pub fn parse(s: &str) -> Result<Self, String> {
+            match s.trim() {
+                "stored" => Ok(ZipLevel::Stored),
+                "deflated" => Ok(ZipLevel::Deflated(None)),
+                x => match x.parse::<i32>() {
+                    Ok(level) if (0..=9).contains(&level) => Ok(ZipLevel::Deflated(Some(level))),
+                    _ => Err(format!("Invalid zip level '{x}'. Expected 'stored', 'deflated', or a level from 0 to 9")),
+                }
+            }
+        }
+
+        /// reads `HIRA_ZIP_LEVEL`, falling back to `runtime_zip_level` (the `zip_level` of the function's `RuntimeMeta`),
+        /// and defaulting to deflated at the default level if neither is set.
+        pub fn from_env_or(runtime_zip_level: Option<&str>) -> Result<Self, String> {
+            match (std::env::var(ZIP_LEVEL_ENV), runtime_zip_level) {
+                (Ok(val), _) => Self::parse(&val),
+                (Err(_), Some(val)) => Self::parse(val),
+                (Err(_), None) => Ok(Self::default()),
+            }
+        }
+    }
+
+    /// environment values starting with this prefix reference a secrets manager secret
+    pub const SECRET_ENV_PREFIX: &str = "secret:";
+
+    /// prefix of the shared data keys used to register a function url
+    /// under a desired path. the full key is `{prefix}{path}` and the value
+    /// is the logical id of the function url.
+    pub const DESIRED_PATH_KEY_PREFIX: &str = "hira_lambda_desired_path:";
+
+    /// prefix of the shared data keys used to detect modules whose functions
+    /// would get the same logical id. the full key is `{prefix}{logical id}` and the value
+    /// is the name of the module that registered it.
+    pub const LOGICAL_FUNCTION_NAME_KEY_PREFIX: &str = "hira_lambda_logical_function_name:";
+
     #[derive(cfn_resources::serde::Serialize, cfn_resources::serde::Deserialize)]
     #[serde(rename_all = "camelCase")]
     #[serde(default)]
@@ -231,17 +414,52 @@ pub mod h_aws_lambda {
         pub headers: std::collections::HashMap<String, String>,
     }
 
+    /// groups statements of the same effect together such that the resulting statements
+    /// grant exactly the same (action, resource) pairs as the input statements.
+    /// first all actions on the same resource are merged, and then all resources
+    /// that ended up with the same set of actions are merged.
+    /// the output contains tuples of: effect, actions, resources. order of first appearance is preserved.
+    pub fn group_policy_statements(statements: &[(String, String, String)]) -> Vec<(String, Vec<String>, Vec<String>)> {
+        let mut by_resource: Vec<(String, String, Vec<String>)> = vec![];
+        for (effect, action, resource) in statements {
+            if let Some((_, _, actions)) = by_resource.iter_mut().find(|(e, r, _)| e == effect && r == resource) {
+                if !actions.contains(action) {
+                    actions.push(action.to_string());
+                }
+            } else {
+                by_resource.push((effect.to_string(), resource.to_string(), vec![action.to_string()]));
+            }
+        }
+        let mut out: Vec<(String, Vec<String>, Vec<String>)> = vec![];
+        for (effect, resource, actions) in by_resource {
+            if let Some((_, _, resources)) = out.iter_mut().find(|(e, a, _)| *e == effect && *a == actions) {
+                resources.push(resource);
+            } else {
+                out.push((effect, actions, vec![resource]));
+            }
+        }
+        out
+    }
+
     /// statements contain a tuple of: effect, action, resource.
     /// eg: ("Allow", "*", "*")
+    /// statements are grouped via `group_policy_statements` to keep the policy small.
     pub fn create_policy_doc(statements: &[(String, String, String)]) -> Value {
         let mut map = cfn_resources::serde_json::Map::default();
         map.insert("Version".to_string(), Value::String("2012-10-17".to_string()));
         let mut statements_out = vec![];
-        for (effect, action, resource) in statements {
+        let to_value = |mut list: Vec<String>| -> Value {
+            if list.len() == 1 {
+                Value::String(list.remove(0))
+            } else {
+                Value::Array(list.into_iter().map(Value::String).collect())
+            }
+        };
+        for (effect, actions, resources) in group_policy_statements(statements) {
             let mut statement_obj = cfn_resources::serde_json::Map::default();
-            statement_obj.insert("Effect".to_string(), Value::String(effect.to_string()));
-            statement_obj.insert("Action".to_string(), Value::String(action.to_string()));
-            statement_obj.insert("Resource".to_string(), Value::String(resource.to_string()));
+            statement_obj.insert("Effect".to_string(), Value::String(effect));
+            statement_obj.insert("Action".to_string(), to_value(actions));
+            statement_obj.insert("Resource".to_string(), to_value(resources));
             statements_out.push(Value::Object(statement_obj));
         }
         map.insert("Statement".to_string(), Value::Array(statements_out));
@@ -265,7 +483,7 @@ pub mod h_aws_lambda {
         Value::Object(map)
     }
 
-    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
     pub enum Arch {
         Arm64,
         X86,
@@ -283,8 +501,102 @@ pub mod h_aws_lambda {
         }
     }
 
+    /// the tool that cross compiles the function's runtime.
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    #[derive(Debug, PartialEq)]
+    pub enum LambdaBuilder {
+        /// `cross`, which compiles in a Docker container
+        Cross,
+        /// `cargo lambda build`, which cross compiles with zig, and doesn't need Docker. The executable is
+        /// output to `lambda/{function name}/bootstrap` in the runtimes directory.
+        /// cargo lambda only builds binaries, so your crate must declare a bin named after the function, which
+        /// is enabled by a feature of the same name, eg: for a function `my_fn`:
+        /// `[[bin]] name = "my_fn", path = "src/lib.rs", required-features = ["my_fn"]` and `[features] my_fn = []`
+        CargoLambda,
+    }
+
+    impl Default for LambdaBuilder {
+        fn default() -> Self { Self::Cross }
+    }
+    impl LambdaBuilder {
+        pub fn cargo_cmd(&self) -> &'static str {
+            match self {
+                LambdaBuilder::Cross => "cross",
+                LambdaBuilder::CargoLambda => "cargo",
+            }
+        }
+    }
+
+    /// the OS only runtime that the function's executable runs on.
+    /// To bring your own runtime, set `extra_options.runtime` instead, which takes precedence.
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    #[derive(Debug, PartialEq)]
+    pub enum LambdaRuntimeChoice {
+        /// `provided.al2`
+        Providedal2,
+    }
+
+    impl Default for LambdaRuntimeChoice {
+        fn default() -> Self { Self::Providedal2 }
+    }
+    impl LambdaRuntimeChoice {
+        pub fn runtime(&self) -> lambda::function::FunctionRuntimeEnum {
+            match self {
+                LambdaRuntimeChoice::Providedal2 => lambda::function::FunctionRuntimeEnum::Providedal2,
+            }
+        }
+    }
+
+    /// attaches the function to a VPC
+    #[derive(Default, Clone)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub struct VpcConfig {
+        pub subnet_ids: Vec<String>,
+        pub security_group_ids: Vec<String>,
+    }
+
+    /// who can invoke the function url
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    #[derive(Debug, PartialEq)]
+    pub enum FunctionUrlAuth {
+        /// anyone can invoke the function url
+        None,
+        /// only IAM users and roles of this account that are allowed to `lambda:InvokeFunctionUrl`
+        /// can invoke the function url. Requests must be signed with SigV4.
+        Iam,
+    }
+
+    impl Default for FunctionUrlAuth {
+        fn default() -> Self { Self::None }
+    }
+
+    /// the CORS settings of the function url, for calling it from a browser
+    #[derive(Default, Clone)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub struct CorsConfig {
+        /// eg: `https://example.com`, or `*`
+        pub allowed_origins: Vec<String>,
+        /// eg: `GET`, `POST`, or `*`
+        pub allowed_methods: Vec<String>,
+        pub allowed_headers: Vec<String>,
+    }
+
+    pub const FUNCTION_URL_CORS_METHODS: &[&str] = &["GET", "PUT", "HEAD", "POST", "PATCH", "DELETE", "*"];
+
+    /// invokes the function with batches of messages from an SQS queue
+    #[derive(Default, Clone)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub struct SqsTrigger {
+        pub queue_arn: String,
+        /// the maximum number of messages per batch. Must be between 1 and 10000.
+        /// Defaults to 10. A batch size greater than 10 requires `maximum_batching_window_seconds`.
+        pub batch_size: Option<u32>,
+        /// how long to wait to gather a batch before invoking the function. Must be between 0 and 300.
+        pub maximum_batching_window_seconds: Option<u32>,
+    }
+
     #[derive(Default)]
-    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
     pub struct Input {
         /// by default, we add policy statements to the lambda's execution role to allow
         /// it to log to cloudwatch. if you'd like to disable cloudwatch logging, set
@@ -306,11 +618,103 @@ pub mod h_aws_lambda {
         /// Note: setting this to false has no effect.
         pub use_function_url: bool,
 
+        /// the auth type of the function url (if used). Defaults to None, ie: anyone can invoke it.
+        pub function_url_auth: FunctionUrlAuth,
+        /// optionally set CORS headers on the responses of the function url (if used)
+        pub function_url_cors: Option<CorsConfig>,
+
         /// valid values: arm64, x86. Defaults to arm64
         /// This controls how the lambda function will be compiled.
         /// arm64: aarch64-unknown-linux-musl
         /// x86: x86_64-unknown-linux-musl
         pub architecture: Arch,
+        /// the tool that compiles the function: cross, or cargo lambda. Defaults to cross. See `LambdaBuilder`
+        pub builder: LambdaBuilder,
+        /// how the function's code is compressed when it is uploaded: `stored`, `deflated`, or a deflate
+        /// level from 0 (fastest) to 9 (smallest). Defaults to deflated. The `HIRA_ZIP_LEVEL` env var
+        /// takes precedence over this when deploying, eg: to use `stored` for faster iterative builds.
+        pub zip_level: Option<String>,
+
+        /// optionally register this function's url under a URL path, eg: "/api".
+        /// distribution modules such as lambda_url_distribution can then
+        /// automatically collect all registered paths instead of listing each endpoint.
+        /// Only applies if this function uses a function url.
+        /// Paths must be unique across all lambda functions, and cannot be used with `function_url_auth: Iam`.
+        pub desired_path: Option<String>,
+
+        /// if true, creates CloudWatch alarms that trigger when this function
+        /// errors or gets throttled.
+        pub create_alarms: bool,
+        /// the alarm triggers if the sum of errors within a 1 minute period
+        /// is greater than or equal to this threshold. Defaults to 1.
+        /// Only applies if create_alarms is true.
+        pub error_alarm_threshold: Option<f64>,
+        /// the alarm triggers if the sum of throttles within a 1 minute period
+        /// is greater than or equal to this threshold. Defaults to 1.
+        /// Only applies if create_alarms is true.
+        pub throttle_alarm_threshold: Option<f64>,
+        /// optionally notify this SNS topic when an alarm triggers.
+        /// Only applies if create_alarms is true.
+        pub alarm_sns_topic_arn: Option<String>,
+
+        /// by default, the function's log group is created implicitly by lambda the first
+        /// time it logs, and its logs never expire. If set, we create the log group
+        /// `/aws/lambda/{function name}` ourselves with this retention.
+        /// Must be one of the retention periods that CloudWatch allows (see `LOG_RETENTION_DAYS`).
+        /// Only applies if disable_cloudwatch_logging is false.
+        /// If the function already logged, its log group already exists, and must be deleted
+        /// (or imported into the stack) before deploying.
+        pub log_retention_days: Option<u32>,
+
+        /// size of the function's /tmp directory in MB. Must be between 512 and 10240.
+        /// Defaults to 512
+        pub ephemeral_storage_mb: Option<i64>,
+        /// the maximum number of concurrent executions reserved for this function.
+        /// Setting this to 0 effectively disables the function.
+        pub reserved_concurrency: Option<i64>,
+        /// the number of execution environments to keep initialized. Provisioned concurrency
+        /// can only be configured on a version/alias, so if set, we publish a version
+        /// of the function, and create an alias called `live` that points to it.
+        /// Note that the function url (if used) still points to the unqualified function.
+        pub provisioned_concurrency: Option<i64>,
+
+        /// environment variables of the function. Values of the form `secret:{secret arn}`
+        /// are not set literally. Instead the value is a dynamic reference
+        /// (`{{resolve:secretsmanager:{secret arn}}}`) that cloudformation resolves at deploy time,
+        /// and the function's role is allowed to `secretsmanager:GetSecretValue` on that secret.
+        /// The arn must be the complete arn, including the 6 character suffix that secrets manager adds
+        /// to the name, and can optionally be followed by `:SecretString:json-key:version-stage:version-id`.
+        /// Note: the resolved value is stored in the function's configuration in plain text, so anyone who can
+        /// read the configuration (eg: `lambda:GetFunctionConfiguration`) can read the secret. To avoid this,
+        /// read the secret at runtime instead, which the role is already allowed to do.
+        pub environment: std::collections::HashMap<String, String>,
+
+        /// by default, function code is uploaded to an artifact bucket that lives in a separate
+        /// stack (`hira-gen-lambda-artifact-bucket`) shared by all functions. If true, an artifact bucket
+        /// is instead created within this function's stack, and shared by all functions in that stack.
+        pub artifact_bucket_in_stack: bool,
+
+        /// the runtime of the function. defaults to `provided.al2`.
+        /// ignored if `extra_options.runtime` is set.
+        pub lambda_runtime: LambdaRuntimeChoice,
+
+        /// ARNs of layers to add to the function, eg: `arn:aws:lambda:us-east-1:123456789012:layer:my-layer:1`
+        /// These are added after any layers in `extra_options.layers`.
+        pub layers: Vec<String>,
+        /// optionally attach the function to a VPC. The function's role is allowed to
+        /// manage the network interfaces that lambda needs to do this.
+        /// Note: if you specify a role_arn, that role must already allow this.
+        /// If `extra_options.vpc_config` is also set, it is used instead, and a warning is emitted.
+        pub vpc_config: Option<VpcConfig>,
+
+        /// optionally invoke this function on a schedule, eg: `rate(5 minutes)` or `cron(0 12 * * ? *)`.
+        /// We create an EventBridge rule with this schedule expression that targets the function.
+        pub schedule: Option<String>,
+
+        /// SQS queues that invoke this function. For each queue we create an event source mapping,
+        /// and allow the function's role to receive and delete messages from the queue.
+        /// Note: if you specify a role_arn, that role must already allow this.
+        pub sqs_triggers: Vec<SqsTrigger>,
 
         /// This module only sets the following fields:
         /// - architectures
@@ -323,6 +727,7 @@ pub mod h_aws_lambda {
         /// ```rust,ignore
         /// extra_options.memory_size = Some(1024);
         /// ```
+        #[cfg_attr(feature = "web", schemars(with = "serde_json::Value"))]
         pub extra_options: lambda::function::CfnFunction,
     }
 
@@ -347,7 +752,23 @@ pub mod h_aws_lambda {
         ("CODE_READ", &["fn:lambda_main"]),
     ];
 
-    fn validate_lambda_main_signature(sig: &FunctionSignature, use_event_func_url: &mut bool) -> Result<(String, String, String), String> {
+    /// the maximum size of a function url response payload.
+    pub const FUNCTION_URL_MAX_PAYLOAD_MB: u32 = 6;
+
+    /// heuristic for return types that hold arbitrary bytes, and therefore
+    /// can easily exceed the function url payload limit.
+    /// `ty` is expected to not contain any whitespace.
+    pub fn is_raw_binary_type(ty: &str) -> bool {
+        let ty = ty.trim_start_matches('&');
+        let last_segment = ty.rsplit("::").next().unwrap_or(ty);
+        matches!(last_segment, "Vec<u8>" | "[u8]" | "Box<[u8]>" | "Bytes" | "BytesMut")
+            || (ty.starts_with("Cow<") && ty.ends_with("[u8]>"))
+    }
+
+    /// validates the signature of the user's lambda_main function, and returns the code
+    /// necessary to call it. If the signature is valid, but likely not what the user intended,
+    /// `warning` will be set.
+    pub fn validate_lambda_main_signature(sig: &FunctionSignature, use_event_func_url: &mut bool, warning: &mut Option<String>) -> Result<(String, String, String), String> {
         if sig.return_ty.is_empty() {
             return Err("Must provide a return type for your lambda_main function".into());
         }
@@ -372,6 +793,15 @@ pub mod h_aws_lambda {
         let input_param_type = &input_param.ty;
         if input_param_type.ends_with("FunctionUrlEvent") {
             *use_event_func_url = true;
+            // function urls accept a plain string body, or a FunctionUrlResponse.
+            // anything else gets serialized to json and returned with default headers/status.
+            let ok_ty = sig.return_ok_ty.replace(" ", "");
+            let ok_ty = ok_ty.as_str();
+            if is_raw_binary_type(ok_ty) {
+                *warning = Some(format!("Your lambda_main function takes a FunctionUrlEvent, but returns raw binary data '{ok_ty}'. Function url responses are limited to {FUNCTION_URL_MAX_PAYLOAD_MB}MB, and binary data grows by a third when it is base64 encoded. If the data can be large, consider streaming the response, or uploading it to S3 and returning a pre-signed url instead"));
+            } else if !ok_ty.ends_with("FunctionUrlResponse") && ok_ty != "String" {
+                *warning = Some(format!("Your lambda_main function takes a FunctionUrlEvent, but returns '{ok_ty}'. The status code and headers of the response will not be set. Consider returning a '::aws_lambda::h_aws_lambda::FunctionUrlResponse' instead"));
+            }
         }
         let mut return_statement = if sig.is_async {
             format!("lambda_main(x).await")
@@ -384,94 +814,679 @@ pub mod h_aws_lambda {
         Ok((return_statement, b, input_param_type.to_string()))
     }
 
-    pub fn config(
-        inp: &mut Input, stackinp: &mut aws_cfn_stack::Input, l0code: &mut L0CodeReader,
-        runtimer: &mut L0RuntimeCreator, l0core: &mut L0Core, l0write: &mut L0CodeWriter
-    ) {
-        let user_mod_name = l0core.users_module_name();
-        let lambda_main_signature = match l0code.get_fn("lambda_main") {
-            Some(sig) => sig,
-            None => {
-                l0core.compiler_error("Missing lambda_main function");
-                return;
+    /// a CloudWatch alarm on one of the AWS/Lambda metrics of a function
+    pub struct LambdaAlarm {
+        pub function_logical_id: String,
+        pub metric_name: String,
+        pub threshold: f64,
+        pub sns_topic_arn: Option<String>,
+    }
+
+    impl cfn_resources::CfnResource for LambdaAlarm {
+        fn type_string(&self) -> &'static str {
+            "AWS::CloudWatch::Alarm"
+        }
+        fn properties(&self) -> Value {
+            let mut dimension = cfn_resources::serde_json::Map::new();
+            dimension.insert("Name".to_string(), Value::String("FunctionName".to_string()));
+            dimension.insert("Value".to_string(), get_ref(&self.function_logical_id));
+            let mut map = cfn_resources::serde_json::Map::new();
+            map.insert("AlarmDescription".to_string(), Value::String(format!("{} of {}", self.metric_name, self.function_logical_id)));
+            map.insert("Namespace".to_string(), Value::String("AWS/Lambda".to_string()));
+            map.insert("MetricName".to_string(), Value::String(self.metric_name.clone()));
+            map.insert("Dimensions".to_string(), Value::Array(vec![Value::Object(dimension)]));
+            map.insert("Statistic".to_string(), Value::String("Sum".to_string()));
+            map.insert("Period".to_string(), Value::from(60));
+            map.insert("EvaluationPeriods".to_string(), Value::from(1));
+            map.insert("Threshold".to_string(), Value::from(self.threshold));
+            map.insert("ComparisonOperator".to_string(), Value::String("GreaterThanOrEqualToThreshold".to_string()));
+            map.insert("TreatMissingData".to_string(), Value::String("notBreaching".to_string()));
+            if let Some(arn) = &self.sns_topic_arn {
+                map.insert("AlarmActions".to_string(), Value::Array(vec![Value::String(arn.clone())]));
             }
+            Value::Object(map)
+        }
+    }
+
+    /// the retention periods (in days) that CloudWatch log groups accept
+    pub const LOG_RETENTION_DAYS: &[u32] = &[
+        1, 3, 5, 7, 14, 30, 60, 90, 120, 150, 180, 365, 400, 545,
+        731, 1096, 1827, 2192, 2557, 2922, 3288, 3653,
+    ];
+
+    /// the actions the function's role needs to poll an SQS queue
+    pub const SQS_TRIGGER_ACTIONS: &[&str] = &["sqs:ReceiveMessage", "sqs:DeleteMessage", "sqs:GetQueueAttributes"];
+
+    pub const PROVISIONED_ALIAS_NAME: &str = "live";
+
+    /// returns a published version of the function, and an alias pointing to it
+    /// with the given provisioned concurrency.
+    pub fn create_provisioned_alias_resources(logical_fn_name: &str, provisioned_concurrency: i64) -> Vec<aws_cfn_stack::Resource> {
+        let version_logical_id = format!("{logical_fn_name}version");
+        let version = lambda::version::CfnVersion {
+            function_name: StrVal::Val(get_ref(logical_fn_name)),
+            ..Default::default()
         };
-        let (return_statement, return_ty, input_param_type) = match validate_lambda_main_signature(lambda_main_signature, &mut inp.use_function_url) {
-            Err(e) => {
-                l0core.compiler_error(&e);
-                return;
+        let alias = lambda::alias::CfnAlias {
+            function_name: StrVal::Val(get_ref(logical_fn_name)),
+            function_version: StrVal::Val(get_att(&version_logical_id, "Version")),
+            name: PROVISIONED_ALIAS_NAME.into(),
+            provisioned_concurrency_config: Some(lambda::alias::ProvisionedConcurrencyConfiguration {
+                provisioned_concurrent_executions: provisioned_concurrency,
+            }),
+            ..Default::default()
+        };
+        vec![
+            aws_cfn_stack::Resource {
+                name: version_logical_id,
+                properties: Box::new(version) as _,
+            },
+            aws_cfn_stack::Resource {
+                name: format!("{logical_fn_name}alias"),
+                properties: Box::new(alias) as _,
+            },
+        ]
+    }
+
+    /// sets the ephemeral storage and reserved concurrency of the function
+    /// according to the input, and validates the concurrency settings.
+    pub fn apply_function_limits(inp: &Input, lambdafn: &mut lambda::function::CfnFunction) -> Result<(), String> {
+        if let Some(mb) = inp.ephemeral_storage_mb {
+            if !(512..=10240).contains(&mb) {
+                return Err(format!("ephemeral_storage_mb must be between 512 and 10240. Instead found {mb}"));
             }
-            Ok(p) => p,
+            lambdafn.ephemeral_storage = Some(lambda::function::EphemeralStorage { size: mb });
+        }
+        if let Some(reserved) = inp.reserved_concurrency {
+            if reserved < 0 {
+                return Err(format!("reserved_concurrency cannot be negative. Instead found {reserved}"));
+            }
+            lambdafn.reserved_concurrent_executions = Some(reserved);
+        }
+        if let Some(provisioned) = inp.provisioned_concurrency {
+            if provisioned < 1 {
+                return Err(format!("provisioned_concurrency must be at least 1. Instead found {provisioned}"));
+            }
+            if let Some(reserved) = inp.reserved_concurrency {
+                if provisioned > reserved {
+                    return Err(format!("provisioned_concurrency ({provisioned}) cannot be greater than reserved_concurrency ({reserved})"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// returns how the runtime of the function is compiled
+    pub fn get_runtime_meta(inp: &Input, user_mod_name: &str) -> RuntimeMeta {
+        let cargo_lambda = inp.builder == LambdaBuilder::CargoLambda;
+        // the function's bin is only enabled by the feature of the same name, see `LambdaBuilder::CargoLambda`
+        let cargo_features = if cargo_lambda { vec![user_mod_name.to_string()] } else { vec![] };
+        RuntimeMeta { cargo_cmd: inp.builder.cargo_cmd().to_string(), target: inp.architecture.to_string(), profile: "release".to_string(), zip_level: inp.zip_level.clone(), cargo_lambda, cargo_features, ..Default::default() }
+    }
+
+    /// the actions the function's role needs to attach the function to a VPC
+    pub const VPC_ACTIONS: &[&str] = &["ec2:CreateNetworkInterface", "ec2:DescribeNetworkInterfaces", "ec2:DeleteNetworkInterface"];
+
+    /// validates the vpc config, and returns the policy statements needed to attach the function to the VPC.
+    pub fn get_vpc_statements(vpc_config: &Option<VpcConfig>) -> Result<Vec<(String, String, String)>, String> {
+        let vpc_config = match vpc_config {
+            Some(v) => v,
+            None => return Ok(vec![]),
         };
+        if vpc_config.subnet_ids.is_empty() {
+            return Err("vpc_config must have at least 1 subnet id".to_string());
+        }
+        if vpc_config.security_group_ids.is_empty() {
+            return Err("vpc_config must have at least 1 security group id".to_string());
+        }
+        // these actions don't support resource level permissions
+        Ok(VPC_ACTIONS.iter().map(|action| ("Allow".to_string(), action.to_string(), "*".to_string())).collect())
+    }
 
-        // Box<dyn std::error::Error + Send + Sync + 'static> = BoxError
-        l0write.write_internal_fn(
-            format!("pub async fn service_func(event: lambda_runtime::LambdaEvent<{}>) -> {}", input_param_type, return_ty),
-            format!("let (x, _context) = event.into_parts(); {}", return_statement)
-        );
-        l0write.write_internal_fn(
-            format!("pub async fn entrypoint() -> Result<(), ::aws_lambda::h_aws_lambda::BoxError>"),
-            format!("let func = lambda_runtime::service_fn(service_func);\nlambda_runtime::run(func).await?;\nOk(())")
-        );
-        runtimer.add_to_runtime_ex(
-            &user_mod_name,
-            format!("{user_mod_name}::entrypoint().await.expect(\"Lambda Error\")"),
-            RuntimeMeta { cargo_cmd: "cross".to_string(), target: inp.architecture.to_string(), profile: "release".to_string(), no_tokio_async_runtime: false }
-        );
-        runtimer.depends_on(&user_mod_name, "deploy");
-        let lambda_executable_path = runtimer.get_full_runtime_path(&user_mod_name);
+    /// returns the function url of the function, and the permission that allows invoking it.
+    /// Both use the same auth type. With IAM auth, the principal is this account rather than everyone.
+    pub fn create_function_url(inp: &Input, logical_fn_name: &str) -> Result<(lambda::url::CfnUrl, lambda::permission::CfnPermission), String> {
+        let (auth_type, permission_auth_type, principal) = match inp.function_url_auth {
+            FunctionUrlAuth::None => (
+                lambda::url::UrlAuthTypeEnum::None,
+                lambda::permission::PermissionFunctionUrlAuthTypeEnum::None,
+                "*".into(),
+            ),
+            FunctionUrlAuth::Iam => (
+                lambda::url::UrlAuthTypeEnum::Awsiam,
+                lambda::permission::PermissionFunctionUrlAuthTypeEnum::Awsiam,
+                StrVal::Val(get_ref("AWS::AccountId")),
+            ),
+        };
+        let cors = match &inp.function_url_cors {
+            Some(cors) => {
+                if cors.allowed_origins.is_empty() {
+                    return Err("function_url_cors must allow at least 1 origin".to_string());
+                }
+                if let Some(method) = cors.allowed_methods.iter().find(|x| !FUNCTION_URL_CORS_METHODS.contains(&x.as_str())) {
+                    return Err(format!("Invalid function_url_cors method '{method}'. Must be one of {:?}", FUNCTION_URL_CORS_METHODS));
+                }
+                let mut out = lambda::url::Cors::default();
+                out.allow_origins = Some(cors.allowed_origins.iter().map(|x| x.clone().into()).collect());
+                if !cors.allowed_methods.is_empty() {
+                    out.allow_methods = Some(cors.allowed_methods.iter().map(|x| x.clone().into()).collect());
+                }
+                if !cors.allowed_headers.is_empty() {
+                    out.allow_headers = Some(cors.allowed_headers.iter().map(|x| x.clone().into()).collect());
+                }
+                Some(out)
+            }
+            None => None,
+        };
+        let func_url = lambda::url::CfnUrl {
+            auth_type,
+            cors,
+            target_function_arn: StrVal::Val(cfn_resources::get_att(logical_fn_name, "Arn")),
+            ..Default::default()
+        };
+        let func_permission = lambda::permission::CfnPermission {
+            action: "lambda:InvokeFunctionUrl".into(),
+            function_name: StrVal::Val(cfn_resources::get_att(logical_fn_name, "Arn")),
+            function_url_auth_type: Some(permission_auth_type),
+            principal,
+            ..Default::default()
+        };
+        Ok((func_url, func_permission))
+    }
 
-        let mut default_statements = vec![
-            ("Allow".to_string(), "logs:CreateLogGroup".to_string(), "*".to_string()),
-            ("Allow".to_string(), "logs:CreateLogStream".to_string(), "*".to_string()),
-            ("Allow".to_string(), "logs:PutLogEvents".to_string(), "*".to_string()),
-        ];
-        if inp.disable_cloudwatch_logging {
-            default_statements.clear();
+    /// sets the runtime of the function according to the input,
+    /// unless the user already set one via `extra_options.runtime`.
+    pub fn apply_runtime(inp: &Input, lambdafn: &mut lambda::function::CfnFunction) {
+        if lambdafn.runtime.is_none() {
+            lambdafn.runtime = Some(inp.lambda_runtime.runtime());
         }
-        default_statements.extend(inp.extra_policy_statements.clone());
+    }
+
+    /// adds the layers of the input to the layers that are already set on the function (ie: via `extra_options`),
+    /// and sets the vpc config of the function according to the input. Like `merge_environment`, settings
+    /// from `extra_options` are not overwritten: returns the names of the input fields that conflicted with them.
+    pub fn apply_layers_and_vpc_config(inp: &Input, lambdafn: &mut lambda::function::CfnFunction) -> Vec<String> {
+        let mut conflicts = vec![];
+        if !inp.layers.is_empty() {
+            let layers = lambdafn.layers.get_or_insert_with(Default::default);
+            for layer in inp.layers.iter() {
+                let exists = layers.iter().any(|x| matches!(x, StrVal::String(existing) if existing == layer));
+                if !exists {
+                    layers.push(layer.clone().into());
+                }
+            }
+        }
+        if let Some(vpc_config) = &inp.vpc_config {
+            if lambdafn.vpc_config.is_some() {
+                conflicts.push("vpc_config".to_string());
+            } else {
+                lambdafn.vpc_config = Some(lambda::function::VpcConfig {
+                    subnet_ids: Some(vpc_config.subnet_ids.iter().map(|x| x.clone().into()).collect()),
+                    security_group_ids: Some(vpc_config.security_group_ids.iter().map(|x| x.clone().into()).collect()),
+                    ..Default::default()
+                });
+            }
+        }
+        conflicts
+    }
+
+    /// validates that `arn` starts with a complete secrets manager secret arn
+    /// (`arn:{partition}:secretsmanager:{region}:{account id}:secret:{name}-{6 random characters}`),
+    /// and returns just that secret arn, without any json key/version suffix.
+    /// partial arns (without the random suffix) are rejected, as cloudformation would
+    /// treat them as the name of a secret that ends in `-{6 characters}`.
+    pub fn get_secret_arn(arn: &str) -> Result<String, String> {
+        let parts: Vec<&str> = arn.split(':').collect();
+        let has_suffix = |name: &str| match name.rsplit_once('-') {
+            Some((prefix, suffix)) => !prefix.is_empty() && suffix.len() == 6 && suffix.chars().all(|c| c.is_ascii_alphanumeric()),
+            None => false,
+        };
+        let is_valid = parts.len() >= 7
+            && parts[0] == "arn"
+            && parts[1].starts_with("aws")
+            && parts[2] == "secretsmanager"
+            && !parts[3].is_empty()
+            && parts[4].len() == 12 && parts[4].chars().all(|c| c.is_ascii_digit())
+            && parts[5] == "secret"
+            && has_suffix(parts[6]);
+        if !is_valid {
+            return Err(format!("Invalid secret arn '{arn}'. Expected the complete arn: arn:aws:secretsmanager:{{region}}:{{account id}}:secret:{{name}}-{{6 characters}}"));
+        }
+        Ok(parts[..7].join(":"))
+    }
+
+    /// the distributions that collect desired paths forward requests to the function url without
+    /// signing them, so a function url that requires IAM auth would reject every request.
+    pub fn validate_desired_path_auth(path: &str, auth: &FunctionUrlAuth) -> Result<(), String> {
+        if *auth == FunctionUrlAuth::Iam {
+            return Err(format!("desired_path '{path}' cannot be used with function_url_auth Iam, as requests forwarded to this path are not signed. Remove desired_path, or use FunctionUrlAuth::None"));
+        }
+        Ok(())
+    }
+
+    /// returns the logical id of the function of `user_mod_name`, and registers it in the shared data.
+    /// logical ids cannot contain underscores, so eg: `my_fn` and `myfn` would collide if they are in the same stack. this is an error.
+    /// `sibling_modules` are the modules processed before this one (see `L0Core::sibling_modules`). A name registered by
+    /// any other module is left over from a previous expansion of the crate (eg: the module was renamed since), so it is replaced.
+    pub fn register_logical_function_name(l0kv: &mut L0KvReader, stack_name: &str, user_mod_name: &str, sibling_modules: &[&str]) -> Result<String, String> {
+        let logical_fn_name = format!("hiragen{user_mod_name}");
+        let logical_fn_name = logical_fn_name.replace("_", "");
+        let key = format!("{LOGICAL_FUNCTION_NAME_KEY_PREFIX}{stack_name}:{logical_fn_name}");
+        if let Some(existing) = l0kv.get(&key) {
+            if existing != user_mod_name && sibling_modules.contains(&existing.as_str()) {
+                return Err(format!("Lambda functions of modules '{existing}' and '{user_mod_name}' would both have the logical name '{logical_fn_name}' in stack '{stack_name}'. Rename one of the modules, or deploy them to different stacks"));
+            }
+        }
+        l0kv.insert(key, user_mod_name.to_string());
+        Ok(logical_fn_name)
+    }
+
+    /// adds the variables to the function's environment. variables that were already set
+    /// (eg: via `extra_options.environment`) are not overwritten. returns the names of those variables, sorted.
+    pub fn merge_environment(lambdafn: &mut lambda::function::CfnFunction, env_variables: std::collections::HashMap<String, String>) -> Vec<String> {
+        let mut conflicts = vec![];
+        if env_variables.is_empty() {
+            return conflicts;
+        }
+        let mut environment = lambdafn.environment.take().unwrap_or_default();
+        let variables = environment.variables.get_or_insert_with(Default::default);
+        for (key, val) in env_variables {
+            if variables.contains_key(&key) {
+                conflicts.push(key);
+                continue;
+            }
+            variables.insert(key, val);
+        }
+        lambdafn.environment = Some(environment);
+        conflicts.sort();
+        conflicts
+    }
+
+    /// returns the environment variables to set on the function, and the policy statements
+    /// needed to read the secrets referenced by the environment.
+    pub fn resolve_environment(environment: &std::collections::HashMap<String, String>) -> Result<(std::collections::HashMap<String, String>, Vec<(String, String, String)>), String> {
+        let mut variables = std::collections::HashMap::new();
+        let mut statements = vec![];
+        for (key, val) in environment {
+            let val = match val.strip_prefix(SECRET_ENV_PREFIX) {
+                Some(arn) => {
+                    let secret_arn = get_secret_arn(arn).map_err(|e| format!("environment variable {key}: {e}"))?;
+                    statements.push(("Allow".to_string(), "secretsmanager:GetSecretValue".to_string(), secret_arn));
+                    format!("{{{{resolve:secretsmanager:{arn}}}}}")
+                }
+                None => val.to_string(),
+            };
+            variables.insert(key.to_string(), val);
+        }
+        // hashmap iteration order is random, sort so the policy doesnt change between builds
+        statements.sort();
+        statements.dedup();
+        Ok((variables, statements))
+    }
 
+    /// returns the logical name of the execution role of the function, and the role itself.
+    /// `iam_name_prefix` is prepended to the role and policy names, but not to the logical name.
+    pub fn create_lambda_role(
+        user_mod_name: &str, statements: &[(String, String, String)],
+        iam_name_prefix: &str, permissions_boundary: Option<String>,
+    ) -> (String, iam::role::CfnRole) {
         let policy = iam::role::Policy {
-            policy_name: format!("hira-gen-policy-{user_mod_name}").into(),
-            policy_document: create_policy_doc(&default_statements),
+            policy_name: format!("{iam_name_prefix}hira-gen-policy-{user_mod_name}").into(),
+            policy_document: create_policy_doc(statements),
         };
         let role_name = format!("hira-gen-{user_mod_name}-role");
         let logical_role_name = role_name.replace("-", "");
         let logical_role_name = logical_role_name.replace("_", "");
-        let logical_fn_name = format!("hiragen{user_mod_name}");
-        let logical_fn_name = logical_fn_name.replace("_", "");
         let role = iam::role::CfnRole {
             description: Some(format!("auto generated for {user_mod_name}").into()),
             assume_role_policy_document: create_assume_role_policy_doc(),
-            role_name: Some(role_name.clone().into()),
+            role_name: Some(format!("{iam_name_prefix}{role_name}").into()),
+            permissions_boundary: permissions_boundary.map(|x| x.into()),
             policies: Some(vec![policy]),
             ..Default::default()
         };
-        let extra_options = std::mem::take(&mut inp.extra_options);
+        (logical_role_name, role)
+    }
 
-        let lambdafn = lambda::function::CfnFunction {
-            architectures: Some(vec![
-                match inp.architecture {
-                    Arch::X86 => "x86_64".to_string(),
-                    Arch::Arm64 => "arm64".to_string(),
-                }
-            ]),
-            code: lambda::function::Code {
-                s3_bucket: BUCKET_UNKNOWN.to_str_val(),
-                s3_key: Some(lambda_executable_path.into()),
-                ..Default::default()
-            },
-            handler: Some("index.handler".into()),
-            role: if inp.role_arn.is_empty() {
-                StrVal::Val(cfn_resources::get_att(&logical_role_name, "Arn"))
-            } else {
-                inp.role_arn.clone().into()
-            },
-            runtime: Some(lambda::function::FunctionRuntimeEnum::Providedal2),
+    /// returns the name of the stack output containing the function's arn, and the output itself.
+    pub fn get_function_arn_output(user_mod_name: &str, logical_fn_name: &str) -> (String, ResourceOutput) {
+        let arn_output_name = format!("LambdaFunctionArn{}", user_mod_name);
+        let arn_output_name = arn_output_name.replace("_", "");
+        let resource_out = ResourceOutput {
+            description: format!("ARN of the lambda function of module '{user_mod_name}'"),
+            value: get_att(logical_fn_name, "Arn")
+        };
+        (arn_output_name, resource_out)
+    }
+
+    /// returns the Errors and Throttles alarms for the given function.
+    /// errors if a threshold is not positive.
+    pub fn create_alarm_resources(inp: &Input, logical_fn_name: &str) -> Result<Vec<aws_cfn_stack::Resource>, String> {
+        let alarms = [
+            ("Errors", inp.error_alarm_threshold.unwrap_or(1.0)),
+            ("Throttles", inp.throttle_alarm_threshold.unwrap_or(1.0)),
+        ];
+        let mut out = vec![];
+        for (metric_name, threshold) in alarms {
+            if threshold.is_nan() || threshold <= 0.0 {
+                return Err(format!("Alarm threshold for {metric_name} must be positive. Instead found {threshold}"));
+            }
+            let alarm = LambdaAlarm {
+                function_logical_id: logical_fn_name.to_string(),
+                metric_name: metric_name.to_string(),
+                threshold,
+                sns_topic_arn: inp.alarm_sns_topic_arn.clone(),
+            };
+            out.push(aws_cfn_stack::Resource {
+                name: format!("{logical_fn_name}{}alarm", metric_name.to_lowercase()),
+                properties: Box::new(alarm) as _,
+            });
+        }
+        Ok(out)
+    }
+
+    /// validates the triggers, and returns the policy statements that allow the function to poll their queues.
+    pub fn get_sqs_trigger_statements(triggers: &[SqsTrigger]) -> Result<Vec<(String, String, String)>, String> {
+        let mut statements = vec![];
+        for trigger in triggers {
+            if trigger.queue_arn.is_empty() {
+                return Err("sqs_triggers must have a queue_arn".to_string());
+            }
+            if let Some(batch_size) = trigger.batch_size {
+                if !(1..=10000).contains(&batch_size) {
+                    return Err(format!("batch_size of the sqs trigger of {} must be between 1 and 10000. Instead found {batch_size}", trigger.queue_arn));
+                }
+                if batch_size > 10 && trigger.maximum_batching_window_seconds.unwrap_or(0) < 1 {
+                    return Err(format!("batch_size of the sqs trigger of {} is greater than 10, which requires a maximum_batching_window_seconds of at least 1", trigger.queue_arn));
+                }
+            }
+            if let Some(window) = trigger.maximum_batching_window_seconds {
+                if window > 300 {
+                    return Err(format!("maximum_batching_window_seconds of the sqs trigger of {} must be between 0 and 300. Instead found {window}", trigger.queue_arn));
+                }
+            }
+            for action in SQS_TRIGGER_ACTIONS {
+                statements.push(("Allow".to_string(), action.to_string(), trigger.queue_arn.clone()));
+            }
+        }
+        Ok(statements)
+    }
+
+    /// returns an event source mapping resource per trigger, which invokes the function with messages from the trigger's queue.
+    pub fn create_sqs_trigger_resources(triggers: &[SqsTrigger], logical_fn_name: &str) -> Vec<aws_cfn_stack::Resource> {
+        triggers.iter().enumerate().map(|(i, trigger)| {
+            let event_source = lambda::event_source_mapping::CfnEventSourceMapping {
+                event_source_arn: trigger.queue_arn.to_str_val(),
+                function_name: StrVal::Val(get_ref(logical_fn_name)),
+                batch_size: trigger.batch_size.map(|x| x as i64),
+                maximum_batching_window_in_seconds: trigger.maximum_batching_window_seconds.map(|x| x as i64),
+                ..Default::default()
+            };
+            aws_cfn_stack::Resource {
+                name: format!("{logical_fn_name}sqstrigger{i}"),
+                properties: Box::new(event_source) as _,
+            }
+        }).collect()
+    }
+
+    /// returns the rule that invokes the function on the input's schedule, and the permission that allows
+    /// the rule to invoke it. Returns nothing if the input has no schedule.
+    pub fn create_schedule_resources(inp: &Input, logical_fn_name: &str) -> Result<Vec<aws_cfn_stack::Resource>, String> {
+        let schedule = match &inp.schedule {
+            Some(s) => s,
+            None => return Ok(vec![]),
+        };
+        let is_valid = (schedule.starts_with("rate(") || schedule.starts_with("cron(")) && schedule.ends_with(')');
+        if !is_valid {
+            return Err(format!("Invalid schedule '{schedule}'. Expected a schedule expression of the form rate(...) or cron(...)"));
+        }
+        let rule_logical_id = format!("{logical_fn_name}schedule");
+        let rule = events::rule::CfnRule {
+            schedule_expression: schedule.to_str_val(),
+            state: Some(events::rule::RuleStateEnum::Enabled),
+            targets: Some(vec![events::rule::Target {
+                arn: StrVal::Val(get_att(logical_fn_name, "Arn")),
+                id: logical_fn_name.into(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+        let permission = lambda::permission::CfnPermission {
+            action: "lambda:InvokeFunction".into(),
+            function_name: StrVal::Val(get_att(logical_fn_name, "Arn")),
+            principal: "events.amazonaws.com".into(),
+            source_arn: Some(StrVal::Val(get_att(&rule_logical_id, "Arn"))),
+            ..Default::default()
+        };
+        Ok(vec![
+            aws_cfn_stack::Resource {
+                name: format!("{rule_logical_id}permission"),
+                properties: Box::new(permission) as _,
+            },
+            aws_cfn_stack::Resource {
+                name: rule_logical_id,
+                properties: Box::new(rule) as _,
+            },
+        ])
+    }
+
+    /// returns the log group that lambda writes the function's logs to, if the input sets a log retention,
+    /// and whether the function should depend on it.
+    /// If the user set the function's name (`extra_options.function_name`) the group is named after it,
+    /// and the function depends on the group, so cloudformation creates the group before the function
+    /// can be invoked and create the group implicitly. Otherwise the function keeps its generated name,
+    /// and the group references it, so the group is created right after the function.
+    pub fn create_log_group_resource(inp: &Input, logical_fn_name: &str, function_name: &Option<StrVal>) -> Result<Option<(aws_cfn_stack::Resource, bool)>, String> {
+        let retention_days = match inp.log_retention_days {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        if !LOG_RETENTION_DAYS.contains(&retention_days) {
+            return Err(format!("log_retention_days must be one of {:?}. Instead found {retention_days}", LOG_RETENTION_DAYS));
+        }
+        let (log_group_name, function_depends_on_group) = match function_name {
+            Some(StrVal::String(name)) => (StrVal::String(format!("/aws/lambda/{name}")), true),
+            // Ref of a function is its name, whether it was generated or not
+            _ => (StrVal::Val(cfn_resources::serde_json::json!({ "Fn::Sub": format!("/aws/lambda/${{{logical_fn_name}}}") })), false),
+        };
+        let log_group = logs::log_group::CfnLogGroup {
+            log_group_name: Some(log_group_name),
+            retention_in_days: Some(retention_days as i64),
+            ..Default::default()
+        };
+        let resource = aws_cfn_stack::Resource {
+            name: format!("{logical_fn_name}loggroup"),
+            properties: Box::new(log_group) as _,
+        };
+        Ok(Some((resource, function_depends_on_group)))
+    }
+
+    /// returns the logical id of the in-stack artifact bucket if the given
+    /// bucket name is a placeholder for one.
+    pub fn get_in_stack_bucket(bucket_name: &str) -> Option<&str> {
+        bucket_name.strip_prefix(IN_STACK_BUCKET_PREFIX)
+    }
+
+    /// adds an artifact bucket (and an output of its name) to the stack, unless
+    /// another function already added one to the same stack. returns the placeholder
+    /// that should be used as the function's bucket.
+    pub fn add_in_stack_artifact_bucket(stackinp: &mut aws_cfn_stack::Input, l0kv: &mut L0KvReader) -> String {
+        let key = format!("{ARTIFACT_BUCKET_KEY_PREFIX}{}", stackinp.stack_name);
+        let already_added = l0kv.get(&key).is_some()
+            || stackinp.resources.iter().any(|x| x.name == ARTIFACT_BUCKET_LOGICAL_NAME);
+        if !already_added {
+            l0kv.insert(key, ARTIFACT_BUCKET_LOGICAL_NAME.to_string());
+            stackinp.resources.push(aws_cfn_stack::Resource {
+                name: ARTIFACT_BUCKET_LOGICAL_NAME.to_string(),
+                properties: Box::new(s3::bucket::CfnBucket::default()) as _,
+            });
+            stackinp.outputs.insert(ARTIFACT_BUCKET_OUTPUT_NAME.to_string(), ResourceOutput {
+                description: "name of the artifact bucket of this stack".to_string(),
+                value: get_ref(ARTIFACT_BUCKET_LOGICAL_NAME),
+            });
+        }
+        format!("{IN_STACK_BUCKET_PREFIX}{ARTIFACT_BUCKET_LOGICAL_NAME}")
+    }
+
+    pub fn config(
+        inp: &mut Input, stackinp: &mut aws_cfn_stack::Input, l0code: &mut L0CodeReader,
+        runtimer: &mut L0RuntimeCreator, l0core: &mut L0Core, l0write: &mut L0CodeWriter,
+        l0kv: &mut L0KvReader,
+    ) {
+        let user_mod_name = l0core.users_module_name();
+        let lambda_main_signature = match l0code.get_fn("lambda_main") {
+            Some(sig) => sig,
+            None => {
+                let span = l0core.module_span_info();
+                let location = if span.file.is_empty() {
+                    format!("line {}", span.line)
+                } else {
+                    format!("{}:{}", span.file, span.line)
+                };
+                l0core.compiler_error(&format!("Missing lambda_main function in module '{user_mod_name}' ({location}). Add a `pub fn lambda_main(...)` inside of your module"));
+                return;
+            }
+        };
+        let mut warning = None;
+        let (return_statement, return_ty, input_param_type) = match validate_lambda_main_signature(lambda_main_signature, &mut inp.use_function_url, &mut warning) {
+            Err(e) => {
+                l0core.compiler_error(&e);
+                return;
+            }
+            Ok(p) => p,
+        };
+        if let Some(warning) = warning {
+            l0core.compiler_warning(&format!("lambda_main in module '{user_mod_name}': {warning}"));
+        }
+
+        // Box<dyn std::error::Error + Send + Sync + 'static> = BoxError
+        l0write.write_internal_fn(
+            format!("pub async fn service_func(event: lambda_runtime::LambdaEvent<{}>) -> {}", input_param_type, return_ty),
+            format!("let (x, _context) = event.into_parts(); {}", return_statement)
+        );
+        l0write.write_internal_fn(
+            format!("pub async fn entrypoint() -> Result<(), ::aws_lambda::h_aws_lambda::BoxError>"),
+            format!("let func = lambda_runtime::service_fn(service_func);\nlambda_runtime::run(func).await?;\nOk(())")
+        );
+        runtimer.add_to_runtime_ex(
+            &user_mod_name,
+            format!("{user_mod_name}::entrypoint().await.expect(\"Lambda Error\")"),
+            get_runtime_meta(inp, &user_mod_name),
+        );
+        runtimer.depends_on(&user_mod_name, "deploy");
+        let lambda_executable_path = runtimer.get_full_runtime_path(&user_mod_name);
+
+        let mut default_statements = vec![
+            ("Allow".to_string(), "logs:CreateLogGroup".to_string(), "*".to_string()),
+            ("Allow".to_string(), "logs:CreateLogStream".to_string(), "*".to_string()),
+            ("Allow".to_string(), "logs:PutLogEvents".to_string(), "*".to_string()),
+        ];
+        if inp.disable_cloudwatch_logging {
+            default_statements.clear();
+        }
+        default_statements.extend(inp.extra_policy_statements.clone());
+        let (env_variables, secret_statements) = match resolve_environment(&inp.environment) {
+            Ok(x) => x,
+            Err(e) => {
+                l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+                return;
+            }
+        };
+        default_statements.extend(secret_statements);
+        match get_sqs_trigger_statements(&inp.sqs_triggers) {
+            Ok(statements) => default_statements.extend(statements),
+            Err(e) => {
+                l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+                return;
+            }
+        }
+        match get_vpc_statements(&inp.vpc_config) {
+            Ok(statements) => default_statements.extend(statements),
+            Err(e) => {
+                l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+                return;
+            }
+        }
+
+        let (logical_role_name, role) = create_lambda_role(&user_mod_name, &default_statements, &l0core.iam_name_prefix(), l0core.iam_permissions_boundary());
+        let full_stack_name = aws_cfn_stack::get_full_stack_name(&stackinp.stack_name, &l0core.stack_name_prefix());
+        let logical_fn_name = match register_logical_function_name(l0kv, &full_stack_name, &user_mod_name, &l0core.sibling_modules()) {
+            Ok(x) => x,
+            Err(e) => {
+                l0core.compiler_error(&e);
+                return;
+            }
+        };
+        let extra_options = std::mem::take(&mut inp.extra_options);
+        let artifact_bucket = if inp.artifact_bucket_in_stack {
+            add_in_stack_artifact_bucket(stackinp, l0kv)
+        } else {
+            BUCKET_UNKNOWN.to_string()
+        };
+
+        let mut lambdafn = lambda::function::CfnFunction {
+            architectures: Some(vec![
+                match inp.architecture {
+                    Arch::X86 => "x86_64".to_string(),
+                    Arch::Arm64 => "arm64".to_string(),
+                }
+            ]),
+            code: lambda::function::Code {
+                s3_bucket: artifact_bucket.to_str_val(),
+                s3_key: Some(lambda_executable_path.into()),
+                ..Default::default()
+            },
+            handler: Some("index.handler".into()),
+            role: if inp.role_arn.is_empty() {
+                StrVal::Val(cfn_resources::get_att(&logical_role_name, "Arn"))
+            } else {
+                inp.role_arn.clone().into()
+            },
             ..extra_options
         };
+        apply_runtime(inp, &mut lambdafn);
+        let conflicts = merge_environment(&mut lambdafn, env_variables);
+        if !conflicts.is_empty() {
+            l0core.compiler_warning(&format!("Lambda function {user_mod_name}: environment variables {:?} are set in both environment and extra_options.environment. The values from extra_options are used", conflicts));
+        }
+        let conflicts = apply_layers_and_vpc_config(inp, &mut lambdafn);
+        if !conflicts.is_empty() {
+            l0core.compiler_warning(&format!("Lambda function {user_mod_name}: {:?} are set in both the input and extra_options. The values from extra_options are used", conflicts));
+        }
+        if let Err(e) = apply_function_limits(inp, &mut lambdafn) {
+            l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+            return;
+        }
+        // the deploy runtime zips the function's code, so the zip level is passed to it in the resource's metadata
+        if let Some(zip_level) = get_runtime_meta(inp, &user_mod_name).zip_level {
+            if let Err(e) = ZipLevel::parse(&zip_level) {
+                l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+                return;
+            }
+            stackinp.add_resource_metadata(&logical_fn_name, ZIP_LEVEL_METADATA_KEY, &zip_level);
+        }
         l0core.set_output("LOGICAL_FUNCTION_NAME", &logical_fn_name);
 
+        if inp.disable_cloudwatch_logging {
+            if inp.log_retention_days.is_some() {
+                l0core.compiler_warning(&format!("Lambda function {user_mod_name} set log_retention_days, but cloudwatch logging is disabled. log_retention_days will be ignored"));
+            }
+        } else {
+            match create_log_group_resource(inp, &logical_fn_name, &lambdafn.function_name) {
+                Ok(Some((log_group, function_depends_on_group))) => {
+                    if function_depends_on_group {
+                        stackinp.add_depends_on(&logical_fn_name, &log_group.name);
+                    }
+                    stackinp.resources.push(log_group);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+                    return;
+                }
+            }
+        }
+
         let resource = aws_cfn_stack::Resource {
             name: logical_fn_name.clone(),
             properties: Box::new(lambdafn) as _,
@@ -485,26 +1500,39 @@ pub mod h_aws_lambda {
         if inp.role_arn.is_empty() {
             stackinp.resources.push(role_resource);
         }
-        let arn_output_name = format!("LambdaFunctionArn{}", user_mod_name);
-        let arn_output_name = arn_output_name.replace("_", "");
-        let resource_out = ResourceOutput {
-            description: "".to_string(),
-            value: get_att(&logical_fn_name, "Arn")
-        };
+        let (arn_output_name, resource_out) = get_function_arn_output(&user_mod_name, &logical_fn_name);
         stackinp.outputs.insert(arn_output_name, resource_out);
 
+        if let Some(provisioned_concurrency) = inp.provisioned_concurrency {
+            stackinp.resources.extend(create_provisioned_alias_resources(&logical_fn_name, provisioned_concurrency));
+        }
+
+        stackinp.resources.extend(create_sqs_trigger_resources(&inp.sqs_triggers, &logical_fn_name));
+        match create_schedule_resources(inp, &logical_fn_name) {
+            Ok(resources) => stackinp.resources.extend(resources),
+            Err(e) => {
+                l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+                return;
+            }
+        }
+
+        if inp.create_alarms {
+            match create_alarm_resources(inp, &logical_fn_name) {
+                Ok(alarms) => stackinp.resources.extend(alarms),
+                Err(e) => {
+                    l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+                    return;
+                }
+            }
+        }
+
         if inp.use_function_url {
-            let func_url = lambda::url::CfnUrl {
-                auth_type: lambda::url::UrlAuthTypeEnum::None,
-                target_function_arn: StrVal::Val(cfn_resources::get_att(&logical_fn_name, "Arn")),
-                ..Default::default()
-            };
-            let func_permission = lambda::permission::CfnPermission {
-                action: "lambda:InvokeFunctionUrl".into(),
-                function_name: StrVal::Val(cfn_resources::get_att(&logical_fn_name, "Arn")),
-                function_url_auth_type: Some(lambda::permission::PermissionFunctionUrlAuthTypeEnum::None),
-                principal: "*".into(),
-                ..Default::default()
+            let (func_url, func_permission) = match create_function_url(inp, &logical_fn_name) {
+                Ok(x) => x,
+                Err(e) => {
+                    l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+                    return;
+                }
             };
             let logical_url_name = format!("hiragen{user_mod_name}url");
             let logical_url_name = logical_url_name.replace("_", "");
@@ -523,12 +1551,899 @@ pub mod h_aws_lambda {
             let arn_output_name = format!("LambdaFunctionUrl{}", user_mod_name);
             let arn_output_name = arn_output_name.replace("_", "");
             let resource_out = ResourceOutput {
-                description: "".to_string(),
+                description: format!("Function URL of the lambda function of module '{user_mod_name}'"),
                 value: get_att(&logical_url_name, "FunctionUrl")
             };
             stackinp.outputs.insert(arn_output_name, resource_out);
 
             l0core.set_output("LOGICAL_FUNCTION_URL_NAME", &logical_url_name);
+
+            if let Some(path) = &inp.desired_path {
+                if let Err(e) = validate_desired_path_auth(path, &inp.function_url_auth) {
+                    l0core.compiler_error(&format!("Lambda function {user_mod_name}: {e}"));
+                    return;
+                }
+                let key = format!("{DESIRED_PATH_KEY_PREFIX}{path}");
+                if let Some(existing) = l0kv.get(&key) {
+                    if existing != logical_url_name {
+                        l0core.compiler_error(&format!("Lambda function {user_mod_name} requested desired_path '{path}', but this path is already used by {existing}. Paths must be unique"));
+                        return;
+                    }
+                }
+                l0kv.insert(key, logical_url_name);
+            }
+        } else if inp.desired_path.is_some() {
+            l0core.compiler_warning(&format!("Lambda function {user_mod_name} set a desired_path, but it does not use a function url. desired_path will be ignored"));
+        }
+        if !inp.use_function_url && (inp.function_url_cors.is_some() || inp.function_url_auth != FunctionUrlAuth::None) {
+            l0core.compiler_warning(&format!("Lambda function {user_mod_name} set function_url_auth or function_url_cors, but it does not use a function url. They will be ignored"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hira_lib::parsing::parse_fn_signature_from_string;
+    use hira_lib::e2e::{assert_contains_str, e2e_module2_run_with_token_stream, get_hira_module_source};
+    use super::*;
+
+    /// expands the aws_cfn_stack and h_aws_lambda modules, followed by `lvl3_modules`.
+    /// returns the output of the last module.
+    fn e2e_lambda_run(lvl3_modules: &[&str]) -> String {
+        let stack_module = get_hira_module_source(include_str!("../../aws_cfn_stack/src/lib.rs"), "aws_cfn_stack");
+        let lambda_module = get_hira_module_source(include_str!("lib.rs"), "h_aws_lambda");
+        let mut code = vec![stack_module.as_str(), lambda_module.as_str()];
+        code.extend(lvl3_modules);
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to expand modules");
+        stream.to_string()
+    }
+
+    /// a lvl3 function url lambda that returns `return_ty`
+    fn function_url_module(name: &str, return_ty: &str) -> String {
+        format!(r#"
+            pub mod {name} {{
+                use super::h_aws_lambda;
+                pub fn config(input: &mut h_aws_lambda::Input) {{}}
+                pub fn lambda_main(a: h_aws_lambda::FunctionUrlEvent) -> {return_ty} {{
+                    todo!()
+                }}
+            }}
+        "#)
+    }
+
+    #[cfg(feature = "web")]
+    #[test]
+    fn input_schema_contains_the_allowed_arch_values() {
+        let schema = serde_json::to_value(h_aws_lambda::schema_for_input()).expect("Failed to serialize schema");
+        // documented fields wrap the reference in an allOf, so that they can have a description
+        assert!(schema["properties"]["architecture"].to_string().contains("\"#/definitions/Arch\""));
+        assert_eq!(schema["definitions"]["Arch"]["type"], "string");
+        assert_eq!(schema["definitions"]["Arch"]["enum"], serde_json::json!(["Arm64", "X86"]));
+    }
+
+    #[test]
+    fn zip_levels_produce_the_same_bootstrap() {
+        use std::io::Read;
+        let data = "fn main() { println!(\"hello\"); }\n".repeat(1000);
+        let mut sizes = vec![];
+        for level in ["stored", "deflated", "0", "1", "9"] {
+            let level = ZipLevel::parse(level).expect("valid zip level");
+            let zipped = create_zip_archive(data.as_bytes(), level).expect("Failed to create zip");
+            sizes.push(zipped.len());
+            let mut archive = zip::ZipArchive::new(Cursor::new(zipped)).expect("Failed to read zip");
+            let mut bootstrap = archive.by_name("bootstrap").expect("Missing bootstrap");
+            assert_eq!(bootstrap.unix_mode().map(|x| x & 0o777), Some(0o755));
+            let mut contents = String::new();
+            bootstrap.read_to_string(&mut contents).expect("Failed to read bootstrap");
+            assert_eq!(contents, data);
+        }
+        // stored is larger than deflated
+        assert!(sizes[0] > sizes[1]);
+        assert!(sizes[0] > sizes[4]);
+
+        assert_eq!(ZipLevel::parse("7"), Ok(ZipLevel::Deflated(Some(7))));
+        assert!(ZipLevel::parse("10").is_err());
+        assert!(ZipLevel::parse("-1").is_err());
+        assert!(ZipLevel::parse("bzip2").is_err());
+    }
+
+    fn lambda_main_sig(input_ty: &str, return_ty: &str) -> FunctionSignature {
+        parse_fn_signature_from_string(format!("pub fn lambda_main(a: {input_ty}) -> {return_ty} {{ todo!() }}"))
+            .expect("Failed to parse lambda_main signature")
+    }
+
+    fn statement(effect: &str, action: &str, resource: &str) -> (String, String, String) {
+        (effect.to_string(), action.to_string(), resource.to_string())
+    }
+
+    #[test]
+    fn policy_statements_are_grouped() {
+        let statements = [
+            statement("Allow", "s3:GetObject", "arn:a"),
+            statement("Allow", "s3:PutObject", "arn:a"),
+            statement("Allow", "s3:GetObject", "arn:b"),
+            statement("Allow", "s3:PutObject", "arn:b"),
+            statement("Allow", "s3:GetObject", "arn:b"),
+            statement("Deny", "s3:PutObject", "arn:c"),
+            statement("Allow", "logs:PutLogEvents", "arn:d"),
+        ];
+        let grouped = h_aws_lambda::group_policy_statements(&statements);
+        assert_eq!(grouped, vec![
+            ("Allow".to_string(), vec!["s3:GetObject".to_string(), "s3:PutObject".to_string()], vec!["arn:a".to_string(), "arn:b".to_string()]),
+            ("Deny".to_string(), vec!["s3:PutObject".to_string()], vec!["arn:c".to_string()]),
+            ("Allow".to_string(), vec!["logs:PutLogEvents".to_string()], vec!["arn:d".to_string()]),
+        ]);
+
+        let doc = h_aws_lambda::create_policy_doc(&statements);
+        let doc_statements = doc["Statement"].as_array().expect("Statement should be an array");
+        assert_eq!(doc_statements.len(), 3);
+        assert_eq!(doc_statements[0]["Action"], cfn_resources::serde_json::json!(["s3:GetObject", "s3:PutObject"]));
+        assert_eq!(doc_statements[0]["Resource"], cfn_resources::serde_json::json!(["arn:a", "arn:b"]));
+        assert_eq!(doc_statements[1]["Action"], cfn_resources::serde_json::json!("s3:PutObject"));
+    }
+
+    #[test]
+    fn policy_statements_with_different_actions_per_resource_arent_merged() {
+        // merging these would grant s3:PutObject on arn:a
+        let statements = [
+            statement("Allow", "s3:GetObject", "arn:a"),
+            statement("Allow", "s3:GetObject", "arn:b"),
+            statement("Allow", "s3:PutObject", "arn:b"),
+        ];
+        let grouped = h_aws_lambda::group_policy_statements(&statements);
+        assert_eq!(grouped.len(), 2);
+        assert_eq!(grouped[0].2, vec!["arn:a".to_string()]);
+        assert_eq!(grouped[1].1, vec!["s3:GetObject".to_string(), "s3:PutObject".to_string()]);
+    }
+
+    #[test]
+    fn ephemeral_storage_must_be_in_range() {
+        for (mb, is_valid) in [(511, false), (512, true), (2048, true), (10240, true), (10241, false)] {
+            let inp = h_aws_lambda::Input { ephemeral_storage_mb: Some(mb), ..Default::default() };
+            let mut lambdafn = lambda::function::CfnFunction::default();
+            let res = h_aws_lambda::apply_function_limits(&inp, &mut lambdafn);
+            assert_eq!(res.is_ok(), is_valid, "{mb}MB");
+            if is_valid {
+                assert_eq!(lambdafn.properties()["EphemeralStorage"]["Size"], mb);
+            }
+        }
+    }
+
+    #[test]
+    fn reserved_concurrency_is_set_on_function() {
+        let inp = h_aws_lambda::Input { reserved_concurrency: Some(5), ..Default::default() };
+        let mut lambdafn = lambda::function::CfnFunction::default();
+        h_aws_lambda::apply_function_limits(&inp, &mut lambdafn).expect("Failed to apply limits");
+        assert_eq!(lambdafn.reserved_concurrent_executions, Some(5));
+        assert_eq!(lambdafn.properties()["ReservedConcurrentExecutions"], 5);
+
+        let inp = h_aws_lambda::Input { reserved_concurrency: Some(5), provisioned_concurrency: Some(6), ..Default::default() };
+        assert!(h_aws_lambda::apply_function_limits(&inp, &mut lambdafn).is_err());
+        let inp = h_aws_lambda::Input { reserved_concurrency: Some(-1), ..Default::default() };
+        assert!(h_aws_lambda::apply_function_limits(&inp, &mut lambdafn).is_err());
+    }
+
+    #[test]
+    fn provisioned_concurrency_is_set_on_an_alias() {
+        let resources = h_aws_lambda::create_provisioned_alias_resources("hiragenmyfn", 3);
+        assert_eq!(resources.len(), 2);
+        let (version, alias) = (&resources[0], &resources[1]);
+        assert_eq!(version.name, "hiragenmyfnversion");
+        assert_eq!(version.properties.type_string(), "AWS::Lambda::Version");
+        assert_eq!(version.properties.properties()["FunctionName"], cfn_resources::serde_json::json!({ "Ref": "hiragenmyfn" }));
+        assert_eq!(alias.name, "hiragenmyfnalias");
+        assert_eq!(alias.properties.type_string(), "AWS::Lambda::Alias");
+        let props = alias.properties.properties();
+        assert_eq!(props["FunctionName"], cfn_resources::serde_json::json!({ "Ref": "hiragenmyfn" }));
+        assert_eq!(props["FunctionVersion"], cfn_resources::serde_json::json!({ "Fn::GetAtt": ["hiragenmyfnversion", "Version"] }));
+        assert_eq!(props["Name"], h_aws_lambda::PROVISIONED_ALIAS_NAME);
+        assert_eq!(props["ProvisionedConcurrencyConfig"]["ProvisionedConcurrentExecutions"], 3);
+    }
+
+    #[test]
+    fn versions_are_republished_when_code_changes() {
+        let resources = h_aws_lambda::create_provisioned_alias_resources("hiragenmyfn", 1);
+        let version = &resources[0].properties;
+        let get_template = |code_key: Option<&str>| {
+            let mut template = SavedTemplate::default();
+            template.resources.insert("hiragenmyfnversion".to_string(), SavedResource {
+                ty: version.type_string().to_string(),
+                properties: version.properties(),
+                metadata: None,
+                depends_on: vec![],
+            });
+            if let Some(key) = code_key {
+                template.resources.insert("hiragenmyfn".to_string(), SavedResource {
+                    ty: "AWS::Lambda::Function".to_string(),
+                    properties: cfn_resources::serde_json::json!({ "Code": { "S3Bucket": "bucket", "S3Key": key } }),
+                    metadata: None,
+                    depends_on: vec![],
+                });
+            }
+            set_version_descriptions(&mut template);
+            template.resources["hiragenmyfnversion"].properties.get("Description").cloned()
+        };
+        let first = get_template(Some("myfn-ABC.zip")).expect("Expected a description");
+        assert_eq!(Some(&first), get_template(Some("myfn-ABC.zip")).as_ref());
+        // new code means a new description, which makes cloudformation publish a new version
+        assert_ne!(Some(&first), get_template(Some("myfn-DEF.zip")).as_ref());
+        // versions of functions that aren't in the template are left as is
+        assert!(get_template(None).is_none());
+    }
+
+    #[test]
+    fn alarms_are_created_for_function() {
+        let inp = h_aws_lambda::Input {
+            create_alarms: true,
+            throttle_alarm_threshold: Some(5.0),
+            alarm_sns_topic_arn: Some("arn:aws:sns:us-east-1:123:topic".to_string()),
+            ..Default::default()
+        };
+        let alarms = h_aws_lambda::create_alarm_resources(&inp, "hiragenmyfn").expect("Failed to create alarms");
+        assert_eq!(alarms.len(), 2);
+        assert_eq!(alarms[0].name, "hiragenmyfnerrorsalarm");
+        assert_eq!(alarms[1].name, "hiragenmyfnthrottlesalarm");
+        for (alarm, metric, threshold) in [(&alarms[0], "Errors", 1.0), (&alarms[1], "Throttles", 5.0)] {
+            assert_eq!(alarm.properties.type_string(), "AWS::CloudWatch::Alarm");
+            let props = alarm.properties.properties();
+            assert_eq!(props["MetricName"], metric);
+            assert_eq!(props["Threshold"], threshold);
+            assert_eq!(props["Dimensions"], cfn_resources::serde_json::json!([
+                { "Name": "FunctionName", "Value": { "Ref": "hiragenmyfn" } }
+            ]));
+            assert_eq!(props["AlarmActions"], cfn_resources::serde_json::json!(["arn:aws:sns:us-east-1:123:topic"]));
+        }
+    }
+
+    #[test]
+    fn in_stack_artifact_bucket_is_added_once_and_used_for_code() {
+        let mut stackinp = aws_cfn_stack::Input::default();
+        let mut kv = L0KvReader::new();
+        let placeholder = h_aws_lambda::add_in_stack_artifact_bucket(&mut stackinp, &mut kv);
+        // a second function in the same stack reuses the same bucket
+        assert_eq!(placeholder, h_aws_lambda::add_in_stack_artifact_bucket(&mut stackinp, &mut kv));
+        assert_eq!(stackinp.resources.len(), 1);
+        assert_eq!(stackinp.resources[0].name, h_aws_lambda::ARTIFACT_BUCKET_LOGICAL_NAME);
+        assert_eq!(stackinp.resources[0].properties.type_string(), "AWS::S3::Bucket");
+        assert!(stackinp.outputs.contains_key(h_aws_lambda::ARTIFACT_BUCKET_OUTPUT_NAME));
+
+        let bucket_logical_name = h_aws_lambda::get_in_stack_bucket(&placeholder).expect("Expected an in-stack bucket");
+        assert_eq!(bucket_logical_name, h_aws_lambda::ARTIFACT_BUCKET_LOGICAL_NAME);
+        assert!(h_aws_lambda::get_in_stack_bucket(h_aws_lambda::BUCKET_UNKNOWN).is_none());
+
+        let mut resource = SavedResource {
+            ty: "AWS::Lambda::Function".to_string(),
+            properties: cfn_resources::serde_json::json!({ "Code": { "S3Bucket": placeholder, "S3Key": "target/myfn" } }),
+            metadata: None,
+            depends_on: vec![],
+        };
+        let (bucket, key) = get_function_code_location(&resource).expect("Expected code location");
+        assert_eq!(bucket, placeholder);
+        assert_eq!(key, "target/myfn");
+        reinsert_value(&mut resource, get_ref(bucket_logical_name), "myfn-ABC.zip".to_string());
+        assert_eq!(resource.properties["Code"], cfn_resources::serde_json::json!({
+            "S3Bucket": { "Ref": h_aws_lambda::ARTIFACT_BUCKET_LOGICAL_NAME },
+            "S3Key": "myfn-ABC.zip",
+        }));
+    }
+
+    #[test]
+    fn iam_prefix_and_permissions_boundary_are_set_on_role() {
+        let statements = vec![statement("Allow", "s3:GetObject", "*")];
+        let boundary = "arn:aws:iam::123:policy/boundary".to_string();
+        let (logical_name, role) = h_aws_lambda::create_lambda_role("my_fn", &statements, "team-", Some(boundary.clone()));
+        assert_eq!(logical_name, "hiragenmyfnrole");
+        let props = role.properties();
+        assert_eq!(props["RoleName"], "team-hira-gen-my_fn-role");
+        assert_eq!(props["PermissionsBoundary"], boundary.as_str());
+        assert_eq!(props["Policies"][0]["PolicyName"], "team-hira-gen-policy-my_fn");
+
+        let (_, role) = h_aws_lambda::create_lambda_role("my_fn", &statements, "", None);
+        let props = role.properties();
+        assert_eq!(props["RoleName"], "hira-gen-my_fn-role");
+        assert!(props.get("PermissionsBoundary").is_none());
+    }
+
+    #[test]
+    fn zip_level_is_passed_to_deploy_in_the_function_metadata() {
+        let mut template = aws_cfn_stack::SavedTemplate::default();
+        let resource = SavedResource { ty: "AWS::Lambda::Function".to_string(), ..Default::default() };
+        template.resources.insert("myfn".to_string(), resource);
+        assert_eq!(get_function_zip_level(&template.resources["myfn"]), None);
+        let metadata = [("myfn".to_string(), h_aws_lambda::ZIP_LEVEL_METADATA_KEY.to_string(), "stored".to_string())];
+        aws_cfn_stack::apply_resource_metadata(&mut template, &metadata).expect("Failed to apply metadata");
+        assert_eq!(get_function_zip_level(&template.resources["myfn"]), Some("stored"));
+        let metadata = [("other".to_string(), h_aws_lambda::ZIP_LEVEL_METADATA_KEY.to_string(), "stored".to_string())];
+        assert!(aws_cfn_stack::apply_resource_metadata(&mut template, &metadata).is_err());
+
+        let mut inp = h_aws_lambda::Input::default();
+        assert_eq!(h_aws_lambda::get_runtime_meta(&inp, "myfn").zip_level, None);
+        inp.zip_level = Some("9".to_string());
+        assert_eq!(h_aws_lambda::get_runtime_meta(&inp, "myfn").zip_level.as_deref(), Some("9"));
+    }
+
+    #[test]
+    fn e2e_invalid_zip_level_is_an_error() {
+        let lambda_module = |zip_level: &str| format!(r#"
+            pub mod my_fn {{
+                use super::h_aws_lambda;
+                pub fn config(input: &mut h_aws_lambda::Input) {{
+                    input.zip_level = Some("{zip_level}".to_string());
+                }}
+                pub fn lambda_main(a: String) -> String {{
+                    todo!()
+                }}
+            }}
+        "#);
+        let out = e2e_lambda_run(&[&lambda_module("10")]);
+        assert_contains_str(out, "Lambda function my_fn: Invalid zip level '10'");
+        let out = e2e_lambda_run(&[&lambda_module("stored")]);
+        assert!(!out.contains("Invalid zip level"));
+    }
+
+    #[test]
+    fn e2e_lambda_url_distribution_collects_desired_paths() {
+        let stack_module = get_hira_module_source(include_str!("../../aws_cfn_stack/src/lib.rs"), "aws_cfn_stack");
+        let lambda_module = get_hira_module_source(include_str!("lib.rs"), "h_aws_lambda");
+        let distr_module = get_hira_module_source(include_str!("../../aws_cloudfront_distribution/src/lib.rs"), "aws_cloudfront_distribution");
+        let url_distr_module = get_hira_module_source(include_str!("../../aws_cloudfront_distribution/src/lambda_url_distribution.rs"), "lambda_url_distribution");
+        let url_fn = |name: &str, path: &str| format!(r#"
+            pub mod {name} {{
+                use super::h_aws_lambda;
+                pub fn config(input: &mut h_aws_lambda::Input) {{
+                    input.desired_path = Some("{path}".to_string());
+                }}
+                pub fn lambda_main(a: h_aws_lambda::FunctionUrlEvent) -> String {{
+                    todo!()
+                }}
+            }}
+        "#);
+        let my_distr = stringify!(
+            pub mod my_distr {
+                use super::lambda_url_distribution;
+                pub fn config(input: &mut lambda_url_distribution::Input) {}
+            }
+        );
+        let root_fn = url_fn("root_fn", "/");
+        let api_fn = url_fn("api_fn", "/api");
+        let code = [stack_module.as_str(), lambda_module.as_str(), distr_module.as_str(), url_distr_module.as_str(), root_fn.as_str(), api_fn.as_str(), my_distr];
+        let (conf, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to expand modules");
+        assert!(!stream.to_string().contains("compile_error"), "{}", stream);
+        // the distribution's origins point to the url of each function
+        let deploy_data = conf.runtimes["deploy"].3.join("\n");
+        assert_contains_str(&deploy_data, "hiragenrootfnurl");
+        assert_contains_str(&deploy_data, "hiragenapifnurl");
+        assert_contains_str(&deploy_data, "/api");
+
+        // without a function registered at "/", the distribution has no default endpoint
+        let code = [stack_module.as_str(), lambda_module.as_str(), distr_module.as_str(), url_distr_module.as_str(), api_fn.as_str(), my_distr];
+        let (_, stream) = e2e_module2_run_with_token_stream(&code, |_| {}).expect("Failed to expand modules");
+        assert_contains_str(stream.to_string(), "Lambda API distribution missing a default endpoint");
+    }
+
+    #[test]
+    fn e2e_colliding_logical_function_names_are_errors() {
+        let lambda_module = |name: &str| format!(r#"
+            pub mod {name} {{
+                use super::h_aws_lambda;
+                pub fn config(input: &mut h_aws_lambda::Input) {{}}
+                pub fn lambda_main(a: String) -> String {{
+                    todo!()
+                }}
+            }}
+        "#);
+        let out = e2e_lambda_run(&[&lambda_module("my_fn"), &lambda_module("myfn")]);
+        assert_contains_str(out, "Lambda functions of modules 'my_fn' and 'myfn' would both have the logical name 'hiragenmyfn' in stack 'hira-gen-default-stack'");
+        let out = e2e_lambda_run(&[&lambda_module("my_fn"), &lambda_module("my_other_fn")]);
+        assert!(!out.contains("would both have the logical name"));
+    }
+
+    #[test]
+    fn colliding_logical_function_names_are_detected() {
+        let mut kv = L0KvReader::new();
+        let register = |kv: &mut L0KvReader, stack: &str, name: &str, siblings: &[&str]| {
+            h_aws_lambda::register_logical_function_name(kv, stack, name, siblings)
+        };
+        assert_eq!(register(&mut kv, "app", "my_fn", &[]), Ok("hiragenmyfn".to_string()));
+        // the same module can be evaluated more than once
+        assert_eq!(register(&mut kv, "app", "my_fn", &["my_fn"]), Ok("hiragenmyfn".to_string()));
+        assert!(register(&mut kv, "app", "other_fn", &["my_fn"]).is_ok());
+        let err = register(&mut kv, "app", "myfn", &["my_fn", "other_fn"]).expect_err("Expected a collision");
+        assert!(err.contains("'my_fn'"));
+        assert!(err.contains("'myfn'"));
+        assert!(err.contains("hiragenmyfn"));
+        assert!(err.contains("'app'"));
+        // functions in different stacks are different resources
+        assert_eq!(register(&mut kv, "other-app", "myfn", &["my_fn", "other_fn"]), Ok("hiragenmyfn".to_string()));
+    }
+
+    #[test]
+    fn logical_function_names_are_checked_against_the_current_expansion() {
+        // the kv data outlives an expansion of the crate. simulate an IDE expanding the crate,
+        // the user renaming `my_fn` to `myfn`, and the crate being expanded again
+        let mut kv = L0KvReader::new();
+        let first_pass = ["my_fn", "other_fn"];
+        for (i, name) in first_pass.iter().enumerate() {
+            h_aws_lambda::register_logical_function_name(&mut kv, "app", name, &first_pass[..i])
+                .expect("first expansion should not collide");
+        }
+        let second_pass = ["myfn", "other_fn"];
+        for (i, name) in second_pass.iter().enumerate() {
+            h_aws_lambda::register_logical_function_name(&mut kv, "app", name, &second_pass[..i])
+                .expect("modules from a previous expansion should not collide");
+        }
+        // but the renamed module now owns the name
+        let err = h_aws_lambda::register_logical_function_name(&mut kv, "app", "my_fn", &second_pass)
+            .expect_err("Expected a collision");
+        assert!(err.contains("'myfn' and 'my_fn'"));
+    }
+
+    #[test]
+    fn desired_path_requires_function_url_without_iam_auth() {
+        assert!(h_aws_lambda::validate_desired_path_auth("/api", &h_aws_lambda::FunctionUrlAuth::None).is_ok());
+        let err = h_aws_lambda::validate_desired_path_auth("/api", &h_aws_lambda::FunctionUrlAuth::Iam)
+            .expect_err("Expected Iam auth to be rejected");
+        assert!(err.contains("'/api'"));
+    }
+
+    #[test]
+    fn environment_is_merged_into_function_properties() {
+        let mut lambdafn = lambda::function::CfnFunction::default();
+        let mut extra = std::collections::HashMap::new();
+        extra.insert("FROM_EXTRA".to_string(), "extra".to_string());
+        extra.insert("SHARED".to_string(), "extra".to_string());
+        lambdafn.environment = Some(lambda::function::Environment { variables: Some(extra) });
+
+        let mut environment = std::collections::HashMap::new();
+        environment.insert("TABLE_NAME".to_string(), "mytable".to_string());
+        environment.insert("LOG_LEVEL".to_string(), "debug".to_string());
+        environment.insert("SHARED".to_string(), "input".to_string());
+        let conflicts = h_aws_lambda::merge_environment(&mut lambdafn, environment);
+        assert_eq!(conflicts, vec!["SHARED"]);
+
+        let props = lambdafn.properties();
+        let variables = &props["Environment"]["Variables"];
+        assert_eq!(variables["TABLE_NAME"], "mytable");
+        assert_eq!(variables["LOG_LEVEL"], "debug");
+        assert_eq!(variables["FROM_EXTRA"], "extra");
+        assert_eq!(variables["SHARED"], "extra");
+
+        let mut lambdafn = lambda::function::CfnFunction::default();
+        assert!(h_aws_lambda::merge_environment(&mut lambdafn, Default::default()).is_empty());
+        assert!(lambdafn.environment.is_none());
+    }
+
+    #[test]
+    fn secret_environment_values_become_dynamic_references() {
+        let arn = "arn:aws:secretsmanager:us-east-1:123456789012:secret:my-secret-AbCdEf";
+        let mut environment = std::collections::HashMap::new();
+        environment.insert("PLAIN".to_string(), "value".to_string());
+        environment.insert("API_KEY".to_string(), format!("secret:{arn}"));
+        environment.insert("DB_PASSWORD".to_string(), format!("secret:{arn}:SecretString:password::"));
+        let (variables, statements) = h_aws_lambda::resolve_environment(&environment).expect("Failed to resolve environment");
+        assert_eq!(variables["PLAIN"], "value");
+        assert_eq!(variables["API_KEY"], format!("{{{{resolve:secretsmanager:{arn}}}}}"));
+        assert_eq!(variables["DB_PASSWORD"], format!("{{{{resolve:secretsmanager:{arn}:SecretString:password::}}}}"));
+        assert_eq!(statements, vec![statement("Allow", "secretsmanager:GetSecretValue", arn)]);
+
+        let (_, role) = h_aws_lambda::create_lambda_role("my_fn", &statements, "", None);
+        let props = role.properties();
+        let doc_statements = &props["Policies"][0]["PolicyDocument"]["Statement"];
+        assert_eq!(doc_statements[0]["Action"], "secretsmanager:GetSecretValue");
+        assert_eq!(doc_statements[0]["Resource"], arn);
+    }
+
+    #[test]
+    fn secret_environment_values_must_be_valid_arns() {
+        for invalid in [
+            "secret:my-secret",
+            "secret:arn:aws:ssm:us-east-1:123456789012:secret:x-AbCdEf",
+            "secret:arn:aws:secretsmanager:us-east-1:123:secret:x-AbCdEf",
+            // partial arns, ie: without the random suffix
+            "secret:arn:aws:secretsmanager:us-east-1:123456789012:secret:mysecret",
+            "secret:arn:aws:secretsmanager:us-east-1:123456789012:secret:my-db-pw",
+            "secret:arn:aws:secretsmanager:us-east-1:123456789012:secret:mysecret:SecretString:password::",
+        ] {
+            let mut environment = std::collections::HashMap::new();
+            environment.insert("KEY".to_string(), invalid.to_string());
+            let err = h_aws_lambda::resolve_environment(&environment).expect_err("Expected invalid arn to fail");
+            assert!(err.contains("Invalid secret arn"), "{err}");
+        }
+    }
+
+    #[test]
+    fn function_arn_output_has_description() {
+        let (name, output) = h_aws_lambda::get_function_arn_output("my_fn", "hiragenmyfn");
+        assert_eq!(name, "LambdaFunctionArnmyfn");
+        assert_eq!(output.description, "ARN of the lambda function of module 'my_fn'");
+        assert_eq!(output.value, cfn_resources::serde_json::json!({"Fn::GetAtt": ["hiragenmyfn", "Arn"]}));
+    }
+
+    #[test]
+    fn sqs_triggers_create_event_source_mappings_and_statements() {
+        let queue_arn = "arn:aws:sqs:us-east-1:123456789012:myqueue";
+        let triggers = vec![
+            h_aws_lambda::SqsTrigger { queue_arn: queue_arn.to_string(), batch_size: Some(5), maximum_batching_window_seconds: Some(2) },
+            h_aws_lambda::SqsTrigger { queue_arn: "arn:aws:sqs:us-east-1:123456789012:other".to_string(), ..Default::default() },
+        ];
+        let mappings = h_aws_lambda::create_sqs_trigger_resources(&triggers, "hiragenmyfn");
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].name, "hiragenmyfnsqstrigger0");
+        assert_eq!(mappings[0].properties.type_string(), "AWS::Lambda::EventSourceMapping");
+        let props = mappings[0].properties.properties();
+        assert_eq!(props["EventSourceArn"], queue_arn);
+        assert_eq!(props["FunctionName"], cfn_resources::serde_json::json!({ "Ref": "hiragenmyfn" }));
+        assert_eq!(props["BatchSize"], 5);
+        assert_eq!(props["MaximumBatchingWindowInSeconds"], 2);
+        assert!(mappings[1].properties.properties().get("BatchSize").is_none());
+        assert!(mappings[1].properties.properties().get("MaximumBatchingWindowInSeconds").is_none());
+
+        let statements = h_aws_lambda::get_sqs_trigger_statements(&triggers[..1]).expect("Failed to get statements");
+        let (_, role) = h_aws_lambda::create_lambda_role("myfn", &statements, "", None);
+        let doc = &role.policies.as_ref().unwrap()[0].policy_document;
+        assert_eq!(doc["Statement"][0]["Action"], cfn_resources::serde_json::json!(["sqs:ReceiveMessage", "sqs:DeleteMessage", "sqs:GetQueueAttributes"]));
+        assert_eq!(doc["Statement"][0]["Resource"], queue_arn);
+
+        let trigger = |batch_size, maximum_batching_window_seconds| h_aws_lambda::SqsTrigger {
+            queue_arn: queue_arn.to_string(), batch_size, maximum_batching_window_seconds,
+        };
+        assert!(h_aws_lambda::get_sqs_trigger_statements(&[trigger(Some(0), None)]).is_err());
+        // more than 10 messages per batch requires a batching window
+        assert!(h_aws_lambda::get_sqs_trigger_statements(&[trigger(Some(11), None)]).is_err());
+        assert!(h_aws_lambda::get_sqs_trigger_statements(&[trigger(Some(11), Some(0))]).is_err());
+        assert!(h_aws_lambda::get_sqs_trigger_statements(&[trigger(Some(11), Some(1))]).is_ok());
+        assert!(h_aws_lambda::get_sqs_trigger_statements(&[trigger(Some(10), None)]).is_ok());
+        assert!(h_aws_lambda::get_sqs_trigger_statements(&[trigger(None, Some(301))]).is_err());
+        assert!(h_aws_lambda::get_sqs_trigger_statements(&[Default::default()]).is_err());
+    }
+
+    #[test]
+    fn runtime_is_built_with_chosen_builder() {
+        let inp = h_aws_lambda::Input::default();
+        let meta = h_aws_lambda::get_runtime_meta(&inp, "myfn");
+        assert_eq!(inp.builder, h_aws_lambda::LambdaBuilder::Cross);
+        assert_eq!(meta.cargo_cmd, "cross");
+        assert_eq!(meta.target, "aarch64-unknown-linux-musl");
+        assert_eq!(meta.profile, "release");
+        assert!(!meta.cargo_lambda);
+        let (_, args, location) = hira_lib::HiraConfig::get_build_runtime_args(&meta, "myfn", "target_dir");
+        assert_eq!(args[0], "rustc");
+        assert_eq!(location, "aarch64-unknown-linux-musl/release");
+
+        let inp = h_aws_lambda::Input {
+            builder: h_aws_lambda::LambdaBuilder::CargoLambda,
+            architecture: h_aws_lambda::Arch::X86,
+            ..Default::default()
+        };
+        let meta = h_aws_lambda::get_runtime_meta(&inp, "myfn");
+        assert_eq!(meta.cargo_cmd, "cargo");
+        assert!(meta.cargo_lambda);
+        let (rustflags, args, location) = hira_lib::HiraConfig::get_build_runtime_args(&meta, "myfn", "target_dir");
+        assert_eq!(rustflags, "--cfg myfn -C strip=symbols");
+        assert_eq!(args, [
+            "lambda", "build",
+            "--bin", "myfn",
+            "--profile", "release",
+            "--target-dir", "target_dir",
+            "--lambda-dir", "target_dir/lambda",
+            "--target", "x86_64-unknown-linux-musl",
+            "--features", "myfn",
+        ]);
+        assert_eq!(location, "lambda/myfn");
+        assert_eq!(hira_lib::HiraConfig::get_built_executable_name(&meta, "mycrate"), "bootstrap");
+        assert_eq!(hira_lib::HiraConfig::get_runtime_output_path("runtimes", "myfn", &meta), "runtimes/lambda/myfn/bootstrap");
+
+        // the function's code is packaged from the same path that the build outputs to
+        let mut runtimer = L0RuntimeCreator::new();
+        runtimer.add_to_runtime_ex("myfn", "myfn::entrypoint().await.expect(\"Lambda Error\")".to_string(), meta);
+        assert_eq!(runtimer.get_full_runtime_path("myfn"), "/lambda/myfn/bootstrap");
+    }
+
+    #[test]
+    fn function_url_is_public_by_default() {
+        let (url, permission) = h_aws_lambda::create_function_url(&Default::default(), "myfn").expect("Failed to create function url");
+        let url_props = url.properties();
+        let permission_props = permission.properties();
+        assert_eq!(url_props["AuthType"], "NONE");
+        assert!(url_props.get("Cors").map(|x| x.is_null()).unwrap_or(true));
+        assert_eq!(permission_props["FunctionUrlAuthType"], "NONE");
+        assert_eq!(permission_props["Principal"], "*");
+    }
+
+    #[test]
+    fn function_url_iam_auth_is_consistent_and_not_public() {
+        let inp = h_aws_lambda::Input {
+            function_url_auth: h_aws_lambda::FunctionUrlAuth::Iam,
+            ..Default::default()
+        };
+        let (url, permission) = h_aws_lambda::create_function_url(&inp, "myfn").expect("Failed to create function url");
+        let url_props = url.properties();
+        let permission_props = permission.properties();
+        assert_eq!(url_props["AuthType"], "AWS_IAM");
+        assert_eq!(permission_props["FunctionUrlAuthType"], "AWS_IAM");
+        assert_ne!(permission_props["Principal"], "*");
+        assert_eq!(permission_props["Principal"], cfn_resources::serde_json::json!({ "Ref": "AWS::AccountId" }));
+    }
+
+    #[test]
+    fn function_url_cors_is_set() {
+        let inp = h_aws_lambda::Input {
+            function_url_cors: Some(h_aws_lambda::CorsConfig {
+                allowed_origins: vec!["https://example.com".to_string()],
+                allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+                allowed_headers: vec!["content-type".to_string()],
+            }),
+            ..Default::default()
+        };
+        let (url, _) = h_aws_lambda::create_function_url(&inp, "myfn").expect("Failed to create function url");
+        let cors = &url.properties()["Cors"];
+        assert_eq!(cors["AllowOrigins"], cfn_resources::serde_json::json!(["https://example.com"]));
+        assert_eq!(cors["AllowMethods"], cfn_resources::serde_json::json!(["GET", "POST"]));
+        assert_eq!(cors["AllowHeaders"], cfn_resources::serde_json::json!(["content-type"]));
+
+        let invalid = h_aws_lambda::Input {
+            function_url_cors: Some(h_aws_lambda::CorsConfig {
+                allowed_origins: vec!["*".to_string()],
+                allowed_methods: vec!["get".to_string()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(h_aws_lambda::create_function_url(&invalid, "myfn").is_err());
+        let no_origins = h_aws_lambda::Input {
+            function_url_cors: Some(Default::default()),
+            ..Default::default()
+        };
+        assert!(h_aws_lambda::create_function_url(&no_origins, "myfn").is_err());
+    }
+
+    #[test]
+    fn runtime_can_be_chosen_or_overridden() {
+        let inp = h_aws_lambda::Input {
+            lambda_runtime: h_aws_lambda::LambdaRuntimeChoice::Providedal2,
+            ..Default::default()
+        };
+        let mut lambdafn = lambda::function::CfnFunction::default();
+        h_aws_lambda::apply_runtime(&inp, &mut lambdafn);
+        assert_eq!(lambdafn.properties()["Runtime"], "provided.al2");
+
+        // a runtime set via extra_options takes precedence
+        let mut lambdafn = lambda::function::CfnFunction {
+            runtime: Some(lambda::function::FunctionRuntimeEnum::Provided),
+            ..Default::default()
+        };
+        h_aws_lambda::apply_runtime(&inp, &mut lambdafn);
+        assert_eq!(lambdafn.properties()["Runtime"], "provided");
+    }
+
+    #[test]
+    fn layers_are_set_on_function() {
+        let layer = "arn:aws:lambda:us-east-1:123456789012:layer:my-layer:1";
+        let inp = h_aws_lambda::Input {
+            layers: vec![layer.to_string()],
+            ..Default::default()
+        };
+        let mut lambdafn = lambda::function::CfnFunction::default();
+        h_aws_lambda::apply_layers_and_vpc_config(&inp, &mut lambdafn);
+        let props = lambdafn.properties();
+        assert_eq!(props["Layers"], cfn_resources::serde_json::json!([layer]));
+        assert!(props.get("VpcConfig").map(|x| x.is_null()).unwrap_or(true));
+
+        let mut lambdafn = lambda::function::CfnFunction::default();
+        h_aws_lambda::apply_layers_and_vpc_config(&Default::default(), &mut lambdafn);
+        assert!(lambdafn.layers.is_none());
+
+        // layers from extra_options are kept, and the input's layers are added after them
+        let other_layer = "arn:aws:lambda:us-east-1:123456789012:layer:other-layer:2";
+        let mut lambdafn = lambda::function::CfnFunction {
+            layers: Some(vec![other_layer.into(), layer.into()]),
+            ..Default::default()
+        };
+        let conflicts = h_aws_lambda::apply_layers_and_vpc_config(&inp, &mut lambdafn);
+        assert!(conflicts.is_empty());
+        assert_eq!(lambdafn.properties()["Layers"], cfn_resources::serde_json::json!([other_layer, layer]));
+    }
+
+    #[test]
+    fn vpc_config_is_set_on_function_and_allows_network_interfaces() {
+        let vpc_config = h_aws_lambda::VpcConfig {
+            subnet_ids: vec!["subnet-a".to_string(), "subnet-b".to_string()],
+            security_group_ids: vec!["sg-a".to_string()],
+        };
+        let inp = h_aws_lambda::Input {
+            vpc_config: Some(vpc_config.clone()),
+            ..Default::default()
+        };
+        let mut lambdafn = lambda::function::CfnFunction::default();
+        h_aws_lambda::apply_layers_and_vpc_config(&inp, &mut lambdafn);
+        let props = lambdafn.properties();
+        assert_eq!(props["VpcConfig"]["SubnetIds"], cfn_resources::serde_json::json!(["subnet-a", "subnet-b"]));
+        assert_eq!(props["VpcConfig"]["SecurityGroupIds"], cfn_resources::serde_json::json!(["sg-a"]));
+
+        // a vpc config from extra_options is not overwritten
+        let mut lambdafn = lambda::function::CfnFunction {
+            vpc_config: Some(lambda::function::VpcConfig {
+                subnet_ids: Some(vec!["subnet-extra".into()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let conflicts = h_aws_lambda::apply_layers_and_vpc_config(&inp, &mut lambdafn);
+        assert_eq!(conflicts, vec!["vpc_config"]);
+        assert_eq!(lambdafn.properties()["VpcConfig"]["SubnetIds"], cfn_resources::serde_json::json!(["subnet-extra"]));
+
+        let statements = h_aws_lambda::get_vpc_statements(&inp.vpc_config).expect("Failed to get vpc statements");
+        let actions: Vec<&str> = statements.iter().map(|x| x.1.as_str()).collect();
+        assert_eq!(actions, h_aws_lambda::VPC_ACTIONS);
+        assert!(h_aws_lambda::get_vpc_statements(&None).unwrap().is_empty());
+
+        let no_subnets = h_aws_lambda::VpcConfig { subnet_ids: vec![], ..vpc_config.clone() };
+        assert!(h_aws_lambda::get_vpc_statements(&Some(no_subnets)).unwrap_err().contains("subnet"));
+        let no_security_groups = h_aws_lambda::VpcConfig { security_group_ids: vec![], ..vpc_config };
+        assert!(h_aws_lambda::get_vpc_statements(&Some(no_security_groups)).unwrap_err().contains("security group"));
+    }
+
+    #[test]
+    fn schedule_creates_rule_target_and_permission() {
+        let inp = h_aws_lambda::Input {
+            schedule: Some("rate(5 minutes)".to_string()),
+            ..Default::default()
+        };
+        let resources = h_aws_lambda::create_schedule_resources(&inp, "hiragenmyfn").expect("Failed to create schedule");
+        assert_eq!(resources.len(), 2);
+        let permission = &resources[0];
+        let rule = &resources[1];
+        assert_eq!(rule.name, "hiragenmyfnschedule");
+        assert_eq!(rule.properties.type_string(), "AWS::Events::Rule");
+        let props = rule.properties.properties();
+        assert_eq!(props["ScheduleExpression"], "rate(5 minutes)");
+        assert_eq!(props["State"], "ENABLED");
+        assert_eq!(props["Targets"], cfn_resources::serde_json::json!([
+            { "Arn": { "Fn::GetAtt": ["hiragenmyfn", "Arn"] }, "Id": "hiragenmyfn" }
+        ]));
+
+        assert_eq!(permission.name, "hiragenmyfnschedulepermission");
+        assert_eq!(permission.properties.type_string(), "AWS::Lambda::Permission");
+        let props = permission.properties.properties();
+        assert_eq!(props["Action"], "lambda:InvokeFunction");
+        assert_eq!(props["Principal"], "events.amazonaws.com");
+        assert_eq!(props["FunctionName"], cfn_resources::serde_json::json!({ "Fn::GetAtt": ["hiragenmyfn", "Arn"] }));
+        assert_eq!(props["SourceArn"], cfn_resources::serde_json::json!({ "Fn::GetAtt": ["hiragenmyfnschedule", "Arn"] }));
+
+        assert!(h_aws_lambda::create_schedule_resources(&Default::default(), "hiragenmyfn").unwrap().is_empty());
+        for invalid in ["every 5 minutes", "rate(5 minutes", "at(2030-01-01T00:00:00)"] {
+            let inp = h_aws_lambda::Input { schedule: Some(invalid.to_string()), ..Default::default() };
+            assert!(h_aws_lambda::create_schedule_resources(&inp, "hiragenmyfn").is_err());
+        }
+    }
+
+    #[test]
+    fn log_group_is_created_with_retention() {
+        let inp = h_aws_lambda::Input::default();
+        assert!(h_aws_lambda::create_log_group_resource(&inp, "hiragenmyfn", &None).expect("Failed to create log group").is_none());
+
+        let inp = h_aws_lambda::Input {
+            log_retention_days: Some(14),
+            ..Default::default()
+        };
+        // without an explicit name, the group references the function's generated name
+        let (log_group, function_depends_on_group) = h_aws_lambda::create_log_group_resource(&inp, "hiragenmyfn", &None)
+            .expect("Failed to create log group")
+            .expect("Expected a log group");
+        assert!(!function_depends_on_group);
+        assert_eq!(log_group.name, "hiragenmyfnloggroup");
+        assert_eq!(log_group.properties.type_string(), "AWS::Logs::LogGroup");
+        let props = log_group.properties.properties();
+        assert_eq!(props["RetentionInDays"], 14);
+        assert_eq!(props["LogGroupName"], cfn_resources::serde_json::json!({ "Fn::Sub": "/aws/lambda/${hiragenmyfn}" }));
+
+        // a name set by the user is used as is, and the function depends on the group
+        let function_name = Some(cfn_resources::StrVal::String("custom".to_string()));
+        let (log_group, function_depends_on_group) = h_aws_lambda::create_log_group_resource(&inp, "hiragenmyfn", &function_name)
+            .expect("Failed to create log group")
+            .expect("Expected a log group");
+        assert!(function_depends_on_group);
+        assert_eq!(log_group.properties.properties()["LogGroupName"], "/aws/lambda/custom");
+
+        let function_name = Some(cfn_resources::StrVal::Val(get_ref("SomeParameter")));
+        let (log_group, function_depends_on_group) = h_aws_lambda::create_log_group_resource(&inp, "hiragenmyfn", &function_name)
+            .expect("Failed to create log group")
+            .expect("Expected a log group");
+        assert!(!function_depends_on_group);
+        assert_eq!(log_group.properties.properties()["LogGroupName"]["Fn::Sub"], "/aws/lambda/${hiragenmyfn}");
+
+        let inp = h_aws_lambda::Input {
+            log_retention_days: Some(10),
+            ..Default::default()
+        };
+        let err = h_aws_lambda::create_log_group_resource(&inp, "hiragenmyfn", &None).err().expect("Expected retention to be invalid");
+        assert!(err.contains("log_retention_days"));
+    }
+
+    #[test]
+    fn alarm_thresholds_must_be_positive() {
+        let inp = h_aws_lambda::Input {
+            create_alarms: true,
+            error_alarm_threshold: Some(0.0),
+            ..Default::default()
+        };
+        let err = h_aws_lambda::create_alarm_resources(&inp, "hiragenmyfn").err().expect("Expected threshold to be invalid");
+        assert!(err.contains("Errors"));
+    }
+
+    #[test]
+    fn warns_if_function_url_event_returns_other_type() {
+        let mut use_func_url = false;
+        let mut warning = None;
+        let sig = lambda_main_sig("h_aws_lambda :: FunctionUrlEvent", "Vec < u8 >");
+        h_aws_lambda::validate_lambda_main_signature(&sig, &mut use_func_url, &mut warning).expect("signature should be valid");
+        assert!(use_func_url);
+        assert!(warning.expect("expected a warning").contains("Vec<u8>"));
+
+        let mut warning = None;
+        let sig = lambda_main_sig("h_aws_lambda :: FunctionUrlEvent", "Result < u32, h_aws_lambda :: BoxError >");
+        h_aws_lambda::validate_lambda_main_signature(&sig, &mut use_func_url, &mut warning).expect("signature should be valid");
+        assert!(warning.expect("expected a warning").contains("'u32'"));
+
+        let mut warning = None;
+        let sig = lambda_main_sig("h_aws_lambda :: FunctionUrlEvent", "Result < HashMap < String, Vec < (A, B) > >, h_aws_lambda :: BoxError >");
+        h_aws_lambda::validate_lambda_main_signature(&sig, &mut use_func_url, &mut warning).expect("signature should be valid");
+        assert!(warning.expect("expected a warning").contains("'HashMap<String,Vec<(A,B)>>'"));
+    }
+
+    #[test]
+    fn warns_about_payload_limit_if_function_url_event_returns_binary() {
+        for return_ty in ["Vec < u8 >", "Result < Vec < u8 >, BoxError >", "bytes :: Bytes", "& [u8]"] {
+            let mut use_func_url = false;
+            let mut warning = None;
+            let sig = lambda_main_sig("FunctionUrlEvent", return_ty);
+            h_aws_lambda::validate_lambda_main_signature(&sig, &mut use_func_url, &mut warning).expect("signature should be valid");
+            let warning = warning.expect("expected a warning");
+            assert!(warning.contains("6MB"), "expected payload warning for {return_ty}, got {warning}");
+        }
+        let mut use_func_url = false;
+        let mut warning = None;
+        let sig = lambda_main_sig("FunctionUrlEvent", "String");
+        h_aws_lambda::validate_lambda_main_signature(&sig, &mut use_func_url, &mut warning).expect("signature should be valid");
+        assert!(warning.is_none());
+        // binary is fine if not returned from a function url
+        let sig = lambda_main_sig("MyEvent", "Vec < u8 >");
+        h_aws_lambda::validate_lambda_main_signature(&sig, &mut use_func_url, &mut warning).expect("signature should be valid");
+        assert!(warning.is_none());
+    }
+
+    #[test]
+    fn e2e_function_url_returning_other_types_is_warned_about() {
+        let out = e2e_lambda_run(&[&function_url_module("map_fn", "Result<HashMap<String, Vec<(A, B)>>, h_aws_lambda::BoxError>")]);
+        assert_contains_str(out, "returns 'HashMap<String,Vec<(A,B)>>'. The status code and headers of the response will not be set");
+        let out = e2e_lambda_run(&[&function_url_module("response_fn", "Result<h_aws_lambda::FunctionUrlResponse, h_aws_lambda::BoxError>")]);
+        assert!(!out.contains("The status code and headers of the response will not be set"));
+    }
+
+    #[test]
+    fn e2e_function_url_returning_binary_is_warned_about() {
+        let out = e2e_lambda_run(&[&function_url_module("binary_fn", "Vec<u8>")]);
+        assert_contains_str(out, "returns raw binary data 'Vec<u8>'. Function url responses are limited to 6MB");
+        let out = e2e_lambda_run(&[&function_url_module("string_fn", "String")]);
+        assert!(!out.contains("Function url responses are limited"));
+    }
+
+    #[test]
+    fn doesnt_warn_if_function_url_event_returns_response_or_string() {
+        for return_ty in ["h_aws_lambda :: FunctionUrlResponse", "String", "Result < FunctionUrlResponse, BoxError >"] {
+            let mut use_func_url = false;
+            let mut warning = None;
+            let sig = lambda_main_sig("FunctionUrlEvent", return_ty);
+            h_aws_lambda::validate_lambda_main_signature(&sig, &mut use_func_url, &mut warning).expect("signature should be valid");
+            assert!(use_func_url);
+            assert!(warning.is_none(), "unexpected warning for {return_ty}");
         }
+        // not a function url, so any return type is fine
+        let mut use_func_url = false;
+        let mut warning = None;
+        let sig = lambda_main_sig("MyEvent", "u32");
+        h_aws_lambda::validate_lambda_main_signature(&sig, &mut use_func_url, &mut warning).expect("signature should be valid");
+        assert!(!use_func_url);
+        assert!(warning.is_none());
     }
 }
@@ -12,6 +12,7 @@ pub mod aws_s3 {
     extern crate s3;
     extern crate lambda;
     extern crate iam;
+    extern crate cloud_front;
     extern crate cfn_resources;
 
     use super::L0Core;
@@ -22,10 +23,17 @@ pub mod aws_s3 {
     use self::cfn_resources::create_policy_doc;
     use self::cfn_resources::StrVal;
     use self::cfn_resources::ToOptStrVal;
+    use self::cfn_resources::CfnResource;
     use self::cfn_resources::serde_json;
     use self::cfn_resources::serde_json::Value;
     pub use self::s3::bucket::CfnBucket;
     pub use self::s3::bucket::WebsiteConfiguration;
+    pub use self::s3::bucket::PublicAccessBlockConfiguration;
+    pub use self::cloud_front::origin_access_control::CfnOriginAccessControl;
+    pub use self::cloud_front::origin_access_control::OriginAccessControlConfig;
+    pub use self::cloud_front::origin_access_control::OriginAccessControlConfigOriginAccessControlOriginTypeEnum;
+    pub use self::cloud_front::origin_access_control::OriginAccessControlConfigSigningBehaviorEnum;
+    pub use self::cloud_front::origin_access_control::OriginAccessControlConfigSigningProtocolEnum;
 
     pub mod outputs {
         /// the logical name of the resource in cloudformation.
@@ -33,10 +41,13 @@ pub mod aws_s3 {
         /// allowing permissions to read/write from this bucket,
         /// pointing a cloudfront distribution to this bucket, etc.
         pub const LOGICAL_BUCKET_NAME: &str = "UNDEFINED";
+        /// the logical name of the origin access control, if `private_website` is set.
+        /// Set it as the origin access control id of the distribution's origin for this bucket.
+        pub const LOGICAL_ORIGIN_ACCESS_CONTROL_NAME: &str = "UNDEFINED";
     }
 
     #[derive(Default)]
-    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
     pub struct Input {
         /// By default, every s3 bucket gets a cleanup resource created for it.
         /// this includes:
@@ -57,10 +68,378 @@ pub mod aws_s3 {
         /// if you'd like to customize this behavior, provide the website configuration
         /// in extra_bucket_settings instead, and leave this option as default.
         pub is_website: bool,
+        /// an alternative to `is_website` for buckets that are only served via a cloudfront distribution.
+        /// All public access is blocked, unless `extra_bucket_settings` sets a public access block.
+        /// Instead we create an origin access control (`AWS::CloudFront::OriginAccessControl`)
+        /// for the distribution to sign its requests with,
+        /// and a bucket policy that only allows cloudfront to read objects.
+        /// Cannot be used together with `is_website`, as S3 website endpoints only support public access.
+        pub private_website: bool,
+        /// the logical id of the distribution that serves the private website. If provided, only that
+        /// distribution can read objects. Otherwise, any distribution of this account can.
+        /// This has no effect unless `private_website` is true.
+        pub private_website_distribution: String,
         /// this module makes no customization, instead opting for cloudformation
         /// to create the s3 bucket name for you based on the logical resource name.
         /// fill any field that you'd like to customize.
+        #[cfg_attr(feature = "web", schemars(with = "serde_json::Value"))]
         pub extra_bucket_settings: s3::bucket::CfnBucket,
+        /// the lambda runtime identifier of the cleanup function, eg: "nodejs20.x".
+        /// must be one of `CLEANUP_RUNTIMES`. If not provided, `DEFAULT_CLEANUP_RUNTIME` is used.
+        /// Selecting a runtime that AWS has deprecated emits a compiler warning.
+        /// This has no effect if `dont_create_cleanup_resources` is true.
+        pub cleanup_runtime: Option<String>,
+        /// if true, versioning is enabled on the bucket.
+        pub versioning: bool,
+        /// optionally set the default encryption of objects in the bucket.
+        pub encryption: Option<SseConfig>,
+        /// rules that expire or transition objects. These are added to the lifecycle rules
+        /// of `extra_bucket_settings` (if any).
+        pub lifecycle_rules: Vec<LifecycleRule>,
+    }
+
+    #[derive(Clone)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub enum SseAlgorithm {
+        /// `AES256`, ie: SSE-S3
+        Aes256,
+        /// `aws:kms`, ie: SSE-KMS
+        Kms,
+    }
+
+    impl Default for SseAlgorithm {
+        fn default() -> Self { Self::Aes256 }
+    }
+
+    #[derive(Default, Clone)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub struct SseConfig {
+        pub algorithm: SseAlgorithm,
+        /// the KMS key to encrypt with. Only valid with `SseAlgorithm::Kms`.
+        /// If not provided, the AWS managed key (`aws/s3`) is used.
+        pub kms_key_id: Option<String>,
+        /// reduces the number of requests to KMS. Only valid with `SseAlgorithm::Kms`.
+        pub bucket_key_enabled: bool,
+    }
+
+    #[derive(Default, Clone)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub struct LifecycleTransition {
+        /// eg: `STANDARD_IA`, or `GLACIER`. Must be one of `LIFECYCLE_STORAGE_CLASSES`
+        pub storage_class: String,
+        pub days: u32,
+    }
+
+    #[derive(Default, Clone)]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
+    pub struct LifecycleRule {
+        /// must be unique within the bucket
+        pub id: String,
+        /// only objects with this key prefix are affected. If empty, the rule applies to every object.
+        pub prefix: String,
+        /// deletes objects this many days after they were created
+        pub expiration_days: Option<u32>,
+        /// deletes previous versions of objects this many days after they became noncurrent.
+        /// Only relevant if the bucket is versioned.
+        pub noncurrent_version_expiration_days: Option<u32>,
+        pub transitions: Vec<LifecycleTransition>,
+    }
+
+    pub const LIFECYCLE_STORAGE_CLASSES: &[&str] = &["DEEP_ARCHIVE", "GLACIER", "GLACIER_IR", "INTELLIGENT_TIERING", "ONEZONE_IA", "STANDARD_IA"];
+
+    /// the lambda runtimes that the cleanup function can be deployed with,
+    /// and whether or not AWS has deprecated that runtime.
+    pub const CLEANUP_RUNTIMES: &[(&str, bool)] = &[
+        ("nodejs16.x", true),
+        ("nodejs18.x", true),
+        ("nodejs20.x", false),
+    ];
+
+    pub const DEFAULT_CLEANUP_RUNTIME: &str = "nodejs20.x";
+
+    /// nodejs16.x and below bundle the v2 aws-sdk.
+    const CLEANUP_CODE_SDK_V2: &str = r#"
+                var AWS = require('aws-sdk')
+                var response = require('cfn-response')
+                const s3 = new AWS.S3({});
+                async function listObjects(bucketName) {
+                    const data = await s3.listObjects({ Bucket: bucketName }).promise();
+                    const objects = data.Contents;
+                    for (let obj of objects) {
+                        await s3.deleteObject({ Bucket: bucketName, Key: obj.Key }).promise();
+                    }
+                }
+                exports.handler = async function(event, context) {
+                    let responseType = response.SUCCESS
+                    if (event.RequestType == 'Delete') {
+                        try {
+                            await listObjects(event.ResourceProperties.BucketName);
+                        } catch (err) {
+                            responseType = response.FAILED
+                        }
+                    }
+                    await response.send(event, context, responseType)
+                }
+                "#;
+
+    /// nodejs18.x and above only bundle the v3 aws-sdk.
+    /// `cfn-response` signals completion via `context.done` rather than a promise, so we
+    /// wait for it explicitly, otherwise an async handler can return before the response is sent.
+    const CLEANUP_CODE_SDK_V3: &str = r#"
+                const { S3Client, ListObjectsCommand, DeleteObjectCommand } = require('@aws-sdk/client-s3')
+                var response = require('cfn-response')
+                const s3 = new S3Client({});
+                function sendResponse(event, context, responseType) {
+                    return new Promise((resolve) => {
+                        context.done = resolve;
+                        response.send(event, context, responseType);
+                    });
+                }
+                async function listObjects(bucketName) {
+                    const data = await s3.send(new ListObjectsCommand({ Bucket: bucketName }));
+                    const objects = data.Contents || [];
+                    for (let obj of objects) {
+                        await s3.send(new DeleteObjectCommand({ Bucket: bucketName, Key: obj.Key }));
+                    }
+                }
+                exports.handler = async function(event, context) {
+                    let responseType = response.SUCCESS
+                    if (event.RequestType == 'Delete') {
+                        try {
+                            await listObjects(event.ResourceProperties.BucketName);
+                        } catch (err) {
+                            responseType = response.FAILED
+                        }
+                    }
+                    await sendResponse(event, context, responseType)
+                }
+                "#;
+
+    pub struct CleanupRuntime {
+        /// the lambda runtime identifier, eg: "nodejs20.x". this is set as a raw string
+        /// because the pinned aws_cfn_type_gen's `FunctionRuntimeEnum` predates the newer nodejs runtimes.
+        pub runtime: &'static str,
+        pub deprecated: bool,
+        /// the inline handler code that works with the aws-sdk bundled in this runtime.
+        pub code: &'static str,
+    }
+
+    /// returns an error if the provided runtime is not one of `CLEANUP_RUNTIMES`.
+    pub fn get_cleanup_runtime(inp: &Input) -> Result<CleanupRuntime, String> {
+        let name = inp.cleanup_runtime.as_deref().unwrap_or(DEFAULT_CLEANUP_RUNTIME);
+        let (runtime, deprecated) = match CLEANUP_RUNTIMES.iter().find(|(x, _)| *x == name) {
+            Some((runtime, deprecated)) => (*runtime, *deprecated),
+            None => {
+                let supported: Vec<&str> = CLEANUP_RUNTIMES.iter().map(|(x, _)| *x).collect();
+                return Err(format!("Invalid cleanup_runtime '{name}'. Must be one of {:?}", supported));
+            }
+        };
+        let code = match runtime {
+            "nodejs16.x" => CLEANUP_CODE_SDK_V2,
+            _ => CLEANUP_CODE_SDK_V3,
+        };
+        Ok(CleanupRuntime { runtime, deprecated, code })
+    }
+
+    pub fn get_bucket_encryption(encryption: &SseConfig) -> Result<s3::bucket::BucketEncryption, String> {
+        let by_default = match encryption.algorithm {
+            SseAlgorithm::Aes256 => {
+                if encryption.kms_key_id.is_some() || encryption.bucket_key_enabled {
+                    return Err("kms_key_id and bucket_key_enabled can only be set when encrypting with SseAlgorithm::Kms".to_string());
+                }
+                s3::bucket::ServerSideEncryptionByDefault {
+                    ssealgorithm: s3::bucket::ServerSideEncryptionByDefaultSSEAlgorithmEnum::Aes256,
+                    ..Default::default()
+                }
+            }
+            SseAlgorithm::Kms => s3::bucket::ServerSideEncryptionByDefault {
+                ssealgorithm: s3::bucket::ServerSideEncryptionByDefaultSSEAlgorithmEnum::Awskms,
+                kmsmaster_key_id: encryption.kms_key_id.clone().map(|x| x.into()),
+            },
+        };
+        let rule = s3::bucket::ServerSideEncryptionRule {
+            server_side_encryption_by_default: Some(by_default),
+            bucket_key_enabled: if encryption.bucket_key_enabled { Some(true) } else { None },
+        };
+        Ok(s3::bucket::BucketEncryption { server_side_encryption_configuration: vec![rule] })
+    }
+
+    pub fn get_transition_storage_class(storage_class: &str) -> Option<s3::bucket::TransitionStorageClassEnum> {
+        use self::s3::bucket::TransitionStorageClassEnum as Class;
+        let class = match storage_class {
+            "DEEP_ARCHIVE" => Class::Deeparchive,
+            "GLACIER" => Class::Glacier,
+            "GLACIER_IR" => Class::Glacierir,
+            "INTELLIGENT_TIERING" => Class::Intelligenttiering,
+            "ONEZONE_IA" => Class::Onezoneia,
+            "STANDARD_IA" => Class::Standardia,
+            _ => return None,
+        };
+        Some(class)
+    }
+
+    pub fn get_lifecycle_rule(rule: &LifecycleRule) -> Result<s3::bucket::Rule, String> {
+        if rule.id.is_empty() || rule.id.len() > 255 {
+            return Err(format!("Invalid lifecycle rule id '{}'. Must be between 1 and 255 characters", rule.id));
+        }
+        if rule.expiration_days.is_none() && rule.noncurrent_version_expiration_days.is_none() && rule.transitions.is_empty() {
+            return Err(format!("Lifecycle rule '{}' must expire or transition objects", rule.id));
+        }
+        let mut transitions = vec![];
+        for transition in rule.transitions.iter() {
+            let storage_class = match get_transition_storage_class(&transition.storage_class) {
+                Some(x) => x,
+                None => return Err(format!("Invalid storage class '{}' in lifecycle rule '{}'. Must be one of {:?}", transition.storage_class, rule.id, LIFECYCLE_STORAGE_CLASSES)),
+            };
+            transitions.push(s3::bucket::Transition {
+                storage_class,
+                transition_in_days: Some(transition.days.into()),
+                ..Default::default()
+            });
+        }
+        Ok(s3::bucket::Rule {
+            id: Some(rule.id.clone().into()),
+            status: s3::bucket::RuleStatusEnum::Enabled,
+            prefix: if rule.prefix.is_empty() { None } else { Some(rule.prefix.clone().into()) },
+            expiration_in_days: rule.expiration_days.map(|x| x.into()),
+            noncurrent_version_expiration: rule.noncurrent_version_expiration_days.map(|days| s3::bucket::NoncurrentVersionExpiration {
+                noncurrent_days: days.into(),
+                ..Default::default()
+            }),
+            transitions: if transitions.is_empty() { None } else { Some(transitions) },
+            ..Default::default()
+        })
+    }
+
+    /// sets the versioning, encryption, and lifecycle rules of the input on the bucket.
+    /// versioning and encryption that are also set in `extra_bucket_settings` are left as is, and
+    /// their names are returned so that the caller can warn about them. Lifecycle rules are added to
+    /// the rules of `extra_bucket_settings`.
+    pub fn set_bucket_settings(inp: &Input, bucket: &mut CfnBucket) -> Result<Vec<&'static str>, String> {
+        let mut conflicts = vec![];
+        if inp.versioning {
+            if bucket.versioning_configuration.is_some() {
+                conflicts.push("versioning");
+            } else {
+                bucket.versioning_configuration = Some(s3::bucket::VersioningConfiguration {
+                    status: s3::bucket::VersioningConfigurationStatusEnum::Enabled,
+                });
+            }
+        }
+        if let Some(encryption) = &inp.encryption {
+            let encryption = get_bucket_encryption(encryption)?;
+            if bucket.bucket_encryption.is_some() {
+                conflicts.push("encryption");
+            } else {
+                bucket.bucket_encryption = Some(encryption);
+            }
+        }
+        if !inp.lifecycle_rules.is_empty() {
+            let lifecycle = bucket.lifecycle_configuration.get_or_insert_with(Default::default);
+            for rule in inp.lifecycle_rules.iter() {
+                let rule = get_lifecycle_rule(rule)?;
+                let id = serde_json::to_value(&rule.id).unwrap_or_default();
+                if lifecycle.rules.iter().any(|x| serde_json::to_value(&x.id).unwrap_or_default() == id) {
+                    return Err(format!("Duplicate lifecycle rule id {id}"));
+                }
+                lifecycle.rules.push(rule);
+            }
+        }
+        Ok(conflicts)
+    }
+
+    pub const CLOUDFRONT_SERVICE_PRINCIPAL: &str = "cloudfront.amazonaws.com";
+
+    pub const MAX_OAC_NAME_LEN: usize = 64;
+
+    /// origin access control names must be unique within the account, so they include the full stack name.
+    pub fn get_oac_name(full_stack_name: &str, logical_oac_name: &str) -> String {
+        aws_cfn_stack::get_physical_name(full_stack_name, logical_oac_name, MAX_OAC_NAME_LEN)
+    }
+
+    /// the origin access control that cloudfront uses to sign its requests to a private website bucket.
+    /// `name` must be unique within the account, see `get_oac_name`.
+    pub fn create_origin_access_control(name: &str) -> CfnOriginAccessControl {
+        CfnOriginAccessControl {
+            origin_access_control_config: OriginAccessControlConfig {
+                name: name.to_str_val().unwrap(),
+                origin_access_control_origin_type: OriginAccessControlConfigOriginAccessControlOriginTypeEnum::S3,
+                signing_behavior: OriginAccessControlConfigSigningBehaviorEnum::Always,
+                signing_protocol: OriginAccessControlConfigSigningProtocolEnum::Sigv4,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// private websites are only read by cloudfront, so all public access is blocked,
+    /// unless the public access block is set in `extra_bucket_settings`.
+    pub fn block_public_access(bucket: &mut CfnBucket) {
+        if bucket.public_access_block_configuration.is_some() {
+            return;
+        }
+        bucket.public_access_block_configuration = Some(PublicAccessBlockConfiguration {
+            block_public_acls: true.into(),
+            block_public_policy: true.into(),
+            ignore_public_acls: true.into(),
+            restrict_public_buckets: true.into(),
+        });
+    }
+
+    /// returns the policy document of the bucket, if it needs one. Websites allow everyone to read objects,
+    /// whereas private websites only allow cloudfront, on behalf of a distribution of this account.
+    pub fn get_bucket_policy_doc(inp: &Input, logical_bucket_name: &str) -> Option<Value> {
+        // { "Fn::Sub": "arn:aws:s3:::${resource_name}/*" }
+        let mut resource_sub = cfn_resources::serde_json::Map::new();
+        resource_sub.insert("Fn::Sub".to_string(), cfn_resources::serde_json::Value::String(
+            format!("arn:aws:s3:::${{{}}}/*", logical_bucket_name)
+        ));
+        let resource_sub = cfn_resources::serde_json::Value::Object(resource_sub);
+        if inp.is_website {
+            return Some(create_policy_doc(&[
+                ("Allow".to_string(), "s3:GetObject".to_string(), StrVal::Val(resource_sub), "*".to_str_val().unwrap()),
+            ]));
+        }
+        if !inp.private_website {
+            return None;
+        }
+        let condition = if inp.private_website_distribution.is_empty() {
+            serde_json::json!({ "StringEquals": { "AWS:SourceAccount": get_ref("AWS::AccountId") } })
+        } else {
+            let distribution_arn = format!("arn:${{AWS::Partition}}:cloudfront::${{AWS::AccountId}}:distribution/${{{}}}", inp.private_website_distribution);
+            serde_json::json!({ "StringEquals": { "AWS:SourceArn": { "Fn::Sub": distribution_arn } } })
+        };
+        Some(serde_json::json!({
+            "Version": "2012-10-17",
+            "Statement": [{
+                "Effect": "Allow",
+                "Principal": { "Service": CLOUDFRONT_SERVICE_PRINCIPAL },
+                "Action": "s3:GetObject",
+                "Resource": resource_sub,
+                "Condition": condition,
+            }],
+        }))
+    }
+
+    /// returns the raw resource json (see `aws_cfn_stack::Input::add_raw_resource`) of the cleanup function.
+    /// The function is built with the typed `CfnFunction`, and then its `Runtime` is set as a string.
+    pub fn create_cleanup_function(cleanup_runtime: CleanupRuntime, logical_role_name: &str) -> Value {
+        let function = lambda::function::CfnFunction {
+            handler: "index.handler".to_str_val(),
+            role: get_att(logical_role_name, "Arn").into(),
+            code: lambda::function::Code {
+                zip_file: cleanup_runtime.code.to_str_val(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut properties = cfn_resources::CfnResource::properties(&function);
+        if let Value::Object(map) = &mut properties {
+            map.insert("Runtime".to_string(), Value::String(cleanup_runtime.runtime.to_string()));
+        }
+        serde_json::json!({
+            "Type": cfn_resources::CfnResource::type_string(&function),
+            "Properties": properties,
+        })
     }
 
     pub fn create_assume_role_policy_doc() -> Value {
@@ -101,6 +480,10 @@ pub mod aws_s3 {
 
     pub fn config(myinput: &mut Input, stackinp: &mut aws_cfn_stack::Input, l0core: &mut L0Core) {
         let user_mod_name = l0core.users_module_name();
+        if myinput.is_website && myinput.private_website {
+            l0core.compiler_error(&format!("Module '{user_mod_name}' cannot set both is_website and private_website"));
+            return;
+        }
         let logical_bucket_name = format!("hiragenbucket{user_mod_name}");
         let logical_bucket_name = logical_bucket_name.replace("_", "");
 
@@ -122,6 +505,22 @@ pub mod aws_s3 {
                 public_block_config.block_public_policy = false.into();
             }
         }
+        if myinput.private_website {
+            block_public_access(&mut bucket);
+        }
+        match set_bucket_settings(myinput, &mut bucket) {
+            Ok(conflicts) => for name in conflicts {
+                l0core.compiler_warning(&format!("Module '{user_mod_name}' set {name}, but it is also set in extra_bucket_settings. The value from extra_bucket_settings is used"));
+            }
+            Err(e) => {
+                l0core.compiler_error(&format!("Module '{user_mod_name}': {e}"));
+                return;
+            }
+        }
+        if let Err(e) = bucket.validate() {
+            l0core.compiler_error(&format!("Invalid bucket settings of module '{user_mod_name}'\n{e}"));
+            return;
+        }
         let resource = aws_cfn_stack::Resource {
             name: logical_bucket_name.clone(),
             properties: Box::new(bucket) as _,
@@ -129,37 +528,40 @@ pub mod aws_s3 {
         let output_name = format!("S3Arn{}", user_mod_name);
         let output_name = output_name.replace("_", "");
         let resource_out = ResourceOutput {
-            description: "".to_string(),
+            description: format!("ARN of the S3 bucket of module '{user_mod_name}'"),
             value: get_att(&logical_bucket_name, "Arn")
         };
         stackinp.outputs.insert(output_name, resource_out);
         stackinp.resources.push(resource);
-        if myinput.is_website {
-            let mut resource_sub = cfn_resources::serde_json::Map::new();
-            // { "Fn::Sub": "arn:aws:s3:::${resource_name}/*" }
-            resource_sub.insert("Fn::Sub".to_string(), cfn_resources::serde_json::Value::String(
-                format!("arn:aws:s3:::${{{}}}/*", logical_bucket_name)
-            ));
-            let resource_sub = cfn_resources::serde_json::Value::Object(resource_sub);
+        if let Some(policy_document) = get_bucket_policy_doc(myinput, &logical_bucket_name) {
             let bucket_policy = s3::bucket_policy::CfnBucketPolicy {
                 bucket: StrVal::Val(get_ref(&logical_bucket_name)),
-                policy_document: create_policy_doc(&[
-                    ("Allow".to_string(), "s3:GetObject".to_string(), StrVal::Val(resource_sub), "*".to_str_val().unwrap()),
-                ])
+                policy_document,
             };
             let logical_policy_name = format!("{logical_bucket_name}policy");
-            let resource = aws_cfn_stack::Resource {
+            stackinp.resources.push(aws_cfn_stack::Resource {
                 name: logical_policy_name.clone(),
                 properties: Box::new(bucket_policy) as _,
-            };
+            });
+        }
+        if myinput.private_website {
+            let logical_oac_name = format!("{logical_bucket_name}oac");
+            let oac_name = get_oac_name(&aws_cfn_stack::get_full_stack_name(&stackinp.stack_name, &l0core.stack_name_prefix()), &logical_oac_name);
+            let oac = create_origin_access_control(&oac_name);
+            stackinp.resources.push(aws_cfn_stack::Resource {
+                name: logical_oac_name.clone(),
+                properties: Box::new(oac) as _,
+            });
+            l0core.set_output("LOGICAL_ORIGIN_ACCESS_CONTROL_NAME", &logical_oac_name);
+        }
+        if myinput.is_website {
             let output_name = format!("S3WebsiteUrl{}", user_mod_name);
             let output_name = output_name.replace("_", "");
             let resource_out = ResourceOutput {
-                description: "".to_string(),
+                description: format!("Website URL of the S3 bucket of module '{user_mod_name}'"),
                 value: get_att(&logical_bucket_name, "WebsiteURL")
             };
             stackinp.outputs.insert(output_name, resource_out);
-            stackinp.resources.push(resource);
         }
 
         l0core.set_output("LOGICAL_BUCKET_NAME", &logical_bucket_name);
@@ -168,13 +570,24 @@ pub mod aws_s3 {
         if myinput.dont_create_cleanup_resources {
             return;
         }
+        let cleanup_runtime = match get_cleanup_runtime(myinput) {
+            Ok(x) => x,
+            Err(e) => {
+                l0core.compiler_error(&e);
+                return;
+            }
+        };
+        if cleanup_runtime.deprecated {
+            l0core.compiler_warning(&format!("The cleanup_runtime of module '{user_mod_name}' is deprecated by AWS. Consider using {DEFAULT_CLEANUP_RUNTIME} instead"));
+        }
         let mut resource_sub = cfn_resources::serde_json::Map::new();
         resource_sub.insert("Fn::Sub".to_string(), cfn_resources::serde_json::Value::String(
             format!("arn:aws:s3:::${{{}}}/*", logical_bucket_name)
         ));
         let resource_sub = cfn_resources::serde_json::Value::Object(resource_sub);
+        let iam_name_prefix = l0core.iam_name_prefix();
         let policy = iam::role::Policy {
-            policy_name: format!("hira-gen-policy-{user_mod_name}").into(),
+            policy_name: format!("{iam_name_prefix}hira-gen-policy-{user_mod_name}").into(),
             policy_document: create_policy_doc(&[
                 (
                     "Allow".to_string(), "s3:ListBucket".to_string(),
@@ -193,43 +606,13 @@ pub mod aws_s3 {
         let role = iam::role::CfnRole {
             description: Some(format!("auto generated cleanup resource for {user_mod_name}").into()),
             assume_role_policy_document: create_assume_role_policy_doc(),
+            permissions_boundary: l0core.iam_permissions_boundary().map(|x| x.into()),
             policies: Some(vec![policy]),
             ..Default::default()
         };
         let logical_fn_name = format!("hiragencleanupfunction{user_mod_name}");
         let logical_fn_name = logical_fn_name.replace("_", "");
-        let cleanup_function = lambda::function::CfnFunction {
-            runtime: lambda::function::FunctionRuntimeEnum::Nodejs16x.into(),
-            handler: "index.handler".to_str_val(),
-            role: get_att(&logical_role_name, "Arn").into(),
-            code: lambda::function::Code {
-                zip_file: r#"
-                var AWS = require('aws-sdk')
-                var response = require('cfn-response')
-                const s3 = new AWS.S3({});
-                async function listObjects(bucketName) {
-                    const data = await s3.listObjects({ Bucket: bucketName }).promise();
-                    const objects = data.Contents;
-                    for (let obj of objects) {
-                        await s3.deleteObject({ Bucket: bucketName, Key: obj.Key }).promise();
-                    }
-                }
-                exports.handler = async function(event, context) {
-                    let responseType = response.SUCCESS
-                    if (event.RequestType == 'Delete') {
-                        try {
-                            await listObjects(event.ResourceProperties.BucketName);
-                        } catch (err) {
-                            responseType = response.FAILED
-                        }
-                    }
-                    await response.send(event, context, responseType)
-                }
-                "#.to_str_val(),
-                ..Default::default()
-            },
-            ..Default::default()
-        };
+        let cleanup_function = create_cleanup_function(cleanup_runtime, &logical_role_name);
 
         let cleanup = CleanupResource {
             lambda_logical_id: logical_fn_name.clone(),
@@ -241,16 +624,240 @@ pub mod aws_s3 {
             name: logical_cleanup_resource_name.into(),
             properties: Box::new(cleanup) as _,
         };
-        let function_resource = aws_cfn_stack::Resource {
-            name: logical_fn_name,
-            properties: Box::new(cleanup_function) as _,
-        };
         let role_resource = aws_cfn_stack::Resource {
             name: logical_role_name,
             properties: Box::new(role) as _,
         };
         stackinp.resources.push(role_resource);
-        stackinp.resources.push(function_resource);
+        if let Err(e) = stackinp.add_raw_resource(&logical_fn_name, cleanup_function) {
+            l0core.compiler_error(&e);
+            return;
+        }
         stackinp.resources.push(cleanup_resource);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_s3::*;
+
+    #[test]
+    fn default_cleanup_runtime_is_not_deprecated() {
+        let runtime = get_cleanup_runtime(&Input::default()).expect("Default runtime should be valid");
+        assert!(!runtime.deprecated);
+        assert_eq!(runtime.runtime, "nodejs20.x");
+        assert!(runtime.code.contains("@aws-sdk/client-s3"));
+    }
+
+    #[test]
+    fn default_cleanup_function_doesnt_use_nodejs16() {
+        let runtime = get_cleanup_runtime(&Input::default()).expect("Default runtime should be valid");
+        let function = create_cleanup_function(runtime, "myrole");
+        assert_eq!(function["Type"], "AWS::Lambda::Function");
+        let properties = &function["Properties"];
+        assert_eq!(properties["Runtime"], "nodejs20.x");
+        assert_eq!(properties["Handler"], "index.handler");
+        assert!(!properties.to_string().contains("nodejs16"));
+        assert!(!properties.to_string().contains("require('aws-sdk')"));
+    }
+
+    fn get_bucket_properties(inp: &Input) -> cfn_resources::serde_json::Value {
+        let mut bucket = CfnBucket::default();
+        let conflicts = set_bucket_settings(inp, &mut bucket).expect("Failed to set bucket settings");
+        assert!(conflicts.is_empty());
+        cfn_resources::CfnResource::validate(&bucket).expect("Bucket should be valid");
+        cfn_resources::CfnResource::properties(&bucket)
+    }
+
+    #[test]
+    fn private_website_only_allows_cloudfront() {
+        let inp = Input { private_website: true, ..Default::default() };
+        let doc = get_bucket_policy_doc(&inp, "mybucket").expect("Private websites should have a bucket policy");
+        let statements = doc["Statement"].as_array().expect("Statement should be an array");
+        assert!(!statements.is_empty());
+        for statement in statements {
+            assert_ne!(statement["Principal"], "*");
+            assert_ne!(statement["Principal"], cfn_resources::serde_json::json!({ "AWS": "*" }));
+            assert_eq!(statement["Principal"]["Service"], CLOUDFRONT_SERVICE_PRINCIPAL);
+        }
+        assert_eq!(statements[0]["Condition"]["StringEquals"]["AWS:SourceAccount"], cfn_resources::serde_json::json!({ "Ref": "AWS::AccountId" }));
+
+        let inp = Input { private_website: true, private_website_distribution: "mydistr".to_string(), ..Default::default() };
+        let doc = get_bucket_policy_doc(&inp, "mybucket").unwrap();
+        let source_arn = doc["Statement"][0]["Condition"]["StringEquals"]["AWS:SourceArn"]["Fn::Sub"].as_str().unwrap();
+        assert!(source_arn.ends_with(":distribution/${mydistr}"));
+
+        // public websites allow everyone, and buckets that are neither dont need a policy
+        assert!(get_bucket_policy_doc(&Input { is_website: true, ..Default::default() }, "mybucket").is_some());
+        assert!(get_bucket_policy_doc(&Input::default(), "mybucket").is_none());
+
+        let oac = create_origin_access_control("myoac");
+        cfn_resources::CfnResource::validate(&oac).expect("Origin access control should be valid");
+        assert_eq!(cfn_resources::CfnResource::type_string(&oac), "AWS::CloudFront::OriginAccessControl");
+        let props = cfn_resources::CfnResource::properties(&oac);
+        assert_eq!(props["OriginAccessControlConfig"]["Name"], "myoac");
+        assert_eq!(props["OriginAccessControlConfig"]["OriginAccessControlOriginType"], "s3");
+        assert_eq!(props["OriginAccessControlConfig"]["SigningBehavior"], "always");
+        assert_eq!(props["OriginAccessControlConfig"]["SigningProtocol"], "sigv4");
+
+        // all public access is blocked, unless the bucket settings say otherwise
+        let mut bucket = CfnBucket::default();
+        block_public_access(&mut bucket);
+        let props = cfn_resources::CfnResource::properties(&bucket);
+        assert_eq!(props["PublicAccessBlockConfiguration"], cfn_resources::serde_json::json!({
+            "BlockPublicAcls": true,
+            "BlockPublicPolicy": true,
+            "IgnorePublicAcls": true,
+            "RestrictPublicBuckets": true,
+        }));
+        let mut bucket = CfnBucket {
+            public_access_block_configuration: Some(PublicAccessBlockConfiguration { block_public_acls: false.into(), ..Default::default() }),
+            ..Default::default()
+        };
+        block_public_access(&mut bucket);
+        let props = cfn_resources::CfnResource::properties(&bucket);
+        assert_eq!(props["PublicAccessBlockConfiguration"], cfn_resources::serde_json::json!({ "BlockPublicAcls": false }));
+
+        // stacks that deploy the same module get different names
+        assert_eq!(get_oac_name("stack-a", "hiragenbucketmysiteoac"), "stack-a-hiragenbucketmysiteoac");
+        assert_ne!(get_oac_name("stack-a", "hiragenbucketmysiteoac"), get_oac_name("stack-b", "hiragenbucketmysiteoac"));
+        let long_stack = "a".repeat(MAX_OAC_NAME_LEN);
+        let name = get_oac_name(&long_stack, "hiragenbucketmysiteoac");
+        assert_eq!(name.len(), MAX_OAC_NAME_LEN);
+        assert_ne!(name, get_oac_name(&long_stack, "hiragenbucketothersiteoac"));
+    }
+
+    #[test]
+    fn versioning_is_enabled() {
+        let properties = get_bucket_properties(&Input { versioning: true, ..Default::default() });
+        assert_eq!(properties["VersioningConfiguration"], cfn_resources::serde_json::json!({ "Status": "Enabled" }));
+        let mut bucket = CfnBucket::default();
+        set_bucket_settings(&Input::default(), &mut bucket).expect("Failed to set bucket settings");
+        assert!(bucket.versioning_configuration.is_none());
+        assert!(bucket.bucket_encryption.is_none());
+        assert!(bucket.lifecycle_configuration.is_none());
+    }
+
+    #[test]
+    fn encryption_is_set() {
+        let properties = get_bucket_properties(&Input { encryption: Some(Default::default()), ..Default::default() });
+        let rule = &properties["BucketEncryption"]["ServerSideEncryptionConfiguration"][0];
+        assert_eq!(rule["ServerSideEncryptionByDefault"]["SSEAlgorithm"], "AES256");
+        assert!(rule["ServerSideEncryptionByDefault"].get("KMSMasterKeyID").is_none());
+        assert!(rule.get("BucketKeyEnabled").is_none());
+
+        let encryption = SseConfig {
+            algorithm: SseAlgorithm::Kms,
+            kms_key_id: Some("arn:aws:kms:us-east-1:123456789012:key/abc".to_string()),
+            bucket_key_enabled: true,
+        };
+        let properties = get_bucket_properties(&Input { encryption: Some(encryption), ..Default::default() });
+        let rule = &properties["BucketEncryption"]["ServerSideEncryptionConfiguration"][0];
+        assert_eq!(rule["ServerSideEncryptionByDefault"]["SSEAlgorithm"], "aws:kms");
+        assert_eq!(rule["ServerSideEncryptionByDefault"]["KMSMasterKeyID"], "arn:aws:kms:us-east-1:123456789012:key/abc");
+        assert_eq!(rule["BucketKeyEnabled"], true);
+
+        let invalid = SseConfig { kms_key_id: Some("key".to_string()), ..Default::default() };
+        let mut bucket = CfnBucket::default();
+        assert!(set_bucket_settings(&Input { encryption: Some(invalid), ..Default::default() }, &mut bucket).is_err());
+    }
+
+    fn existing_lifecycle_rule(id: &str) -> s3::bucket::Rule {
+        s3::bucket::Rule {
+            id: Some(id.to_string().into()),
+            status: s3::bucket::RuleStatusEnum::Enabled,
+            expiration_in_days: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lifecycle_rules_are_merged_with_extra_bucket_settings() {
+        let inp = Input {
+            lifecycle_rules: vec![LifecycleRule {
+                id: "logs".to_string(),
+                prefix: "logs/".to_string(),
+                expiration_days: Some(365),
+                noncurrent_version_expiration_days: Some(30),
+                transitions: vec![LifecycleTransition { storage_class: "GLACIER".to_string(), days: 90 }],
+            }],
+            ..Default::default()
+        };
+        let mut bucket = CfnBucket {
+            lifecycle_configuration: Some(s3::bucket::LifecycleConfiguration {
+                rules: vec![existing_lifecycle_rule("existing")],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        set_bucket_settings(&inp, &mut bucket).expect("Failed to set bucket settings");
+        cfn_resources::CfnResource::validate(&bucket).expect("Bucket should be valid");
+        let properties = cfn_resources::CfnResource::properties(&bucket);
+        let rules = properties["LifecycleConfiguration"]["Rules"].as_array().expect("Rules should be an array");
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0]["Id"], "existing");
+        assert_eq!(rules[1]["Id"], "logs");
+        assert_eq!(rules[1]["Status"], "Enabled");
+        assert_eq!(rules[1]["Prefix"], "logs/");
+        assert_eq!(rules[1]["ExpirationInDays"], 365);
+        assert_eq!(rules[1]["NoncurrentVersionExpiration"]["NoncurrentDays"], 30);
+        assert_eq!(rules[1]["Transitions"][0]["StorageClass"], "GLACIER");
+        assert_eq!(rules[1]["Transitions"][0]["TransitionInDays"], 90);
+
+        let properties = get_bucket_properties(&inp);
+        assert_eq!(properties["LifecycleConfiguration"]["Rules"][0]["Id"], "logs");
+
+        let mut duplicate = Input { lifecycle_rules: inp.lifecycle_rules.clone(), ..Default::default() };
+        duplicate.lifecycle_rules[0].id = "existing".to_string();
+        let mut bucket = CfnBucket {
+            lifecycle_configuration: Some(s3::bucket::LifecycleConfiguration {
+                rules: vec![existing_lifecycle_rule("existing")],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(set_bucket_settings(&duplicate, &mut bucket).is_err());
+
+        let mut invalid = Input { lifecycle_rules: inp.lifecycle_rules.clone(), ..Default::default() };
+        invalid.lifecycle_rules[0].transitions[0].storage_class = "COLD".to_string();
+        assert!(set_bucket_settings(&invalid, &mut CfnBucket::default()).is_err());
+        for class in LIFECYCLE_STORAGE_CLASSES {
+            assert!(get_transition_storage_class(class).is_some());
+        }
+    }
+
+    #[test]
+    fn extra_bucket_settings_take_precedence() {
+        let inp = Input { versioning: true, encryption: Some(Default::default()), ..Default::default() };
+        let mut bucket = CfnBucket {
+            versioning_configuration: Some(s3::bucket::VersioningConfiguration {
+                status: s3::bucket::VersioningConfigurationStatusEnum::Suspended,
+            }),
+            bucket_encryption: Some(Default::default()),
+            ..Default::default()
+        };
+        let conflicts = set_bucket_settings(&inp, &mut bucket).expect("Failed to set bucket settings");
+        assert_eq!(conflicts, vec!["versioning", "encryption"]);
+        let properties = cfn_resources::CfnResource::properties(&bucket);
+        assert_eq!(properties["VersioningConfiguration"]["Status"], "Suspended");
+    }
+
+    #[test]
+    fn cleanup_runtime_can_be_overridden() {
+        let inp = Input {
+            cleanup_runtime: Some("nodejs16.x".to_string()),
+            ..Default::default()
+        };
+        let runtime = get_cleanup_runtime(&inp).expect("nodejs16.x should be valid");
+        assert!(runtime.deprecated);
+        assert_eq!(runtime.runtime, "nodejs16.x");
+        assert!(runtime.code.contains("require('aws-sdk')"));
+
+        let inp = Input {
+            cleanup_runtime: Some("python3.9".to_string()),
+            ..Default::default()
+        };
+        assert!(get_cleanup_runtime(&inp).is_err());
+    }
+}
@@ -6,7 +6,7 @@ pub mod dotenv_reader {
     use super::L0Core;
 
     #[derive(Default)]
-    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize))]
+    #[cfg_attr(feature = "web", derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema))]
     pub struct Input {
         /// the path to your .env file.
         /// it should be relative to the root of your crate, ie:
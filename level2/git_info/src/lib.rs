@@ -0,0 +1,81 @@
+use hira_lib::level0::*;
+
+/// Exposes the git commit of your crate as outputs, so that deployed resources can
+/// be labeled with the version of the source code they were deployed from.
+/// git is ran once per build from the directory of your Cargo.toml. If git is not installed,
+/// or your crate is not in a git repository, the commit outputs are `unknown`.
+#[hira::hira]
+pub mod git_info {
+    use super::L0Core;
+
+    pub mod outputs {
+        /// the full hash of the commit currently checked out
+        pub const GIT_COMMIT: &str = "unknown";
+        /// `true` if there are uncommitted changes, otherwise `false`
+        pub const GIT_DIRTY: &str = "false";
+        /// the first 7 characters of the commit hash
+        pub const GIT_SHORT: &str = "unknown";
+    }
+
+    #[derive(Default)]
+    pub struct Input {}
+
+    pub fn config(_inp: &mut Input, l0core: &mut L0Core) {
+        l0core.use_git_info();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hira_lib::e2e::{e2e_module2_run, get_hira_module_source};
+
+    #[test]
+    fn git_output_is_parsed() {
+        let info = GitInfo::parse(Some("0123456789abcdef0123456789abcdef01234567\n"), Some(""));
+        assert_eq!(info.commit, "0123456789abcdef0123456789abcdef01234567");
+        assert!(!info.dirty);
+        assert_eq!(info.outputs(), [
+            ("GIT_COMMIT", "0123456789abcdef0123456789abcdef01234567".to_string()),
+            ("GIT_DIRTY", "false".to_string()),
+            ("GIT_SHORT", "0123456".to_string()),
+        ]);
+
+        let info = GitInfo::parse(Some("0123456789abcdef0123456789abcdef01234567\n"), Some(" M src/lib.rs\n?? new.txt\n"));
+        assert!(info.dirty);
+    }
+
+    #[test]
+    fn git_output_degrades_gracefully() {
+        // git not installed, or not a repository
+        let info = GitInfo::parse(None, None);
+        assert_eq!(info.commit, GitInfo::UNKNOWN_COMMIT);
+        assert!(!info.dirty);
+        assert_eq!(info.short_commit(), "unknown");
+        // a repository without any commits prints the ref instead of a hash
+        let info = GitInfo::parse(Some("HEAD\n"), Some("?? a.txt\n"));
+        assert_eq!(info.commit, GitInfo::UNKNOWN_COMMIT);
+    }
+
+    #[test]
+    fn e2e_git_info_outputs_are_filled_in() {
+        let git_info_module = get_hira_module_source(include_str!("lib.rs"), "git_info");
+        let user_module = stringify!(
+            pub mod myapp {
+                use super::git_info;
+                pub mod outputs {
+                    pub use git_info::outputs::*;
+                }
+                pub fn config(input: &mut git_info::Input) {}
+            }
+        );
+        let conf = e2e_module2_run(&[&git_info_module, user_module], |c| {
+            // git is only ran if it hasn't been read yet
+            c.git_info = Some(GitInfo { commit: "0123456789abcdef0123456789abcdef01234567".to_string(), dirty: true });
+        }).expect("Failed to compile");
+        let module = conf.get_mod2("myapp").expect("Failed to find myapp");
+        assert_eq!(module.resolved_outputs["GIT_COMMIT"], "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(module.resolved_outputs["GIT_DIRTY"], "true");
+        assert_eq!(module.resolved_outputs["GIT_SHORT"], "0123456");
+    }
+}
@@ -11,6 +11,7 @@ pub mod aws_acm_cert {
     extern crate cfn_resources;
 
     use super::L0Core;
+    use super::validate_domain_name;
     use super::aws_cfn_stack;
     use self::certificate_manager::certificate::CertificateValidationMethodEnum;
     // use self::aws_cfn_stack::ResourceOutput;
@@ -53,17 +54,9 @@ pub mod aws_acm_cert {
         //     core.compiler_error(&format!("Must provide the hosted zone ID of where your domain resides"));
         //     return;
         // }
-        // TODO: optional domain validation?
-        if self_input.domain_name.contains("*") {
-            let matches = self_input.domain_name.matches("*");
-            if matches.count() > 1 {
-                l0core.compiler_error(&format!("Must only provide 1 wildcard. {} is invalid.", self_input.domain_name));
-                return;
-            }
-            if !self_input.domain_name.starts_with("*") {
-                l0core.compiler_error(&format!("If using a wildcard, it must be the first component of your domain, eg: \"*.something.com\". {} is invalid.", self_input.domain_name));
-                return;
-            }
+        if let Err(e) = validate_domain_name(&self_input.domain_name, true) {
+            l0core.compiler_error(&e);
+            return;
         }
         let cert = certificate_manager::certificate::CfnCertificate {
             domain_name: self_input.domain_name.clone().into(),
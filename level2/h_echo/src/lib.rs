@@ -16,7 +16,8 @@ pub mod echo {
 
     pub fn config(self_input: &mut Input, l0core: &mut L0Core, runtimer: &mut L0RuntimeCreator) {
         let meta = RuntimeMeta {
-            cargo_cmd: Default::default(), target: Default::default(), profile: Default::default(), no_tokio_async_runtime: true
+            no_tokio_async_runtime: true,
+            ..Default::default()
         };
         runtimer.add_to_runtime_ex(&l0core.users_module_name(), format!("println!(r#\"{}\"#)", self_input.echo), meta);
     }